@@ -0,0 +1,182 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use colors_transform::Rgb;
+use log::{debug, error, info, warn};
+use serde_json::{json, Value};
+use tuya_bulb_screen_color::stats::FrameStats;
+
+use crate::{
+    format_emitted_color, generate_screenshot_and_get_dominant_color, grabber::Grabber, latest,
+    pacing::FramePacer, pairing, CaptureOptions, EmitFormat,
+};
+
+/// `--agent` value that looks the hub up over mDNS.
+pub const DISCOVER: &str = "auto";
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Captures the screen and streams every extracted color to the hub at `address` (or the one
+/// found over mDNS), one JSON line per color after a line with the pairing code. Reconnects
+/// whenever the hub goes away. Streaming runs on a thread of its own, so a stalled connection
+/// doesn't hold up the capture, and the hub gets the newest color once it is back. A color is
+/// extracted every `interval`.
+pub fn run(address: &str, code: &str, capture: CaptureOptions, interval: Duration) {
+    let mut grabber = Grabber::new(&capture).unwrap_or_else(|error| {
+        error!("{}", error);
+        std::process::exit(1);
+    });
+    let mut pacer = FramePacer::new(capture.refresh_rate);
+    let mut last_stats: Option<FrameStats> = None;
+
+    let (lines, receiver) = latest::channel();
+    let (address, code) = (address.to_string(), code.to_string());
+    thread::spawn(move || stream(&address, &code, receiver));
+
+    loop {
+        let Some((dominant_color, stats, _)) = generate_screenshot_and_get_dominant_color(
+            &mut grabber,
+            None,
+            None,
+            &capture,
+            last_stats.as_ref(),
+        ) else {
+            pacer.wait(interval);
+            continue;
+        };
+        let line = format_emitted_color(&dominant_color, &stats, EmitFormat::Json);
+        last_stats = Some(stats);
+
+        if lines.send(line).is_err() {
+            return;
+        }
+
+        pacer.wait(interval);
+    }
+}
+
+fn stream(address: &str, code: &str, lines: latest::Receiver<String>) {
+    loop {
+        let address = if address == DISCOVER {
+            match pairing::discover() {
+                Ok(address) => address.to_string(),
+                Err(error) => {
+                    warn!("{}", error);
+                    thread::sleep(RECONNECT_INTERVAL);
+                    continue;
+                }
+            }
+        } else {
+            address.to_string()
+        };
+
+        let mut stream = match TcpStream::connect(&address) {
+            Ok(stream) => stream,
+            Err(error) => {
+                warn!("Couldn't reach the hub at {}: {}", address, error);
+                thread::sleep(RECONNECT_INTERVAL);
+                continue;
+            }
+        };
+        // A hub that stops reading is given up on and reconnected to.
+        if let Err(error) = stream.set_write_timeout(Some(WRITE_TIMEOUT)) {
+            warn!("Failed to set the write timeout: {}", error);
+        }
+
+        if let Err(error) = writeln!(stream, "{}", json!({ "pair": code })) {
+            warn!("Lost the hub at {}: {}", address, error);
+            continue;
+        }
+
+        info!("Streaming colors to the hub at {}.", address);
+
+        loop {
+            let Some(line) = lines.recv() else {
+                return;
+            };
+            if let Err(error) = writeln!(stream, "{}", line) {
+                warn!("Lost the hub at {}: {}", address, error);
+                break;
+            }
+        }
+    }
+}
+
+/// Accepts agents presenting `code` on `address` and hands the colors they stream over the
+/// returned channel, which keeps only the newest.
+pub fn listen(address: &str, code: String) -> Result<latest::Receiver<Rgb>, String> {
+    let listener = TcpListener::bind(address)
+        .map_err(|error| format!("Failed to listen on {}: {}", address, error))?;
+    let (colors, receiver) = latest::channel();
+
+    info!("Waiting for agents on {}.", address);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let colors = colors.clone();
+                    let code = code.clone();
+                    thread::spawn(move || receive(stream, &code, colors));
+                }
+                Err(error) => warn!("Failed to accept an agent: {}", error),
+            }
+        }
+    });
+
+    Ok(receiver)
+}
+
+fn receive(stream: TcpStream, code: &str, colors: latest::Sender<Rgb>) {
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "unknown".to_string(), |peer| peer.to_string());
+    let mut lines = BufReader::new(stream).lines();
+
+    let paired = lines
+        .next()
+        .and_then(Result::ok)
+        .and_then(|line| serde_json::from_str::<Value>(&line).ok())
+        .is_some_and(|line| line.get("pair").and_then(Value::as_str) == Some(code));
+    if !paired {
+        warn!("Rejected agent {}: wrong pairing code.", peer);
+        return;
+    }
+
+    info!("Agent {} connected.", peer);
+
+    for line in lines {
+        let line = match line {
+            Ok(line) => line,
+            Err(error) => {
+                warn!("Failed to read from agent {}: {}", peer, error);
+                break;
+            }
+        };
+
+        match parse_color(&line) {
+            Some(color) => {
+                if colors.send(color).is_err() {
+                    return;
+                }
+            }
+            None => debug!("Ignoring a malformed line from agent {}: {}", peer, line),
+        }
+    }
+
+    info!("Agent {} disconnected.", peer);
+}
+
+/// Reads the `rgb` triple of a line written by `format_emitted_color`.
+fn parse_color(line: &str) -> Option<Rgb> {
+    let value: Value = serde_json::from_str(line).ok()?;
+    let rgb = value.get("rgb")?.as_array()?;
+    let channel = |i: usize| rgb.get(i)?.as_f64().map(|c| c as f32);
+
+    Some(Rgb::from(channel(0)?, channel(1)?, channel(2)?))
+}
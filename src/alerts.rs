@@ -0,0 +1,82 @@
+use log::{info, warn};
+use notify_rust::Notification;
+
+/// Consecutive failed sends after which a device is reported as not responding.
+const FAILURE_THRESHOLD: u32 = 3;
+
+pub fn notify(summary: &str, body: &str) {
+    if let Err(error) = Notification::new()
+        .appname("tuya-bulb-screen-color")
+        .summary(summary)
+        .body(body)
+        .show()
+    {
+        warn!("Failed to show desktop notification: {}", error);
+    }
+}
+
+/// Tracks send results for one device and raises a desktop notification when it stops
+/// responding, and again once it recovers.
+pub struct FailureMonitor {
+    label: String,
+    enabled: bool,
+    consecutive_failures: u32,
+    reported: bool,
+}
+
+impl FailureMonitor {
+    pub fn new(label: &str, enabled: bool) -> FailureMonitor {
+        FailureMonitor {
+            label: label.to_string(),
+            enabled,
+            consecutive_failures: 0,
+            reported: false,
+        }
+    }
+
+    pub fn record(&mut self, success: bool) {
+        if success {
+            if self.reported {
+                info!("{} is responding again.", self.label);
+                self.alert(
+                    "Bulb is back",
+                    &format!("{} is responding again.", self.label),
+                );
+            }
+            self.consecutive_failures = 0;
+            self.reported = false;
+            return;
+        }
+
+        self.consecutive_failures += 1;
+
+        if self.consecutive_failures >= FAILURE_THRESHOLD && !self.reported {
+            warn!(
+                "{} failed {} updates in a row.",
+                self.label, self.consecutive_failures
+            );
+            self.reported = true;
+            self.alert(
+                "Bulb stopped responding",
+                &format!(
+                    "{} failed {} updates in a row.",
+                    self.label, self.consecutive_failures
+                ),
+            );
+        }
+    }
+
+    pub fn connection_failed(&mut self) {
+        self.reported = true;
+        self.alert(
+            "Bulb unreachable",
+            &format!("Failed to connect to {}.", self.label),
+        );
+    }
+
+    fn alert(&self, summary: &str, body: &str) {
+        if self.enabled {
+            notify(summary, body);
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    thread,
+    time::Duration,
+};
+
+use colors_transform::Rgb;
+use log::{debug, info, warn};
+use serde_json::Value;
+use tuya_bulb_screen_color::colorspace::ColorSpace;
+
+use crate::{
+    capabilities::Capabilities,
+    config::DeviceConfig,
+    create_color_payload,
+    grading::Grade,
+    http, rgb2hsv, send,
+    zones::{ZoneLayout, EDGES},
+    Output,
+};
+
+/// Port of the JointSpace API on the TV.
+const PORT: u16 = 1925;
+/// Port of JointSpace 6 on Philips' Android TVs, which only answer paired clients there, over
+/// HTTPS with digest authentication.
+const PAIRED_PORT: u16 = 1926;
+
+/// The colors a Philips TV computed for its Ambilight, per side. Sides the TV has no LEDs on
+/// are `None`.
+#[derive(Debug, Default)]
+struct Sides {
+    /// In the order of `EDGES`: top, right, bottom, left.
+    edges: [Option<Rgb>; 4],
+    all: Option<Rgb>,
+}
+
+/// Reads the processed Ambilight colors over JointSpace, which has them as
+/// `{"layer1": {"left": {"0": {"r": 255, "g": 0, "b": 0}, ...}, ...}}`, one entry per LED.
+fn read(tv: &str, api: u32, color_space: ColorSpace) -> Result<Sides, String> {
+    let url = format!("http://{}:{}/{}/ambilight/processed", tv, PORT, api);
+    let body = http::request("GET", &url, &[], &[])?;
+    let json: Value = serde_json::from_slice(&body)
+        .map_err(|error| format!("Unexpected answer from {}: {}", url, error))?;
+    let layer = &json["layer1"];
+
+    let leds = |side: &str| -> Vec<[f32; 3]> {
+        layer[side]
+            .as_object()
+            .into_iter()
+            .flat_map(|leds| leds.values())
+            .filter_map(|led| {
+                let channel = |name: &str| led[name].as_f64().map(|value| value as f32);
+                Some([channel("r")?, channel("g")?, channel("b")?])
+            })
+            .collect()
+    };
+    let average = |leds: Vec<[f32; 3]>| {
+        color_space
+            .average(leds)
+            .map(|[r, g, b]| Rgb::from(r, g, b))
+    };
+
+    let mut sides = Sides::default();
+    for (edge, name) in sides.edges.iter_mut().zip(EDGES) {
+        *edge = average(leds(name));
+    }
+    sides.all = average(EDGES.iter().flat_map(|name| leds(name)).collect());
+    if sides.all.is_none() {
+        return Err(format!("{} reported no Ambilight colors.", tv));
+    }
+    Ok(sides)
+}
+
+/// Whether the `/system` of a TV says it only answers paired clients.
+fn needs_pairing(system: &Value) -> bool {
+    system["featuring"]["systemfeatures"]["pairing_type"] == "digest_auth_pairing"
+}
+
+/// Fails when the TV is one that needs pairing, which this mode doesn't do. A TV that can't be
+/// reached at all passes, it may just be off.
+pub fn check(tv: &str, api: u32) -> Result<(), String> {
+    let paired = || {
+        Err(format!(
+            "{} only answers paired clients over HTTPS on port {} (JointSpace 6 on Philips' \
+             Android TVs), which the ambilight mode doesn't support.",
+            tv, PAIRED_PORT
+        ))
+    };
+
+    match http::request(
+        "GET",
+        &format!("http://{}:{}/{}/system", tv, PORT, api),
+        &[],
+        &[],
+    ) {
+        Ok(body) => match serde_json::from_slice(&body) {
+            Ok(system) if needs_pairing(&system) => paired(),
+            _ => Ok(()),
+        },
+        Err(error) => {
+            debug!("{}", error);
+            let listening = (tv.trim_matches(['[', ']']), PAIRED_PORT)
+                .to_socket_addrs()
+                .into_iter()
+                .flatten()
+                .any(|address| {
+                    TcpStream::connect_timeout(&address, Duration::from_secs(2)).is_ok()
+                });
+            if listening {
+                paired()
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Mirrors the Ambilight of a Philips TV onto the devices, read from the TV every `interval`
+/// instead of capturing a screen. A device with an edge as its `zone` shows that side of the
+/// TV, the others the average of all sides.
+pub fn run(
+    tv: &str,
+    api: u32,
+    interval: Duration,
+    devices: Vec<(DeviceConfig, Capabilities, Output)>,
+    color_space: ColorSpace,
+    grade: Grade,
+) {
+    let sides: Vec<Option<usize>> = devices
+        .iter()
+        .map(|(target, _, _)| {
+            let zone = target.zone.as_deref()?;
+            let side = ZoneLayout::Edges.index(zone);
+            if side.is_none() {
+                warn!(
+                    "{} follows the whole Ambilight, {} isn't a side of the TV.",
+                    target.label(),
+                    zone
+                );
+            }
+            side
+        })
+        .collect();
+
+    info!("Mirroring the Ambilight of {}...", tv);
+    let mut sent: Vec<Option<String>> = vec![None; devices.len()];
+    let mut failing = false;
+
+    loop {
+        match read(tv, api, color_space) {
+            Ok(colors) => {
+                if failing {
+                    info!("Reading the Ambilight of {} again.", tv);
+                    failing = false;
+                }
+
+                for (((target, capabilities, device), side), sent) in
+                    devices.iter().zip(&sides).zip(&mut sent)
+                {
+                    let color = side
+                        .and_then(|side| colors.edges[side])
+                        .or(colors.all)
+                        .map(|color| grade.apply(&color));
+                    let Some(color) = color else {
+                        continue;
+                    };
+
+                    // The TV fades smoothly, most reads repeat the last color.
+                    let hex = color.to_css_hex_string();
+                    if sent.as_ref() == Some(&hex) {
+                        continue;
+                    }
+                    debug!("{}: {}", target.label(), hex);
+                    let payload =
+                        create_color_payload(target.id.clone(), capabilities, rgb2hsv(&color));
+                    if send(device, payload) {
+                        *sent = Some(hex);
+                    }
+                }
+            }
+            Err(error) if !failing => {
+                warn!("{}", error);
+                failing = true;
+            }
+            Err(error) => debug!("{}", error),
+        }
+
+        thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn tells_tvs_that_need_pairing() {
+        let android = json!({
+            "api_version": { "Major": 6, "Minor": 2, "Patch": 0 },
+            "featuring": { "systemfeatures": { "pairing_type": "digest_auth_pairing" } }
+        });
+        assert!(needs_pairing(&android));
+
+        let saphi = json!({
+            "api_version": { "Major": 6, "Minor": 1, "Patch": 0 },
+            "featuring": { "systemfeatures": { "tvtype": "consumer" } }
+        });
+        assert!(!needs_pairing(&saphi));
+        assert!(!needs_pairing(&json!({ "name": "55PUS6501/12" })));
+    }
+}
@@ -0,0 +1,149 @@
+use std::time::{Duration, Instant};
+
+use tuya_bulb_screen_color::{
+    color::{clustered_color, kmeans_color, mean_color, palette_color},
+    stats::FrameStats,
+};
+
+use crate::{grabber::Grabber, memory, CaptureOptions, GRID_COLUMNS, GRID_ROWS};
+
+/// Start of a measurement: the time and allocation counters when a stage began.
+struct Mark {
+    started: Instant,
+    allocations: usize,
+    bytes: usize,
+}
+
+impl Mark {
+    fn now() -> Mark {
+        let (allocations, bytes) = memory::allocations();
+        Mark {
+            allocations,
+            bytes,
+            started: Instant::now(),
+        }
+    }
+}
+
+struct Stage {
+    name: &'static str,
+    times: Vec<Duration>,
+    allocations: usize,
+    bytes: usize,
+}
+
+impl Stage {
+    fn new(name: &'static str) -> Stage {
+        Stage {
+            name,
+            times: Vec::new(),
+            allocations: 0,
+            bytes: 0,
+        }
+    }
+
+    fn record(&mut self, mark: Mark) {
+        self.times.push(mark.started.elapsed());
+        let (allocations, bytes) = memory::allocations();
+        self.allocations += allocations - mark.allocations;
+        self.bytes += bytes - mark.bytes;
+    }
+
+    fn print(&mut self) {
+        self.times.sort();
+        let runs = self.times.len().max(1);
+        let mean = self.times.iter().sum::<Duration>() / runs as u32;
+        let p95 = self.times[(runs * 95 / 100).min(runs - 1)];
+        let max = self.times[runs - 1];
+
+        println!(
+            "{:<10} {:>10.2?} {:>10.2?} {:>10.2?} {:>12} {:>12}",
+            self.name,
+            mean,
+            p95,
+            max,
+            self.allocations / runs,
+            self.bytes / runs
+        );
+    }
+}
+
+/// Runs the capture pipeline `iterations` times and prints per-stage timings and allocations.
+pub fn run(iterations: usize, capture: CaptureOptions) -> Result<(), String> {
+    if iterations == 0 {
+        return Err("Need at least one iteration.".to_string());
+    }
+
+    let mut grabber = Grabber::new(&capture)?;
+    let (w, h) = grabber.size();
+    let (columns, rows) = (GRID_COLUMNS.min(w), GRID_ROWS.min(h));
+
+    let mut grab = Stage::new("grab");
+    let mut stats_stage = Stage::new("stats");
+    let mut palette = Stage::new("palette");
+    let mut average = Stage::new("average");
+    let mut kmeans = Stage::new("kmeans");
+    let mut grab_grid = Stage::new("grab-grid");
+    let mut cluster = Stage::new("cluster");
+
+    let mut previous: Option<FrameStats> = None;
+
+    for _ in 0..iterations {
+        let mark = Mark::now();
+        let img = grabber.grab_image()?;
+        grab.record(mark);
+
+        let mark = Mark::now();
+        let stats = FrameStats::compute(img.as_raw(), w, h, previous.as_ref());
+        stats_stage.record(mark);
+
+        let mark = Mark::now();
+        palette_color(img.as_raw(), capture.color_space);
+        palette.record(mark);
+
+        let mark = Mark::now();
+        mean_color(img.as_raw(), capture.decimation, capture.color_space);
+        average.record(mark);
+
+        let mark = Mark::now();
+        kmeans_color(img.as_raw(), capture.clusters);
+        kmeans.record(mark);
+
+        previous = Some(stats);
+
+        let mark = Mark::now();
+        let samples = grabber.grab_grid(columns, rows)?;
+        grab_grid.record(mark);
+
+        let mark = Mark::now();
+        clustered_color(samples, capture.color_space);
+        cluster.record(mark);
+    }
+
+    println!(
+        "{} iterations on a {}x{} display, blending in {}",
+        iterations, w, h, capture.color_space
+    );
+    println!(
+        "{:<10} {:>10} {:>10} {:>10} {:>12} {:>12}",
+        "stage", "mean", "p95", "max", "allocs/iter", "bytes/iter"
+    );
+    for stage in [
+        &mut grab,
+        &mut stats_stage,
+        &mut palette,
+        &mut average,
+        &mut kmeans,
+        &mut grab_grid,
+        &mut cluster,
+    ] {
+        stage.print();
+    }
+    println!(
+        "Full sampling runs grab, stats and palette (or average or kmeans), grid sampling grab-grid and \
+         cluster."
+    );
+    println!("Peak heap usage: {} bytes", memory::peak());
+
+    Ok(())
+}
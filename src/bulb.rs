@@ -0,0 +1,478 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use colors_transform::{Color, Hsl, Rgb};
+use log::{debug, info};
+use rust_tuyapi::Payload;
+use serde_json::Value;
+use tuya_bulb_screen_color::{colorspace::ColorSpace, stats::FrameStats};
+
+use crate::{
+    alerts::FailureMonitor,
+    apply_gain,
+    capabilities::Capabilities,
+    color_diff, color_picker_hsv,
+    config::DeviceConfig,
+    control::Control,
+    coordination::Coordinator,
+    create_color_payload, create_switch_led_payload, describe_color,
+    health::Health,
+    hsv2rgb,
+    profiles::ContentType,
+    query_dps, query_power,
+    reachability::ConnectionConfig,
+    reachability::Reachability,
+    rgb2hsv,
+    rules::{self, Rules},
+    send, Output,
+};
+
+/// Change in auto gain below which the bulb keeps its brightness.
+const GAIN_STEP: f32 = 0.05;
+/// How long a bulb that comes back in white eases into the screen color, unless its
+/// `soft-start-ms` says otherwise.
+const SOFT_START: Duration = Duration::from_secs(3);
+/// The white soft starts begin from, what bulbs show when they are powered on.
+const WHITE: [f32; 3] = [255.0, 255.0, 255.0];
+
+/// How a bulb fades to a new color with `--transition-steps`.
+#[derive(Debug, Clone, Copy)]
+pub struct Transition {
+    /// Most payloads per fade, fewer when the bulb can't take them that fast.
+    pub steps: u32,
+    /// Time the fade is spread over, the update interval.
+    pub duration: Duration,
+}
+
+/// What the color loop took from the current frame, shared by all bulbs.
+pub struct Frame {
+    pub color: Hsl,
+    /// Colors of the `--zones`, empty without them.
+    pub zones: Vec<Hsl>,
+    /// Brightness factor from `--auto-gain`, 1.0 without it.
+    pub gain: f32,
+    pub stats: FrameStats,
+    pub content: Option<ContentType>,
+    /// Change below which the bulb keeps its color, from the active profile.
+    pub threshold: f32,
+    /// Thresholds of the `--zones` set in `[zones.<name>]`, over `threshold` for their bulbs.
+    pub zone_thresholds: Vec<Option<f32>>,
+    pub screen_black_for: Duration,
+    /// Hue and saturation to keep when only the brightness follows the screen.
+    pub fixed_hue: Option<(u32, u32)>,
+    /// Set while a gamepad flash or a color forced through the control API is showing, when the
+    /// screen color is held back.
+    pub flashing: bool,
+    /// Set when `[presence]` reports that nobody is home, which switches the bulbs off.
+    pub nobody_home: bool,
+    /// Fade to new colors in steps rather than at once, `None` to jump.
+    pub transition: Option<Transition>,
+    /// Space the colors of transitions and soft starts are mixed in.
+    pub color_space: ColorSpace,
+    /// Brightness in percent the colors sent don't go below.
+    pub min_brightness: u32,
+}
+
+/// One bulb driven by the color loop, with everything tracked about it between frames.
+pub struct Bulb {
+    pub target: DeviceConfig,
+    pub capabilities: Capabilities,
+    /// The zone whose color the bulb shows, `None` for the whole frame's.
+    pub zone: Option<usize>,
+    device: Output,
+    monitor: FailureMonitor,
+    rules: Rules,
+    reachability: Option<Reachability>,
+    /// Extra attempts at a failed update.
+    retries: u32,
+    /// With `--wake-on-demand`, set once the bulb was powered on because it was off.
+    woke: Option<Arc<AtomicBool>>,
+    reachable: bool,
+    enabled: bool,
+    owned: bool,
+    powered: bool,
+    resync: bool,
+    check_power: bool,
+    device_offline: bool,
+    last_color: Hsl,
+    last_max_brightness: Option<u32>,
+    last_gain: f32,
+    /// The last color sent, `None` before the first one.
+    last_hsv: Option<(u32, u32, u32)>,
+    /// When the last color went out, to space them during `[quiet-hours]`.
+    last_update: Option<Instant>,
+    /// The last payload sent by the latest `update`, for `--debug-dump-on-change`.
+    last_payload: Option<Payload>,
+    /// Time the bulb takes to ease into the screen color when it comes back in white, zero to
+    /// jump to it.
+    soft_start: Duration,
+    /// Set when the bulb is back online, to check whether it lost its color.
+    check_soft_start: bool,
+    /// When the running soft start began.
+    soft_start_since: Option<Instant>,
+}
+
+impl Bulb {
+    pub fn new(
+        target: DeviceConfig,
+        capabilities: Capabilities,
+        device: Output,
+        monitor: FailureMonitor,
+        rules: Rules,
+        woke: Option<Arc<AtomicBool>>,
+        connection: ConnectionConfig,
+    ) -> Bulb {
+        let reachability = target
+            .ip
+            .as_deref()
+            .and_then(|ip| ip.parse().ok())
+            .map(|ip| Reachability::new(target.label(), ip, connection));
+        let soft_start = target
+            .soft_start_ms
+            .map_or(SOFT_START, Duration::from_millis);
+
+        Bulb {
+            reachability,
+            retries: connection.retries,
+            check_power: woke.is_some(),
+            target,
+            capabilities,
+            zone: None,
+            device,
+            monitor,
+            rules,
+            woke,
+            reachable: true,
+            enabled: true,
+            owned: true,
+            powered: true,
+            resync: false,
+            device_offline: false,
+            last_color: Hsl::from(0.0, 0.0, 0.0),
+            last_max_brightness: None,
+            last_gain: 1.0,
+            last_hsv: None,
+            last_update: None,
+            last_payload: None,
+            soft_start,
+            check_soft_start: false,
+            soft_start_since: None,
+        }
+    }
+
+    /// Starts from the color the previous run left the bulb at, so an unchanged screen isn't sent
+    /// again.
+    pub fn restore_color(&mut self, color: Hsl) {
+        self.last_color = color;
+    }
+
+    /// The brightness the bulb is at, as far as this instance knows; `None` while it is off,
+    /// unreachable or driven by another instance.
+    pub fn brightness(&self) -> Option<u32> {
+        self.last_hsv
+            .map(|hsv| hsv.2)
+            .filter(|_| self.powered && self.reachable && self.owned)
+    }
+
+    /// The color last sent, `None` before the first one.
+    pub fn last_sent(&self) -> Option<(u32, u32, u32)> {
+        self.last_hsv
+    }
+
+    /// Whether the bulb answers, as far as the reachability probe knows.
+    pub fn is_online(&self) -> bool {
+        self.reachability
+            .as_ref()
+            .is_none_or(Reachability::is_online)
+    }
+
+    pub fn label(&self) -> &str {
+        self.target.label()
+    }
+
+    /// What the latest `update` delivered last, `None` when nothing got through.
+    pub fn last_payload(&self) -> Option<&Payload> {
+        self.last_payload.as_ref()
+    }
+
+    fn send_and_track(&mut self, payload: Payload, health: &Health) -> bool {
+        let mut success = send(&self.device, payload.clone());
+        for attempt in 1..=self.retries {
+            if success {
+                break;
+            }
+            debug!(
+                "Retrying the update of {} ({}/{}).",
+                self.label(),
+                attempt,
+                self.retries
+            );
+            success = send(&self.device, payload.clone());
+        }
+
+        self.monitor.record(success);
+        health.send_finished(self.target.label(), success);
+        if success {
+            self.last_payload = Some(payload);
+        } else if let Some(reachability) = &self.reachability {
+            reachability.lost();
+        }
+        success
+    }
+
+    /// Sends colors mixed from `from` towards `to`, evenly spread over the transition so that
+    /// `to` goes out one step before its end. Stops at the first failed step.
+    fn fade(
+        &mut self,
+        from: (u32, u32, u32),
+        to: (u32, u32, u32),
+        transition: Transition,
+        color_space: ColorSpace,
+        health: &Health,
+    ) -> bool {
+        let min_interval = self.capabilities.min_interval.max(Duration::from_millis(1));
+        let steps = transition
+            .steps
+            .min((transition.duration.as_millis() / min_interval.as_millis()) as u32)
+            .max(1);
+        let pause = transition.duration / steps;
+        let channels = |rgb: Rgb| [rgb.get_red(), rgb.get_green(), rgb.get_blue()];
+        let (start, end) = (channels(hsv2rgb(from)), channels(hsv2rgb(to)));
+
+        for step in 1..=steps {
+            let hsv = if step == steps {
+                to
+            } else {
+                let [r, g, b] = color_space.mix(start, end, step as f32 / steps as f32);
+                rgb2hsv(&Rgb::from(r, g, b))
+            };
+            let payload = create_color_payload(self.target.id.clone(), &self.capabilities, hsv);
+            if !self.send_and_track(payload, health) {
+                return false;
+            }
+            if step < steps {
+                thread::sleep(pause);
+            }
+        }
+        true
+    }
+
+    /// Shows `color` right away; the screen color is sent again once the flash is over.
+    pub fn flash(&mut self, color: &Rgb, health: &Health) {
+        let payload =
+            create_color_payload(self.target.id.clone(), &self.capabilities, rgb2hsv(color));
+        self.send_and_track(payload, health);
+        self.resync = true;
+    }
+
+    /// Brings the bulb in line with the frame, sending only what changed.
+    pub fn update(
+        &mut self,
+        frame: &Frame,
+        health: &Health,
+        control: &Control,
+        coordinator: Option<&Coordinator>,
+    ) {
+        let label = self.target.label().to_string();
+        let waking = self.woke.is_some();
+        self.last_payload = None;
+
+        if control.is_enabled(&label) != self.enabled {
+            self.enabled = !self.enabled;
+            info!(
+                "Syncing {} {}.",
+                label,
+                if self.enabled { "resumed" } else { "paused" }
+            );
+            self.resync = self.enabled;
+            self.check_power = self.enabled && waking;
+        }
+
+        let drives = coordinator.is_none_or(|coordinator| {
+            coordinator.drives(&self.target.id, self.target.owner.as_deref())
+        });
+        if drives != self.owned {
+            self.owned = drives;
+            info!(
+                "{} {}.",
+                if self.owned { "Taking over" } else { "Leaving" },
+                label
+            );
+            self.resync = self.owned;
+            self.check_power = self.owned && waking;
+        }
+
+        let online = self
+            .reachability
+            .as_ref()
+            .is_none_or(Reachability::is_online);
+        if online != self.reachable {
+            self.reachable = online;
+            if online {
+                self.device.reset();
+                self.resync = true;
+                self.check_power = waking;
+                self.check_soft_start = !self.soft_start.is_zero();
+            } else {
+                health.send_finished(&label, false);
+            }
+        }
+
+        if !self.enabled || !self.owned || !online || frame.flashing {
+            return;
+        }
+
+        if let Some(woke) = self.woke.clone().filter(|_| self.check_power) {
+            self.check_power = false;
+            if query_power(&self.device, &self.target.id, &self.capabilities) == Some(false) {
+                info!("{} is off, powering it on.", label);
+                let payload =
+                    create_switch_led_payload(self.target.id.clone(), &self.capabilities, true);
+                if self.send_and_track(payload, health) {
+                    woke.store(true, Ordering::SeqCst);
+                    self.resync = true;
+                }
+            }
+        }
+
+        let outcome = self.rules.evaluate(&rules::Context {
+            time: rules::TimeOfDay::now(),
+            date: rules::Date::today(),
+            screen_black_for: frame.screen_black_for,
+            device_offline: self.device_offline,
+            stats: &frame.stats,
+            profile: frame.content,
+        });
+
+        let power_off = outcome.power_off || frame.nobody_home;
+        if power_off == self.powered {
+            self.powered = !power_off;
+            let reason = match (self.powered, frame.nobody_home) {
+                (true, _) => "",
+                (false, true) => ", nobody is home",
+                (false, false) => " by a rule",
+            };
+            info!(
+                "Switched {} {}{}.",
+                label,
+                if self.powered { "on" } else { "off" },
+                reason
+            );
+            let payload =
+                create_switch_led_payload(self.target.id.clone(), &self.capabilities, self.powered);
+            self.device_offline = !self.send_and_track(payload, health);
+        }
+
+        if !self.powered {
+            return;
+        }
+
+        // Bulbs power on in white after a power cut, which is jarring next to a dark screen.
+        if self.check_soft_start {
+            self.check_soft_start = false;
+            let mode_dp = self.capabilities.mode_dp.to_string();
+            let white = query_dps(&self.device, &self.target.id)
+                .is_some_and(|dps| dps.get(&mode_dp).and_then(Value::as_str) == Some("white"));
+            if white {
+                info!("{} came back in white, easing it into the color.", label);
+                self.soft_start_since = Some(Instant::now());
+            }
+        }
+        let easing = self
+            .soft_start_since
+            .map(|since| since.elapsed().as_secs_f32() / self.soft_start.as_secs_f32());
+
+        // Zones only have colors in color-picker, brightness-sync leaves the bulb on the frame's.
+        let zone = self.zone.filter(|&zone| zone < frame.zones.len());
+        let color = zone.map_or(frame.color, |zone| frame.zones[zone]);
+        let diff = color_diff(&self.last_color, &color);
+        let threshold = zone
+            .and_then(|zone| frame.zone_thresholds.get(zone).copied().flatten())
+            .unwrap_or(frame.threshold);
+        let quiet = self.device.quiet_interval().and_then(|interval| {
+            self.last_update
+                .and_then(|sent| (sent + interval).checked_duration_since(Instant::now()))
+        });
+
+        if !self.resync
+            && easing.is_none()
+            && diff <= threshold
+            && outcome.max_brightness == self.last_max_brightness
+            && (frame.gain - self.last_gain).abs() <= GAIN_STEP
+        {
+            info!("Color is the same for {}, not sending payload.", label);
+        } else if let Some(wait) = quiet {
+            // Left to a later frame, compared against the color last sent.
+            debug!(
+                "Quiet hours, holding the update of {} back for {:?}.",
+                label, wait
+            );
+            return;
+        } else {
+            let hsv = match frame.fixed_hue {
+                Some((hue, saturation)) => {
+                    let value = (color.get_lightness().round() as u32).max(1);
+                    let value = apply_gain(value, frame.gain);
+                    let value = outcome.max_brightness.map_or(value, |max| value.min(max));
+                    (hue, saturation, value)
+                }
+                None => color_picker_hsv(color, frame.gain, outcome.max_brightness),
+            };
+            // Rules that cap the brightness still win over the floor.
+            let floor = outcome
+                .max_brightness
+                .map_or(frame.min_brightness, |max| frame.min_brightness.min(max));
+            let hsv = (hsv.0, hsv.1, hsv.2.max(floor));
+            let eased = match easing {
+                Some(t) if t < 1.0 => {
+                    let rgb = hsv2rgb(hsv);
+                    let target = [rgb.get_red(), rgb.get_green(), rgb.get_blue()];
+                    let [r, g, b] = frame.color_space.mix(WHITE, target, t);
+                    Some(rgb2hsv(&Rgb::from(r, g, b)))
+                }
+                Some(_) => {
+                    self.soft_start_since = None;
+                    None
+                }
+                None => None,
+            };
+            let hsv = eased.unwrap_or(hsv);
+
+            info!(
+                "Color is different for {}, sending {}.",
+                label,
+                describe_color(&hsv2rgb(hsv))
+            );
+            let from = self.last_hsv.filter(|_| !self.resync && eased.is_none());
+            let sent = match (frame.transition, from) {
+                (Some(transition), Some(from)) if from != hsv => {
+                    self.fade(from, hsv, transition, frame.color_space, health)
+                }
+                _ => {
+                    let payload =
+                        create_color_payload(self.target.id.clone(), &self.capabilities, hsv);
+                    self.send_and_track(payload, health)
+                }
+            };
+            self.device_offline = !sent;
+            if !sent {
+                // Nothing is tracked, so the next frame tries again against what the bulb shows.
+                return;
+            }
+            self.last_hsv = Some(hsv);
+            self.last_update = Some(Instant::now());
+            self.resync = false;
+            // Compared against the last sent gain, as it drifts too slowly to show between frames.
+            self.last_gain = frame.gain;
+        }
+
+        self.last_color = color;
+        self.last_max_brightness = outcome.max_brightness;
+    }
+}
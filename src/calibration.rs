@@ -0,0 +1,58 @@
+use serde::Deserialize;
+use tuya_bulb_screen_color::colorspace::ColorSpace;
+
+const IDENTITY: [[f32; 3]; 3] = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// Corrects the colors sent to one device so it matches the display, `calibration` in its
+/// `[[devices]]` entry. The matrix is applied to the RGB color first, then the hue offset and the
+/// saturation scale to its HSV.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Calibration {
+    /// Degrees added to the hue, e.g. negative for a bulb whose reds come out orange.
+    pub hue_offset: f32,
+    /// Factor the saturation is multiplied with.
+    pub saturation: f32,
+    /// Rows of the matrix the red, green and blue channels are mixed with, e.g. a diagonal of
+    /// gains to fix the bulb's white point.
+    pub matrix: [[f32; 3]; 3],
+}
+
+impl Default for Calibration {
+    fn default() -> Calibration {
+        Calibration {
+            hue_offset: 0.0,
+            saturation: 1.0,
+            matrix: IDENTITY,
+        }
+    }
+}
+
+impl Calibration {
+    /// Corrects a color given as hue (0–360), saturation and value (0–100).
+    pub fn apply(&self, hsv: (u32, u32, u32)) -> (u32, u32, u32) {
+        if *self == Calibration::default() {
+            return hsv;
+        }
+
+        let rgb = ColorSpace::Hsv.to_rgb([hsv.0 as f32, hsv.1 as f32, hsv.2 as f32]);
+        let mixed = self.matrix.map(|row| {
+            row.iter()
+                .zip(rgb)
+                .map(|(gain, channel)| gain * channel)
+                .sum()
+        });
+        let [h, s, v] = ColorSpace::Hsv.from_rgb(ColorSpace::Rgb.to_rgb(mixed));
+
+        // Greys have no hue to turn.
+        let h = if s > 0.0 {
+            (h + self.hue_offset).rem_euclid(360.0)
+        } else {
+            h
+        };
+        let s = (s * self.saturation).clamp(0.0, 100.0);
+        // A color that had some brightness keeps some, bulbs treat 0 as off.
+        let v = if hsv.2 > 0 { v.max(1.0) } else { v };
+        (h.round() as u32 % 360, s.round() as u32, v.round() as u32)
+    }
+}
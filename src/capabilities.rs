@@ -0,0 +1,246 @@
+use std::{collections::HashMap, time::Duration};
+
+use log::debug;
+use serde::Deserialize;
+use tuya_bulb_screen_color::tuya::{hsv2tuya, hsv2tuya_v1};
+
+use crate::{calibration::Calibration, config::DeviceConfig};
+
+/// How a bulb expects its colour DP to be encoded.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColourFormat {
+    /// 14 character `colour_data` (`RRGGBBHHHHSSVV`) of older bulbs.
+    V1,
+    /// 12 character `colour_data_v2` (`HHHHSSSSVVVV`).
+    V2,
+}
+
+/// A firmware oddity the payloads work around, listed in `quirks` of a device or product.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Quirk {
+    /// Brightness changes only apply when the white mode is sent along with them.
+    NeedsModeResend,
+    /// Colours without any saturation are dropped, so greys are sent with the least there is.
+    IgnoresZeroSaturation,
+    /// Colours only apply while the bulb is on, so every colour switches it on too.
+    RequiresPowerBeforeColour,
+}
+
+/// The data points and limits of one kind of bulb.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    pub switch_dp: u32,
+    pub mode_dp: u32,
+    /// White brightness, see `encode_brightness`.
+    pub brightness_dp: u32,
+    /// White colour temperature, see `encode_temperature`.
+    pub temperature_dp: u32,
+    /// Colour temperatures in Kelvin at the ends of the temperature DP's range.
+    pub kelvin_range: (u32, u32),
+    pub colour_dp: u32,
+    pub colour_format: ColourFormat,
+    /// Shortest interval between updates the bulb keeps up with.
+    pub min_interval: Duration,
+    /// Correction applied to every color before it is encoded.
+    pub calibration: Calibration,
+    /// Factor the saturation of every color is multiplied with, see `--saturation-boost`.
+    pub saturation_boost: f32,
+    pub quirks: Vec<Quirk>,
+}
+
+impl Capabilities {
+    pub fn has_quirk(&self, quirk: Quirk) -> bool {
+        self.quirks.contains(&quirk)
+    }
+
+    /// The color actually sent for `hsv`: calibrated, then with the saturation boosted.
+    pub fn adjust(&self, hsv: (u32, u32, u32)) -> (u32, u32, u32) {
+        let (h, s, v) = self.calibration.apply(hsv);
+        let s = ((s as f32 * self.saturation_boost).round() as u32).min(100);
+        (h, s, v)
+    }
+
+    pub fn encode_colour(&self, hsv: (u32, u32, u32)) -> String {
+        let mut hsv = self.adjust(hsv);
+        if self.has_quirk(Quirk::IgnoresZeroSaturation) {
+            hsv.1 = hsv.1.max(1);
+        }
+        match self.colour_format {
+            ColourFormat::V1 => hsv2tuya_v1(hsv),
+            ColourFormat::V2 => hsv2tuya(hsv),
+        }
+    }
+
+    /// Maps a colour temperature in Kelvin onto the range of the temperature DP, linearly
+    /// between the warmest and coolest white of the bulb, clamping what is beyond them.
+    pub fn encode_temperature(&self, kelvin: u32) -> u32 {
+        let (warmest, coolest) = self.kelvin_range;
+        let kelvin = kelvin.clamp(warmest, coolest);
+        (kelvin - warmest) * self.temperature_max() / (coolest - warmest).max(1)
+    }
+
+    /// The colour temperature in Kelvin a value of the temperature DP stands for.
+    pub fn decode_temperature(&self, value: u32) -> u32 {
+        let (warmest, coolest) = self.kelvin_range;
+        let value = value.min(self.temperature_max());
+        warmest + coolest.saturating_sub(warmest) * value / self.temperature_max()
+    }
+
+    /// Scales a brightness of 0-100% onto the range of the brightness DP, which follows the
+    /// generation of the colour DP.
+    pub fn encode_brightness(&self, percent: u32) -> u32 {
+        let (min, max) = self.brightness_range();
+        min + (max - min) * percent.min(100) / 100
+    }
+
+    /// The brightness in percent a value of the brightness DP stands for, rounded.
+    pub fn decode_brightness(&self, value: u32) -> u32 {
+        let (min, max) = self.brightness_range();
+        ((value.clamp(min, max) - min) * 100 + (max - min) / 2) / (max - min)
+    }
+
+    fn brightness_range(&self) -> (u32, u32) {
+        match self.colour_format {
+            ColourFormat::V1 => (25, 255),
+            ColourFormat::V2 => (10, 1000),
+        }
+    }
+
+    fn temperature_max(&self) -> u32 {
+        match self.colour_format {
+            ColourFormat::V1 => 255,
+            ColourFormat::V2 => 1000,
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Capabilities {
+        lookup(DEFAULT_MODEL).unwrap()
+    }
+}
+
+const DEFAULT_MODEL: &str = "generic-v2";
+
+/// The range of white most bulbs cover.
+const KELVIN_RANGE: (u32, u32) = (2700, 6500);
+
+/// Known bulb models: name, switch, mode, brightness, temperature and colour DPs, colour format
+/// and the shortest update interval in milliseconds.
+type Model = (&'static str, [u32; 5], ColourFormat, u64);
+
+const MODELS: &[Model] = &[
+    // Bulbs on the current standard light DPs (20-26), the vast majority of Tuya bulbs.
+    ("generic-v2", [20, 21, 22, 23, 24], ColourFormat::V2, 100),
+    // Early bulbs with the legacy DPs (1-5), they drop updates sent much faster.
+    ("generic-v1", [1, 2, 3, 4, 5], ColourFormat::V1, 300),
+    // LED strip controllers on the standard DPs, they take updates at a higher rate.
+    ("led-strip-v2", [20, 21, 22, 23, 24], ColourFormat::V2, 50),
+];
+
+fn lookup(model: &str) -> Option<Capabilities> {
+    MODELS.iter().find(|entry| entry.0 == model).map(
+        |&(
+            _,
+            [switch_dp, mode_dp, brightness_dp, temperature_dp, colour_dp],
+            colour_format,
+            min_interval_ms,
+        )| {
+            Capabilities {
+                switch_dp,
+                mode_dp,
+                brightness_dp,
+                temperature_dp,
+                kelvin_range: KELVIN_RANGE,
+                colour_dp,
+                colour_format,
+                min_interval: Duration::from_millis(min_interval_ms),
+                calibration: Calibration::default(),
+                saturation_boost: 1.0,
+                quirks: Vec::new(),
+            }
+        },
+    )
+}
+
+/// Overrides for a product in the `[products.<product key>]` config section.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ProductConfig {
+    /// Built-in model to start from.
+    pub model: Option<String>,
+    pub switch_dp: Option<u32>,
+    pub mode_dp: Option<u32>,
+    pub brightness_dp: Option<u32>,
+    pub temperature_dp: Option<u32>,
+    pub colour_dp: Option<u32>,
+    pub colour_format: Option<ColourFormat>,
+    pub min_interval_ms: Option<u64>,
+    pub quirks: Vec<Quirk>,
+    /// Warmest and coolest white of the product in Kelvin.
+    pub min_kelvin: Option<u32>,
+    pub max_kelvin: Option<u32>,
+}
+
+/// Works out the capabilities of a device from its model or product key, applying the
+/// overrides configured for its product.
+pub fn resolve(
+    device: &DeviceConfig,
+    products: &HashMap<String, ProductConfig>,
+) -> Result<Capabilities, String> {
+    let overrides = device
+        .product_key
+        .as_ref()
+        .and_then(|product_key| products.get(product_key));
+
+    let model = overrides
+        .and_then(|overrides| overrides.model.as_deref())
+        .or(device.model.as_deref())
+        .unwrap_or(DEFAULT_MODEL);
+
+    let mut capabilities = lookup(model).ok_or_else(|| {
+        let known: Vec<_> = MODELS.iter().map(|entry| entry.0).collect();
+        format!(
+            "Unknown bulb model \"{}\" for {}, known models are {}.",
+            model,
+            device.label(),
+            known.join(", ")
+        )
+    })?;
+
+    if let Some(overrides) = overrides {
+        capabilities.switch_dp = overrides.switch_dp.unwrap_or(capabilities.switch_dp);
+        capabilities.mode_dp = overrides.mode_dp.unwrap_or(capabilities.mode_dp);
+        capabilities.brightness_dp = overrides
+            .brightness_dp
+            .unwrap_or(capabilities.brightness_dp);
+        capabilities.temperature_dp = overrides
+            .temperature_dp
+            .unwrap_or(capabilities.temperature_dp);
+        capabilities.kelvin_range = (
+            overrides.min_kelvin.unwrap_or(capabilities.kelvin_range.0),
+            overrides.max_kelvin.unwrap_or(capabilities.kelvin_range.1),
+        );
+        capabilities.colour_dp = overrides.colour_dp.unwrap_or(capabilities.colour_dp);
+        capabilities.colour_format = overrides
+            .colour_format
+            .unwrap_or(capabilities.colour_format);
+        capabilities.min_interval = overrides
+            .min_interval_ms
+            .map_or(capabilities.min_interval, Duration::from_millis);
+        capabilities.quirks.extend(&overrides.quirks);
+    }
+
+    capabilities.calibration = device.calibration.unwrap_or_default();
+    for quirk in &device.quirks {
+        if !capabilities.has_quirk(*quirk) {
+            capabilities.quirks.push(*quirk);
+        }
+    }
+
+    debug!("Using {:?} for {}.", capabilities, device.label());
+
+    Ok(capabilities)
+}
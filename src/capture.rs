@@ -0,0 +1,339 @@
+//! Capturing the screen and turning its frames into RGBA.
+//!
+//! The capture APIs deliver BGRA frames whose rows may be padded; everything here hands out
+//! tightly packed RGBA with alpha 0 on the pixels to leave out, which is what
+//! [`color`](crate::color) takes.
+//!
+//! ```no_run
+//! use tuya_bulb_screen_color::{
+//!     capture::{AlphaMode, Screen},
+//!     color::{rgb2hsv, Extraction},
+//! };
+//!
+//! let mut screen = Screen::open(0, AlphaMode::Skip)?;
+//! let frame = screen.grab()?;
+//! let hsv = rgb2hsv(Extraction::default().color(frame.as_raw()));
+//! # Ok::<(), String>(())
+//! ```
+
+use std::{io::ErrorKind::WouldBlock, str::FromStr, thread, time::Duration};
+
+use image::RgbaImage;
+use rayon::prelude::*;
+use scrap::{Capturer, Display};
+use serde::Deserialize;
+
+/// What to do with the alpha channel of captured pixels.
+#[derive(clap::ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlphaMode {
+    /// Leave fully transparent pixels out of the extraction
+    #[default]
+    Skip,
+    /// Like skip, and blend partially transparent pixels onto black
+    Blend,
+    /// Treat every pixel as opaque
+    Ignore,
+}
+
+/// One side of a `Region`, in pixels or percent of the frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Extent {
+    Pixels(usize),
+    Percent(f32),
+}
+
+impl Extent {
+    fn resolve(self, size: usize) -> usize {
+        match self {
+            Extent::Pixels(pixels) => pixels,
+            Extent::Percent(percent) => (size as f32 * percent / 100.0).round() as usize,
+        }
+    }
+}
+
+/// The part of the frame colors are taken from, written as `x,y,width,height` in pixels or in
+/// percent of the frame, e.g. `0,0,1280,720` or `0%,0%,75%,100%`.
+///
+/// ```
+/// use tuya_bulb_screen_color::capture::{Area, Region};
+///
+/// let region: Region = "0,50%,100%,2000".parse().unwrap();
+/// let area = Area { left: 0, top: 540, width: 1920, height: 540 };
+///
+/// assert_eq!(region.resolve(1920, 1080), Ok(area));
+/// assert!("0,0,120%,100%".parse::<Region>().is_err());
+/// ```
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Region([Extent; 4]);
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid region \"{}\", expected x,y,width,height in pixels or percent",
+                value
+            )
+        };
+
+        let extents = value
+            .split(',')
+            .map(|extent| {
+                let extent = extent.trim();
+                match extent.strip_suffix('%') {
+                    Some(percent) => percent
+                        .parse()
+                        .ok()
+                        .filter(|percent: &f32| (0.0..=100.0).contains(percent))
+                        .map(Extent::Percent),
+                    None => extent.parse().ok().map(Extent::Pixels),
+                }
+                .ok_or_else(invalid)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        extents.try_into().map(Region).map_err(|_| invalid())
+    }
+}
+
+impl TryFrom<String> for Region {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// A rectangle of the combined frame in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Area {
+    pub left: usize,
+    pub top: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Region {
+    /// The part of a `width` × `height` frame the region covers, cut off at the frame's edges.
+    pub fn resolve(self, width: usize, height: usize) -> Result<Area, String> {
+        let [x, y, w, h] = self.0;
+        let (left, top) = (x.resolve(width), y.resolve(height));
+        let area = Area {
+            left,
+            top,
+            width: w.resolve(width).min(width.saturating_sub(left)),
+            height: h.resolve(height).min(height.saturating_sub(top)),
+        };
+
+        if area.width == 0 || area.height == 0 {
+            return Err(format!(
+                "The region leaves nothing of the {}x{} frame.",
+                width, height
+            ));
+        }
+        Ok(area)
+    }
+}
+
+/// One display captured through the platform's screen capture API.
+pub struct Screen {
+    capturer: Capturer,
+    width: usize,
+    height: usize,
+    alpha: AlphaMode,
+    image: RgbaImage,
+}
+
+impl Screen {
+    /// Opens display `index`, counted in the order of [`display_sizes`].
+    pub fn open(index: usize, alpha: AlphaMode) -> Result<Screen, String> {
+        let mut displays = displays()?;
+        if index >= displays.len() {
+            return Err(format!(
+                "There is no display {}, only {}.",
+                index,
+                displays.len()
+            ));
+        }
+        let capturer = Capturer::new(displays.swap_remove(index))
+            .map_err(|error| format!("Failed to create capturer: {}", error))?;
+        let (width, height) = (capturer.width(), capturer.height());
+
+        Ok(Screen {
+            capturer,
+            width,
+            height,
+            alpha,
+            image: RgbaImage::new(width as u32, height as u32),
+        })
+    }
+
+    /// Width and height of the frames.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Waits for the next frame and converts it into an RGBA image that is reused by the next
+    /// call.
+    pub fn grab(&mut self) -> Result<&RgbaImage, String> {
+        let (width, height, alpha) = (self.width, self.height, self.alpha);
+        let image = self.image.as_mut();
+        read_frame(&mut self.capturer, width, height, |frame, stride| {
+            let alpha = effective_alpha(frame, stride, width, height, alpha);
+            swap_color_channels(frame, stride, width, alpha, image, width)
+        })?;
+        Ok(&self.image)
+    }
+}
+
+/// Every display there is to capture.
+pub fn displays() -> Result<Vec<Display>, String> {
+    Display::all().map_err(|error| format!("Couldn't list displays: {}", error))
+}
+
+/// Width and height of every display, in the order `Screen::open` counts them.
+pub fn display_sizes() -> Result<Vec<(usize, usize)>, String> {
+    Ok(displays()?
+        .iter()
+        .map(|display| (display.width(), display.height()))
+        .collect())
+}
+
+/// Waits for the next frame of `capturer`, a display of `width` × `height`, and hands it to
+/// `process` together with its stride, the number of bytes per row. Backends may pad rows, so
+/// the stride is derived from the frame size rather than assumed to be `width * 4`. Empty
+/// frames and frames too short for the display size are rejected instead of being processed
+/// into garbled colors.
+pub fn read_frame<T>(
+    capturer: &mut Capturer,
+    width: usize,
+    height: usize,
+    process: impl FnOnce(&[u8], usize) -> T,
+) -> Result<T, String> {
+    let one_frame = Duration::new(1, 0) / 60;
+
+    loop {
+        match capturer.frame() {
+            Ok(frame) if frame.is_empty() => return Err("Skipping an empty frame.".to_string()),
+            Ok(frame) if frame.len() / height.max(1) < width * 4 => {
+                return Err(format!(
+                    "Skipping a partial frame of {} bytes, expected at least {} for {}x{}.",
+                    frame.len(),
+                    width * height * 4,
+                    width,
+                    height
+                ))
+            }
+            Ok(frame) => {
+                let stride = frame.len() / height;
+                return Ok(process(&frame, stride));
+            }
+            Err(error) if error.kind() == WouldBlock => thread::sleep(one_frame),
+            Err(error) => return Err(format!("Failed to capture a frame: {}", error)),
+        }
+    }
+}
+
+/// Many backends leave the alpha byte of opaque captures at zero. A frame without a single
+/// non-zero alpha value among a spread of pixels is taken to carry no alpha at all. Only the
+/// `height` full rows of `stride` bytes are looked at, whatever follows them is left out.
+///
+/// ```
+/// use tuya_bulb_screen_color::capture::{effective_alpha, AlphaMode};
+///
+/// let mut frame = vec![0; 2 * 8 + 3];
+/// assert_eq!(effective_alpha(&frame, 8, 2, 2, AlphaMode::Skip), AlphaMode::Ignore);
+/// frame[3] = 255;
+/// assert_eq!(effective_alpha(&frame, 8, 2, 2, AlphaMode::Skip), AlphaMode::Skip);
+/// ```
+pub fn effective_alpha(
+    buffer: &[u8],
+    stride: usize,
+    width: usize,
+    height: usize,
+    alpha: AlphaMode,
+) -> AlphaMode {
+    if alpha == AlphaMode::Ignore {
+        return alpha;
+    }
+
+    let has_alpha = buffer
+        .chunks_exact(stride)
+        .take(height)
+        .step_by(16)
+        .any(|row| {
+            row[..width * 4]
+                .chunks_exact(4)
+                .step_by(16)
+                .any(|pixel| pixel[3] != 0)
+        });
+
+    if has_alpha {
+        alpha
+    } else {
+        AlphaMode::Ignore
+    }
+}
+
+/// Converts a BGRA pixel to RGBA. Pixels to be left out end up with alpha 0, all others are
+/// made opaque, so later stages only need to skip alpha 0.
+///
+/// ```
+/// use tuya_bulb_screen_color::capture::{to_rgba, AlphaMode};
+///
+/// assert_eq!(to_rgba(&[10, 20, 30, 0], AlphaMode::Skip), [30, 20, 10, 0]);
+/// assert_eq!(to_rgba(&[10, 20, 30, 0], AlphaMode::Ignore), [30, 20, 10, 255]);
+/// assert_eq!(to_rgba(&[0, 0, 255, 51], AlphaMode::Blend), [51, 0, 0, 255]);
+/// ```
+pub fn to_rgba(pixel: &[u8], alpha: AlphaMode) -> [u8; 4] {
+    let (b, g, r, a) = (pixel[0], pixel[1], pixel[2], pixel[3]);
+    match alpha {
+        AlphaMode::Ignore => [r, g, b, 255],
+        AlphaMode::Skip if a == 0 => [r, g, b, 0],
+        AlphaMode::Skip => [r, g, b, 255],
+        AlphaMode::Blend if a == 0 => [r, g, b, 0],
+        AlphaMode::Blend => {
+            let blend = |channel: u8| (channel as u16 * a as u16 / 255) as u8;
+            [blend(r), blend(g), blend(b), 255]
+        }
+    }
+}
+
+/// Converts a BGRA frame of `width` pixels with `stride` bytes per row into RGBA rows of
+/// `rgba_width` pixels, leaving the pixels past `width` alone.
+pub fn swap_color_channels(
+    buffer: &[u8],
+    stride: usize,
+    width: usize,
+    alpha: AlphaMode,
+    rgba: &mut [u8],
+    rgba_width: usize,
+) {
+    rgba.par_chunks_mut(rgba_width * 4)
+        .zip(buffer.par_chunks_exact(stride))
+        .for_each(|(swapped, row)| {
+            for (swapped, pixel) in swapped[..width * 4]
+                .chunks_exact_mut(4)
+                .zip(row.chunks_exact(4))
+            {
+                swapped.copy_from_slice(&to_rgba(pixel, alpha));
+            }
+        });
+}
+
+/// Frame coordinates of the grid sample in `column` and `row` of a grid over `area`, at the
+/// center of its cell.
+pub fn grid_point(
+    column: usize,
+    row: usize,
+    area: Area,
+    (columns, rows): (usize, usize),
+) -> (usize, usize) {
+    (
+        area.left + (2 * column + 1) * area.width / (2 * columns),
+        area.top + (2 * row + 1) * area.height / (2 * rows),
+    )
+}
@@ -0,0 +1,272 @@
+use std::{
+    io::{Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+use log::debug;
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+use openssl::ssl::{SslConnector, SslMethod, SslStream, SslVerifyMode};
+use serde_json::{json, Value};
+
+const SERVICE_TYPE: &str = "_googlecast._tcp.local.";
+const DISCOVERY_TIME: Duration = Duration::from_secs(2);
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONNECTION: &str = "urn:x-cast:com.google.cast.tp.connection";
+const HEARTBEAT: &str = "urn:x-cast:com.google.cast.tp.heartbeat";
+const RECEIVER: &str = "urn:x-cast:com.google.cast.receiver";
+const MEDIA: &str = "urn:x-cast:com.google.cast.media";
+const SENDER: &str = "sender-0";
+const PLATFORM: &str = "receiver-0";
+/// Largest message accepted, a status is a few kilobytes.
+const MAX_MESSAGE: usize = 64 * 1024;
+
+/// A Chromecast or other Cast receiver on the LAN.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Receiver {
+    pub name: String,
+    address: SocketAddr,
+}
+
+/// Finds the Cast receivers on the LAN.
+pub fn discover() -> Vec<Receiver> {
+    let daemon = match ServiceDaemon::new() {
+        Ok(daemon) => daemon,
+        Err(error) => {
+            debug!("Can't search for Cast receivers: {}", error);
+            return Vec::new();
+        }
+    };
+    let events = match daemon.browse(SERVICE_TYPE) {
+        Ok(events) => events,
+        Err(error) => {
+            debug!("Can't search for Cast receivers: {}", error);
+            let _ = daemon.shutdown();
+            return Vec::new();
+        }
+    };
+
+    let mut receivers: Vec<Receiver> = Vec::new();
+    let deadline = Instant::now() + DISCOVERY_TIME;
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        match events.recv_timeout(left) {
+            Ok(ServiceEvent::ServiceResolved(service)) => {
+                let Some(address) = service.get_addresses_v4().into_iter().next() else {
+                    continue;
+                };
+                let name = service
+                    .get_property_val_str("fn")
+                    .unwrap_or(&service.fullname)
+                    .to_string();
+                let address = SocketAddr::new(IpAddr::V4(address), service.get_port());
+                if receivers.iter().all(|receiver| receiver.address != address) {
+                    receivers.push(Receiver { name, address });
+                }
+            }
+            Ok(event) => debug!("mDNS: {:?}", event),
+            Err(_) => break,
+        }
+    }
+
+    let _ = daemon.shutdown();
+    receivers
+}
+
+fn varint(mut value: usize, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn string_field(field: u8, value: &str, out: &mut Vec<u8>) {
+    out.push(field << 3 | 2);
+    varint(value.len(), out);
+    out.extend_from_slice(value.as_bytes());
+}
+
+/// A `CastMessage` protobuf with a UTF-8 payload, prefixed with its big endian length.
+fn encode(destination: &str, namespace: &str, payload: &Value) -> Vec<u8> {
+    let mut message = vec![0x08, 0x00]; // protocol_version CASTV2_1_0
+    string_field(2, SENDER, &mut message);
+    string_field(3, destination, &mut message);
+    string_field(4, namespace, &mut message);
+    message.extend_from_slice(&[0x28, 0x00]); // payload_type STRING
+    string_field(6, &payload.to_string(), &mut message);
+
+    let mut framed = (message.len() as u32).to_be_bytes().to_vec();
+    framed.extend(message);
+    framed
+}
+
+/// The namespace and the JSON payload of a `CastMessage`, skipping the other fields.
+fn decode(mut message: &[u8]) -> Option<(String, Value)> {
+    let mut namespace = String::new();
+    let mut payload = Value::Null;
+
+    while let Some((&key, rest)) = message.split_first() {
+        message = rest;
+        let mut read_varint = || {
+            let mut value = 0usize;
+            for shift in (0..64).step_by(7) {
+                let (&byte, rest) = message.split_first()?;
+                message = rest;
+                value |= ((byte & 0x7f) as usize) << shift;
+                if byte < 0x80 {
+                    return Some(value);
+                }
+            }
+            None
+        };
+
+        match key & 7 {
+            0 => {
+                read_varint()?;
+            }
+            2 => {
+                let length = read_varint()?;
+                let bytes = message.get(..length)?;
+                message = &message[length..];
+                match key >> 3 {
+                    4 => namespace = String::from_utf8_lossy(bytes).into_owned(),
+                    6 => payload = serde_json::from_slice(bytes).ok()?,
+                    _ => {}
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    Some((namespace, payload))
+}
+
+struct Session {
+    stream: SslStream<TcpStream>,
+    request_id: u64,
+}
+
+impl Session {
+    fn open(address: SocketAddr) -> Result<Session, String> {
+        let failed =
+            |error: &dyn std::fmt::Display| format!("Cast to {} failed: {}", address, error);
+
+        let stream =
+            TcpStream::connect_timeout(&address, TIMEOUT).map_err(|error| failed(&error))?;
+        stream
+            .set_read_timeout(Some(TIMEOUT))
+            .map_err(|error| failed(&error))?;
+        // Receivers present a self-signed certificate.
+        let mut connector =
+            SslConnector::builder(SslMethod::tls()).map_err(|error| failed(&error))?;
+        connector.set_verify(SslVerifyMode::NONE);
+        let stream = connector
+            .build()
+            .connect(&address.ip().to_string(), stream)
+            .map_err(|error| failed(&error))?;
+
+        let mut session = Session {
+            stream,
+            request_id: 0,
+        };
+        session.send(PLATFORM, CONNECTION, json!({ "type": "CONNECT" }))?;
+        Ok(session)
+    }
+
+    fn send(&mut self, destination: &str, namespace: &str, payload: Value) -> Result<(), String> {
+        self.stream
+            .write_all(&encode(destination, namespace, &payload))
+            .map_err(|error| format!("Failed to send to the Cast receiver: {}", error))
+    }
+
+    fn receive(&mut self) -> Result<(String, Value), String> {
+        let failed = |error: &dyn std::fmt::Display| {
+            format!("Failed to read from the Cast receiver: {}", error)
+        };
+
+        let mut length = [0; 4];
+        self.stream
+            .read_exact(&mut length)
+            .map_err(|error| failed(&error))?;
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_MESSAGE {
+            return Err(failed(&format!("{} byte message", length)));
+        }
+
+        let mut message = vec![0; length];
+        self.stream
+            .read_exact(&mut message)
+            .map_err(|error| failed(&error))?;
+        decode(&message).ok_or_else(|| failed(&"malformed message"))
+    }
+
+    /// Sends a request and waits for the answer of the given type, answering pings meanwhile.
+    fn request(
+        &mut self,
+        destination: &str,
+        namespace: &str,
+        mut payload: Value,
+        answer: &str,
+    ) -> Result<Value, String> {
+        self.request_id += 1;
+        payload["requestId"] = json!(self.request_id);
+        self.send(destination, namespace, payload)?;
+
+        loop {
+            let (namespace, payload) = self.receive()?;
+            if namespace == HEARTBEAT && payload["type"] == "PING" {
+                self.send(PLATFORM, HEARTBEAT, json!({ "type": "PONG" }))?;
+            } else if payload["type"] == answer {
+                return Ok(payload);
+            }
+        }
+    }
+}
+
+impl Receiver {
+    /// The artwork of what the receiver is playing, `None` while it isn't playing or the media
+    /// has no artwork.
+    pub fn artwork(&self) -> Result<Option<String>, String> {
+        let mut session = Session::open(self.address)?;
+
+        let status = session.request(
+            PLATFORM,
+            RECEIVER,
+            json!({ "type": "GET_STATUS" }),
+            "RECEIVER_STATUS",
+        )?;
+        // Only apps that play media have a media namespace to ask.
+        let app = status["status"]["applications"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|app| {
+                app["namespaces"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .any(|namespace| namespace["name"] == MEDIA)
+            });
+        let Some(transport) = app.and_then(|app| app["transportId"].as_str()) else {
+            return Ok(None);
+        };
+
+        session.send(transport, CONNECTION, json!({ "type": "CONNECT" }))?;
+        let media = session.request(
+            transport,
+            MEDIA,
+            json!({ "type": "GET_STATUS" }),
+            "MEDIA_STATUS",
+        )?;
+
+        let playing = media["status"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|status| status["playerState"] == "PLAYING");
+        Ok(playing
+            .and_then(|status| status["media"]["metadata"]["images"][0]["url"].as_str())
+            .map(str::to_string))
+    }
+}
@@ -0,0 +1,92 @@
+//! k-means clustering of colors, or of any other three coordinates.
+
+/// Rounds of assigning points and moving the centers before giving up on convergence.
+const ITERATIONS: usize = 50;
+
+/// A group of points found by [`k_means`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cluster {
+    /// Mean of the points in the cluster.
+    pub center: [f32; 3],
+    /// Number of points in the cluster.
+    pub size: usize,
+}
+
+fn distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (0..3).map(|i| (a[i] - b[i]).powi(2)).sum()
+}
+
+fn nearest(clusters: &[Cluster], point: &[f32; 3]) -> usize {
+    (0..clusters.len())
+        .min_by(|&a, &b| {
+            distance(&clusters[a].center, point).total_cmp(&distance(&clusters[b].center, point))
+        })
+        .unwrap_or(0)
+}
+
+/// Groups `points` into `k` clusters (at least one) by k-means. The centers start out at the
+/// points farthest apart, so the result doesn't depend on chance. Returns the clusters and the
+/// cluster of every point; no points make no clusters.
+///
+/// ```
+/// use tuya_bulb_screen_color::cluster::k_means;
+///
+/// let points = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [10.0, 10.0, 10.0]];
+/// let (clusters, assigned) = k_means(&points, 2);
+///
+/// assert_eq!(assigned, [0, 0, 1]);
+/// assert_eq!(clusters[0].center, [0.5, 0.0, 0.0]);
+/// assert_eq!(clusters[0].size, 2);
+/// assert_eq!(clusters[1].center, [10.0, 10.0, 10.0]);
+/// assert!(k_means(&[], 3).0.is_empty());
+/// ```
+pub fn k_means(points: &[[f32; 3]], k: usize) -> (Vec<Cluster>, Vec<usize>) {
+    let Some(&first) = points.first() else {
+        return (Vec::new(), Vec::new());
+    };
+
+    let mut clusters = vec![Cluster {
+        center: first,
+        size: 0,
+    }];
+    while clusters.len() < k.max(1) {
+        let farthest = points.iter().max_by(|a, b| {
+            let a = distance(&clusters[nearest(&clusters, a)].center, a);
+            let b = distance(&clusters[nearest(&clusters, b)].center, b);
+            a.total_cmp(&b)
+        });
+        clusters.push(Cluster {
+            center: *farthest.unwrap_or(&first),
+            size: 0,
+        });
+    }
+
+    let mut assigned = vec![usize::MAX; points.len()];
+    for _ in 0..ITERATIONS {
+        let next: Vec<usize> = points
+            .iter()
+            .map(|point| nearest(&clusters, point))
+            .collect();
+        if next == assigned {
+            break;
+        }
+        assigned = next;
+
+        let mut sums = vec![([0.0f64; 3], 0usize); clusters.len()];
+        for (point, &cluster) in points.iter().zip(&assigned) {
+            let (sum, count) = &mut sums[cluster];
+            for i in 0..3 {
+                sum[i] += point[i] as f64;
+            }
+            *count += 1;
+        }
+        for (cluster, (sum, count)) in clusters.iter_mut().zip(sums) {
+            cluster.size = count;
+            if count > 0 {
+                cluster.center = sum.map(|sum| (sum / count as f64) as f32);
+            }
+        }
+    }
+
+    (clusters, assigned)
+}
@@ -0,0 +1,235 @@
+//! Picking the one color a bulb shows for a frame.
+//!
+//! Frames are passed as RGBA buffers, as [`capture::Screen`](crate::capture::Screen) hands them
+//! out; pixels with alpha 0 are left out. Colors come back as `[r, g, b]` with channels in
+//! 0.0–255.0, like in [`colorspace`](crate::colorspace).
+
+use std::collections::HashMap;
+
+use color_thief::get_palette;
+use log::{debug, warn};
+use serde::Deserialize;
+
+use crate::{cluster::k_means, colorspace::ColorSpace};
+
+/// Most pixels clustered by [`kmeans_color`].
+pub const KMEANS_SAMPLES: usize = 4096;
+
+/// How the color of a frame is picked.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Algorithm {
+    /// The most prominent color of a palette extracted from the frame (clustering with grid
+    /// sampling)
+    Palette,
+    /// The mean of all pixels, fast and true to the overall feel of the scene
+    Average,
+    /// The center of the largest of --clusters k-means clusters, steadier than the palette on
+    /// gradients and dark scenes
+    Kmeans,
+}
+
+/// Everything that shapes how the color of a frame is picked.
+///
+/// ```
+/// use tuya_bulb_screen_color::color::{rgb2hsv, Algorithm, Extraction};
+///
+/// let frame = [[255, 0, 0, 255], [255, 0, 0, 255], [0, 0, 255, 0]].concat();
+/// let extraction = Extraction {
+///     algorithm: Algorithm::Average,
+///     ..Extraction::default()
+/// };
+///
+/// assert_eq!(rgb2hsv(extraction.color(&frame)), (0, 100, 100));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extraction {
+    pub algorithm: Algorithm,
+    /// The space `Average` and the clustering of small frames average colors in.
+    pub color_space: ColorSpace,
+    /// `Average` reads every `decimation`-th pixel.
+    pub decimation: usize,
+    /// Number of clusters of `Kmeans`.
+    pub clusters: usize,
+}
+
+impl Default for Extraction {
+    fn default() -> Extraction {
+        Extraction {
+            algorithm: Algorithm::Palette,
+            color_space: ColorSpace::default(),
+            decimation: 1,
+            clusters: 5,
+        }
+    }
+}
+
+impl Extraction {
+    /// The color of a whole RGBA frame.
+    pub fn color(&self, rgba: &[u8]) -> [f32; 3] {
+        match self.algorithm {
+            Algorithm::Palette => palette_color(rgba, self.color_space),
+            Algorithm::Average => mean_color(rgba, self.decimation, self.color_space),
+            Algorithm::Kmeans => kmeans_color(rgba, self.clusters),
+        }
+    }
+
+    /// The color of a handful of RGBA samples, e.g. a sparse grid of the frame. Palette
+    /// extraction needs more pixels than that, it is replaced by [`clustered_color`].
+    pub fn sample_color(&self, samples: &[u8]) -> [f32; 3] {
+        match self.algorithm {
+            Algorithm::Palette => clustered_color(samples, self.color_space),
+            Algorithm::Average => mean_color(samples, 1, self.color_space),
+            Algorithm::Kmeans => kmeans_color(samples, self.clusters),
+        }
+    }
+}
+
+/// The most prominent color of a palette extracted from an RGBA frame, the mean color when
+/// there is no palette to be had.
+pub fn palette_color(rgba: &[u8], color_space: ColorSpace) -> [f32; 3] {
+    let palette = get_palette(rgba, color_thief::ColorFormat::Rgba, 10, 2);
+
+    let dominant_color = match palette.as_deref() {
+        Ok([dominant_color, ..]) => dominant_color,
+        Ok([]) => {
+            warn!("Palette extraction returned no colors, falling back to the mean color.");
+            return mean_color(rgba, 1, color_space);
+        }
+        Err(error) => {
+            warn!(
+                "Palette extraction failed ({:?}), falling back to the mean color.",
+                error
+            );
+            return mean_color(rgba, 1, color_space);
+        }
+    };
+
+    debug!("palette_color: {:?}", dominant_color);
+
+    [
+        dominant_color.r as f32,
+        dominant_color.g as f32,
+        dominant_color.b as f32,
+    ]
+}
+
+/// Averages every `step`-th visible pixel of an RGBA buffer, or every `step`-th pixel when the
+/// whole frame is transparent.
+///
+/// ```
+/// use tuya_bulb_screen_color::{color::mean_color, colorspace::ColorSpace};
+///
+/// let frame = [[200, 0, 0, 255], [0, 0, 100, 255], [0, 255, 0, 0]].concat();
+///
+/// assert_eq!(mean_color(&frame, 1, ColorSpace::Rgb), [100.0, 0.0, 50.0]);
+/// assert_eq!(mean_color(&frame, 2, ColorSpace::Rgb), [200.0, 0.0, 0.0]);
+/// ```
+pub fn mean_color(rgba: &[u8], step: usize, color_space: ColorSpace) -> [f32; 3] {
+    let pixels = || rgba.chunks_exact(4).step_by(step.max(1));
+    let rgb = |pixel: &[u8]| [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+
+    let mean = color_space
+        .average(pixels().filter(|pixel| pixel[3] != 0).map(rgb))
+        .or_else(|| color_space.average(pixels().map(rgb)))
+        .unwrap_or([0.0, 0.0, 0.0]);
+
+    debug!("mean_color: {:?}", mean);
+
+    mean
+}
+
+/// Splits the visible pixels of an RGBA buffer into `clusters` groups by k-means in Oklab and
+/// returns the center of the largest. Large frames are thinned out to [`KMEANS_SAMPLES`] pixels
+/// first, as every round of k-means walks all of them.
+pub fn kmeans_color(rgba: &[u8], clusters: usize) -> [f32; 3] {
+    let step = (rgba.len() / 4 / KMEANS_SAMPLES).max(1);
+    let points: Vec<[f32; 3]> = rgba
+        .chunks_exact(4)
+        .step_by(step)
+        .filter(|pixel| pixel[3] != 0)
+        .map(|pixel| {
+            ColorSpace::Oklab.from_rgb([pixel[0] as f32, pixel[1] as f32, pixel[2] as f32])
+        })
+        .collect();
+
+    let (clusters, _) = k_means(&points, clusters);
+    let largest = clusters.iter().max_by_key(|cluster| cluster.size);
+    let rgb = largest.map_or([0.0; 3], |cluster| ColorSpace::Oklab.to_rgb(cluster.center));
+
+    debug!("kmeans_color: {:?}", rgb);
+
+    rgb
+}
+
+/// Groups RGBA samples into coarse color cells (3 bits per channel) and averages the most
+/// populated one, a cheap stand-in for palette extraction on a handful of pixels.
+///
+/// ```
+/// use tuya_bulb_screen_color::{color::clustered_color, colorspace::ColorSpace};
+///
+/// let samples = [[250, 0, 0, 255], [230, 0, 0, 255], [0, 0, 255, 255]].concat();
+///
+/// assert_eq!(clustered_color(&samples, ColorSpace::Rgb), [240.0, 0.0, 0.0]);
+/// assert_eq!(clustered_color(&[], ColorSpace::Rgb), [0.0, 0.0, 0.0]);
+/// ```
+pub fn clustered_color(samples: &[u8], color_space: ColorSpace) -> [f32; 3] {
+    let mut cells: HashMap<(u8, u8, u8), Vec<[f32; 3]>> = HashMap::new();
+
+    for pixel in samples.chunks_exact(4).filter(|pixel| pixel[3] != 0) {
+        cells
+            .entry((pixel[0] >> 5, pixel[1] >> 5, pixel[2] >> 5))
+            .or_default()
+            .push([pixel[0] as f32, pixel[1] as f32, pixel[2] as f32]);
+    }
+
+    let largest = cells.into_values().max_by_key(Vec::len).unwrap_or_default();
+
+    color_space.average(largest).unwrap_or([0.0, 0.0, 0.0])
+}
+
+/// Converts a color to hue (0–359), saturation (0–100) and value (0–100), the ranges Tuya
+/// bulbs take them in.
+///
+/// ```
+/// use tuya_bulb_screen_color::color::rgb2hsv;
+///
+/// assert_eq!(rgb2hsv([255.0, 0.0, 0.0]), (0, 100, 100));
+/// assert_eq!(rgb2hsv([0.0, 0.0, 127.5]), (240, 100, 50));
+/// assert_eq!(rgb2hsv([0.0, 0.0, 0.0]), (0, 0, 0));
+/// ```
+pub fn rgb2hsv(rgb: [f32; 3]) -> (u32, u32, u32) {
+    let [r, g, b] = rgb.map(|channel| channel / 255.0);
+
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let h = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * (((g - b) / delta).rem_euclid(6.0))
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let s = if max == 0.0 { 0.0 } else { delta / max };
+
+    (
+        h.round() as u32 % 360,
+        (s * 100.0).round() as u32,
+        (max * 100.0).round() as u32,
+    )
+}
+
+/// Converts hue (0–360), saturation (0–100) and value (0–100) back to a color.
+///
+/// ```
+/// use tuya_bulb_screen_color::color::hsv2rgb;
+///
+/// assert_eq!(hsv2rgb((120, 100, 100)), [0.0, 255.0, 0.0]);
+/// ```
+pub fn hsv2rgb((hue, saturation, value): (u32, u32, u32)) -> [f32; 3] {
+    ColorSpace::Hsv.to_rgb([hue as f32, saturation as f32, value as f32])
+}
@@ -0,0 +1,320 @@
+//! Color spaces used for blending colors.
+//!
+//! Colors are passed around as `[r, g, b]` with channels in 0.0–255.0 (sRGB encoded). Blending
+//! converts into the chosen space, blends there and converts back. Perceptual spaces like Oklab
+//! avoid the grey, desaturated midpoints that mixing sRGB or HSL values produces, and linear RGB
+//! mixes colors the way light does.
+
+use std::{fmt, str::FromStr};
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorSpace {
+    Rgb,
+    /// RGB with the sRGB transfer curve undone, so channels are proportional to light.
+    LinearRgb,
+    Hsl,
+    Hsv,
+    #[default]
+    Oklab,
+    Lab,
+}
+
+impl FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "rgb" => Ok(ColorSpace::Rgb),
+            "linear-rgb" => Ok(ColorSpace::LinearRgb),
+            "hsl" => Ok(ColorSpace::Hsl),
+            "hsv" => Ok(ColorSpace::Hsv),
+            "oklab" => Ok(ColorSpace::Oklab),
+            "lab" => Ok(ColorSpace::Lab),
+            _ => Err(format!(
+                "unknown color space \"{}\", expected rgb, linear-rgb, hsl, hsv, oklab or lab",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorSpace::Rgb => "rgb",
+            ColorSpace::LinearRgb => "linear-rgb",
+            ColorSpace::Hsl => "hsl",
+            ColorSpace::Hsv => "hsv",
+            ColorSpace::Oklab => "oklab",
+            ColorSpace::Lab => "lab",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ColorSpace {
+    fn has_hue(&self) -> bool {
+        matches!(self, ColorSpace::Hsl | ColorSpace::Hsv)
+    }
+
+    /// Converts an sRGB color into the coordinates of this space. For HSL and HSV the first
+    /// coordinate is the hue in degrees, linear RGB keeps channels in 0.0–255.0.
+    pub fn from_rgb(&self, rgb: [f32; 3]) -> [f32; 3] {
+        match self {
+            ColorSpace::Rgb => rgb,
+            ColorSpace::LinearRgb => rgb.map(|channel| to_linear(channel) * 255.0),
+            ColorSpace::Hsl => rgb_to_hsl(rgb),
+            ColorSpace::Hsv => rgb_to_hsv(rgb),
+            ColorSpace::Oklab => linear_to_oklab(rgb.map(to_linear)),
+            ColorSpace::Lab => linear_to_lab(rgb.map(to_linear)),
+        }
+    }
+
+    /// Converts coordinates of this space back to sRGB, clamped to the displayable range.
+    pub fn to_rgb(&self, coordinates: [f32; 3]) -> [f32; 3] {
+        let rgb = match self {
+            ColorSpace::Rgb => coordinates,
+            ColorSpace::LinearRgb => coordinates.map(|channel| to_srgb(channel / 255.0)),
+            ColorSpace::Hsl => hsl_to_rgb(coordinates),
+            ColorSpace::Hsv => hsv_to_rgb(coordinates),
+            ColorSpace::Oklab => oklab_to_linear(coordinates).map(to_srgb),
+            ColorSpace::Lab => lab_to_linear(coordinates).map(to_srgb),
+        };
+        rgb.map(|channel| channel.clamp(0.0, 255.0))
+    }
+
+    /// Interpolates from `from` (t = 0.0) to `to` (t = 1.0) in this space. Hues take the
+    /// shorter way around the color wheel.
+    ///
+    /// ```
+    /// use tuya_bulb_screen_color::colorspace::ColorSpace;
+    ///
+    /// let red = [255.0, 0.0, 0.0];
+    /// let blue = [0.0, 0.0, 255.0];
+    ///
+    /// assert_eq!(ColorSpace::Rgb.mix(red, blue, 0.5), [127.5, 0.0, 127.5]);
+    /// assert_eq!(ColorSpace::Oklab.mix(red, blue, 0.0).map(f32::round), red);
+    /// ```
+    pub fn mix(&self, from: [f32; 3], to: [f32; 3], t: f32) -> [f32; 3] {
+        let t = t.clamp(0.0, 1.0);
+        let a = self.from_rgb(from);
+        let b = self.from_rgb(to);
+
+        let mut mixed = [0.0; 3];
+        for i in 0..3 {
+            mixed[i] = if i == 0 && self.has_hue() {
+                let delta = (b[0] - a[0] + 540.0).rem_euclid(360.0) - 180.0;
+                (a[0] + delta * t).rem_euclid(360.0)
+            } else {
+                a[i] + (b[i] - a[i]) * t
+            };
+        }
+
+        self.to_rgb(mixed)
+    }
+
+    /// Averages colors in this space, hues are averaged on the circle. Returns `None` for no
+    /// colors.
+    ///
+    /// ```
+    /// use tuya_bulb_screen_color::colorspace::ColorSpace;
+    ///
+    /// let black_and_white = [[0.0; 3], [255.0; 3]];
+    ///
+    /// // Half the pixels lit give half the light, which sRGB encodes brighter than halfway.
+    /// assert_eq!(ColorSpace::Rgb.average(black_and_white), Some([127.5; 3]));
+    /// let [grey, ..] = ColorSpace::LinearRgb.average(black_and_white).unwrap();
+    /// assert_eq!(grey.round(), 188.0);
+    /// ```
+    pub fn average<I>(&self, colors: I) -> Option<[f32; 3]>
+    where
+        I: IntoIterator<Item = [f32; 3]>,
+    {
+        let mut sums = [0.0f64; 3];
+        let mut hue = (0.0f64, 0.0f64);
+        let mut count = 0usize;
+
+        for color in colors {
+            let coordinates = self.from_rgb(color);
+            if self.has_hue() {
+                let radians = (coordinates[0] as f64).to_radians();
+                hue.0 += radians.cos();
+                hue.1 += radians.sin();
+            }
+            for i in 0..3 {
+                sums[i] += coordinates[i] as f64;
+            }
+            count += 1;
+        }
+
+        if count == 0 {
+            return None;
+        }
+
+        let mut average = sums.map(|sum| (sum / count as f64) as f32);
+        if self.has_hue() {
+            average[0] = (hue.1.atan2(hue.0).to_degrees() as f32).rem_euclid(360.0);
+        }
+
+        Some(self.to_rgb(average))
+    }
+}
+
+fn to_linear(channel: f32) -> f32 {
+    let c = channel / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn to_srgb(linear: f32) -> f32 {
+    let c = linear.clamp(0.0, 1.0);
+    let encoded = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    encoded * 255.0
+}
+
+fn linear_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.412_221_46 * r + 0.536_332_55 * g + 0.051_445_995 * b).cbrt();
+    let m = (0.211_903_5 * r + 0.680_699_5 * g + 0.107_396_96 * b).cbrt();
+    let s = (0.088_302_46 * r + 0.281_718_85 * g + 0.629_978_7 * b).cbrt();
+
+    [
+        0.210_454_26 * l + 0.793_617_8 * m - 0.004_072_047 * s,
+        1.977_998_5 * l - 2.428_592_2 * m + 0.450_593_7 * s,
+        0.025_904_037 * l + 0.782_771_77 * m - 0.808_675_77 * s,
+    ]
+}
+
+fn oklab_to_linear([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let l_ = (l + 0.396_337_78 * a + 0.215_803_76 * b).powi(3);
+    let m_ = (l - 0.105_561_346 * a - 0.063_854_17 * b).powi(3);
+    let s_ = (l - 0.089_484_18 * a - 1.291_485_5 * b).powi(3);
+
+    [
+        4.076_741_7 * l_ - 3.307_711_6 * m_ + 0.230_969_94 * s_,
+        -1.268_438 * l_ + 2.609_757_4 * m_ - 0.341_319_38 * s_,
+        -0.004_196_086_3 * l_ - 0.703_418_6 * m_ + 1.707_614_7 * s_,
+    ]
+}
+
+/// D65 reference white.
+const WHITE: [f32; 3] = [0.950_47, 1.0, 1.088_83];
+
+fn linear_to_lab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let xyz = [
+        0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b,
+        0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b,
+        0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b,
+    ];
+
+    let f = |t: f32| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let [fx, fy, fz] = [
+        f(xyz[0] / WHITE[0]),
+        f(xyz[1] / WHITE[1]),
+        f(xyz[2] / WHITE[2]),
+    ];
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn lab_to_linear([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let fy = (l + 16.0) / 116.0;
+    let fx = fy + a / 500.0;
+    let fz = fy - b / 200.0;
+
+    let f_inv = |t: f32| {
+        if t > 6.0 / 29.0 {
+            t.powi(3)
+        } else {
+            (116.0 * t - 16.0) * 27.0 / 24389.0
+        }
+    };
+    let [x, y, z] = [
+        f_inv(fx) * WHITE[0],
+        f_inv(fy) * WHITE[1],
+        f_inv(fz) * WHITE[2],
+    ];
+
+    [
+        3.240_454_2 * x - 1.537_138_5 * y - 0.498_531_4 * z,
+        -0.969_266 * x + 1.876_010_8 * y + 0.041_556 * z,
+        0.055_643_4 * x - 0.204_025_9 * y + 1.057_225_2 * z,
+    ]
+}
+
+fn hue_of(r: f32, g: f32, b: f32, max: f32, delta: f32) -> f32 {
+    if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    }
+}
+
+fn rgb_to_hsv(rgb: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(|channel| channel / 255.0);
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let s = if max == 0.0 { 0.0 } else { delta / max };
+
+    [hue_of(r, g, b, max, delta), s * 100.0, max * 100.0]
+}
+
+fn rgb_to_hsl(rgb: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(|channel| channel / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let l = (max + min) / 2.0;
+    let s = if delta == 0.0 {
+        0.0
+    } else {
+        delta / (1.0 - (2.0 * l - 1.0).abs())
+    };
+
+    [hue_of(r, g, b, max, delta), s * 100.0, l * 100.0]
+}
+
+fn from_chroma(h: f32, c: f32, m: f32) -> [f32; 3] {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let x = c * (1.0 - (h.rem_euclid(2.0) - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    [(r + m) * 255.0, (g + m) * 255.0, (b + m) * 255.0]
+}
+
+fn hsv_to_rgb([h, s, v]: [f32; 3]) -> [f32; 3] {
+    let (s, v) = (s / 100.0, v / 100.0);
+    let c = v * s;
+    from_chroma(h, c, v - c)
+}
+
+fn hsl_to_rgb([h, s, l]: [f32; 3]) -> [f32; 3] {
+    let (s, l) = (s / 100.0, l / 100.0);
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    from_chroma(h, c, l - c / 2.0)
+}
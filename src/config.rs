@@ -0,0 +1,264 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use colors_transform::Rgb;
+use log::info;
+use serde::Deserialize;
+use toml_edit::DocumentMut;
+
+use crate::calibration::Calibration;
+use crate::capabilities::{ProductConfig, Quirk};
+use crate::coordination::CoordinationConfig;
+use crate::gain::AutoGainConfig;
+use crate::gamepad::Binding;
+use crate::grabber::CaptureConfig;
+use crate::grading::Grade;
+use crate::mqtt::MqttConfig;
+use crate::presence::PresenceConfig;
+use crate::profiles::{ContentType, ProfileConfig};
+use crate::quiet::QuietHoursConfig;
+use crate::reachability::ConnectionConfig;
+use crate::rules::Rule;
+use crate::state::Startup;
+use crate::zones::ZoneConfig;
+use crate::Feature;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Mode used when `--mode` isn't given.
+    pub mode: Option<Feature>,
+    /// What to do when `--mode` isn't given, see `state`.
+    pub startup: Startup,
+    pub devices: Vec<DeviceConfig>,
+    pub rules: Vec<Rule>,
+    pub profiles: HashMap<ContentType, ProfileConfig>,
+    pub grade: Grade,
+    pub capture: CaptureConfig,
+    /// Smoothing and threshold of single zones, by zone name.
+    pub zones: HashMap<String, ZoneConfig>,
+    /// Update rate and threshold used while no content profile is active.
+    pub update: ProfileConfig,
+    pub products: HashMap<String, ProductConfig>,
+    pub connection: ConnectionConfig,
+    pub coordination: Option<CoordinationConfig>,
+    pub gamepad: Vec<Binding>,
+    #[serde(rename = "auto-gain")]
+    pub auto_gain: AutoGainConfig,
+    pub mqtt: Option<MqttConfig>,
+    pub presence: Option<PresenceConfig>,
+    #[serde(rename = "quiet-hours")]
+    pub quiet_hours: Option<QuietHoursConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DeviceConfig {
+    pub name: Option<String>,
+    pub id: String,
+    pub key: String,
+    pub ip: Option<String>,
+    #[serde(default = "default_version")]
+    pub version: String,
+    /// Tuya product key, used to look up overrides in `[products]`.
+    pub product_key: Option<String>,
+    /// Built-in bulb model, see `capabilities`.
+    pub model: Option<String>,
+    /// Instance that drives this device when several coordinate, see `coordination`.
+    pub owner: Option<String>,
+    /// Zone of the screen the device follows, e.g. `left`, see `zones`.
+    pub zone: Option<String>,
+    /// Time the device takes to ease from white into the color when it comes back after a power
+    /// cut, 0 to jump, see `bulb`.
+    pub soft_start_ms: Option<u64>,
+    /// Color correction for the device, see `calibration`.
+    pub calibration: Option<Calibration>,
+    /// Firmware oddities of the device, on top of those of its product.
+    #[serde(default)]
+    pub quirks: Vec<Quirk>,
+}
+
+impl DeviceConfig {
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.id)
+    }
+}
+
+/// A `#rrggbb` color in the config.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub struct HexColor(pub Rgb);
+
+impl TryFrom<String> for HexColor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Rgb::from_hex_str(&value)
+            .map(HexColor)
+            .map_err(|_| format!("invalid color \"{}\", expected #rrggbb", value))
+    }
+}
+
+fn default_version() -> String {
+    "3.3".to_string()
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("tuya-bulb-screen-color").join("config.toml"))
+}
+
+/// Loads the config from `path`, or from the default location when no path is given.
+/// A missing default config is not an error, an explicitly requested one is.
+pub fn load(path: Option<PathBuf>) -> Result<Config, String> {
+    let (path, explicit) = match path {
+        Some(path) => (path, true),
+        None => match default_path() {
+            Some(path) => (path, false),
+            None => return Ok(Config::default()),
+        },
+    };
+
+    if !explicit && !path.exists() {
+        return Ok(Config::default());
+    }
+
+    let contents = fs::read_to_string(&path)
+        .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+
+    toml::from_str(&contents)
+        .map_err(|error| format!("Failed to parse {}: {}", path.display(), error))
+}
+
+/// Applies `change` to the config document and writes it back, keeping comments and formatting
+/// of everything it doesn't touch. The config is created if it doesn't exist yet.
+pub fn edit<F>(path: Option<PathBuf>, change: F) -> Result<PathBuf, String>
+where
+    F: FnOnce(&mut DocumentMut) -> Result<(), String>,
+{
+    let path = path
+        .or_else(default_path)
+        .ok_or_else(|| "Could not determine the config directory.".to_string())?;
+
+    let mut document = read_document(&path)?;
+    change(&mut document)?;
+
+    // Make sure the result still is a valid config before replacing the old one.
+    toml::from_str::<Config>(&document.to_string())
+        .map_err(|error| format!("Refusing to write an invalid config: {}", error))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|error| format!("Failed to create {}: {}", parent.display(), error))?;
+    }
+
+    if path.exists() {
+        backup(&path)?;
+    }
+
+    fs::write(&path, document.to_string())
+        .map_err(|error| format!("Failed to write {}: {}", path.display(), error))?;
+
+    Ok(path)
+}
+
+fn read_document(path: &Path) -> Result<DocumentMut, String> {
+    if !path.exists() {
+        return Ok(DocumentMut::new());
+    }
+
+    fs::read_to_string(path)
+        .map_err(|error| format!("Failed to read {}: {}", path.display(), error))?
+        .parse::<DocumentMut>()
+        .map_err(|error| format!("Failed to parse {}: {}", path.display(), error))
+}
+
+/// How many backups are kept per config file, older ones are deleted.
+const MAX_BACKUPS: usize = 20;
+
+fn backup_dir(path: &Path) -> PathBuf {
+    path.with_file_name("backups")
+}
+
+fn backup_prefix(path: &Path) -> String {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    format!("{}-", stem)
+}
+
+/// Backups of `path`, oldest first.
+fn backups(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let dir = backup_dir(path);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let prefix = backup_prefix(path);
+    let mut backups: Vec<PathBuf> = fs::read_dir(&dir)
+        .map_err(|error| format!("Failed to read {}: {}", dir.display(), error))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|backup| {
+            backup
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+        })
+        .collect();
+
+    backups.sort();
+    Ok(backups)
+}
+
+/// Copies the current config into the backup directory under a timestamped name.
+fn backup(path: &Path) -> Result<(), String> {
+    let dir = backup_dir(path);
+    fs::create_dir_all(&dir)
+        .map_err(|error| format!("Failed to create {}: {}", dir.display(), error))?;
+
+    let name = format!(
+        "{}{}.toml",
+        backup_prefix(path),
+        Local::now().format("%Y%m%dT%H%M%S%3f")
+    );
+    fs::copy(path, dir.join(&name))
+        .map_err(|error| format!("Failed to back up {}: {}", path.display(), error))?;
+
+    info!(
+        "Backed up the previous config to {}.",
+        dir.join(name).display()
+    );
+
+    let backups = backups(path)?;
+    for old in backups
+        .iter()
+        .take(backups.len().saturating_sub(MAX_BACKUPS))
+    {
+        let _ = fs::remove_file(old);
+    }
+
+    Ok(())
+}
+
+/// Replaces the config with its most recent backup. The restored backup is consumed, so
+/// rolling back again goes one version further back.
+pub fn rollback(path: Option<PathBuf>) -> Result<PathBuf, String> {
+    let path = path
+        .or_else(default_path)
+        .ok_or_else(|| "Could not determine the config directory.".to_string())?;
+
+    let latest = backups(&path)?
+        .pop()
+        .ok_or_else(|| format!("No backups found for {}.", path.display()))?;
+
+    fs::rename(&latest, &path).map_err(|error| {
+        format!(
+            "Failed to restore {} to {}: {}",
+            latest.display(),
+            path.display(),
+            error
+        )
+    })?;
+
+    Ok(latest)
+}
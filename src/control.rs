@@ -0,0 +1,98 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Runtime switches that the control API flips and the color loop follows, and what the color
+/// loop reports back.
+#[derive(Clone, Default)]
+pub struct Control {
+    devices: Arc<Mutex<BTreeMap<String, bool>>>,
+    status: Arc<Mutex<LoopStatus>>,
+}
+
+/// What the color loop is doing, as of its last update.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct LoopStatus {
+    pub mode: String,
+    pub profile: String,
+    /// Color of the whole frame as `#rrggbb`, `None` before the first update.
+    pub color: Option<String>,
+    /// Color shown instead of the screen's, as `#rrggbb`.
+    pub forced: Option<String>,
+}
+
+impl Control {
+    pub fn add_device(&self, label: &str) {
+        self.devices.lock().unwrap().insert(label.to_string(), true);
+    }
+
+    pub fn is_enabled(&self, label: &str) -> bool {
+        self.devices
+            .lock()
+            .unwrap()
+            .get(label)
+            .copied()
+            .unwrap_or(true)
+    }
+
+    /// Enables or disables syncing a device, returning `false` when there is no such device.
+    pub fn set_enabled(&self, label: &str, enabled: bool) -> bool {
+        match self.devices.lock().unwrap().get_mut(label) {
+            Some(state) => {
+                *state = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Enables or disables syncing every device.
+    pub fn set_all_enabled(&self, enabled: bool) {
+        for state in self.devices.lock().unwrap().values_mut() {
+            *state = enabled;
+        }
+    }
+
+    /// Labels of the devices that are paused.
+    pub fn paused(&self) -> Vec<String> {
+        self.devices
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, enabled)| !**enabled)
+            .map(|(label, _)| label.clone())
+            .collect()
+    }
+
+    pub fn set_status(&self, status: LoopStatus) {
+        *self.status.lock().unwrap() = status;
+    }
+
+    pub fn report(&self) -> Value {
+        let devices: Vec<Value> = self
+            .devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(label, enabled)| json!({ "name": label, "enabled": enabled }))
+            .collect();
+
+        json!({ "devices": devices })
+    }
+
+    /// The status of the color loop along with the devices; paused means every device is.
+    pub fn status(&self) -> Value {
+        let mut status = json!(*self.status.lock().unwrap());
+        let paused = {
+            let devices = self.devices.lock().unwrap();
+            !devices.is_empty() && devices.values().all(|enabled| !enabled)
+        };
+        status["paused"] = json!(paused);
+        status["devices"] = self.report()["devices"].clone();
+        status
+    }
+}
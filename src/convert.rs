@@ -0,0 +1,111 @@
+use colors_transform::Rgb;
+use tuya_bulb_screen_color::tuya::tuya2hsv;
+
+use crate::{
+    capabilities::{self, Capabilities},
+    config::{Config, DeviceConfig},
+    describe_color, hsv2rgb, rgb2hsv,
+};
+
+/// A color as given to `convert`.
+enum Input {
+    Rgb(Rgb),
+    Hsv((u32, u32, u32)),
+    /// A colour DP string, already in the bulb's terms.
+    Tuya((u32, u32, u32)),
+}
+
+impl Input {
+    fn parse(value: &str) -> Result<Input, String> {
+        let value = value.trim();
+        let is_hex = |digits: &str| digits.chars().all(|c| c.is_ascii_hexdigit());
+
+        if value.contains(',') {
+            let components: Vec<u32> = value
+                .split(',')
+                .map(|component| component.trim().parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("invalid HSV \"{}\", expected HUE,SATURATION,VALUE", value))?;
+            return match components[..] {
+                [hue, saturation, brightness]
+                    if hue <= 360 && saturation <= 100 && brightness <= 100 =>
+                {
+                    Ok(Input::Hsv((hue, saturation, brightness)))
+                }
+                _ => Err(format!(
+                    "invalid HSV \"{}\", expected hue 0-360, saturation and value 0-100",
+                    value
+                )),
+            };
+        }
+
+        let digits = value.strip_prefix('#').unwrap_or(value);
+        match digits.len() {
+            6 if is_hex(digits) => Rgb::from_hex_str(digits)
+                .map(Input::Rgb)
+                .map_err(|_| format!("invalid color \"{}\"", value)),
+            12 | 14 if !value.starts_with('#') => tuya2hsv(digits)
+                .map(Input::Tuya)
+                .map_err(|error| format!("invalid colour string \"{}\": {}", value, error)),
+            _ => Err(format!(
+                "invalid color \"{}\", expected #rrggbb, HUE,SATURATION,VALUE or a colour DP \
+                 string",
+                value
+            )),
+        }
+    }
+}
+
+/// Prints what `color` becomes for `target`, or for the default model without one: the color
+/// after the `[grade]` (unless `raw`), its HSV, adjusted by the device's calibration and
+/// `saturation_boost`, and the string sent on the colour DP. A colour DP string is decoded instead, as it is already what
+/// the bulb shows.
+pub fn run(
+    color: &str,
+    target: Option<&DeviceConfig>,
+    config: &Config,
+    raw: bool,
+    saturation_boost: f32,
+) -> Result<(), String> {
+    let mut capabilities = match target {
+        Some(target) => capabilities::resolve(target, &config.products)?,
+        None => Capabilities::default(),
+    };
+    capabilities.saturation_boost = saturation_boost.max(0.0);
+
+    let input = Input::parse(color)?;
+    println!("{:<8} {}", "input", color.trim());
+    let graded = |rgb: Rgb| {
+        let graded = config.grade.apply(&rgb);
+        if graded != rgb {
+            println!("{:<8} {}", "graded", graded.to_css_hex_string());
+        }
+        rgb2hsv(&graded)
+    };
+    let hsv = match input {
+        Input::Tuya(hsv) => hsv,
+        Input::Hsv(hsv) if raw => hsv,
+        Input::Rgb(rgb) if raw => rgb2hsv(&rgb),
+        Input::Hsv(hsv) => graded(hsv2rgb(hsv)),
+        Input::Rgb(rgb) => graded(rgb),
+    };
+
+    println!("{:<8} {}", "hex", describe_color(&hsv2rgb(hsv)));
+    println!("{:<8} {}, {}, {}", "hsv", hsv.0, hsv.1, hsv.2);
+    let adjusted = capabilities.adjust(hsv);
+    if adjusted != hsv {
+        println!(
+            "{:<8} {}, {}, {}",
+            "adjusted", adjusted.0, adjusted.1, adjusted.2
+        );
+    }
+    println!(
+        "{:<8} {} (DP {} of {})",
+        "tuya",
+        capabilities.encode_colour(hsv),
+        capabilities.colour_dp,
+        target.map_or("the default model", DeviceConfig::label)
+    );
+
+    Ok(())
+}
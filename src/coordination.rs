@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+/// Peers that haven't announced themselves for this long are considered gone.
+const PEER_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// `[coordination]` in the config: lets instances on the same network agree on who drives
+/// which bulb.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CoordinationConfig {
+    /// Name of this instance, matched against a device's `owner`. Defaults to the host name.
+    pub instance: Option<String>,
+    /// Among instances that want the same bulb, the highest priority drives it; ties go to the
+    /// greater instance name.
+    #[serde(default)]
+    pub priority: i32,
+    /// UDP port the instances announce themselves on.
+    #[serde(default = "default_port")]
+    pub port: u16,
+}
+
+fn default_port() -> u16 {
+    6670
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Announcement {
+    instance: String,
+    priority: i32,
+    /// Ids of the devices the instance wants to drive.
+    devices: Vec<String>,
+}
+
+struct Peer {
+    priority: i32,
+    devices: Vec<String>,
+    seen: Instant,
+}
+
+/// Announces this instance on the LAN and keeps track of the others, to decide whether this
+/// instance should drive a device.
+#[derive(Clone)]
+pub struct Coordinator {
+    instance: String,
+    priority: i32,
+    peers: Arc<Mutex<HashMap<String, Peer>>>,
+}
+
+impl Coordinator {
+    /// Starts announcing that this instance wants to drive the devices with the given ids. It
+    /// listens for a few announcements before returning, so instances that are already running
+    /// are known before the first update is sent.
+    pub fn start(config: &CoordinationConfig, devices: Vec<String>) -> Result<Coordinator, String> {
+        let instance = config.instance.clone().unwrap_or_else(host_name);
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, config.port))
+            .map_err(|error| format!("Failed to bind UDP port {}: {}", config.port, error))?;
+        socket
+            .set_broadcast(true)
+            .map_err(|error| format!("Failed to enable broadcasts: {}", error))?;
+        let sender = socket
+            .try_clone()
+            .map_err(|error| format!("Failed to share the socket: {}", error))?;
+
+        info!(
+            "Coordinating as {} with priority {} on UDP port {}.",
+            instance, config.priority, config.port
+        );
+
+        let coordinator = Coordinator {
+            instance,
+            priority: config.priority,
+            peers: Arc::new(Mutex::new(HashMap::new())),
+        };
+
+        let announcement = serde_json::to_vec(&Announcement {
+            instance: coordinator.instance.clone(),
+            priority: coordinator.priority,
+            devices,
+        })
+        .map_err(|error| format!("Failed to encode the announcement: {}", error))?;
+        let broadcast = SocketAddr::from((Ipv4Addr::BROADCAST, config.port));
+        thread::spawn(move || loop {
+            if let Err(error) = sender.send_to(&announcement, broadcast) {
+                warn!("Failed to announce this instance: {}", error);
+            }
+            thread::sleep(ANNOUNCE_INTERVAL);
+        });
+
+        let listener = coordinator.clone();
+        thread::spawn(move || listener.listen(socket));
+        thread::sleep(ANNOUNCE_INTERVAL * 2);
+
+        Ok(coordinator)
+    }
+
+    fn listen(&self, socket: UdpSocket) {
+        let mut buffer = [0; 4096];
+        loop {
+            let (length, from) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(error) => {
+                    warn!("Failed to receive announcements: {}", error);
+                    thread::sleep(ANNOUNCE_INTERVAL);
+                    continue;
+                }
+            };
+
+            let announcement: Announcement = match serde_json::from_slice(&buffer[..length]) {
+                Ok(announcement) => announcement,
+                Err(error) => {
+                    debug!("Ignoring a malformed announcement from {}: {}", from, error);
+                    continue;
+                }
+            };
+
+            if announcement.instance == self.instance {
+                continue;
+            }
+
+            let mut peers = self.peers.lock().unwrap();
+            if !peers.contains_key(&announcement.instance) {
+                info!("Found instance {} at {}.", announcement.instance, from);
+            }
+            peers.insert(
+                announcement.instance,
+                Peer {
+                    priority: announcement.priority,
+                    devices: announcement.devices,
+                    seen: Instant::now(),
+                },
+            );
+        }
+    }
+
+    /// Whether this instance should drive the device. A configured owner always decides,
+    /// otherwise the instance with the highest priority among those that want it does.
+    pub fn drives(&self, device_id: &str, owner: Option<&str>) -> bool {
+        if let Some(owner) = owner {
+            return owner == self.instance;
+        }
+
+        let mut peers = self.peers.lock().unwrap();
+        peers.retain(|_, peer| peer.seen.elapsed() < PEER_TIMEOUT);
+
+        let us = (self.priority, self.instance.as_str());
+        !peers.iter().any(|(instance, peer)| {
+            peer.devices.iter().any(|id| id == device_id) && (peer.priority, instance.as_str()) > us
+        })
+    }
+}
+
+pub fn host_name() -> String {
+    env::var("HOSTNAME")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "tuya-bulb-screen-color".to_string())
+}
@@ -0,0 +1,163 @@
+use serde::Deserialize;
+
+/// Whether the mouse cursor is part of the captured frames.
+#[derive(clap::ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum CursorMode {
+    /// Leave the cursor out, which is what the capture backends do on their own
+    #[default]
+    Exclude,
+    /// Draw the cursor into frames where the platform allows reading it
+    Include,
+}
+
+/// The cursor's image at its position on the captured display.
+pub struct CursorImage {
+    left: i64,
+    top: i64,
+    width: usize,
+    height: usize,
+    /// Premultiplied ARGB, row by row.
+    pixels: Vec<u32>,
+}
+
+impl CursorImage {
+    /// Blends the cursor over the RGBA `pixel` at `(x, y)` of the frame.
+    pub fn blend(&self, x: usize, y: usize, pixel: [u8; 4]) -> [u8; 4] {
+        let (cx, cy) = (x as i64 - self.left, y as i64 - self.top);
+        if cx < 0 || cy < 0 || cx as usize >= self.width || cy as usize >= self.height {
+            return pixel;
+        }
+
+        let argb = self.pixels[cy as usize * self.width + cx as usize];
+        let a = argb >> 24;
+        let over = |channel: u8, shift: u32| {
+            let cursor = (argb >> shift) & 0xff;
+            (cursor + channel as u32 * (255 - a) / 255).min(255) as u8
+        };
+
+        match a {
+            0 => pixel,
+            _ => [
+                over(pixel[0], 16),
+                over(pixel[1], 8),
+                over(pixel[2], 0),
+                255,
+            ],
+        }
+    }
+
+    /// Blends the cursor over a tightly packed RGBA frame of `width` pixels per row.
+    pub fn draw(&self, rgba: &mut [u8], width: usize) {
+        let height = rgba.len() / (width * 4).max(1);
+        let rows = self.top.max(0) as usize..(self.top + self.height as i64).max(0) as usize;
+        let columns = self.left.max(0) as usize..(self.left + self.width as i64).max(0) as usize;
+
+        for y in rows.start.min(height)..rows.end.min(height) {
+            for x in columns.start.min(width)..columns.end.min(width) {
+                let i = (y * width + x) * 4;
+                let pixel = [rgba[i], rgba[i + 1], rgba[i + 2], rgba[i + 3]];
+                rgba[i..i + 4].copy_from_slice(&self.blend(x, y, pixel));
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use self::x11::Cursor;
+
+#[cfg(not(target_os = "linux"))]
+pub use self::unsupported::Cursor;
+
+#[cfg(target_os = "linux")]
+mod x11 {
+    use std::ptr;
+
+    use x11_dl::{xfixes, xlib};
+
+    use super::CursorImage;
+
+    /// Reads the cursor image through the XFixes extension. The libraries are loaded at runtime,
+    /// so nothing changes for users who leave the cursor out.
+    pub struct Cursor {
+        xlib: xlib::Xlib,
+        xfixes: xfixes::Xlib,
+        display: *mut xlib::Display,
+    }
+
+    impl Cursor {
+        pub fn open() -> Result<Cursor, String> {
+            let xlib =
+                xlib::Xlib::open().map_err(|error| format!("Couldn't load Xlib: {}", error))?;
+            let xfixes =
+                xfixes::Xlib::open().map_err(|error| format!("Couldn't load XFixes: {}", error))?;
+
+            let display = unsafe { (xlib.XOpenDisplay)(ptr::null()) };
+            if display.is_null() {
+                return Err("Couldn't open the X display to read the cursor.".to_string());
+            }
+
+            let (mut event_base, mut error_base) = (0, 0);
+            if unsafe { (xfixes.XFixesQueryExtension)(display, &mut event_base, &mut error_base) }
+                == 0
+            {
+                unsafe { (xlib.XCloseDisplay)(display) };
+                return Err("The X server doesn't support XFixes.".to_string());
+            }
+
+            Ok(Cursor {
+                xlib,
+                xfixes,
+                display,
+            })
+        }
+
+        pub fn image(&mut self) -> Option<CursorImage> {
+            let image = unsafe { (self.xfixes.XFixesGetCursorImage)(self.display) };
+            if image.is_null() {
+                return None;
+            }
+
+            let cursor = unsafe { &*image };
+            let (width, height) = (cursor.width as usize, cursor.height as usize);
+            // XFixes hands out one ARGB pixel per `unsigned long`, whatever its size.
+            let pixels = unsafe { std::slice::from_raw_parts(cursor.pixels, width * height) }
+                .iter()
+                .map(|&pixel| pixel as u32)
+                .collect();
+            let image_at = CursorImage {
+                left: cursor.x as i64 - cursor.xhot as i64,
+                top: cursor.y as i64 - cursor.yhot as i64,
+                width,
+                height,
+                pixels,
+            };
+
+            unsafe { (self.xlib.XFree)(image.cast()) };
+            Some(image_at)
+        }
+    }
+
+    impl Drop for Cursor {
+        fn drop(&mut self) {
+            unsafe { (self.xlib.XCloseDisplay)(self.display) };
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod unsupported {
+    use super::CursorImage;
+
+    pub struct Cursor;
+
+    impl Cursor {
+        pub fn open() -> Result<Cursor, String> {
+            Err("Including the cursor is only supported on X11.".to_string())
+        }
+
+        pub fn image(&mut self) -> Option<CursorImage> {
+            None
+        }
+    }
+}
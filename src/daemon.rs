@@ -0,0 +1,115 @@
+use std::{
+    env, fs,
+    fs::OpenOptions,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use log::{info, warn};
+
+use crate::state;
+
+/// Set in the environment of the copy running in the background, which mustn't detach again.
+const DETACHED: &str = "TUYA_BULB_SCREEN_COLOR_DETACHED";
+
+/// Where the copy running in the background logs to.
+pub fn log_path() -> Option<PathBuf> {
+    state::dir().map(|dir| dir.join("daemon.log"))
+}
+
+/// Starts a copy of this process with the same arguments in the background, detached from the
+/// terminal and logging to `log_path`, and exits. Returns in that copy, which carries on with
+/// the work.
+pub fn detach() -> Result<(), String> {
+    if env::var_os(DETACHED).is_some() {
+        return Ok(());
+    }
+
+    let log_path =
+        log_path().ok_or_else(|| "Could not determine the state directory.".to_string())?;
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|error| format!("Failed to create {}: {}", parent.display(), error))?;
+    }
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .map_err(|error| format!("Failed to open {}: {}", log_path.display(), error))?;
+
+    let exe = env::current_exe().map_err(|error| error.to_string())?;
+    let mut command = Command::new(exe);
+    command
+        .args(env::args_os().skip(1))
+        .env(DETACHED, "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log);
+    // Out of the terminal's process group, so Ctrl+C there doesn't reach it.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    #[cfg(windows)]
+    std::os::windows::process::CommandExt::creation_flags(&mut command, DETACHED_PROCESS);
+
+    let child = command
+        .spawn()
+        .map_err(|error| format!("Failed to start in the background: {}", error))?;
+    info!(
+        "Running in the background as PID {}, logging to {}.",
+        child.id(),
+        log_path.display()
+    );
+    std::process::exit(0);
+}
+
+#[cfg(windows)]
+const DETACHED_PROCESS: u32 = 0x0000_0008;
+
+/// The PID of this process written to a file, removed again when dropped.
+pub struct PidFile(PathBuf);
+
+impl PidFile {
+    pub fn create(path: &Path) -> Result<PidFile, String> {
+        fs::write(path, format!("{}\n", std::process::id()))
+            .map_err(|error| format!("Failed to write {}: {}", path.display(), error))?;
+        Ok(PidFile(path.to_path_buf()))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(error) = fs::remove_file(&self.0) {
+            warn!("Failed to remove {}: {}", self.0.display(), error);
+        }
+    }
+}
+
+/// Set once Ctrl+C or SIGTERM asks the color loop to stop, which it does after the update in
+/// progress so that no bulb is left halfway through a payload. A second signal exits right away.
+#[derive(Clone)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    pub fn install() -> Shutdown {
+        let requested = Arc::new(AtomicBool::new(false));
+        let flag = requested.clone();
+        let result = ctrlc::set_handler(move || {
+            if flag.swap(true, Ordering::SeqCst) {
+                std::process::exit(1);
+            }
+            info!("Stopping after the current update...");
+        });
+        if let Err(error) = result {
+            warn!("Failed to install the exit handler: {}", error);
+        }
+        Shutdown(requested)
+    }
+
+    pub fn requested(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,297 @@
+use std::{fs, net::IpAddr, path::Path};
+
+use clap::Args;
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use toml_edit::{value, ArrayOfTables, DocumentMut, Item, Table};
+
+use crate::{config::DeviceConfig, discovery::Announcement};
+
+/// Device dumps come either as a bare array (tinytuya `devices.json`, tuya-cli wizard) or wrapped
+/// in an object with a `devices` array (tinytuya `snapshot.json`).
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Snapshot {
+    Devices(Vec<SnapshotDevice>),
+    Wrapped { devices: Vec<SnapshotDevice> },
+}
+
+#[derive(Deserialize)]
+struct SnapshotDevice {
+    id: String,
+    name: Option<String>,
+    key: Option<String>,
+    ip: Option<String>,
+    #[serde(alias = "version")]
+    ver: Option<Value>,
+    #[serde(alias = "productKey", alias = "product_id")]
+    product_key: Option<String>,
+}
+
+impl SnapshotDevice {
+    fn version(&self) -> Option<String> {
+        match &self.ver {
+            Some(Value::String(version)) if !version.is_empty() => Some(version.clone()),
+            Some(Value::Number(version)) => Some(version.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a tinytuya/tuya-cli device dump and merges its devices into the config document,
+/// matching existing entries by id. Returns how many devices were imported.
+pub fn import(snapshot: &Path, document: &mut DocumentMut) -> Result<usize, String> {
+    let contents = fs::read_to_string(snapshot)
+        .map_err(|error| format!("Failed to read {}: {}", snapshot.display(), error))?;
+
+    let devices = match serde_json::from_str::<Snapshot>(&contents)
+        .map_err(|error| format!("Unrecognized device dump {}: {}", snapshot.display(), error))?
+    {
+        Snapshot::Devices(devices) | Snapshot::Wrapped { devices } => devices,
+    };
+
+    let tables = device_tables(document)?;
+    let mut imported = 0;
+
+    for device in devices {
+        let Some(key) = device.key.as_deref().filter(|key| !key.is_empty()) else {
+            warn!("Skipping device {} without a local key.", device.id);
+            continue;
+        };
+
+        let existing = tables
+            .iter_mut()
+            .find(|table| table.get("id").and_then(Item::as_str) == Some(device.id.as_str()));
+
+        let table = match existing {
+            Some(table) => {
+                info!("Updating device {}.", device.id);
+                table
+            }
+            None => {
+                info!("Adding device {}.", device.id);
+                let mut table = Table::new();
+                table["id"] = value(device.id.clone());
+                tables.push(table);
+                tables.iter_mut().last().unwrap()
+            }
+        };
+
+        if let Some(name) = &device.name {
+            table["name"] = value(name.clone());
+        }
+        table["key"] = value(key);
+        match device.ip.as_deref().filter(|ip| !ip.is_empty()) {
+            Some(ip) if ip.parse::<IpAddr>().is_err() => {
+                warn!(
+                    "Ignoring the IP address \"{}\" of device {}.",
+                    ip, device.id
+                );
+            }
+            Some(ip) => table["ip"] = value(ip),
+            None => {}
+        }
+        if let Some(version) = device.version() {
+            table["version"] = value(version);
+        }
+        if let Some(product_key) = device.product_key.as_deref().filter(|key| !key.is_empty()) {
+            table["product-key"] = value(product_key);
+        }
+
+        imported += 1;
+    }
+
+    Ok(imported)
+}
+
+fn device_tables(document: &mut DocumentMut) -> Result<&mut ArrayOfTables, String> {
+    document
+        .entry("devices")
+        .or_insert(Item::ArrayOfTables(ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .ok_or_else(|| "`devices` in the config is not an array of tables.".to_string())
+}
+
+/// Index of the entry whose id or name is `device`.
+fn find(tables: &ArrayOfTables, device: &str) -> Result<usize, String> {
+    tables
+        .iter()
+        .position(|table| {
+            table.get("id").and_then(Item::as_str) == Some(device)
+                || table.get("name").and_then(Item::as_str) == Some(device)
+        })
+        .ok_or_else(|| format!("No device \"{}\" in the config.", device))
+}
+
+/// The fields of a `[[devices]]` entry that `devices add` and `devices edit` set.
+#[derive(Args, Debug, Default)]
+pub struct DeviceFields {
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Local key of the device
+    #[arg(long)]
+    pub key: Option<String>,
+    #[arg(long)]
+    pub ip: Option<String>,
+    /// Tuya protocol version
+    #[arg(long, value_parser = ["3.1", "3.3", "3.4", "3.5"])]
+    pub version: Option<String>,
+    #[arg(long)]
+    pub product_key: Option<String>,
+    /// Built-in bulb model
+    #[arg(long)]
+    pub model: Option<String>,
+}
+
+impl DeviceFields {
+    fn fields(&self) -> [(&'static str, Option<&String>); 6] {
+        [
+            ("name", self.name.as_ref()),
+            ("key", self.key.as_ref()),
+            ("ip", self.ip.as_ref()),
+            ("version", self.version.as_ref()),
+            ("product-key", self.product_key.as_ref()),
+            ("model", self.model.as_ref()),
+        ]
+    }
+
+    /// Sets the given fields on `table`, returns whether there were any. Fails without touching
+    /// `table` when the IP address isn't one.
+    fn apply(&self, table: &mut Table) -> Result<bool, String> {
+        if let Some(ip) = &self.ip {
+            ip.parse::<IpAddr>()
+                .map_err(|_| format!("\"{}\" is not an IP address.", ip))?;
+        }
+
+        let mut changed = false;
+        for (key, field) in self.fields() {
+            if let Some(field) = field {
+                table[key] = value(field.as_str());
+                changed = true;
+            }
+        }
+        Ok(changed)
+    }
+}
+
+/// Adds a device to the config document, failing when one with that id or name is there already.
+pub fn add(id: &str, fields: &DeviceFields, document: &mut DocumentMut) -> Result<(), String> {
+    if fields.key.is_none() {
+        return Err("A new device needs its --key.".to_string());
+    }
+
+    let tables = device_tables(document)?;
+    for device in [Some(id), fields.name.as_deref()].into_iter().flatten() {
+        if find(tables, device).is_ok() {
+            return Err(format!("There already is a device \"{}\".", device));
+        }
+    }
+
+    let mut table = Table::new();
+    table["id"] = value(id);
+    fields.apply(&mut table)?;
+    tables.push(table);
+    Ok(())
+}
+
+/// Changes the given fields of the device with the id or name `device`.
+pub fn edit(device: &str, fields: &DeviceFields, document: &mut DocumentMut) -> Result<(), String> {
+    let tables = device_tables(document)?;
+    let index = find(tables, device)?;
+    let table = tables.get_mut(index).unwrap();
+    if !fields.apply(table)? {
+        return Err("Nothing to change, pass the fields to set.".to_string());
+    }
+    Ok(())
+}
+
+/// Removes the device with the id or name `device`.
+pub fn remove(device: &str, document: &mut DocumentMut) -> Result<(), String> {
+    let tables = device_tables(document)?;
+    let index = find(tables, device)?;
+    tables.remove(index);
+    Ok(())
+}
+
+/// Prints the devices in the config, one per line.
+pub fn list(devices: &[DeviceConfig]) {
+    if devices.is_empty() {
+        println!("No devices in the config, add one with `devices add`.");
+        return;
+    }
+
+    println!(
+        "{:<20} {:<24} {:<16} {:<8} model",
+        "name", "id", "ip", "version"
+    );
+    for device in devices {
+        println!(
+            "{:<20} {:<24} {:<16} {:<8} {}",
+            device.name.as_deref().unwrap_or("-"),
+            device.id,
+            device.ip.as_deref().unwrap_or("-"),
+            device.version,
+            device.model.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+/// Whether any of the devices was seen at another IP or protocol version than configured.
+pub fn moved(devices: &[DeviceConfig], announcements: &[Announcement]) -> bool {
+    devices.iter().any(|device| {
+        announcements.iter().any(|seen| {
+            seen.id == device.id
+                && (device.ip.as_deref() != Some(seen.ip.as_str())
+                    || device.version != seen.version)
+        })
+    })
+}
+
+/// Moves the devices in the config to the IP and protocol version they were last seen
+/// announcing, so that bulbs the router gave a new address keep working. Returns the labels of
+/// the devices that changed.
+pub fn track(
+    announcements: &[Announcement],
+    document: &mut DocumentMut,
+) -> Result<Vec<String>, String> {
+    let tables = device_tables(document)?;
+    let mut moved = Vec::new();
+
+    for table in tables.iter_mut() {
+        let Some(id) = table.get("id").and_then(Item::as_str).map(str::to_string) else {
+            continue;
+        };
+        let Some(announcement) = announcements.iter().find(|seen| seen.id == id) else {
+            continue;
+        };
+
+        let label = table
+            .get("name")
+            .and_then(Item::as_str)
+            .unwrap_or(&id)
+            .to_string();
+        let ip = table.get("ip").and_then(Item::as_str);
+        if ip != Some(announcement.ip.as_str()) {
+            info!(
+                "{} moved from {} to {}.",
+                label,
+                ip.unwrap_or("an unknown address"),
+                announcement.ip
+            );
+            table["ip"] = value(announcement.ip.as_str());
+            moved.push(label.clone());
+        }
+        let version = table.get("version").and_then(Item::as_str);
+        // 3.3 is the default, which older entries leave out.
+        if version.unwrap_or("3.3") != announcement.version {
+            info!("{} now speaks protocol {}.", label, announcement.version);
+            table["version"] = value(announcement.version.as_str());
+            if !moved.contains(&label) {
+                moved.push(label);
+            }
+        }
+    }
+
+    Ok(moved)
+}
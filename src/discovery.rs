@@ -0,0 +1,114 @@
+use std::{
+    collections::HashSet,
+    net::UdpSocket,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+use openssl::symm::{decrypt, Cipher};
+use serde::Deserialize;
+
+/// Ports devices broadcast their presence on: 6666 in plain text (protocol 3.1), 6667 encrypted.
+const PORTS: [u16; 2] = [6666, 6667];
+const PREFIX: [u8; 4] = [0x00, 0x00, 0x55, 0xaa];
+/// Every device encrypts its broadcasts with the MD5 of this well-known key.
+const BROADCAST_KEY: &[u8] = b"yGAdlopoPVldABfn";
+
+/// What a device tells about itself in its broadcasts.
+#[derive(Deserialize, Debug)]
+pub struct Announcement {
+    #[serde(rename = "gwId")]
+    pub id: String,
+    pub ip: String,
+    pub version: String,
+    #[serde(rename = "productKey")]
+    pub product_key: Option<String>,
+}
+
+/// Listens for device broadcasts for `duration`, prints every device once and returns what they
+/// announced.
+pub fn run(duration: Duration) -> Result<Vec<Announcement>, String> {
+    let (announcements, receiver) = mpsc::channel();
+
+    for port in PORTS {
+        let socket = UdpSocket::bind(("0.0.0.0", port))
+            .map_err(|error| format!("Failed to listen on UDP port {}: {}", port, error))?;
+        let announcements = announcements.clone();
+
+        thread::spawn(move || {
+            let mut buffer = [0; 1024];
+            loop {
+                let length = match socket.recv(&mut buffer) {
+                    Ok(length) => length,
+                    Err(error) => {
+                        warn!("Failed to read a broadcast on port {}: {}", port, error);
+                        continue;
+                    }
+                };
+                match parse(&buffer[..length]) {
+                    Some(announcement) => {
+                        if announcements.send(announcement).is_err() {
+                            return;
+                        }
+                    }
+                    None => debug!("Ignoring an unreadable broadcast on port {}.", port),
+                }
+            }
+        });
+    }
+
+    println!("Listening for devices for {} s...", duration.as_secs());
+    println!("{:<24} {:<16} {:<8} product key", "id", "ip", "version");
+
+    let deadline = Instant::now() + duration;
+    let mut seen = HashSet::new();
+    let mut announcements = Vec::new();
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let announcement = match receiver.recv_timeout(left) {
+            Ok(announcement) => announcement,
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+        };
+
+        if seen.insert(announcement.id.clone()) {
+            println!(
+                "{:<24} {:<16} {:<8} {}",
+                announcement.id,
+                announcement.ip,
+                announcement.version,
+                announcement.product_key.as_deref().unwrap_or("-")
+            );
+            announcements.push(announcement);
+        }
+    }
+
+    if seen.is_empty() {
+        println!("No devices found. Devices on protocol 3.5 don't broadcast on these ports.");
+    }
+
+    Ok(announcements)
+}
+
+/// Reads a broadcast frame: prefix, sequence number, command, length, an optional return code,
+/// the payload, CRC and suffix.
+fn parse(frame: &[u8]) -> Option<Announcement> {
+    if frame.len() < 24 || frame[..4] != PREFIX {
+        return None;
+    }
+
+    let length = u32::from_be_bytes(frame[12..16].try_into().ok()?) as usize;
+    let mut payload = frame.get(16..(16 + length).checked_sub(8)?)?;
+    if payload.len() >= 4 && payload[..3] == [0, 0, 0] {
+        payload = &payload[4..];
+    }
+
+    if payload.first() == Some(&b'{') {
+        return serde_json::from_slice(payload).ok();
+    }
+
+    let key = md5::compute(BROADCAST_KEY);
+    let decrypted = decrypt(Cipher::aes_128_ecb(), &key.0, None, payload).ok()?;
+    serde_json::from_slice(&decrypted).ok()
+}
@@ -0,0 +1,152 @@
+use std::{
+    net::UdpSocket,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+use crate::http;
+
+const SSDP_ADDRESS: &str = "239.255.255.250:1900";
+const AV_TRANSPORT: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+/// How long renderers get to answer the search.
+const SEARCH_TIME: Duration = Duration::from_secs(2);
+
+/// A UPnP media renderer, e.g. a smart TV or a DLNA speaker, by the URL its playback is
+/// controlled through.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Renderer {
+    pub name: String,
+    control_url: String,
+}
+
+/// The text of the first `<name>` element in `xml`, with or without a namespace prefix,
+/// unescaped.
+fn element(xml: &str, name: &str) -> Option<String> {
+    let mut rest = xml;
+    loop {
+        let start = rest.find('<')?;
+        rest = &rest[start + 1..];
+        let tag_end = rest.find('>')?;
+        let tag = &rest[..tag_end];
+        let tag_name = tag.split_whitespace().next().unwrap_or_default();
+        let local = tag_name.rsplit(':').next().unwrap_or(tag_name);
+
+        if local == name && !tag.ends_with('/') {
+            let content = &rest[tag_end + 1..];
+            let end = content.find(&format!("</{}>", tag_name))?;
+            return Some(unescape(&content[..end]));
+        }
+        rest = &rest[tag_end..];
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Finds the renderers on the LAN that can report what they are playing.
+pub fn discover() -> Vec<Renderer> {
+    let socket = match UdpSocket::bind("0.0.0.0:0") {
+        Ok(socket) => socket,
+        Err(error) => {
+            debug!("Can't search for DLNA renderers: {}", error);
+            return Vec::new();
+        }
+    };
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: 1\r\nST: {}\r\n\r\n",
+        SSDP_ADDRESS, AV_TRANSPORT
+    );
+    if let Err(error) = socket.send_to(search.as_bytes(), SSDP_ADDRESS) {
+        debug!("Can't search for DLNA renderers: {}", error);
+        return Vec::new();
+    }
+
+    let mut locations: Vec<String> = Vec::new();
+    let deadline = Instant::now() + SEARCH_TIME;
+    let mut buffer = [0; 2048];
+    while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        let _ = socket.set_read_timeout(Some(left.max(Duration::from_millis(1))));
+        let Ok((length, _)) = socket.recv_from(&mut buffer) else {
+            break;
+        };
+        let response = String::from_utf8_lossy(&buffer[..length]);
+        let location = response
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("location"))
+            .map(|(_, value)| value.trim().to_string());
+        if let Some(location) = location.filter(|location| !locations.contains(location)) {
+            locations.push(location);
+        }
+    }
+
+    locations
+        .iter()
+        .filter_map(|location| describe(location).map_err(|error| debug!("{}", error)).ok())
+        .collect()
+}
+
+/// Reads the device description at `location` for the renderer's name and control URL.
+fn describe(location: &str) -> Result<Renderer, String> {
+    let description = http::request("GET", location, &[], &[])?;
+    let description = String::from_utf8_lossy(&description);
+
+    let service = description
+        .split("<service>")
+        .find(|service| element(service, "serviceType").as_deref() == Some(AV_TRANSPORT))
+        .ok_or_else(|| format!("{} has no AVTransport service.", location))?;
+    let control = element(service, "controlURL")
+        .ok_or_else(|| format!("{} has no AVTransport control URL.", location))?;
+    let base = element(&description, "URLBase").unwrap_or_else(|| location.to_string());
+
+    Ok(Renderer {
+        name: element(&description, "friendlyName").unwrap_or_else(|| location.to_string()),
+        control_url: http::join(&base, &control),
+    })
+}
+
+impl Renderer {
+    fn call(&self, action: &str) -> Result<String, String> {
+        let body = format!(
+            "<?xml version=\"1.0\"?>\
+             <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+             s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+             <s:Body><u:{action} xmlns:u=\"{service}\"><InstanceID>0</InstanceID></u:{action}>\
+             </s:Body></s:Envelope>",
+            action = action,
+            service = AV_TRANSPORT
+        );
+        let soap_action = format!("\"{}#{}\"", AV_TRANSPORT, action);
+        let response = http::request(
+            "POST",
+            &self.control_url,
+            &[
+                ("Content-Type", "text/xml; charset=\"utf-8\""),
+                ("SOAPAction", &soap_action),
+            ],
+            body.as_bytes(),
+        )?;
+        Ok(String::from_utf8_lossy(&response).into_owned())
+    }
+
+    /// The artwork of what the renderer is playing, `None` while it isn't playing or the media
+    /// has no artwork.
+    pub fn artwork(&self) -> Result<Option<String>, String> {
+        let state = self.call("GetTransportInfo")?;
+        if element(&state, "CurrentTransportState").as_deref() != Some("PLAYING") {
+            return Ok(None);
+        }
+
+        // The metadata is DIDL-Lite XML, escaped into the SOAP response.
+        let media = self.call("GetMediaInfo")?;
+        Ok(element(&media, "CurrentURIMetaData")
+            .and_then(|metadata| element(&metadata, "albumArtURI"))
+            .map(|art| http::join(&self.control_url, art.trim())))
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use color_thief::{get_palette, ColorFormat};
+use colors_transform::Rgb;
+use rust_tuyapi::Payload;
+use serde_json::{json, Value};
+use tuya_bulb_screen_color::stats::FrameStats;
+
+use crate::{describe_color, rgb_bytes, CaptureOptions};
+
+/// Colors in the palette written to a dump.
+const PALETTE_SIZE: u8 = 8;
+/// Side of each color in `palette.png` and `swatch.png`.
+const SWATCH_SIZE: u32 = 64;
+
+/// What the color loop saw and did in the update a dump is written for.
+pub struct Update<'a> {
+    /// The RGBA pixels the color came from, see `Grabber::last_frame`.
+    pub frame: (&'a [u8], usize, usize),
+    pub stats: &'a FrameStats,
+    /// The color before the update.
+    pub previous: Rgb,
+    /// The color of the update, after the `[grade]` and smoothing.
+    pub color: Rgb,
+}
+
+fn payload_json(payload: &Payload) -> Value {
+    match payload {
+        Payload::Struct(payload) => serde_json::to_value(payload).unwrap_or_default(),
+        Payload::String(payload) => json!(payload),
+    }
+}
+
+/// Writes one row of `SWATCH_SIZE` squares, one per color.
+fn save_swatches(path: &Path, colors: &[[u8; 3]]) -> Result<(), String> {
+    let width = SWATCH_SIZE * colors.len().max(1) as u32;
+    let image = image::RgbImage::from_fn(width, SWATCH_SIZE, |x, _| {
+        image::Rgb(
+            colors
+                .get((x / SWATCH_SIZE) as usize)
+                .copied()
+                .unwrap_or_default(),
+        )
+    });
+    image
+        .save(path)
+        .map_err(|error| format!("Failed to write {}: {}", path.display(), error))
+}
+
+/// Writes everything needed to tell why an update picked its color into a new timestamped
+/// folder under `dir`: the frame (`frame.png`), its palette (`palette.png`), the color sent
+/// (`swatch.png`), and `summary.json` with the same colors as hex, the capture settings, the
+/// frame statistics and the payload each bulb was last sent, by label. Returns the folder.
+pub fn write(
+    dir: &Path,
+    update: &Update,
+    capture: &CaptureOptions,
+    payloads: &[(String, Payload)],
+) -> Result<PathBuf, String> {
+    let folder = dir.join(Local::now().format("%Y%m%dT%H%M%S%3f").to_string());
+    fs::create_dir_all(&folder)
+        .map_err(|error| format!("Failed to create {}: {}", folder.display(), error))?;
+
+    let (rgba, width, height) = update.frame;
+    let frame_path = folder.join("frame.png");
+    image::save_buffer(
+        &frame_path,
+        rgba,
+        width as u32,
+        height as u32,
+        image::ColorType::Rgba8,
+    )
+    .map_err(|error| format!("Failed to write {}: {}", frame_path.display(), error))?;
+
+    // Every pixel of a grid of samples counts, a full frame is sampled like the extraction does.
+    let quality = (width * height / 10_000).clamp(1, 10) as u8;
+    let mut palette: Vec<[u8; 3]> = get_palette(rgba, ColorFormat::Rgba, quality, PALETTE_SIZE)
+        .unwrap_or_default()
+        .iter()
+        .map(|color| [color.r, color.g, color.b])
+        .collect();
+    // Small palettes come padded with copies of one color.
+    palette.dedup();
+    save_swatches(&folder.join("palette.png"), &palette)?;
+    save_swatches(&folder.join("swatch.png"), &[rgb_bytes(&update.color)])?;
+
+    let payloads: Vec<Value> = payloads
+        .iter()
+        .map(|(label, payload)| json!({ "device": label, "payload": payload_json(payload) }))
+        .collect();
+    let summary = json!({
+        "previous": describe_color(&update.previous),
+        "color": describe_color(&update.color),
+        "palette": palette
+            .iter()
+            .map(|[r, g, b]| Rgb::from(*r as f32, *g as f32, *b as f32).to_css_hex_string())
+            .collect::<Vec<_>>(),
+        "capture": format!("{:?}", capture),
+        "stats": update.stats,
+        "payloads": payloads,
+    });
+    let summary_path = folder.join("summary.json");
+    let summary = serde_json::to_string_pretty(&summary).map_err(|error| error.to_string())?;
+    fs::write(&summary_path, summary)
+        .map_err(|error| format!("Failed to write {}: {}", summary_path.display(), error))?;
+
+    Ok(folder)
+}
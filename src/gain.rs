@@ -0,0 +1,65 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+
+/// `[auto-gain]` in the config, used with `--auto-gain`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AutoGainConfig {
+    /// Mean screen luminance (0.0–1.0) the gain steers towards.
+    pub target: f32,
+    /// How far back the mean luminance is averaged.
+    pub window_secs: u64,
+    pub min_gain: f32,
+    pub max_gain: f32,
+}
+
+impl Default for AutoGainConfig {
+    fn default() -> AutoGainConfig {
+        AutoGainConfig {
+            target: 0.4,
+            window_secs: 120,
+            min_gain: 0.5,
+            max_gain: 2.5,
+        }
+    }
+}
+
+/// Scales the output brightness by how far the screen's average luminance over a rolling window
+/// is from the target, so long dark or bright stretches end up at a similar room brightness.
+/// Short scenes barely move the average, so the picture's own contrast is kept.
+pub struct AutoGain {
+    config: AutoGainConfig,
+    samples: VecDeque<(Instant, f32)>,
+}
+
+impl AutoGain {
+    pub fn new(config: AutoGainConfig) -> AutoGain {
+        AutoGain {
+            config,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Adds the luminance of a frame and returns the gain to apply.
+    pub fn update(&mut self, mean_luminance: f32) -> f32 {
+        let now = Instant::now();
+        let window = Duration::from_secs(self.config.window_secs);
+
+        self.samples.push_back((now, mean_luminance));
+        while self
+            .samples
+            .front()
+            .is_some_and(|&(at, _)| now.duration_since(at) > window)
+        {
+            self.samples.pop_front();
+        }
+
+        let sum: f32 = self.samples.iter().map(|&(_, luminance)| luminance).sum();
+        let average = (sum / self.samples.len() as f32).max(f32::EPSILON);
+        (self.config.target / average).clamp(self.config.min_gain, self.config.max_gain)
+    }
+}
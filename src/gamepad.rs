@@ -0,0 +1,142 @@
+use std::sync::mpsc::Receiver;
+
+use serde::Deserialize;
+
+use crate::{config::HexColor, profiles::ContentType};
+
+/// A single `[[gamepad]]` entry: pressing all `buttons` together triggers `action`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+#[cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+pub struct Binding {
+    pub buttons: Vec<Button>,
+    pub action: GamepadAction,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GamepadAction {
+    /// Pause syncing, or resume it when paused.
+    TogglePause,
+    /// Show a color for a moment before going back to the screen color.
+    Flash(HexColor),
+    /// Use a content profile until another one is picked.
+    Profile(ContentType),
+    /// Go back to detecting the profile.
+    AutoProfile,
+}
+
+/// Gamepad buttons, named after their position on the pad.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Button {
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    Mode,
+    LeftThumb,
+    RightThumb,
+    DpadUp,
+    DpadDown,
+    DpadLeft,
+    DpadRight,
+}
+
+/// Starts watching the gamepads for the bound combos and returns the triggered actions, or `None`
+/// when nothing is bound.
+#[cfg(feature = "gamepad")]
+pub fn listen(bindings: Vec<Binding>) -> Option<Receiver<GamepadAction>> {
+    use std::{sync::mpsc, thread};
+
+    use gilrs::{EventType, Gilrs};
+    use log::{debug, error, info};
+
+    if bindings.is_empty() {
+        return None;
+    }
+
+    let (actions, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(error) => {
+                error!("Failed to watch gamepads: {}", error);
+                return;
+            }
+        };
+
+        for (_, gamepad) in gilrs.gamepads() {
+            info!("Watching gamepad {}.", gamepad.name());
+        }
+
+        loop {
+            let Some(event) = gilrs.next_event_blocking(None) else {
+                continue;
+            };
+            let EventType::ButtonPressed(pressed, _) = event.event else {
+                continue;
+            };
+
+            let gamepad = gilrs.gamepad(event.id);
+            for binding in &bindings {
+                let triggered = binding
+                    .buttons
+                    .iter()
+                    .any(|&button| to_gilrs(button) == pressed)
+                    && binding
+                        .buttons
+                        .iter()
+                        .all(|&button| gamepad.is_pressed(to_gilrs(button)));
+
+                if triggered {
+                    debug!("Gamepad combo {:?} pressed.", binding.buttons);
+                    if actions.send(binding.action).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    });
+
+    Some(receiver)
+}
+
+#[cfg(feature = "gamepad")]
+fn to_gilrs(button: Button) -> gilrs::Button {
+    match button {
+        Button::South => gilrs::Button::South,
+        Button::East => gilrs::Button::East,
+        Button::North => gilrs::Button::North,
+        Button::West => gilrs::Button::West,
+        Button::LeftTrigger => gilrs::Button::LeftTrigger,
+        Button::LeftTrigger2 => gilrs::Button::LeftTrigger2,
+        Button::RightTrigger => gilrs::Button::RightTrigger,
+        Button::RightTrigger2 => gilrs::Button::RightTrigger2,
+        Button::Select => gilrs::Button::Select,
+        Button::Start => gilrs::Button::Start,
+        Button::Mode => gilrs::Button::Mode,
+        Button::LeftThumb => gilrs::Button::LeftThumb,
+        Button::RightThumb => gilrs::Button::RightThumb,
+        Button::DpadUp => gilrs::Button::DPadUp,
+        Button::DpadDown => gilrs::Button::DPadDown,
+        Button::DpadLeft => gilrs::Button::DPadLeft,
+        Button::DpadRight => gilrs::Button::DPadRight,
+    }
+}
+
+#[cfg(not(feature = "gamepad"))]
+pub fn listen(bindings: Vec<Binding>) -> Option<Receiver<GamepadAction>> {
+    if !bindings.is_empty() {
+        log::warn!("Ignoring the [[gamepad]] bindings, this build lacks the `gamepad` feature.");
+    }
+
+    None
+}
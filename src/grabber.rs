@@ -0,0 +1,617 @@
+use std::{
+    fmt,
+    process::{Command, Stdio},
+    str::FromStr,
+};
+
+use image::RgbaImage;
+use log::{debug, info, warn};
+use scrap::Capturer;
+use serde::{de, Deserialize, Deserializer};
+use tuya_bulb_screen_color::{
+    capture::{
+        display_sizes, displays, effective_alpha, grid_point, read_frame, swap_color_channels,
+        to_rgba, AlphaMode, Area, Region,
+    },
+    colorspace::ColorSpace,
+    letterbox::{self, Bars},
+};
+
+use crate::{
+    cursor::{Cursor, CursorMode},
+    zones::ZoneLayout,
+    Algorithm, CaptureOptions, Sampling,
+};
+
+/// Which display is captured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisplaySelection {
+    /// One display, by its index in `--list-displays`.
+    One(usize),
+    /// Every display, stacked top to bottom into one frame.
+    All,
+}
+
+impl Default for DisplaySelection {
+    fn default() -> DisplaySelection {
+        DisplaySelection::One(0)
+    }
+}
+
+impl FromStr for DisplaySelection {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "all" => Ok(DisplaySelection::All),
+            _ => value.parse().map(DisplaySelection::One).map_err(|_| {
+                format!(
+                    "invalid display \"{}\", expected an index or \"all\"",
+                    value
+                )
+            }),
+        }
+    }
+}
+
+/// Written as an index or `"all"` in the config.
+impl<'de> Deserialize<'de> for DisplaySelection {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Index(usize),
+            Name(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Index(index) => Ok(DisplaySelection::One(index)),
+            Raw::Name(name) => name.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+/// A backend frames are captured with. `--source` lists them in order of preference.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+pub enum Source {
+    /// The platform's capture API: DXGI on Windows, X11 shared memory on Linux, Core Graphics on
+    /// macOS.
+    Native,
+    /// A command that prints a screenshot as PNG or JPEG to stdout, run through the shell for
+    /// every frame, e.g. `grim -` on Wayland.
+    Command(String),
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            _ if value == "native" => Ok(Source::Native),
+            Some(("command", command)) if !command.trim().is_empty() => {
+                Ok(Source::Command(command.to_string()))
+            }
+            _ => Err(format!(
+                "invalid source \"{}\", expected native or command:COMMAND",
+                value
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Source {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Native => write!(f, "native"),
+            Source::Command(command) => write!(f, "command:{}", command),
+        }
+    }
+}
+
+/// `[capture]` in the config, each value overridden by its command line flag.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CaptureConfig {
+    pub sources: Option<Vec<Source>>,
+    pub display: Option<DisplaySelection>,
+    pub region: Option<Region>,
+    pub sampling: Option<Sampling>,
+    pub algorithm: Option<Algorithm>,
+    pub decimation: Option<u32>,
+    pub clusters: Option<u32>,
+    pub color_space: Option<ColorSpace>,
+    pub refresh_rate: Option<f64>,
+    pub alpha: Option<AlphaMode>,
+    pub cursor: Option<CursorMode>,
+    pub warm_up_frames: Option<u32>,
+    pub frames_per_update: Option<u32>,
+    pub min_confidence: Option<f32>,
+    pub smoothing: Option<f32>,
+    pub zones: Option<ZoneLayout>,
+    pub zone_fallback: Option<bool>,
+    pub letterbox: Option<bool>,
+}
+
+/// Where a display's pixels go in the combined frame.
+#[derive(Debug, Clone, Copy)]
+struct Placement {
+    width: usize,
+    height: usize,
+    /// First row of the display in the combined frame.
+    top: usize,
+}
+
+/// Runs a `Source::Command` for every frame.
+struct Screenshots {
+    command: String,
+    /// The last screenshot, converted to BGRA like native frames.
+    frame: Vec<u8>,
+}
+
+impl Screenshots {
+    /// Runs the command and decodes its screenshot into `frame`, returning its size.
+    fn take(&mut self) -> Result<(usize, usize), String> {
+        let output = shell(&self.command)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|error| format!("Failed to run {}: {}", self.command, error))?;
+        if !output.status.success() {
+            return Err(format!("{} failed ({}).", self.command, output.status));
+        }
+        let image = image::load_from_memory(&output.stdout)
+            .map_err(|error| format!("Unexpected screenshot from {}: {}", self.command, error))?
+            .to_bgra8();
+
+        let size = (image.width() as usize, image.height() as usize);
+        self.frame = image.into_raw();
+        Ok(size)
+    }
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.args(["/C", command]);
+    shell
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.args(["-c", command]);
+    shell
+}
+
+enum Frames {
+    Native(Capturer),
+    Command(Screenshots),
+}
+
+struct Screen {
+    frames: Frames,
+    placement: Placement,
+}
+
+/// Consecutive failed frames after which capturing switches to the next source.
+const FAILOVER_AFTER: u32 = 3;
+
+/// Owns the capturers and the buffers frames are converted into, so that no frame-sized buffer
+/// is allocated per iteration. With several displays, their frames are stacked top to bottom
+/// into one frame as wide as the widest of them; the space next to narrower displays is left
+/// transparent, which keeps it out of the extraction and the statistics.
+pub struct Grabber {
+    /// Kept to open the next source with when the active one fails.
+    capture: CaptureOptions,
+    /// Index of the active source in `capture.sources`.
+    source: usize,
+    /// Frames in a row the active source failed to deliver.
+    failures: u32,
+    screens: Vec<Screen>,
+    /// Width of the combined frame.
+    width: usize,
+    /// The part of the frame handed out, all of it without a region.
+    area: Area,
+    image: RgbaImage,
+    /// The area copied out of `image`, `None` when it is the whole frame.
+    cropped: Option<RgbaImage>,
+    samples: Vec<u8>,
+    /// Size of the grid last read by `grab_grid`, `None` when `grab_image` came last.
+    grid: Option<(usize, usize)>,
+    alpha: AlphaMode,
+    cursor: Option<Cursor>,
+    /// The bars found in the last frame, `None` when they are left in.
+    bars: Option<Bars>,
+}
+
+impl Grabber {
+    /// Opens the first of `capture.sources` that works.
+    pub fn new(capture: &CaptureOptions) -> Result<Grabber, String> {
+        let mut errors = Vec::new();
+        for source in 0..capture.sources.len() {
+            match Grabber::open(capture, source) {
+                Ok(grabber) => return Ok(grabber),
+                Err(error) if capture.sources.len() > 1 => {
+                    warn!("Can't capture with {}: {}", capture.sources[source], error);
+                    errors.push(error);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Err(format!("No capture source works: {}", errors.join(" ")))
+    }
+
+    /// Creates the capturers of a source. A command makes one screen of its screenshots; for
+    /// the native API those of `capture.display` are created, and `capture.warm_up_frames`
+    /// frames of each are discarded, which some backends deliver black or stale right after
+    /// the capturer is created.
+    fn open(capture: &CaptureOptions, source: usize) -> Result<Grabber, String> {
+        let screens = match &capture.sources[source] {
+            Source::Native => native_screens(capture)?,
+            Source::Command(command) => {
+                let mut screenshots = Screenshots {
+                    command: command.clone(),
+                    frame: Vec::new(),
+                };
+                let (width, height) = screenshots.take()?;
+                vec![Screen {
+                    frames: Frames::Command(screenshots),
+                    placement: Placement {
+                        width,
+                        height,
+                        top: 0,
+                    },
+                }]
+            }
+        };
+        let height = screens.iter().map(|screen| screen.placement.height).sum();
+        let width = screens
+            .iter()
+            .map(|screen| screen.placement.width)
+            .max()
+            .unwrap_or_default();
+
+        let whole = Area {
+            left: 0,
+            top: 0,
+            width,
+            height,
+        };
+        let area = match capture.region {
+            Some(region) => region.resolve(width, height)?,
+            None => whole,
+        };
+        if area != whole {
+            debug!(
+                "Taking colors from {:?} of the {}x{} frame.",
+                area, width, height
+            );
+        }
+
+        let cursor = match capture.cursor {
+            CursorMode::Exclude => None,
+            CursorMode::Include if screens.len() > 1 => {
+                warn!("Capturing without the cursor, it isn't drawn across several displays.");
+                None
+            }
+            CursorMode::Include => Cursor::open()
+                .map_err(|error| warn!("Capturing without the cursor: {}", error))
+                .ok(),
+        };
+
+        info!("Capturing with {}.", capture.sources[source]);
+        Ok(Grabber {
+            capture: capture.clone(),
+            source,
+            failures: 0,
+            screens,
+            width,
+            area,
+            image: RgbaImage::new(width as u32, height as u32),
+            cropped: (area != whole).then(|| RgbaImage::new(area.width as u32, area.height as u32)),
+            samples: Vec::new(),
+            grid: None,
+            alpha: capture.alpha,
+            cursor,
+            bars: capture.letterbox.then(Bars::default),
+        })
+    }
+
+    /// Size of the frames handed out, the region's when there is one.
+    pub fn size(&self) -> (usize, usize) {
+        (self.area.width, self.area.height)
+    }
+
+    /// The source frames are captured with.
+    pub fn source(&self) -> &Source {
+        &self.capture.sources[self.source]
+    }
+
+    /// Counts a failed frame, switching to another source once the active one keeps failing.
+    /// Returns the error for the caller to report.
+    fn failed(&mut self, error: String) -> String {
+        self.failures += 1;
+        if self.failures >= FAILOVER_AFTER && self.capture.sources.len() > 1 {
+            self.fail_over();
+        }
+        error
+    }
+
+    /// Opens the sources after the active one in turn, then those before it, and the active
+    /// one again as the last resort.
+    fn fail_over(&mut self) {
+        let count = self.capture.sources.len();
+        for offset in 1..=count {
+            let source = (self.source + offset) % count;
+            match Grabber::open(&self.capture, source) {
+                Ok(grabber) => {
+                    warn!(
+                        "{} keeps failing, switched to {}.",
+                        self.source(),
+                        grabber.source()
+                    );
+                    *self = grabber;
+                    return;
+                }
+                Err(error) => {
+                    warn!(
+                        "Can't capture with {}: {}",
+                        self.capture.sources[source], error
+                    )
+                }
+            }
+        }
+        self.failures = 0;
+    }
+
+    /// Captures a frame of every display into the reused RGBA image, and copies the region out
+    /// of it when there is one.
+    pub fn grab_image(&mut self) -> Result<&RgbaImage, String> {
+        self.grid = None;
+        let width = self.width;
+        let alpha = self.alpha;
+        for screen in &mut self.screens {
+            let placement = screen.placement;
+            let rows = placement.top * width * 4..(placement.top + placement.height) * width * 4;
+            let rows = &mut self.image.as_mut()[rows];
+            let grabbed = with_frame(
+                &mut screen.frames,
+                placement.width,
+                placement.height,
+                |frame, stride| {
+                    let alpha =
+                        effective_alpha(frame, stride, placement.width, placement.height, alpha);
+                    swap_color_channels(frame, stride, placement.width, alpha, rows, width)
+                },
+            );
+            if let Err(error) = grabbed {
+                return Err(self.failed(error));
+            }
+        }
+        self.failures = 0;
+
+        if let Some(cursor) = self.cursor.as_mut().and_then(Cursor::image) {
+            cursor.draw(&mut self.image, width);
+        }
+
+        let area = self.area;
+        let frame = match &mut self.cropped {
+            Some(cropped) => {
+                for (row, cropped) in cropped.chunks_exact_mut(area.width * 4).enumerate() {
+                    let start = ((area.top + row) * width + area.left) * 4;
+                    cropped.copy_from_slice(&self.image.as_raw()[start..start + area.width * 4]);
+                }
+                cropped
+            }
+            None => &mut self.image,
+        };
+        if let Some(bars) = &mut self.bars {
+            update_bars(bars, frame, area.width, area.height, (1, 1));
+        }
+        Ok(frame)
+    }
+
+    /// The RGBA pixels handed out last with their width and height: the grid samples after
+    /// `grab_grid`, the frame or its region after `grab_image`.
+    pub fn last_frame(&self) -> (&[u8], usize, usize) {
+        match (self.grid, &self.cropped) {
+            (Some((columns, rows)), _) => (&self.samples, columns, rows),
+            (None, Some(cropped)) => (cropped.as_raw(), self.area.width, self.area.height),
+            (None, None) => (self.image.as_raw(), self.area.width, self.area.height),
+        }
+    }
+
+    /// Captures a frame of every display and reads `columns` × `rows` pixels, spread evenly over
+    /// the region or the combined frame, into the reused RGBA sample buffer.
+    pub fn grab_grid(&mut self, columns: usize, rows: usize) -> Result<&[u8], String> {
+        let area = self.area;
+        let alpha = self.alpha;
+        self.grid = Some((columns, rows));
+        self.samples.clear();
+        self.samples.resize(columns * rows * 4, 0);
+
+        for screen in &mut self.screens {
+            let placement = screen.placement;
+            let samples = &mut self.samples;
+            let grabbed = with_frame(
+                &mut screen.frames,
+                placement.width,
+                placement.height,
+                |frame, stride| {
+                    let alpha =
+                        effective_alpha(frame, stride, placement.width, placement.height, alpha);
+                    sample_grid(
+                        frame,
+                        stride,
+                        placement,
+                        area,
+                        (columns, rows),
+                        alpha,
+                        samples,
+                    )
+                },
+            );
+            if let Err(error) = grabbed {
+                return Err(self.failed(error));
+            }
+        }
+        self.failures = 0;
+
+        if let Some(cursor) = self.cursor.as_mut().and_then(Cursor::image) {
+            for (i, pixel) in self.samples.chunks_exact_mut(4).enumerate() {
+                let (x, y) = grid_point(i % columns, i / columns, area, (columns, rows));
+                let blended = cursor.blend(x, y, [pixel[0], pixel[1], pixel[2], pixel[3]]);
+                pixel.copy_from_slice(&blended);
+            }
+        }
+
+        if let Some(bars) = &mut self.bars {
+            let scale = (area.width / columns.max(1), area.height / rows.max(1));
+            update_bars(bars, &mut self.samples, columns, rows, scale);
+        }
+
+        Ok(&self.samples)
+    }
+}
+
+/// Masks the bars of a frame, or of a grid of samples `scale` pixels apart, and logs when they
+/// change, e.g. from a letterboxed movie to the menu.
+fn update_bars(
+    bars: &mut Bars,
+    rgba: &mut [u8],
+    width: usize,
+    height: usize,
+    scale: (usize, usize),
+) {
+    let found = letterbox::mask(rgba, width, height);
+    if found != *bars {
+        debug!(
+            "Black bars: {} top, {} bottom, {} left, {} right (pixels).",
+            found.top * scale.1,
+            found.bottom * scale.1,
+            found.left * scale.0,
+            found.right * scale.0
+        );
+        *bars = found;
+    }
+}
+
+/// Creates a capturer for every display of `capture.display`, stacked top to bottom.
+fn native_screens(capture: &CaptureOptions) -> Result<Vec<Screen>, String> {
+    let mut displays = displays()?;
+    if displays.is_empty() {
+        return Err("Couldn't find any display.".to_string());
+    }
+    let displays = match capture.display {
+        DisplaySelection::All => displays,
+        DisplaySelection::One(index) if index < displays.len() => {
+            vec![displays.swap_remove(index)]
+        }
+        DisplaySelection::One(index) => {
+            return Err(format!(
+                "There is no display {}, only {} (see --list-displays).",
+                index,
+                displays.len()
+            ))
+        }
+    };
+
+    let mut screens = Vec::new();
+    let mut height = 0;
+    for display in displays {
+        let capturer = Capturer::new(display)
+            .map_err(|error| format!("Failed to create capturer: {}", error))?;
+        let placement = Placement {
+            width: capturer.width(),
+            height: capturer.height(),
+            top: height,
+        };
+        let mut frames = Frames::Native(capturer);
+
+        for _ in 0..capture.warm_up_frames {
+            if let Err(error) =
+                with_frame(&mut frames, placement.width, placement.height, |_, _| ())
+            {
+                debug!("Warm-up: {}", error);
+            }
+        }
+
+        height += placement.height;
+        screens.push(Screen { frames, placement });
+    }
+    Ok(screens)
+}
+
+/// Prints the index and size of every display, in the order `--display` counts them.
+pub fn list_displays() -> Result<(), String> {
+    let sizes = display_sizes()?;
+    if sizes.is_empty() {
+        println!("No displays found.");
+    }
+    for (index, (width, height)) in sizes.iter().enumerate() {
+        println!("{}: {}x{}", index, width, height);
+    }
+    Ok(())
+}
+
+/// Waits for the next frame of a source and hands it to `process` together with its stride,
+/// see `read_frame`. Screenshots of another size than the source's are rejected.
+fn with_frame<T>(
+    frames: &mut Frames,
+    width: usize,
+    height: usize,
+    process: impl FnOnce(&[u8], usize) -> T,
+) -> Result<T, String> {
+    match frames {
+        Frames::Native(capturer) => read_frame(capturer, width, height, process),
+        Frames::Command(screenshots) => {
+            let size = screenshots.take()?;
+            if size != (width, height) {
+                return Err(format!(
+                    "Skipping a {}x{} screenshot, expected {}x{}.",
+                    size.0, size.1, width, height
+                ));
+            }
+            Ok(process(&screenshots.frame, width * 4))
+        }
+    }
+}
+
+/// Reads the points of a `columns` × `rows` grid spread evenly over `area` that fall on the
+/// display at `placement`, from its BGRA frame with `stride` bytes per row, into
+/// `samples` as RGBA. Points elsewhere are left alone.
+fn sample_grid(
+    buffer: &[u8],
+    stride: usize,
+    placement: Placement,
+    area: Area,
+    (columns, rows): (usize, usize),
+    alpha: AlphaMode,
+    samples: &mut [u8],
+) {
+    for row in 0..rows {
+        for column in 0..columns {
+            let (x, y) = grid_point(column, row, area, (columns, rows));
+            if x >= placement.width
+                || !(placement.top..placement.top + placement.height).contains(&y)
+            {
+                continue;
+            }
+
+            let i = (y - placement.top) * stride + x * 4;
+            if let Some(pixel) = buffer.get(i..i + 4) {
+                let j = (row * columns + column) * 4;
+                samples[j..j + 4].copy_from_slice(&to_rgba(pixel, alpha));
+            }
+        }
+    }
+}
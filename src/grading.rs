@@ -0,0 +1,82 @@
+use colors_transform::{Color, Rgb};
+use serde::Deserialize;
+
+/// Range `gamma` is clamped to, beyond it every color ends up black or white.
+const MIN_GAMMA: f32 = 0.2;
+const MAX_GAMMA: f32 = 5.0;
+
+/// Two-point grade applied to the picked color before it is sent, configured in `[grade]`.
+///
+/// Works like the lift and gain controls of a color grade: the shadows tint is added to dark
+/// colors and the highlights tint scales bright colors, blending linearly in between. `gamma`
+/// is then applied to every channel, for bulbs whose LEDs don't follow the screen's curve.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Grade {
+    pub shadows: Tint,
+    pub shadows_amount: f32,
+    pub highlights: Tint,
+    pub highlights_amount: f32,
+    /// Exponent of the output curve, 1.0 leaves channels as they are and higher values darken
+    /// the midtones, e.g. 2.2 to turn sRGB values into roughly linear light.
+    pub gamma: f32,
+}
+
+impl Default for Grade {
+    fn default() -> Grade {
+        Grade {
+            shadows: Tint([1.0; 3]),
+            shadows_amount: 0.0,
+            highlights: Tint([1.0; 3]),
+            highlights_amount: 0.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+/// A tint color, written as `"#rrggbb"` in the config. Channels are kept in 0.0–1.0.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Tint([f32; 3]);
+
+impl TryFrom<String> for Tint {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let rgb = Rgb::from_hex_str(&value)
+            .map_err(|_| format!("invalid tint \"{}\", expected #rrggbb", value))?;
+        Ok(Tint([
+            rgb.get_red() / 255.0,
+            rgb.get_green() / 255.0,
+            rgb.get_blue() / 255.0,
+        ]))
+    }
+}
+
+impl Grade {
+    fn is_identity(&self) -> bool {
+        self.shadows_amount == 0.0 && self.highlights_amount == 0.0 && self.gamma == 1.0
+    }
+
+    pub fn apply(&self, rgb: &Rgb) -> Rgb {
+        if self.is_identity() {
+            return *rgb;
+        }
+
+        let shadows = self.shadows_amount.clamp(0.0, 1.0);
+        let highlights = self.highlights_amount.clamp(0.0, 1.0);
+        let gamma = self.gamma.clamp(MIN_GAMMA, MAX_GAMMA);
+
+        let channels = [rgb.get_red(), rgb.get_green(), rgb.get_blue()];
+        let mut graded = [0.0; 3];
+        for i in 0..3 {
+            let lift = shadows * self.shadows.0[i];
+            let gain = 1.0 - highlights * (1.0 - self.highlights.0[i]);
+            let value = channels[i] / 255.0;
+            let value = (lift + value * (gain - lift)).clamp(0.0, 1.0);
+            graded[i] = value.powf(gamma) * 255.0;
+        }
+
+        Rgb::from(graded[0], graded[1], graded[2])
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use serde_json::{json, Value};
+
+use crate::{grabber::Source, memory};
+
+/// The capture loop counts as wedged when no frame was processed for this long.
+const CAPTURE_STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// Liveness information shared between the color loop and the health endpoint.
+#[derive(Clone, Default)]
+pub struct Health(Arc<Mutex<State>>);
+
+#[derive(Default)]
+struct State {
+    last_capture: Option<SystemTime>,
+    /// The capture source the last frame came from, see `--source`.
+    source: Option<String>,
+    devices: BTreeMap<String, DeviceHealth>,
+}
+
+#[derive(Default)]
+struct DeviceHealth {
+    reachable: bool,
+    last_success: Option<SystemTime>,
+}
+
+impl Health {
+    pub fn add_device(&self, label: &str) {
+        let mut state = self.0.lock().unwrap();
+        state.devices.insert(
+            label.to_string(),
+            DeviceHealth {
+                reachable: true,
+                last_success: None,
+            },
+        );
+    }
+
+    pub fn capture_succeeded(&self, source: &Source) {
+        let mut state = self.0.lock().unwrap();
+        state.last_capture = Some(SystemTime::now());
+        state.source = Some(source.to_string());
+    }
+
+    pub fn send_finished(&self, label: &str, success: bool) {
+        let mut state = self.0.lock().unwrap();
+        let device = state.devices.entry(label.to_string()).or_default();
+        device.reachable = success;
+        if success {
+            device.last_success = Some(SystemTime::now());
+        }
+    }
+
+    /// Returns whether everything is healthy, together with a JSON report of the details.
+    pub fn report(&self) -> (bool, Value) {
+        let state = self.0.lock().unwrap();
+
+        let capture_alive = state.last_capture.is_some_and(|last| {
+            last.elapsed()
+                .is_ok_and(|elapsed| elapsed < CAPTURE_STALE_AFTER)
+        });
+        let devices_reachable = state.devices.values().all(|device| device.reachable);
+        let last_send = state
+            .devices
+            .values()
+            .filter_map(|device| device.last_success)
+            .max();
+
+        let devices: Vec<Value> = state
+            .devices
+            .iter()
+            .map(|(label, device)| {
+                json!({
+                    "name": label,
+                    "reachable": device.reachable,
+                    "last_success": device.last_success.map(unix_seconds),
+                })
+            })
+            .collect();
+
+        let healthy = capture_alive && devices_reachable;
+
+        (
+            healthy,
+            json!({
+                "healthy": healthy,
+                "capture": {
+                    "alive": capture_alive,
+                    "last_frame": state.last_capture.map(unix_seconds),
+                    "source": state.source,
+                },
+                "devices": devices,
+                "last_send": last_send.map(unix_seconds),
+                "memory": {
+                    "in_use": memory::in_use(),
+                    "peak": memory::peak(),
+                },
+            }),
+        )
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
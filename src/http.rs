@@ -0,0 +1,290 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+use openssl::ssl::{SslConnector, SslMethod};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REDIRECTS: usize = 3;
+/// Largest response read, headers included. Descriptions and SOAP answers are a few kilobytes,
+/// cover art a few hundred.
+const MAX_RESPONSE: u64 = 16 * 1024 * 1024;
+
+/// The parts of an `http://` or `https://` URL a request needs.
+struct Url {
+    tls: bool,
+    /// Host and port as written, for the `Host` header.
+    authority: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Url, String> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(format!("Unsupported URL {}.", url));
+        };
+
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let port = authority
+            .rsplit_once(':')
+            .filter(|(_, port)| !port.is_empty() && port.bytes().all(|byte| byte.is_ascii_digit()));
+        let (host, port) = match port {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("Invalid port in {}.", url))?,
+            ),
+            None => (authority, if tls { 443 } else { 80 }),
+        };
+
+        Ok(Url {
+            tls,
+            authority: authority.to_string(),
+            host: host.to_string(),
+            port,
+            path: if path.is_empty() { "/" } else { path }.to_string(),
+        })
+    }
+}
+
+/// `base` with `reference` resolved against it, for the relative URLs in UPnP descriptions and
+/// redirects.
+pub fn join(base: &str, reference: &str) -> String {
+    if reference.starts_with("http://") || reference.starts_with("https://") {
+        return reference.to_string();
+    }
+
+    let scheme_end = base.find("://").map_or(0, |index| index + 3);
+    let origin_end = base[scheme_end..]
+        .find('/')
+        .map_or(base.len(), |index| scheme_end + index);
+    if reference.starts_with('/') {
+        return format!("{}{}", &base[..origin_end], reference);
+    }
+
+    let directory_end = base.rfind('/').filter(|&index| index >= origin_end);
+    match directory_end {
+        Some(index) => format!("{}{}", &base[..=index], reference),
+        None => format!("{}/{}", &base[..origin_end], reference),
+    }
+}
+
+/// Sends a request and returns the body of a successful response, following redirects.
+pub fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<Vec<u8>, String> {
+    let mut url = url.to_string();
+
+    for _ in 0..=MAX_REDIRECTS {
+        let (status, response_headers, response) = send(method, &url, headers, body)?;
+        let header = |name: &str| {
+            response_headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+
+        match status {
+            200..=299 => return Ok(response),
+            301 | 302 | 303 | 307 | 308 => {
+                let location = header("location")
+                    .ok_or_else(|| format!("{} redirected without a location.", url))?;
+                url = join(&url, location);
+            }
+            _ => return Err(format!("{} answered with status {}.", url, status)),
+        }
+    }
+
+    Err(format!("Too many redirects from {}.", url))
+}
+
+type Response = (u16, Vec<(String, String)>, Vec<u8>);
+
+fn send(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<Response, String> {
+    let parsed = Url::parse(url)?;
+    let failed = |error: &dyn std::fmt::Display| format!("Request to {} failed: {}", url, error);
+
+    let address = (parsed.host.trim_matches(['[', ']']), parsed.port)
+        .to_socket_addrs()
+        .map_err(|error| failed(&error))?
+        .next()
+        .ok_or_else(|| format!("{} doesn't resolve.", parsed.host))?;
+    let stream = TcpStream::connect_timeout(&address, TIMEOUT).map_err(|error| failed(&error))?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(TIMEOUT)))
+        .map_err(|error| failed(&error))?;
+
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        parsed.path,
+        parsed.authority,
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    let mut raw = Vec::new();
+    if parsed.tls {
+        let connector = SslConnector::builder(SslMethod::tls())
+            .map_err(|error| failed(&error))?
+            .build();
+        let mut stream = connector
+            .connect(&parsed.host, stream)
+            .map_err(|error| failed(&error))?;
+        stream
+            .write_all(head.as_bytes())
+            .and_then(|()| stream.write_all(body))
+            .map_err(|error| failed(&error))?;
+        read_to_end(&mut stream, &mut raw).map_err(|error| failed(&error))?;
+    } else {
+        let mut stream = stream;
+        stream
+            .write_all(head.as_bytes())
+            .and_then(|()| stream.write_all(body))
+            .map_err(|error| failed(&error))?;
+        read_to_end(&mut stream, &mut raw).map_err(|error| failed(&error))?;
+    }
+
+    parse_response(&raw).ok_or_else(|| format!("Malformed response from {}.", url))
+}
+
+/// Reads until the peer closes, which TLS peers may do without a close notify, and fails once
+/// the response grows past `MAX_RESPONSE`.
+fn read_to_end(stream: &mut impl Read, raw: &mut Vec<u8>) -> io::Result<()> {
+    let result = stream.take(MAX_RESPONSE + 1).read_to_end(raw);
+    if raw.len() as u64 > MAX_RESPONSE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the response is larger than {} bytes", MAX_RESPONSE),
+        ));
+    }
+    match result {
+        Ok(_) => Ok(()),
+        Err(_) if !raw.is_empty() => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+fn parse_response(raw: &[u8]) -> Option<Response> {
+    let end = raw.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let head = String::from_utf8_lossy(&raw[..end]);
+    let mut lines = head.split("\r\n");
+
+    let status = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    let body = &raw[end + 4..];
+    let chunked = headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case("transfer-encoding") && value.eq_ignore_ascii_case("chunked")
+    });
+    let body = if chunked {
+        dechunk(body)?
+    } else {
+        body.to_vec()
+    };
+
+    Some((status, headers, body))
+}
+
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        if size == 0 {
+            return Some(decoded);
+        }
+        let start = line_end.checked_add(2)?;
+        let end = start.checked_add(size)?;
+        decoded.extend_from_slice(body.get(start..end)?);
+        if body.get(end..end.checked_add(2)?)? != b"\r\n" {
+            return None;
+        }
+        body = &body[end + 2..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn dechunks_bodies() {
+        assert_eq!(
+            dechunk(b"4\r\nWiki\r\n6;name=value\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\n\r\n"),
+            Some(b"Wikipedia in \r\n\r\nchunks.".to_vec())
+        );
+        assert_eq!(dechunk(b"0\r\n\r\n"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn rejects_malformed_chunk_sizes() {
+        assert_eq!(dechunk(b"zz\r\nWiki\r\n0\r\n\r\n"), None);
+        assert_eq!(dechunk(b"\r\nWiki\r\n0\r\n\r\n"), None);
+        assert_eq!(dechunk(b"-4\r\nWiki\r\n0\r\n\r\n"), None);
+        // A chunk longer than its size says.
+        assert_eq!(dechunk(b"2\r\nWiki\r\n0\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn rejects_oversized_chunk_sizes() {
+        assert_eq!(dechunk(b"ffffffffffffffff\r\nWiki\r\n0\r\n\r\n"), None);
+        assert_eq!(dechunk(b"fffffffffffffffd\r\nWiki\r\n0\r\n\r\n"), None);
+        assert_eq!(dechunk(b"10000000000000000\r\nWiki\r\n0\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn rejects_truncated_bodies() {
+        assert_eq!(dechunk(b""), None);
+        assert_eq!(dechunk(b"4\r\nWi"), None);
+        assert_eq!(dechunk(b"4\r\nWiki"), None);
+        assert_eq!(dechunk(b"4\r\nWiki\r\n"), None);
+    }
+
+    #[test]
+    fn parses_chunked_responses() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n0\r\n\r\n";
+        let (status, headers, body) = parse_response(raw).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(headers.len(), 1);
+        assert_eq!(body, b"Wiki");
+    }
+
+    #[test]
+    fn caps_the_response_size() {
+        let mut raw = Vec::new();
+        read_to_end(&mut Cursor::new(vec![0; 16]), &mut raw).unwrap();
+        assert_eq!(raw.len(), 16);
+
+        let mut raw = Vec::new();
+        let mut huge = io::repeat(0).take(MAX_RESPONSE + 10);
+        let error = read_to_end(&mut huge, &mut raw).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
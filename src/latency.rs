@@ -0,0 +1,202 @@
+#[cfg(not(feature = "latency-test"))]
+use crate::{capabilities::Capabilities, config::DeviceConfig, CaptureOptions, Output};
+
+#[cfg(feature = "latency-test")]
+pub use self::window::run;
+
+/// Without the `latency-test` feature there is no window to flash the pattern in.
+#[cfg(not(feature = "latency-test"))]
+pub fn run(
+    _rounds: u32,
+    _capture: CaptureOptions,
+    _device: (DeviceConfig, Capabilities, Output),
+) -> Result<(), String> {
+    Err("The latency test needs the `latency-test` feature.".to_string())
+}
+
+#[cfg(feature = "latency-test")]
+mod window {
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use colors_transform::Rgb;
+    use log::{debug, info};
+    use minifb::{Window, WindowOptions};
+
+    use crate::{
+        capabilities::Capabilities, config::DeviceConfig, create_color_payload, extract_color,
+        grabber::Grabber, rgb2hsv, rgb_bytes, send, CaptureOptions, Output,
+    };
+
+    /// The colors the window alternates between, far enough apart for any algorithm.
+    const PATTERN: [[u8; 3]; 2] = [[255, 0, 0], [0, 0, 255]];
+    const SIZE: usize = 160;
+    const MARGIN: isize = 16;
+    /// Pause between rounds, so the bulb and the compositor are idle when the next one starts.
+    const PAUSE: Duration = Duration::from_secs(1);
+    /// A round fails when the pattern hasn't been captured after this long.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// How long each round of a latency test took at every stage of the pipeline.
+    struct Round {
+        /// From drawing the pattern to capturing the first frame that shows it.
+        capture: Duration,
+        /// From that frame to a payload with its color.
+        processing: Duration,
+        /// From sending the payload to the device acknowledging it.
+        send: Duration,
+    }
+
+    impl Round {
+        fn total(&self) -> Duration {
+            self.capture + self.processing + self.send
+        }
+    }
+
+    /// Prints the minimum, median and maximum of every stage over the rounds.
+    fn report(rounds: &[Round]) {
+        let times = |stage: fn(&Round) -> Duration| -> Vec<Duration> {
+            let mut times: Vec<Duration> = rounds.iter().map(stage).collect();
+            times.sort();
+            times
+        };
+        let stages = [
+            ("capture", times(|round| round.capture)),
+            ("processing", times(|round| round.processing)),
+            ("send", times(|round| round.send)),
+            ("total", times(Round::total)),
+        ];
+
+        println!("{:<12} {:>8} {:>8} {:>8}", "stage", "min", "median", "max");
+        for (name, times) in stages {
+            let ms = |time: Duration| format!("{:.1}", time.as_secs_f64() * 1000.0);
+            println!(
+                "{:<12} {:>8} {:>8} {:>8}",
+                name,
+                ms(times[0]),
+                ms(times[times.len() / 2]),
+                ms(times[times.len() - 1])
+            );
+        }
+    }
+
+    /// Flashes the pattern in a window in the top left corner `rounds` times, capturing just the
+    /// window, and times each flash through capture, color extraction and the device's
+    /// acknowledgement of the payload.
+    pub fn run(
+        rounds: u32,
+        mut capture: CaptureOptions,
+        (target, capabilities, device): (DeviceConfig, Capabilities, Output),
+    ) -> Result<(), String> {
+        let options = WindowOptions {
+            borderless: true,
+            title: false,
+            topmost: true,
+            ..WindowOptions::default()
+        };
+        let mut window = Window::new("tuya-bulb-screen-color", SIZE, SIZE, options)
+            .map_err(|error| format!("Failed to open the test window: {}", error))?;
+        window.set_position(MARGIN, MARGIN);
+
+        capture.region = Some(
+            format!("{},{},{},{}", MARGIN, MARGIN, SIZE, SIZE)
+                .parse()
+                .expect("the window's region is valid"),
+        );
+        let mut grabber = Grabber::new(&capture)?;
+
+        let mut results = Vec::new();
+        for round in 0..rounds as usize {
+            let shown = PATTERN[round % 2];
+            draw(&mut window, PATTERN[(round + 1) % 2])?;
+            pause(&mut window, PAUSE);
+
+            let result = flash(&mut window, &mut grabber, &capture, shown, |color| {
+                let payload =
+                    create_color_payload(target.id.clone(), &capabilities, rgb2hsv(&color));
+                let sent = Instant::now();
+                send(&device, payload).then(|| sent.elapsed())
+            })?;
+            info!(
+                "Round {}: {:.0} ms.",
+                round + 1,
+                result.total().as_secs_f64() * 1000.0
+            );
+            results.push(result);
+        }
+
+        report(&results);
+        Ok(())
+    }
+
+    /// Draws `color`, waits for a frame whose extracted color is closer to it than to the rest
+    /// of the pattern, and hands that color to `send`, which returns how long sending took.
+    fn flash(
+        window: &mut Window,
+        grabber: &mut Grabber,
+        capture: &CaptureOptions,
+        color: [u8; 3],
+        send: impl FnOnce(Rgb) -> Option<Duration>,
+    ) -> Result<Round, String> {
+        draw(window, color)?;
+        let drawn = Instant::now();
+
+        loop {
+            if drawn.elapsed() > TIMEOUT {
+                return Err(format!(
+                    "The pattern wasn't captured within {} s, is the window on the captured \
+                     display and not covered?",
+                    TIMEOUT.as_secs()
+                ));
+            }
+
+            window.update();
+            let frame = match grabber.grab_image() {
+                Ok(frame) => frame,
+                Err(error) => {
+                    debug!("{}", error);
+                    continue;
+                }
+            };
+            let captured = Instant::now();
+            let seen = extract_color(frame, capture);
+            if closest(rgb_bytes(&seen)) == color {
+                let processing = captured.elapsed();
+                let send = send(seen).ok_or("The device didn't take the payload.")?;
+                return Ok(Round {
+                    capture: captured - drawn,
+                    processing,
+                    send,
+                });
+            }
+        }
+    }
+
+    /// The pattern color nearest to `rgb`.
+    fn closest(rgb: [u8; 3]) -> [u8; 3] {
+        let distance = |color: &[u8; 3]| -> i32 {
+            (0..3)
+                .map(|i| (color[i] as i32 - rgb[i] as i32).pow(2))
+                .sum()
+        };
+        *PATTERN.iter().min_by_key(|color| distance(color)).unwrap()
+    }
+
+    fn draw(window: &mut Window, [r, g, b]: [u8; 3]) -> Result<(), String> {
+        let pixel = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        window
+            .update_with_buffer(&vec![pixel; SIZE * SIZE], SIZE, SIZE)
+            .map_err(|error| format!("Failed to draw the test window: {}", error))
+    }
+
+    /// Waits while keeping the window responsive.
+    fn pause(window: &mut Window, duration: Duration) {
+        let until = Instant::now() + duration;
+        while Instant::now() < until {
+            window.update();
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// A channel that holds one value at most: sending replaces the value the receiver hasn't
+/// taken yet. Between a thread that produces colors and one that sends them over the network,
+/// a stalled send then neither holds up the producer nor leaves a backlog of stale colors.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        slot: Mutex::new(Slot {
+            value: None,
+            senders: 1,
+            receiving: true,
+        }),
+        ready: Condvar::new(),
+    });
+    (Sender(shared.clone()), Receiver(shared))
+}
+
+struct Slot<T> {
+    value: Option<T>,
+    senders: usize,
+    receiving: bool,
+}
+
+struct Shared<T> {
+    slot: Mutex<Slot<T>>,
+    ready: Condvar,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, Slot<T>> {
+        // The slot is consistent at every point a holder could panic.
+        self.slot
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+pub struct Sender<T>(Arc<Shared<T>>);
+
+impl<T> Sender<T> {
+    /// Replaces the value waiting to be received. Fails with the value once the receiver is
+    /// gone.
+    pub fn send(&self, value: T) -> Result<(), T> {
+        let mut slot = self.0.lock();
+        if !slot.receiving {
+            return Err(value);
+        }
+        slot.value = Some(value);
+        self.0.ready.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Sender<T> {
+        self.0.lock().senders += 1;
+        Sender(self.0.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut slot = self.0.lock();
+        slot.senders -= 1;
+        if slot.senders == 0 {
+            self.0.ready.notify_one();
+        }
+    }
+}
+
+pub struct Receiver<T>(Arc<Shared<T>>);
+
+impl<T> Receiver<T> {
+    /// Waits for the newest value, `None` once every sender is gone and the last value was
+    /// taken.
+    pub fn recv(&self) -> Option<T> {
+        let mut slot = self.0.lock();
+        loop {
+            if let Some(value) = slot.value.take() {
+                return Some(value);
+            }
+            if slot.senders == 0 {
+                return None;
+            }
+            slot = self
+                .0
+                .ready
+                .wait(slot)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.0.lock().receiving = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn sending_replaces_the_value_not_yet_received() {
+        let (sender, receiver) = channel();
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+        sender.clone().send(3).unwrap();
+
+        assert_eq!(receiver.recv(), Some(3));
+        sender.send(4).unwrap();
+        assert_eq!(receiver.recv(), Some(4));
+    }
+
+    #[test]
+    fn the_last_value_outlives_the_senders() {
+        let (sender, receiver) = channel();
+        sender.send(1).unwrap();
+        drop(sender);
+
+        assert_eq!(receiver.recv(), Some(1));
+        assert_eq!(receiver.recv(), None);
+    }
+
+    #[test]
+    fn dropping_the_senders_wakes_a_waiting_receiver() {
+        let (sender, receiver) = channel::<u32>();
+        let other = sender.clone();
+        let waiting = thread::spawn(move || receiver.recv());
+
+        thread::sleep(Duration::from_millis(50));
+        drop(sender);
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished(), "woke up while a sender was left");
+        drop(other);
+
+        assert_eq!(waiting.join().unwrap(), None);
+    }
+
+    #[test]
+    fn sending_wakes_a_waiting_receiver() {
+        let (sender, receiver) = channel();
+        let waiting = thread::spawn(move || receiver.recv());
+
+        thread::sleep(Duration::from_millis(50));
+        sender.send(7).unwrap();
+
+        assert_eq!(waiting.join().unwrap(), Some(7));
+    }
+
+    #[test]
+    fn sending_fails_once_the_receiver_is_gone() {
+        let (sender, receiver) = channel();
+        drop(receiver);
+
+        assert_eq!(sender.send(1), Err(1));
+    }
+}
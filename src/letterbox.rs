@@ -0,0 +1,96 @@
+//! Detection of the black bars around video that doesn't fill the screen.
+
+/// Brightest channel value a bar pixel may have. Video black is 16 in limited range, and
+/// compression noise lifts it a little further.
+const BLACK_LEVEL: u8 = 24;
+/// Bars thicker than this share of the frame are more likely a dark scene than a letterbox.
+const MAX_BAR: f32 = 0.3;
+
+/// Thickness in pixels of the black bars around the picture.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Bars {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Bars {
+    pub fn is_empty(&self) -> bool {
+        *self == Bars::default()
+    }
+}
+
+fn is_black(pixel: &[u8]) -> bool {
+    // Transparent pixels are already out of the picture, e.g. next to a narrower display.
+    pixel[3] == 0 || pixel[..3].iter().all(|&channel| channel <= BLACK_LEVEL)
+}
+
+/// Counts the lines from one edge in which every pixel is black, `line(i)` being the pixels
+/// of the `i`-th line from that edge, or none when they go on past `MAX_BAR`.
+fn thickness<'a, I>(lines: usize, line: impl Fn(usize) -> I) -> usize
+where
+    I: Iterator<Item = &'a [u8]>,
+{
+    let max = (lines as f32 * MAX_BAR) as usize;
+    (0..max).position(|i| !line(i).all(is_black)).unwrap_or(0)
+}
+
+/// Finds the letterbox (top and bottom) and pillarbox (left and right) bars of a `width` ×
+/// `height` RGBA frame and makes them transparent, which keeps them out of the extracted colors
+/// and the statistics. Bars only count in pairs on opposite edges, the way video is framed, so
+/// a dark sky or a black taskbar along one edge stays in; a frame that is black all over has no
+/// bars.
+///
+/// ```
+/// use tuya_bulb_screen_color::letterbox::{mask, Bars};
+///
+/// // A 4×10 frame: two black rows above and below a red picture.
+/// let mut frame = Vec::new();
+/// for y in 0..10 {
+///     let pixel = if (2..8).contains(&y) { [200, 0, 0, 255] } else { [0, 0, 0, 255] };
+///     frame.extend(pixel.repeat(4));
+/// }
+///
+/// let bars = mask(&mut frame, 4, 10);
+/// assert_eq!(bars, Bars { top: 2, bottom: 2, left: 0, right: 0 });
+/// assert_eq!(frame[3], 0);
+/// assert_eq!(frame[2 * 4 * 4 + 3], 255);
+///
+/// // Black along one edge only is part of the picture.
+/// let mut frame = [[0, 0, 0, 255]; 2].concat();
+/// frame.extend([200, 0, 0, 255].repeat(8));
+/// assert!(mask(&mut frame, 1, 10).is_empty());
+/// ```
+pub fn mask(rgba: &mut [u8], width: usize, height: usize) -> Bars {
+    let pixel = |x: usize, y: usize| &rgba[(y * width + x) * 4..(y * width + x) * 4 + 4];
+    let row = |y: usize| (0..width).map(move |x| pixel(x, y));
+    let column = |x: usize| (0..height).map(move |y| pixel(x, y));
+
+    let mut bars = Bars {
+        top: thickness(height, row),
+        bottom: thickness(height, |i| row(height - 1 - i)),
+        left: thickness(width, column),
+        right: thickness(width, |i| column(width - 1 - i)),
+    };
+    if bars.top == 0 || bars.bottom == 0 {
+        (bars.top, bars.bottom) = (0, 0);
+    }
+    if bars.left == 0 || bars.right == 0 {
+        (bars.left, bars.right) = (0, 0);
+    }
+    if bars.is_empty() {
+        return bars;
+    }
+
+    for y in 0..height {
+        let in_bar = y < bars.top || y >= height - bars.bottom;
+        for x in 0..width {
+            if in_bar || x < bars.left || x >= width - bars.right {
+                rgba[(y * width + x) * 4 + 3] = 0;
+            }
+        }
+    }
+
+    bars
+}
@@ -0,0 +1,32 @@
+//! Building blocks of `tuya-bulb-screen-color` that are useful to other Tuya tooling.
+//!
+//! [`capture`] grabs frames of a display, [`color`] picks the color a bulb shows for them and
+//! [`tuya`] encodes that color into the payload the bulb takes, which is all it takes to sync a
+//! bulb to the screen from another program:
+//!
+//! ```no_run
+//! use rust_tuyapi::TuyaDevice;
+//! use tuya_bulb_screen_color::{
+//!     capture::{AlphaMode, Screen},
+//!     color::{rgb2hsv, Extraction},
+//!     tuya::colour_payload,
+//! };
+//!
+//! let bulb = TuyaDevice::create("ver3.3", Some("0123456789abcdef"), "192.168.1.20".parse()?)?;
+//! let mut screen = Screen::open(0, AlphaMode::Skip)?;
+//! loop {
+//!     let color = Extraction::default().color(screen.grab()?.as_raw());
+//!     bulb.set(colour_payload("bf0123456789abcdef", rgb2hsv(color)), 0)?;
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod capture;
+pub mod cluster;
+pub mod color;
+pub mod colorspace;
+pub mod letterbox;
+pub mod names;
+pub mod stats;
+pub mod tuya;
+pub mod vectors;
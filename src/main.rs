@@ -1,180 +1,2407 @@
-use std::io::ErrorKind::WouldBlock;
 use std::{
-    collections::HashMap,
-    hash::Hash,
-    net::IpAddr,
+    collections::{BTreeMap, HashMap},
+    io::{self, BufRead, Write},
+    iter,
+    net::{IpAddr, SocketAddr},
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
     thread,
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
-use std::{env, path};
+use std::{env, fs, path};
 
-use clap::Parser;
-use color_thief::get_palette;
+mod agent;
+mod alerts;
+mod ambilight;
+mod bench;
+mod bulb;
+mod calibration;
+mod capabilities;
+mod chromecast;
+mod config;
+mod control;
+mod convert;
+mod coordination;
+mod cursor;
+mod daemon;
+mod devices;
+mod discovery;
+mod dlna;
+mod dump;
+mod gain;
+mod gamepad;
+mod grabber;
+mod grading;
+mod health;
+mod http;
+mod latency;
+mod latest;
+mod memory;
+mod mqtt;
+mod now_playing;
+mod osd;
+mod pacing;
+mod pairing;
+mod pipeline;
+mod presence;
+mod profiles;
+mod quiet;
+mod reachability;
+mod rules;
+mod server;
+mod session;
+mod setup;
+mod shm;
+#[cfg(feature = "simulate")]
+mod simulator;
+mod slideshow;
+mod snapshot;
+mod state;
+mod status;
+mod tray;
+mod usage;
+mod websocket;
+mod zones;
+
+use clap::{Parser, Subcommand};
 use colors_transform::{Color, Hsl, Rgb};
 use image::RgbaImage;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use rust_tuyapi::Payload;
-use rust_tuyapi::{error::ErrorKind, PayloadStruct, TuyaDevice};
-use scrap::{Capturer, Display};
-use serde::Serialize;
-use serde_json::json;
+use rust_tuyapi::{error::ErrorKind, mesparse::Message, PayloadStruct, TuyaDevice};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::watch;
+use tuya_bulb_screen_color::{
+    capture::{AlphaMode, Region},
+    color::{self, Algorithm, Extraction},
+    colorspace::ColorSpace,
+    names::name,
+    stats::{dominant_share, FrameStats},
+    tuya::{dps_payload, parse_dps},
+    vectors::{identify, Mapping, VECTORS},
+};
+
+use alerts::FailureMonitor;
+use bulb::Bulb;
+use capabilities::{Capabilities, Quirk};
+use config::{Config, DeviceConfig};
+use control::{Control, LoopStatus};
+use coordination::Coordinator;
+use cursor::CursorMode;
+use daemon::{PidFile, Shutdown};
+use gain::AutoGain;
+use gamepad::GamepadAction;
+use grabber::{CaptureConfig, DisplaySelection, Grabber, Source};
+use grading::Grade;
+use health::Health;
+use mqtt::{Bridge, Discovery};
+use osd::Osd;
+use pacing::FramePacer;
+use pipeline::Sender;
+use presence::Presence;
+use profiles::{ContentClassifier, ContentType, Profile, ProfileConfig};
+use reachability::ConnectionConfig;
+use rules::Rules;
+use session::{Protocol, SessionDevice};
+use shm::SharedFrame;
+use state::{Recorder, Startup, State};
+use tray::TrayAction;
+use usage::Usage;
+use websocket::Feed;
+use zones::{ZoneConfig, ZoneLayout};
+
+extern crate pretty_env_logger;
+
+#[derive(clap::ValueEnum, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Feature {
+    #[default]
+    SwitchLedOn,
+    SwitchLedOff,
+    ColorPicker,
+    WhiteMode,
+    ColorMode,
+    Brightness,
+    ColorTemperature,
+    Pipe,
+    BrightnessSync,
+    VerifyEncoding,
+    Hub,
+    Slideshow,
+    NowPlaying,
+    Ambilight,
+    LatencyTest,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Sampling {
+    /// Extract the palette from every pixel of the frame
+    Full,
+    /// Only read a sparse grid of points straight from the capture buffer
+    Grid,
+}
+
+/// Everything that shapes how `color_picker` turns frames into updates.
+struct PickerOptions {
+    /// Content profiles to switch between, `None` when `--auto-profile` is off.
+    profiles: Option<HashMap<ContentType, ProfileConfig>>,
+    capture: CaptureOptions,
+    /// `[zones.<name>]` of the config, applied once the zone layout is known.
+    zone_overrides: HashMap<String, ZoneConfig>,
+    /// Update rate and threshold used while no content profile is active.
+    default_profile: Profile,
+    /// Hue and saturation to keep when only the brightness follows the screen.
+    fixed_hue: Option<(u32, u32)>,
+    grade: Grade,
+    /// Where captured frames are saved with `--save-screenshots`.
+    screenshots: Option<path::PathBuf>,
+    /// Where every frame is published with `--shared-memory`.
+    shared_frame: Option<SharedFrame>,
+    /// Set when a `[coordination]` section lets instances on the LAN share devices.
+    coordinator: Option<Coordinator>,
+    /// Actions triggered by the `[[gamepad]]` bindings.
+    gamepad: Option<mpsc::Receiver<GamepadAction>>,
+    /// The on-screen display with `--osd`.
+    osd: Option<Osd>,
+    /// What was picked in the tray menu with `--tray`.
+    tray: Option<mpsc::Receiver<TrayAction>>,
+    /// Brightness normalization, `None` when `--auto-gain` is off.
+    auto_gain: Option<AutoGain>,
+    /// Whether anyone is home, `None` without a `[presence]` section.
+    presence: Option<Presence>,
+    /// State left by the previous run, kept up to date while running. `None` when not persisted.
+    state: Option<State>,
+    /// Most payloads a bulb fades to a new color in, 0 to jump straight to it.
+    transition_steps: u32,
+    /// Brightness the colors sent don't go below, see `--min-brightness`.
+    min_brightness: u32,
+    /// Where `--debug-dump-on-change` writes its dumps, `None` when it is off.
+    debug_dumps: Option<path::PathBuf>,
+    /// Where `--mqtt-url` publishes the colors and in which format, `None` without it.
+    bridge: Option<(Bridge, EmitFormat)>,
+    /// What was asked for through the control API with `--listen`.
+    api: Option<mpsc::Receiver<TrayAction>>,
+    /// Streams the colors to the WebSockets of the `--listen` server.
+    feed: Feed,
+}
+
+/// How frames are captured and sampled, shared by all capturing loops.
+#[derive(Debug, Clone)]
+struct CaptureOptions {
+    /// Backends to capture with in order of preference, see `Grabber::new`.
+    sources: Vec<Source>,
+    display: DisplaySelection,
+    region: Option<Region>,
+    sampling: Sampling,
+    algorithm: Algorithm,
+    /// Only every this many pixels are averaged by the average algorithm.
+    decimation: usize,
+    /// Number of clusters the kmeans algorithm splits the pixels into.
+    clusters: usize,
+    color_space: ColorSpace,
+    refresh_rate: f64,
+    alpha: AlphaMode,
+    cursor: CursorMode,
+    warm_up_frames: u32,
+    /// Frames captured over each update interval, whose colors are combined by their median.
+    frames_per_update: u32,
+    /// Share of the frame the dominant color has to stand for to be sent, see `dominant_share`.
+    min_confidence: f32,
+    /// Weight of each new color in the moving average colors are smoothed with, 1.0 for none.
+    smoothing: f32,
+    /// Splits the frame so each bulb gets the color of its own zone, `None` for one color.
+    zones: Option<ZoneLayout>,
+    /// Whether the zone of an offline bulb is shown by the nearest bulb still online.
+    zone_fallback: bool,
+    /// Whether black bars around the picture are left out of every frame, see `letterbox::mask`.
+    letterbox: bool,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum EmitFormat {
+    Hex,
+    Json,
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Device id, defaults to the first device in the config. Repeat --id/--key/--ip to drive
+    /// several devices
+    #[arg(long, requires_all = ["key", "ip"])]
+    id: Vec<String>,
+
+    #[arg(long, requires_all = ["id", "ip"])]
+    key: Vec<String>,
+
+    #[arg(long, requires_all = ["id", "key"])]
+    ip: Vec<String>,
+
+    /// Tuya protocol version of the devices, overriding their `version` in the config
+    /// [default: 3.3]
+    #[arg(long, value_name = "VERSION", value_parser = ["3.1", "3.3", "3.4", "3.5"])]
+    protocol: Option<String>,
+
+    /// Drive every device in the config instead of only the first
+    #[arg(long, default_value_t = false, conflicts_with = "id")]
+    all_devices: bool,
+
+    /// Drive this device from the config, by name or id, instead of the first. Repeat it to drive
+    /// several devices
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["id", "all_devices"])]
+    device: Vec<String>,
+
+    #[arg(long, default_value_t = false)]
+    debug: bool,
+
+    /// What to do, defaults to `mode` in the config
+    #[arg(long)]
+    mode: Option<Feature>,
+
+    /// Switch the bulb on, short for `--mode switch-led-on`
+    #[arg(long, conflicts_with_all = ["mode", "off"])]
+    on: bool,
+
+    /// Switch the bulb off, short for `--mode switch-led-off`
+    #[arg(long, conflicts_with = "mode")]
+    off: bool,
+
+    /// Write every extracted screen color to stdout instead of sending it to a device
+    #[arg(long, value_name = "FORMAT")]
+    emit_colors: Option<EmitFormat>,
+
+    /// Capture the screen and stream the colors to the hub at this address instead of a device,
+    /// without an address the hub is looked up over mDNS
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        num_args = 0..=1,
+        default_missing_value = agent::DISCOVER,
+        conflicts_with_all = ["mode", "emit_colors"],
+        requires = "pair_code"
+    )]
+    agent: Option<String>,
+
+    /// Address the hub mode accepts agents on
+    #[arg(long, value_name = "ADDRESS", default_value = "0.0.0.0:9124")]
+    hub_listen: String,
+
+    /// Code agents pair with the hub by; the hub makes one up and logs it when not given
+    #[arg(long, value_name = "CODE")]
+    pair_code: Option<String>,
+
+    /// Path to the config file [default: ~/.config/tuya-bulb-screen-color/config.toml]
+    #[arg(long)]
+    config: Option<path::PathBuf>,
+
+    /// Don't raise desktop notifications when the device stops responding
+    #[arg(long, default_value_t = false)]
+    no_notifications: bool,
+
+    /// Brightness in percent (0-100) set by brightness mode
+    #[arg(long, value_parser = clap::value_parser!(u32).range(0..=100))]
+    value: Option<u32>,
+
+    /// White color temperature in Kelvin set by color-temperature mode, e.g. 2700 for warm white
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1000..=10000))]
+    kelvin: Option<u32>,
+
+    /// Directory of images whose colors slideshow mode cycles through
+    #[arg(long, value_name = "DIR")]
+    slideshow_dir: Option<path::PathBuf>,
+
+    /// Seconds each image is shown by slideshow mode
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    slideshow_interval: u64,
+
+    /// Address of the Philips TV whose Ambilight colors ambilight mode mirrors
+    #[arg(long, value_name = "HOST")]
+    ambilight_tv: Option<String>,
+
+    /// Version of the TV's JointSpace API, 1 for TVs from before 2016
+    #[arg(long, value_name = "VERSION", default_value_t = 6)]
+    ambilight_api: u32,
+
+    /// Number of flashes timed by latency-test mode
+    #[arg(long, value_name = "ROUNDS", default_value_t = 10)]
+    latency_rounds: u32,
+
+    /// Hue (0-360) kept by brightness-sync mode
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(0..=360))]
+    hue: u32,
+
+    /// Saturation (0-100) kept by brightness-sync mode
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(0..=100))]
+    saturation: u32,
+
+    /// Time between updates of color-picker and brightness-sync, e.g. 100 for games or 2000 for
+    /// the desktop; content profiles still use their own [default: 1000]
+    #[arg(long, value_name = "MS", conflicts_with = "fps", value_parser = clap::value_parser!(u64).range(1..))]
+    interval_ms: Option<u64>,
+
+    /// Updates per second, the other way to set --interval-ms
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=1000))]
+    fps: Option<u32>,
+
+    /// Difference to the last color sent below which a bulb isn't updated: the hue difference in
+    /// degrees plus the saturation and lightness differences in percentage points; content
+    /// profiles still use their own [default: 10]
+    #[arg(long, value_name = "DIFFERENCE")]
+    threshold: Option<f32>,
+
+    /// Detect whether a desktop, video or game is on screen and adapt update rate and threshold
+    #[arg(long, default_value_t = false)]
+    auto_profile: bool,
+
+    /// Scale the brightness so the room stays about as bright through long dark or bright stretches
+    #[arg(long, default_value_t = false)]
+    auto_gain: bool,
+
+    /// Fade to every new color over the update interval in up to this many payloads instead of
+    /// jumping to it, 0 jumps
+    #[arg(long, value_name = "STEPS", default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=20))]
+    transition_steps: u32,
+
+    /// Brightness in percent (0-100) colors of color-picker and brightness-sync don't go below,
+    /// so the bulb stays lit through dark scenes
+    #[arg(long, value_name = "PERCENT", default_value_t = 0, value_parser = clap::value_parser!(u32).range(0..=100))]
+    min_brightness: u32,
+
+    /// Multiply the saturation of every color sent by this factor, capped at full saturation,
+    /// e.g. 1.3 for a slightly oversaturated glow around the screen
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0)]
+    saturation_boost: f32,
+
+    /// Capture with this backend: native, or command:COMMAND for a command printing a screenshot
+    /// to stdout. Repeat it to fall back to the next one when a backend keeps failing [default:
+    /// native]
+    #[arg(long = "source", value_name = "SOURCE")]
+    sources: Vec<Source>,
+
+    /// Capture this display, counted as in --list-displays, or `all` for every display at once
+    /// [default: 0]
+    #[arg(long, value_name = "INDEX")]
+    display: Option<DisplaySelection>,
+
+    /// Only take colors from this part of the frame: x,y,width,height in pixels or percent, e.g.
+    /// 0,0,1280,720 or 0%,0%,75%,100%
+    #[arg(long, value_name = "X,Y,W,H")]
+    region: Option<Region>,
+
+    /// Print the displays that can be captured and exit
+    #[arg(long, default_value_t = false)]
+    list_displays: bool,
+
+    /// Refresh rate of the captured display in Hz, updates are paced on whole frames of it
+    /// [default: 60]
+    #[arg(long)]
+    refresh_rate: Option<f64>,
+
+    /// Which pixels of a captured frame are used to extract the color [default: full]
+    #[arg(long, value_enum)]
+    sampling: Option<Sampling>,
+
+    /// How the color is extracted from the sampled pixels [default: palette]
+    #[arg(long, value_enum)]
+    algorithm: Option<Algorithm>,
+
+    /// Only average every Nth pixel with --algorithm average, trading accuracy for speed on large
+    /// frames [default: 1]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    decimation: Option<u32>,
+
+    /// Number of clusters --algorithm kmeans splits the pixels into; more find smaller areas of
+    /// color but cost more time [default: 5]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=16))]
+    clusters: Option<u32>,
+
+    /// How the alpha channel of captured pixels is treated [default: skip]
+    #[arg(long, value_enum)]
+    alpha: Option<AlphaMode>,
+
+    /// Whether the mouse cursor is part of the captured frames [default: exclude]
+    #[arg(long, value_enum)]
+    cursor: Option<CursorMode>,
+
+    /// Number of frames thrown away after the capturer is created, as the first ones can be black
+    /// [default: 3]
+    #[arg(long)]
+    warm_up_frames: Option<u32>,
+
+    /// Capture this many frames spread over each update and send the median of their colors,
+    /// which keeps single-frame flashes off the bulb [default: 1]
+    #[arg(long, value_name = "FRAMES", value_parser = clap::value_parser!(u32).range(1..))]
+    frames_per_update: Option<u32>,
+
+    /// Keep the previous color when less than this share (0.0-1.0) of the frame is close to its
+    /// dominant color, e.g. 0.2 to ignore busy, fragmented frames [default: 0.0]
+    #[arg(long, value_name = "SHARE")]
+    min_confidence: Option<f32>,
+
+    /// Smooth colors with an exponential moving average before comparing them to the last one
+    /// sent, giving each new color this weight (0.0-1.0), e.g. 0.3 so a single bright frame
+    /// barely moves the bulb [default: 1.0, no smoothing]
+    #[arg(long, value_name = "ALPHA")]
+    smoothing: Option<f32>,
+
+    /// Split the frame into zones, `edges` or a COLUMNSxROWS grid, and give each bulb the color
+    /// of its own zone in config order; bulbs past the last zone follow the whole frame
+    #[arg(long, value_name = "LAYOUT")]
+    zones: Option<ZoneLayout>,
+
+    /// While a bulb with a zone is offline, blend its zone into that of the nearest bulb still
+    /// online instead of leaving that part of the room dark
+    #[arg(long, default_value_t = false)]
+    zone_fallback: bool,
+
+    /// Keep the black bars around letterboxed or pillarboxed video in the frame, by default
+    /// they are detected on every frame and left out so they don't darken the color
+    #[arg(long, default_value_t = false)]
+    no_letterbox: bool,
+
+    /// Color space colors are blended in: rgb, linear-rgb, hsl, hsv, oklab or lab [default: oklab]
+    #[arg(long)]
+    color_space: Option<ColorSpace>,
+
+    /// Serve the HTTP endpoints (/healthz, /devices, /status, the controls and the /colors
+    /// WebSocket) on this address, e.g. 127.0.0.1:9123
+    #[arg(long, value_name = "ADDRESS")]
+    listen: Option<String>,
+
+    /// Also publish the colors of color-picker and brightness-sync to this MQTT broker,
+    /// mqtt://[USER[:PASSWORD]@]HOST[:PORT], or to the one in [mqtt] without a URL. Without a
+    /// device to drive, the colors only go to the broker
+    #[arg(long, value_name = "URL", num_args = 0..=1, default_missing_value = mqtt::FROM_CONFIG)]
+    mqtt_url: Option<String>,
+
+    /// Topic --mqtt-url publishes the color of the whole frame to, {host} is replaced by the
+    /// host name
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "tuya-bulb-screen-color/{host}/color"
+    )]
+    mqtt_topic: String,
+
+    /// Topic --mqtt-url publishes the color of each of the --zones to, {zone} is replaced by the
+    /// name of the zone
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "tuya-bulb-screen-color/{host}/zone/{zone}"
+    )]
+    mqtt_zone_topic: String,
+
+    /// Format of the colors published with --mqtt-url, the same as with --emit-colors
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = EmitFormat::Json)]
+    mqtt_format: EmitFormat,
+
+    /// Announce a switch to pause syncing, the current color and the mode to Home Assistant
+    /// through MQTT discovery under this topic prefix [default: homeassistant]
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        num_args = 0..=1,
+        default_missing_value = "homeassistant",
+        requires = "mqtt_url"
+    )]
+    mqtt_discovery: Option<String>,
+
+    /// Power the bulb on when it is off as syncing starts or resumes, and back off on exit
+    #[arg(long, default_value_t = false)]
+    wake_on_demand: bool,
+
+    /// Run color-picker or brightness-sync in the background, detached from the terminal and
+    /// logging to daemon.log in the state directory
+    #[arg(long, default_value_t = false)]
+    daemon: bool,
+
+    /// Write the process ID to this file while running, removed again on a clean exit
+    #[arg(long, value_name = "PATH")]
+    pid_file: Option<path::PathBuf>,
+
+    /// Save every captured frame as a JPEG into --screenshot-dir
+    #[arg(long, default_value_t = false)]
+    save_screenshots: bool,
+
+    /// Directory for --save-screenshots, created when missing
+    #[arg(long, value_name = "DIR", default_value = "screenshots")]
+    screenshot_dir: path::PathBuf,
+
+    /// On every large color change, write the frame, its palette, the color and the payloads
+    /// sent into a timestamped folder of --debug-dump-dir, for bug reports about wrong colors
+    #[arg(long, default_value_t = false)]
+    debug_dump_on_change: bool,
+
+    /// Directory for --debug-dump-on-change, created when missing
+    #[arg(long, value_name = "DIR", default_value = "debug-dumps")]
+    debug_dump_dir: path::PathBuf,
+
+    /// Publish every frame, scaled down, and its colors into this shared memory file for other
+    /// programs [default: /dev/shm/tuya-bulb-screen-color]
+    #[arg(long, value_name = "PATH", num_args = 0..=1)]
+    shared_memory: Option<Option<path::PathBuf>>,
+
+    /// Show the mode, profile and output color in a small overlay (needs the `osd` feature)
+    #[arg(long, default_value_t = false)]
+    osd: bool,
+
+    /// Global hotkey that hides and shows the --osd overlay
+    #[arg(long, value_name = "HOTKEY", default_value = "ctrl+alt+o")]
+    osd_hotkey: String,
+
+    /// Put an icon in the system tray to pause, switch between color-picker and brightness-sync
+    /// and quit (needs the `tray` feature, on Linux)
+    #[arg(long, default_value_t = false)]
+    tray: bool,
+
+    /// Show the output in a window instead of sending it to a bulb (needs the `simulate` feature)
+    #[arg(long, default_value_t = false)]
+    simulate: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage the devices in the config
+    Devices {
+        #[command(subcommand)]
+        command: DevicesCommand,
+    },
+    /// Manage the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Query the health endpoint of a running instance, exiting non-zero when it is unhealthy
+    IsHealthy {
+        #[arg(long, default_value = "127.0.0.1:9123")]
+        address: String,
+    },
+    /// Time the capture and extraction stages over a number of frames
+    Bench {
+        #[arg(short = 'n', long, default_value_t = 50)]
+        iterations: usize,
+    },
+    /// Summarize how long the bulbs were on and how bright, per day
+    Stats {
+        #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(i64).range(1..))]
+        days: i64,
+    },
+    /// Ask the devices for their data points and print what they are doing
+    Status {
+        /// Print the status as JSON instead
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+    /// Watch the screen, cluster it into one color region per bulb and propose a --zones layout
+    /// that follows the regions, written to the config once confirmed
+    Zones {
+        /// How long to watch the screen
+        #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+        duration: u64,
+        /// Number of bulbs to lay zones out for, defaults to the devices in the config
+        #[arg(long)]
+        bulbs: Option<usize>,
+        /// Write the proposal without asking
+        #[arg(long, short = 'y', default_value_t = false)]
+        yes: bool,
+    },
+    /// Print the --zones layout as the `leds` of a Hyperion config, for moving to LED strips
+    ExportZones,
+    /// Show what a color becomes for the device: #rrggbb or HUE,SATURATION,VALUE after the
+    /// [grade], as HSV and as the colour DP string, or a colour DP string decoded
+    Convert {
+        color: String,
+        /// Leave the [grade] out
+        #[arg(long, default_value_t = false)]
+        raw: bool,
+    },
+    /// Listen for the broadcasts of Tuya devices on the LAN and print their id, IP and version
+    Discover {
+        /// How long to listen, devices broadcast every few seconds
+        #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+        duration: u64,
+    },
+    /// Set up the config step by step: find the devices, test them, pick the display and zones
+    Setup,
+    /// Save the state of every device in the config under a name, or put them back into it
+    Snapshot {
+        #[command(subcommand)]
+        command: SnapshotCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum DevicesCommand {
+    /// Import devices from a tinytuya wizard/snapshot or tuya-cli wizard JSON dump
+    Import { snapshot: path::PathBuf },
+    /// List the devices in the config
+    List,
+    /// Add a device to the config
+    Add {
+        id: String,
+        #[command(flatten)]
+        fields: devices::DeviceFields,
+    },
+    /// Change fields of a device in the config, given by name or id
+    Edit {
+        device: String,
+        #[command(flatten)]
+        fields: devices::DeviceFields,
+    },
+    /// Remove a device from the config, given by name or id
+    Remove { device: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum SnapshotCommand {
+    /// Save all data points of the devices as NAME, replacing an earlier snapshot of that name
+    Save { name: String },
+    /// Send the devices the data points saved as NAME
+    Recall { name: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Restore the config from its most recent backup
+    Rollback,
+}
+
+/// Update rate and change threshold used when no content profile is active.
+const DEFAULT_PROFILE: Profile = Profile {
+    interval: Duration::from_secs(1),
+    threshold: 10.0,
+};
+
+/// Size of the point grid read in `--sampling grid`, about a thousand samples.
+const GRID_COLUMNS: usize = 40;
+const GRID_ROWS: usize = 25;
+
+/// Frames with at least this share of black pixels count as a black screen for the rules.
+const BLACK_RATIO: f32 = 0.95;
+/// Change in `color_diff` between two updates that `--debug-dump-on-change` writes a dump for.
+const DUMP_CHANGE: f32 = 60.0;
+/// How long a gamepad flash stays on the bulb.
+const FLASH: Duration = Duration::from_secs(1);
+/// How long to give the bulb to apply a colour before reading it back in `verify-encoding`.
+const VERIFY_SETTLE: Duration = Duration::from_millis(500);
+
+fn main() {
+    let mut args = Args::parse();
+
+    if args.debug {
+        env::set_var("RUST_LOG", "none,tuya_bulb_screen_color=debug");
+    } else {
+        env::set_var("RUST_LOG", "none,tuya_bulb_screen_color=info");
+    }
+
+    pretty_env_logger::init();
+
+    if args.on || args.off {
+        args.mode = Some(if args.on {
+            Feature::SwitchLedOn
+        } else {
+            Feature::SwitchLedOff
+        });
+    }
+
+    if let Some(command) = args.command.take() {
+        run_command(command, &args);
+        return;
+    }
+
+    if args.list_displays {
+        if let Err(error) = grabber::list_displays() {
+            error!("{}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let nothing_given = args.mode.is_none() && args.id.is_empty() && !args.simulate;
+    if nothing_given && args.agent.is_none() && setup::first_run(args.config.as_deref()) {
+        if let Err(error) = setup::run(args.config.clone()) {
+            error!("{}", error);
+            std::process::exit(1);
+        }
+    }
+
+    let config = config::load(args.config.clone()).unwrap_or_else(|error| {
+        error!("{}", error);
+        std::process::exit(1);
+    });
+    let capture = capture_options(&args, &config.capture);
+
+    if let Some(format) = args.emit_colors {
+        info!("Emitting screen colors to stdout...");
+        emit_colors(
+            format,
+            capture,
+            default_profile(&args, &config.update).interval,
+        );
+        return;
+    }
+
+    if let Some(address) = &args.agent {
+        info!("Running as an agent...");
+        agent::run(
+            address,
+            args.pair_code.as_deref().unwrap_or_default(),
+            capture,
+            default_profile(&args, &config.update).interval,
+        );
+        return;
+    }
+
+    let startup = if args.mode.is_some() {
+        Startup::Mode
+    } else {
+        config.startup
+    };
+    let mode = match startup {
+        Startup::Mode => args.mode.clone().or(config.mode.clone()),
+        Startup::Resume => state::load().mode.or(config.mode.clone()),
+        Startup::Off => Some(Feature::SwitchLedOff),
+        Startup::Scene(_) => None,
+    };
+    if mode.is_none() && !matches!(startup, Startup::Scene(_)) {
+        error!("No mode given, pass --mode or set `mode` in the config.");
+        std::process::exit(1);
+    }
+
+    if args.daemon {
+        if !matches!(mode, Some(Feature::ColorPicker | Feature::BrightnessSync)) {
+            error!("Only color-picker and brightness-sync run with --daemon.");
+            std::process::exit(1);
+        }
+        if let Err(error) = daemon::detach() {
+            error!("{}", error);
+            std::process::exit(1);
+        }
+    }
+    let _pid_file = args.pid_file.as_deref().map(|path| {
+        PidFile::create(path).unwrap_or_else(|error| {
+            error!("{}", error);
+            std::process::exit(1);
+        })
+    });
+
+    let bridging = args.mqtt_url.is_some()
+        && matches!(mode, Some(Feature::ColorPicker | Feature::BrightnessSync));
+    let targets = match resolve_targets(&args, &config) {
+        Ok(targets) => targets,
+        // Without a device the broker is all that gets the colors.
+        Err(_) if bridging && args.id.is_empty() && args.device.is_empty() => {
+            info!("No device given, only publishing the colors over MQTT.");
+            Vec::new()
+        }
+        Err(error) => {
+            error!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let mut devices: Vec<Target> = Vec::new();
+    for target in targets {
+        let mut capabilities =
+            capabilities::resolve(&target, &config.products).unwrap_or_else(|error| {
+                error!("{}", error);
+                std::process::exit(1);
+            });
+        capabilities.saturation_boost = args.saturation_boost.max(0.0);
+
+        let device = if args.simulate {
+            open_simulator(&capabilities, capture.color_space)
+        } else {
+            let Some(ip) = target.ip.clone() else {
+                error!("Device {} has no IP address configured.", target.label());
+                std::process::exit(1);
+            };
+
+            debug!("Using device {} at {}.", target.label(), ip);
+
+            connect(
+                target.key.clone(),
+                ip,
+                target.version.clone(),
+                config.connection,
+            )
+            .inspect_err(|error| error!("Can't use {}: {}", target.label(), error))
+        };
+        let device = match &config.quiet_hours {
+            Some(quiet) => device.map(|device| {
+                let quiet = quiet::QuietHours::new(quiet.clone(), capabilities.clone());
+                Output::Quiet(Box::new(device), quiet)
+            }),
+            None => device,
+        };
+
+        devices.push((target, capabilities, device));
+    }
+
+    let Some(mode) = mode else {
+        if let Startup::Scene(color) = startup {
+            info!("Showing {}.", describe_color(&color.0));
+            for (target, capabilities, device) in devices {
+                show_color(device, target.id, &capabilities, &color.0);
+            }
+        }
+        return;
+    };
+
+    // Remembered for `startup = "resume"`; the encoding check and the latency test are one-offs,
+    // not modes to resume.
+    let one_off = matches!(mode, Feature::VerifyEncoding | Feature::LatencyTest);
+    let state = (!one_off && !args.simulate).then(|| {
+        let previous = state::load();
+        let state = if previous.mode.as_ref() == Some(&mode) {
+            previous
+        } else {
+            state::State {
+                mode: Some(mode.clone()),
+                ..state::State::default()
+            }
+        };
+        if let Err(error) = state::save(&state) {
+            warn!("{}", error);
+        }
+        state
+    });
+
+    match mode {
+        Feature::SwitchLedOn | Feature::SwitchLedOff => {
+            let on = matches!(mode, Feature::SwitchLedOn);
+            info!("Turning {} the LED...", if on { "on" } else { "off" });
+            for (target, capabilities, device) in devices {
+                switch_led(device, target.id, &capabilities, on);
+            }
+        }
+        Feature::ColorMode | Feature::WhiteMode => {
+            let mode = if matches!(mode, Feature::ColorMode) {
+                "colour"
+            } else {
+                "white"
+            };
+            info!("Changing mode to {}", mode);
+            for (target, capabilities, device) in devices {
+                color_mode(device, target.id, &capabilities, mode.to_string());
+            }
+        }
+        Feature::Brightness => {
+            let Some(percent) = args.value else {
+                error!("Brightness mode needs --value.");
+                std::process::exit(1);
+            };
+            info!("Setting the brightness to {}%...", percent);
+            for (target, capabilities, device) in devices {
+                set_brightness(device, target.id, &capabilities, percent);
+            }
+        }
+        Feature::ColorTemperature => {
+            let Some(kelvin) = args.kelvin else {
+                error!("Color-temperature mode needs --kelvin.");
+                std::process::exit(1);
+            };
+            info!("Setting the white to {}K...", kelvin);
+            for (target, capabilities, device) in devices {
+                set_temperature(device, target.id, &capabilities, kelvin);
+            }
+        }
+        Feature::ColorPicker | Feature::BrightnessSync => {
+            let fixed_hue =
+                matches!(mode, Feature::BrightnessSync).then_some((args.hue, args.saturation));
+
+            if fixed_hue.is_some() {
+                info!("Starting to follow the brightness of the screen...");
+            } else {
+                info!("Starting to see color on the screen...");
+            }
+
+            let health = Health::default();
+            let control = Control::default();
+            let mut bulbs = Vec::new();
+            let mut restore = Vec::new();
+
+            for (target, capabilities, device) in devices {
+                health.add_device(target.label());
+                control.add_device(target.label());
+                let mut monitor = FailureMonitor::new(target.label(), !args.no_notifications);
+
+                let Ok(device) = device else {
+                    error!("Failed to connect to {}.", target.label());
+                    health.send_finished(target.label(), false);
+                    monitor.connection_failed();
+                    continue;
+                };
+
+                let woke = args.wake_on_demand.then(|| {
+                    let woke = Arc::new(AtomicBool::new(false));
+                    restore.push((target.clone(), capabilities.clone(), woke.clone()));
+                    woke
+                });
+
+                let rules = Rules::new(config.rules.clone());
+                bulbs.push(Bulb::new(
+                    target,
+                    capabilities,
+                    device,
+                    monitor,
+                    rules,
+                    woke,
+                    config.connection,
+                ));
+            }
+
+            let bridge = args.mqtt_url.as_deref().map(|url| {
+                let broker = if url == mqtt::FROM_CONFIG {
+                    config.mqtt.clone().ok_or_else(|| {
+                        "--mqtt-url needs a URL or the broker in [mqtt].".to_string()
+                    })
+                } else {
+                    mqtt::parse_url(url)
+                };
+                let broker = broker.unwrap_or_else(|error| {
+                    error!("{}", error);
+                    std::process::exit(1);
+                });
+                let discovery = args.mqtt_discovery.clone().map(|prefix| Discovery {
+                    prefix,
+                    brightness_sync: (args.hue, args.saturation),
+                    json: matches!(args.mqtt_format, EmitFormat::Json),
+                });
+                let bridge =
+                    Bridge::start(&broker, &args.mqtt_topic, &args.mqtt_zone_topic, discovery);
+                (bridge, args.mqtt_format)
+            });
+
+            if bulbs.is_empty() && bridge.is_none() {
+                return;
+            }
+
+            let feed = Feed::default();
+            let api = args.listen.as_ref().map(|address| {
+                server::spawn(
+                    address,
+                    health.clone(),
+                    control.clone(),
+                    (args.hue, args.saturation),
+                    feed.clone(),
+                )
+                .unwrap_or_else(|error| {
+                    error!("{}", error);
+                    std::process::exit(1);
+                })
+            });
+
+            let ids: Vec<String> = bulbs.iter().map(|bulb| bulb.target.id.clone()).collect();
+            let options = PickerOptions {
+                profiles: args.auto_profile.then_some(config.profiles),
+                capture,
+                zone_overrides: config.zones,
+                default_profile: default_profile(&args, &config.update),
+                fixed_hue,
+                grade: config.grade,
+                screenshots: args
+                    .save_screenshots
+                    .then(|| screenshot_dir(&args.screenshot_dir))
+                    .flatten(),
+                shared_frame: args.shared_memory.as_ref().map(|path| {
+                    let path = path.clone().unwrap_or_else(shm::default_path);
+                    SharedFrame::create(&path).unwrap_or_else(|error| {
+                        error!("{}", error);
+                        std::process::exit(1);
+                    })
+                }),
+                coordinator: config.coordination.map(|coordination| {
+                    Coordinator::start(&coordination, ids).unwrap_or_else(|error| {
+                        error!("{}", error);
+                        std::process::exit(1);
+                    })
+                }),
+                gamepad: gamepad::listen(config.gamepad),
+                auto_gain: args.auto_gain.then(|| AutoGain::new(config.auto_gain)),
+                presence: config.presence.map(|presence| {
+                    let Some(mqtt) = &config.mqtt else {
+                        error!("[presence] needs the broker in [mqtt].");
+                        std::process::exit(1);
+                    };
+                    presence::watch(mqtt, presence)
+                }),
+                state,
+                transition_steps: args.transition_steps,
+                min_brightness: args.min_brightness,
+                debug_dumps: args
+                    .debug_dump_on_change
+                    .then(|| args.debug_dump_dir.clone()),
+                osd: args.osd.then(|| {
+                    osd::open(&args.osd_hotkey).unwrap_or_else(|error| {
+                        error!("{}", error);
+                        std::process::exit(1);
+                    })
+                }),
+                tray: args.tray.then(|| {
+                    tray::open((args.hue, args.saturation), fixed_hue).unwrap_or_else(|error| {
+                        error!("{}", error);
+                        std::process::exit(1);
+                    })
+                }),
+                bridge,
+                api,
+                feed,
+            };
+
+            let runtime = tokio::runtime::Runtime::new().unwrap_or_else(|error| {
+                error!("Failed to start the runtime: {}", error);
+                std::process::exit(1);
+            });
+            runtime.block_on(color_picker(
+                bulbs,
+                options,
+                health,
+                control,
+                Shutdown::install(),
+            ));
+            switch_back_off(restore, config.connection);
+            info!("Stopped.");
+        }
+        Feature::Slideshow => {
+            let Some(dir) = &args.slideshow_dir else {
+                error!("Slideshow mode needs --slideshow-dir.");
+                std::process::exit(1);
+            };
+
+            slideshow::run(
+                dir,
+                Duration::from_secs(args.slideshow_interval.max(1)),
+                connected(devices),
+                capture_options(&args, &config.capture),
+                config.grade,
+            );
+        }
+        Feature::Ambilight => {
+            let Some(tv) = &args.ambilight_tv else {
+                error!("Ambilight mode needs --ambilight-tv.");
+                std::process::exit(1);
+            };
+
+            if let Err(error) = ambilight::check(tv, args.ambilight_api) {
+                error!("{}", error);
+                std::process::exit(1);
+            }
+            ambilight::run(
+                tv,
+                args.ambilight_api,
+                default_profile(&args, &config.update).interval,
+                connected(devices),
+                capture_options(&args, &config.capture).color_space,
+                config.grade,
+            );
+        }
+        Feature::NowPlaying => now_playing::run(
+            connected(devices),
+            capture_options(&args, &config.capture),
+            config.grade,
+        ),
+        Feature::Pipe => {
+            let (target, capabilities, device) = first_device(devices);
+            info!("Reading colors from stdin...");
+            pipe(device, target.id, &capabilities);
+        }
+        Feature::LatencyTest => {
+            let (target, capabilities, device) = first_device(devices);
+            let Ok(device) = device else {
+                error!("Failed to connect to the device.");
+                std::process::exit(1);
+            };
+            info!("Measuring the latency from the screen to the bulb...");
+            if let Err(error) = latency::run(
+                args.latency_rounds.max(1),
+                capture_options(&args, &config.capture),
+                (target, capabilities, device),
+            ) {
+                error!("{}", error);
+                std::process::exit(1);
+            }
+        }
+        Feature::VerifyEncoding => {
+            let (target, capabilities, device) = first_device(devices);
+            info!("Checking which colour encoding the bulb expects...");
+            verify_encoding(device, target.id, &capabilities);
+        }
+        Feature::Hub => {
+            let (target, capabilities, device) = first_device(devices);
+            let code = args.pair_code.unwrap_or_else(pairing::new_code);
+            info!("Pairing code for agents: {}", code);
+
+            let colors = agent::listen(&args.hub_listen, code).unwrap_or_else(|error| {
+                error!("{}", error);
+                std::process::exit(1);
+            });
+            let _advertisement = args
+                .hub_listen
+                .parse::<SocketAddr>()
+                .map_err(|error| error.to_string())
+                .and_then(|address| pairing::advertise(address.port()))
+                .map_err(|error| warn!("Agents will need the hub's address: {}", error))
+                .ok();
+
+            hub(device, target.id, &capabilities, colors);
+        }
+    }
+}
+
+/// The devices to drive: the simulator, the ones given with `--id/--key/--ip` or the ones in the
+/// config, with `--protocol` applied.
+fn resolve_targets(args: &Args, config: &Config) -> Result<Vec<DeviceConfig>, String> {
+    select_targets(args, config, args.all_devices)
+}
+
+/// Like `resolve_targets`, with `all` in place of `--all-devices`.
+fn select_targets(args: &Args, config: &Config, all: bool) -> Result<Vec<DeviceConfig>, String> {
+    let mut targets = if args.simulate {
+        vec![DeviceConfig {
+            name: Some("simulator".to_string()),
+            id: "simulator".to_string(),
+            key: String::new(),
+            ip: None,
+            version: "3.3".to_string(),
+            product_key: None,
+            model: None,
+            owner: None,
+            zone: None,
+            soft_start_ms: None,
+            calibration: None,
+            quirks: Vec::new(),
+        }]
+    } else if !args.id.is_empty() {
+        if args.id.len() != args.key.len() || args.id.len() != args.ip.len() {
+            return Err("Every --id needs its own --key and --ip.".to_string());
+        }
+
+        args.id
+            .iter()
+            .zip(&args.key)
+            .zip(&args.ip)
+            .map(|((id, key), ip)| DeviceConfig {
+                name: None,
+                id: id.clone(),
+                key: key.clone(),
+                ip: Some(ip.clone()),
+                version: "3.3".to_string(),
+                product_key: None,
+                model: None,
+                owner: None,
+                zone: None,
+                soft_start_ms: None,
+                calibration: None,
+                quirks: Vec::new(),
+            })
+            .collect()
+    } else if !args.device.is_empty() {
+        args.device
+            .iter()
+            .map(|device| {
+                config
+                    .devices
+                    .iter()
+                    .find(|target| target.name.as_deref() == Some(device) || target.id == *device)
+                    .cloned()
+                    .ok_or_else(|| format!("No device \"{}\" in the config.", device))
+            })
+            .collect::<Result<_, _>>()?
+    } else if all {
+        config.devices.clone()
+    } else {
+        config.devices.first().cloned().into_iter().collect()
+    };
+
+    if targets.is_empty() {
+        return Err("No device given, pass --id/--key/--ip or add one to the config.".to_string());
+    }
+
+    if let Some(protocol) = &args.protocol {
+        for target in &mut targets {
+            target.version = protocol.clone();
+        }
+    }
+
+    Ok(targets)
+}
+
+type Target = (DeviceConfig, Capabilities, Result<Output, ErrorKind>);
+
+/// Picks the device for modes that only drive one.
+fn first_device(mut devices: Vec<Target>) -> Target {
+    if devices.len() > 1 {
+        warn!("Only the first device is used in this mode.");
+    }
+
+    devices.swap_remove(0)
+}
+
+/// The devices that connected, for modes that send to all of them without tracking each bulb.
+fn connected(devices: Vec<Target>) -> Vec<(DeviceConfig, Capabilities, Output)> {
+    devices
+        .into_iter()
+        .filter_map(|(target, capabilities, device)| match device {
+            Ok(device) => Some((target, capabilities, device)),
+            Err(_) => {
+                error!("Failed to connect to {}.", target.label());
+                None
+            }
+        })
+        .collect()
+}
+
+/// The update rate and threshold while no content profile is active: `--interval-ms` or
+/// `--fps` and `--threshold` over `[update]` in the config over the defaults.
+fn default_profile(args: &Args, update: &ProfileConfig) -> Profile {
+    let mut profile = DEFAULT_PROFILE.with(Some(update));
+    if let Some(interval_ms) = args.interval_ms {
+        profile.interval = Duration::from_millis(interval_ms);
+    } else if let Some(fps) = args.fps {
+        profile.interval = Duration::from_secs(1) / fps;
+    }
+    if let Some(threshold) = args.threshold {
+        profile.threshold = threshold.max(0.0);
+    }
+    profile
+}
+
+/// Takes each capture setting from its flag, then from `[capture]` in the config, then the default.
+fn capture_options(args: &Args, config: &CaptureConfig) -> CaptureOptions {
+    CaptureOptions {
+        sources: match (&args.sources, &config.sources) {
+            (sources, _) if !sources.is_empty() => sources.clone(),
+            (_, Some(sources)) if !sources.is_empty() => sources.clone(),
+            _ => vec![Source::Native],
+        },
+        display: args.display.or(config.display).unwrap_or_default(),
+        region: args.region.or(config.region),
+        sampling: args.sampling.or(config.sampling).unwrap_or(Sampling::Full),
+        algorithm: args
+            .algorithm
+            .or(config.algorithm)
+            .unwrap_or(Algorithm::Palette),
+        decimation: args.decimation.or(config.decimation).unwrap_or(1).max(1) as usize,
+        clusters: args.clusters.or(config.clusters).unwrap_or(5).clamp(1, 16) as usize,
+        color_space: args.color_space.or(config.color_space).unwrap_or_default(),
+        refresh_rate: args.refresh_rate.or(config.refresh_rate).unwrap_or(60.0),
+        alpha: args.alpha.or(config.alpha).unwrap_or_default(),
+        cursor: args.cursor.or(config.cursor).unwrap_or_default(),
+        warm_up_frames: args.warm_up_frames.or(config.warm_up_frames).unwrap_or(3),
+        frames_per_update: args
+            .frames_per_update
+            .or(config.frames_per_update)
+            .unwrap_or(1)
+            .max(1),
+        min_confidence: args.min_confidence.or(config.min_confidence).unwrap_or(0.0),
+        smoothing: args
+            .smoothing
+            .or(config.smoothing)
+            .unwrap_or(1.0)
+            .clamp(0.01, 1.0),
+        zones: args.zones.or(config.zones),
+        zone_fallback: args.zone_fallback || config.zone_fallback.unwrap_or(false),
+        letterbox: !args.no_letterbox && config.letterbox.unwrap_or(true),
+    }
+}
+
+fn run_command(command: Command, args: &Args) {
+    let config_path = args.config.clone();
+    let result = match command {
+        Command::Devices {
+            command: DevicesCommand::Import { snapshot },
+        } => {
+            let mut imported = 0;
+            config::edit(config_path, |document| {
+                imported = devices::import(&snapshot, document)?;
+                Ok(())
+            })
+            .map(|path| info!("Imported {} device(s) into {}.", imported, path.display()))
+        }
+        Command::Devices {
+            command: DevicesCommand::List,
+        } => config::load(config_path).map(|config| devices::list(&config.devices)),
+        Command::Devices {
+            command: DevicesCommand::Add { id, fields },
+        } => config::edit(config_path, |document| devices::add(&id, &fields, document))
+            .map(|path| info!("Added {} to {}.", id, path.display())),
+        Command::Devices {
+            command: DevicesCommand::Edit { device, fields },
+        } => config::edit(config_path, |document| {
+            devices::edit(&device, &fields, document)
+        })
+        .map(|path| info!("Changed {} in {}.", device, path.display())),
+        Command::Devices {
+            command: DevicesCommand::Remove { device },
+        } => config::edit(config_path, |document| devices::remove(&device, document))
+            .map(|path| info!("Removed {} from {}.", device, path.display())),
+        Command::Config {
+            command: ConfigCommand::Rollback,
+        } => config::rollback(config_path)
+            .map(|backup| info!("Restored the config from {}.", backup.display())),
+        Command::IsHealthy { address } => match server::probe(&address) {
+            Ok((healthy, body)) => {
+                println!("{}", body);
+                std::process::exit(if healthy { 0 } else { 1 });
+            }
+            Err(error) => Err(error),
+        },
+        Command::Bench { iterations } => config::load(config_path)
+            .and_then(|config| bench::run(iterations, capture_options(args, &config.capture))),
+        Command::Stats { days } => usage::report(days),
+        Command::Status { json } => config::load(config_path).and_then(|config| {
+            resolve_targets(args, &config).and_then(|targets| status::run(targets, &config, json))
+        }),
+        Command::ExportZones => config::load(config_path).and_then(|config| {
+            let layout = capture_options(args, &config.capture)
+                .zones
+                .ok_or("No zones to export, pass --zones or set `zones` in [capture].")?;
+            let leds = serde_json::to_string_pretty(&zones::hyperion_leds(layout))
+                .map_err(|error| error.to_string())?;
+            println!("{}", leds);
+            Ok(())
+        }),
+        Command::Discover { duration } => {
+            discovery::run(Duration::from_secs(duration)).and_then(|seen| {
+                let config = config::load(config_path.clone())?;
+                if !devices::moved(&config.devices, &seen) {
+                    return Ok(());
+                }
+                let mut moved = Vec::new();
+                config::edit(config_path, |document| {
+                    moved = devices::track(&seen, document)?;
+                    Ok(())
+                })
+                .map(|path| info!("Updated {} in {}.", moved.join(", "), path.display()))
+            })
+        }
+        Command::Setup => setup::run(config_path),
+        Command::Snapshot { command } => config::load(config_path).and_then(|config| {
+            let targets = select_targets(args, &config, true)?;
+            match command {
+                SnapshotCommand::Save { name } => snapshot::save(&name, targets, &config),
+                SnapshotCommand::Recall { name } => snapshot::recall(&name, targets, &config),
+            }
+        }),
+        Command::Convert { color, raw } => config::load(config_path).and_then(|config| {
+            let targets = resolve_targets(args, &config).unwrap_or_default();
+            convert::run(&color, targets.first(), &config, raw, args.saturation_boost)
+        }),
+        Command::Zones {
+            duration,
+            bulbs,
+            yes,
+        } => config::load(config_path.clone()).and_then(|config| {
+            zones::propose(
+                capture_options(args, &config.capture),
+                bulbs.unwrap_or(config.devices.len()),
+                Duration::from_secs(duration),
+                config_path,
+                yes,
+            )
+        }),
+    };
+
+    if let Err(error) = result {
+        error!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+fn switch_led(
+    device: Result<Output, ErrorKind>,
+    device_id: String,
+    capabilities: &Capabilities,
+    mode: bool,
+) {
+    if let Ok(device) = device {
+        let payload = create_switch_led_payload(device_id.clone(), capabilities, mode);
+        let _ = device.set(payload, 0);
+    } else {
+        error!("Failed to connect to the device.");
+    }
+}
+
+fn set_brightness(
+    device: Result<Output, ErrorKind>,
+    device_id: String,
+    capabilities: &Capabilities,
+    percent: u32,
+) {
+    if let Ok(device) = device {
+        let mut dps = HashMap::new();
+        if capabilities.has_quirk(Quirk::NeedsModeResend) {
+            dps.insert(capabilities.mode_dp.to_string(), json!("white"));
+        }
+        dps.insert(
+            capabilities.brightness_dp.to_string(),
+            json!(capabilities.encode_brightness(percent)),
+        );
+        let _ = device.set(create_dps_payload(device_id, dps), 0);
+    } else {
+        error!("Failed to connect to the device.");
+    }
+}
+
+/// Switches to white and sets its color temperature.
+fn set_temperature(
+    device: Result<Output, ErrorKind>,
+    device_id: String,
+    capabilities: &Capabilities,
+    kelvin: u32,
+) {
+    if let Ok(device) = device {
+        let mut dps = HashMap::new();
+        dps.insert(capabilities.mode_dp.to_string(), json!("white"));
+        dps.insert(
+            capabilities.temperature_dp.to_string(),
+            json!(capabilities.encode_temperature(kelvin)),
+        );
+        let _ = device.set(create_dps_payload(device_id, dps), 0);
+    } else {
+        error!("Failed to connect to the device.");
+    }
+}
+
+fn show_color(
+    device: Result<Output, ErrorKind>,
+    device_id: String,
+    capabilities: &Capabilities,
+    color: &Rgb,
+) {
+    if let Ok(device) = device {
+        let payload = create_color_payload(device_id, capabilities, rgb2hsv(color));
+        let _ = device.set(payload, 0);
+    } else {
+        error!("Failed to connect to the device.");
+    }
+}
+
+fn color_mode(
+    device: Result<Output, ErrorKind>,
+    device_id: String,
+    capabilities: &Capabilities,
+    mode: String,
+) {
+    if let Ok(device) = device {
+        let payload = create_color_mode_payload(device_id.clone(), capabilities, mode);
+        let _ = device.set(payload, 0);
+    } else {
+        error!("Failed to connect to the device.");
+    }
+}
+
+/// Runs the color loop until `shutdown` is requested, between two updates.
+async fn color_picker(
+    mut bulbs: Vec<Bulb>,
+    options: PickerOptions,
+    health: Health,
+    control: Control,
+    shutdown: Shutdown,
+) {
+    let PickerOptions {
+        profiles,
+        capture,
+        zone_overrides,
+        default_profile,
+        mut fixed_hue,
+        grade,
+        screenshots,
+        shared_frame,
+        coordinator,
+        gamepad,
+        osd,
+        tray,
+        mut auto_gain,
+        presence,
+        state,
+        transition_steps,
+        min_brightness,
+        debug_dumps,
+        bridge,
+        api,
+        feed,
+    } = options;
+    let mut classifier = ContentClassifier::new();
+    let mut content = None;
+    let mut profile_forced = false;
+    let mut flash_until: Option<Instant> = None;
+    let mut forced: Option<Rgb> = None;
+    let mut profile = default_profile;
+    let mut black_since: Option<Instant> = None;
+    let min_interval = bulbs
+        .iter()
+        .map(|bulb| bulb.capabilities.min_interval)
+        .max()
+        .unwrap_or_default();
+
+    // Zones named on the devices pick edges when no layout is given.
+    let mapped = bulbs.iter().any(|bulb| bulb.target.zone.is_some());
+    let mut capture = capture;
+    capture.zones = capture
+        .zones
+        .or_else(|| mapped.then_some(ZoneLayout::Edges));
+    // Set up whatever the mode, so switching to color-picker later finds the bulbs' zones.
+    if let Some(layout) = capture.zones {
+        if fixed_hue.is_some() {
+            info!(
+                "Zones are ignored by brightness-sync, they apply once the mode is color-picker."
+            );
+        }
+        for (index, bulb) in bulbs.iter_mut().enumerate() {
+            bulb.zone = match &bulb.target.zone {
+                Some(name) => {
+                    let zone = layout.index(name);
+                    if zone.is_none() {
+                        warn!(
+                            "{} has zone \"{}\", which --zones {} doesn't have.",
+                            bulb.label(),
+                            name,
+                            layout
+                        );
+                    }
+                    zone
+                }
+                None if mapped => None,
+                None => Some(index).filter(|&index| index < layout.len()),
+            };
+            match bulb.zone {
+                Some(zone) => info!("{} follows the {} zone.", bulb.label(), layout.name(zone)),
+                None => info!("{} follows the whole frame.", bulb.label()),
+            }
+        }
+    }
+    let zone_overrides = match capture.zones {
+        Some(layout) => {
+            let (overrides, unknown) = layout.overrides(&zone_overrides);
+            for name in unknown {
+                warn!("[zones.\"{}\"] isn't a zone of --zones {}.", name, layout);
+            }
+            overrides
+        }
+        None => {
+            if !zone_overrides.is_empty() {
+                warn!("[zones.<name>] is ignored without zones.");
+            }
+            Vec::new()
+        }
+    };
+    // The weight of each new color: the frame's first, then those of its zones.
+    let smoothing: Vec<f32> = iter::once(capture.smoothing)
+        .chain(zone_overrides.iter().map(|zone| {
+            zone.smoothing
+                .map_or(capture.smoothing, |smoothing| smoothing.clamp(0.01, 1.0))
+        }))
+        .collect();
+    let zone_thresholds: Vec<Option<f32>> = zone_overrides
+        .iter()
+        .map(|zone| zone.threshold.map(|threshold| threshold.max(0.0)))
+        .collect();
+
+    if let Some(state) = &state {
+        for label in &state.paused {
+            control.set_enabled(label, false);
+        }
+        if let Some(forced) = state.profile {
+            profile_forced = true;
+            content = Some(forced);
+            profile = forced.profile(profiles.as_ref().and_then(|p| p.get(&forced)));
+            info!("Using profile {:?} from the previous run.", profile);
+        }
+        if let Some([h, s, l]) = state.color {
+            for bulb in bulbs.iter_mut() {
+                bulb.restore_color(Hsl::from(h, s, l));
+            }
+        }
+    }
+    let (interval, interval_receiver) =
+        watch::channel(profile.interval.max(min_interval) / capture.frames_per_update);
+    let (capture_task, mut captured) = pipeline::capture(
+        pipeline::Capture {
+            options: capture.clone(),
+            screenshots,
+            shared_frame,
+            keep_pixels: debug_dumps.is_some(),
+        },
+        interval_receiver,
+        health.clone(),
+        shutdown,
+    );
+    let (updates, frame_receiver) = watch::channel(None);
+    let shared = pipeline::Shared {
+        health,
+        control: control.clone(),
+        coordinator,
+        feed: feed.clone(),
+    };
+    let senders: Vec<Sender> = bulbs
+        .into_iter()
+        .map(|bulb| Sender::spawn(bulb, frame_receiver.clone(), shared.clone()))
+        .collect();
+
+    // Like the state, usage isn't kept for the simulator.
+    let mut usage = state.is_some().then(Usage::load);
+    let mut recorder = state.map(Recorder::new);
+    let mut last_frame = Instant::now();
+    let mut colors = Vec::new();
+    let mut frames = 0;
+    let mut held: Option<Vec<Hsl>> = None;
+    let mut smoothed: Option<Vec<Hsl>> = None;
+    let mut last_sent: Option<Hsl> = None;
+    let mut zones_taken_over = Vec::new();
+
+    while let Some(pipeline::Captured {
+        color: dominant_color,
+        stats,
+        zones: zone_colors,
+        pixels,
+    }) = captured.recv().await
+    {
+        // The frame's color first, then those of its zones.
+        let sample: Vec<Hsl> = match fixed_hue {
+            Some((hue, saturation)) => vec![Hsl::from(
+                hue as f32,
+                saturation as f32,
+                stats.mean_luminance * 100.0,
+            )],
+            None => iter::once(&dominant_color)
+                .chain(&zone_colors)
+                .map(|color| grade.apply(&color.to_rgb()).to_hsl())
+                .collect(),
+        };
+        // Brightness-sync follows the mean luminance, which doesn't depend on the dominant color.
+        if fixed_hue.is_some() || stats.dominant_share >= capture.min_confidence {
+            colors.push(sample.clone());
+        } else {
+            debug!(
+                "Not confident in the color, only {:.0}% of the frame is close to it.",
+                stats.dominant_share * 100.0
+            );
+        }
+
+        frames += 1;
+        if frames < capture.frames_per_update {
+            continue;
+        }
+        frames = 0;
+
+        let combined = match held.take() {
+            Some(held) if colors.is_empty() => held,
+            _ if colors.is_empty() => sample,
+            _ => (0..sample.len())
+                .map(|index| {
+                    let channel: Vec<Hsl> = colors.iter().map(|colors| colors[index]).collect();
+                    median_color(&channel)
+                })
+                .collect(),
+        };
+        held = Some(combined.clone());
+        colors.clear();
+        let combined = match smoothed.take() {
+            Some(previous) if smoothing.iter().any(|&alpha| alpha < 1.0) => {
+                smooth(&previous, combined, &smoothing, capture.color_space)
+            }
+            _ => combined,
+        };
+        let dominant_color = combined[0];
+        smoothed = Some(combined.clone());
+
+        for action in gamepad.iter().flat_map(|actions| actions.try_iter()) {
+            match action {
+                GamepadAction::TogglePause => {
+                    for sender in &senders {
+                        let enable = !control.is_enabled(sender.label());
+                        control.set_enabled(sender.label(), enable);
+                    }
+                }
+                GamepadAction::Flash(color) => {
+                    info!("Flashing {}.", describe_color(&color.0));
+                    for sender in &senders {
+                        sender.flash(color.0);
+                    }
+                    flash_until = Some(Instant::now() + FLASH);
+                }
+                GamepadAction::Profile(forced) => {
+                    profile_forced = true;
+                    content = Some(forced);
+                    profile = forced.profile(profiles.as_ref().and_then(|p| p.get(&forced)));
+                    info!("Using profile {:?}.", profile);
+                }
+                GamepadAction::AutoProfile => {
+                    profile_forced = false;
+                    content = None;
+                    profile = default_profile;
+                    info!("Back to detecting the profile.");
+                }
+            }
+        }
+
+        let mut quit = false;
+        let switched = bridge.iter().flat_map(|(bridge, _)| bridge.actions());
+        for action in tray
+            .iter()
+            .chain(&api)
+            .flat_map(|actions| actions.try_iter())
+            .chain(switched)
+        {
+            match action {
+                TrayAction::Pause(paused) => {
+                    for sender in &senders {
+                        control.set_enabled(sender.label(), !paused);
+                    }
+                }
+                TrayAction::Mode(mode) => {
+                    fixed_hue = mode;
+                    // Colors of the other mode don't carry over.
+                    held = None;
+                    smoothed = None;
+                    if fixed_hue.is_some() {
+                        info!("Following the brightness of the screen.");
+                    } else {
+                        info!("Following the colors of the screen.");
+                    }
+                }
+                TrayAction::Color(color) => {
+                    match color {
+                        Some(color) => {
+                            info!("Showing {} instead of the screen.", describe_color(&color));
+                            for sender in &senders {
+                                sender.flash(color);
+                            }
+                        }
+                        None => info!("Back to the colors of the screen."),
+                    }
+                    forced = color;
+                }
+                TrayAction::Quit => quit = true,
+            }
+        }
+        if quit {
+            break;
+        }
 
-extern crate pretty_env_logger;
+        if flash_until.is_some_and(|until| Instant::now() >= until) {
+            flash_until = None;
+        }
+
+        if let Some(profiles) = profiles.as_ref().filter(|_| !profile_forced) {
+            let classified = classifier.update(&stats);
+            if classified != content {
+                content = classified;
+                profile = content.map_or(default_profile, |content| {
+                    content.profile(profiles.get(&content))
+                });
+                info!("Using profile {:?}.", profile);
+            }
+        }
+
+        if stats.black_ratio >= BLACK_RATIO {
+            black_since.get_or_insert_with(Instant::now);
+        } else {
+            black_since = None;
+        }
+
+        let gain = auto_gain
+            .as_mut()
+            .map_or(1.0, |auto_gain| auto_gain.update(stats.mean_luminance));
+
+        let mut zone_colors = smoothed
+            .as_ref()
+            .map_or(Vec::new(), |colors| colors[1..].to_vec());
+        if let Some(layout) = capture.zones.filter(|_| capture.zone_fallback) {
+            let owners: Vec<(usize, bool)> = senders
+                .iter()
+                .filter_map(|sender| sender.zone().map(|zone| (zone, sender.status().online)))
+                .collect();
+            let (shown, taken_over) =
+                zones::fall_back(layout, &zone_colors, &owners, capture.color_space);
+            if taken_over != zones_taken_over {
+                if taken_over.is_empty() {
+                    info!("Every zone is back on its own bulb.");
+                }
+                for &(orphan, heir) in &taken_over {
+                    let heir = senders.iter().find(|sender| sender.zone() == Some(heir));
+                    if let Some(heir) = heir {
+                        info!(
+                            "{} also shows the {} zone while its bulb is offline.",
+                            heir.label(),
+                            layout.name(orphan)
+                        );
+                    }
+                }
+                zones_taken_over = taken_over;
+            }
+            zone_colors = shown;
+        }
+
+        let frame = bulb::Frame {
+            color: dominant_color,
+            zones: zone_colors,
+            gain,
+            stats: stats.clone(),
+            content,
+            threshold: profile.threshold,
+            zone_thresholds: zone_thresholds.clone(),
+            screen_black_for: black_since.map_or(Duration::ZERO, |since| since.elapsed()),
+            fixed_hue,
+            flashing: flash_until.is_some() || forced.is_some(),
+            nobody_home: presence
+                .as_ref()
+                .is_some_and(|presence| !presence.anyone_home()),
+            transition: (transition_steps > 0).then_some(bulb::Transition {
+                steps: transition_steps,
+                duration: profile.interval.max(min_interval),
+            }),
+            color_space: capture.color_space,
+            min_brightness,
+        };
+
+        let mode_name = if fixed_hue.is_some() {
+            "brightness-sync"
+        } else {
+            "color-picker"
+        };
+        let profile_name = content.map_or("default".to_string(), |content| content.to_string());
+        if let Some(osd) = &osd {
+            osd.show(osd::Status {
+                mode: mode_name,
+                profile: profile_name.clone(),
+                color: rgb_bytes(&dominant_color.to_rgb()),
+            });
+        }
+        control.set_status(LoopStatus {
+            mode: mode_name.to_string(),
+            profile: profile_name,
+            color: Some(dominant_color.to_rgb().to_css_hex_string()),
+            forced: forced.map(|color| color.to_css_hex_string()),
+        });
+        feed.publish(|| {
+            let mut message = emitted_color(&dominant_color, &stats);
+            message["type"] = json!("color");
+            message["zones"] = json!(frame
+                .zones
+                .iter()
+                .map(|color| color.to_rgb().to_css_hex_string())
+                .collect::<Vec<_>>());
+            message["forced"] = json!(forced.map(|color| color.to_css_hex_string()));
+            message
+        });
+
+        if let Some((bridge, format)) = &bridge {
+            let paused = !senders.is_empty()
+                && senders
+                    .iter()
+                    .all(|sender| !control.is_enabled(sender.label()));
+            bridge.report(paused, fixed_hue.is_some());
+            bridge.publish(format_emitted_color(&dominant_color, &stats, *format));
+            if let (Some(layout), Some(colors)) = (capture.zones, &smoothed) {
+                for (zone, color) in colors[1..].iter().enumerate() {
+                    bridge.publish_zone(
+                        &layout.name(zone),
+                        format_emitted_color(color, &stats, *format),
+                    );
+                }
+            }
+        }
+
+        updates.send_replace(Some(Arc::new(frame)));
+
+        let previous = last_sent.replace(dominant_color);
+        if let (Some(dir), Some(previous), Some((rgba, width, height))) =
+            (&debug_dumps, previous, &pixels)
+        {
+            if color_diff(&previous, &dominant_color) >= DUMP_CHANGE {
+                let update = dump::Update {
+                    frame: (rgba, *width, *height),
+                    stats: &stats,
+                    previous: previous.to_rgb(),
+                    color: dominant_color.to_rgb(),
+                };
+                let payloads: Vec<(String, Payload)> = senders
+                    .iter()
+                    .filter_map(|sender| {
+                        let payload = sender.status().last_payload?;
+                        Some((sender.label().to_string(), payload))
+                    })
+                    .collect();
+                match dump::write(dir, &update, &capture, &payloads) {
+                    Ok(folder) => info!("Color changed a lot, dumped to {}.", folder.display()),
+                    Err(error) => warn!("{}", error),
+                }
+            }
+        }
+
+        let elapsed = last_frame.elapsed();
+        last_frame = Instant::now();
+        if let Some(usage) = &mut usage {
+            for sender in &senders {
+                usage.record(sender.label(), sender.status().brightness, elapsed);
+            }
+        }
+
+        if let Some(recorder) = &mut recorder {
+            recorder.record(State {
+                mode: Some(if fixed_hue.is_some() {
+                    Feature::BrightnessSync
+                } else {
+                    Feature::ColorPicker
+                }),
+                profile: content.filter(|_| profile_forced),
+                paused: control.paused(),
+                color: Some([
+                    dominant_color.get_hue(),
+                    dominant_color.get_saturation(),
+                    dominant_color.get_lightness(),
+                ]),
+            });
+        }
+
+        // Frames of one update are spread evenly over its interval.
+        interval.send_replace(profile.interval.max(min_interval) / capture.frames_per_update);
+    }
+
+    // The senders finish the update in progress, the capture its frame.
+    drop(captured);
+    drop(updates);
+    for sender in senders {
+        sender.stopped().await;
+    }
+    if let Err(error) = capture_task.await {
+        error!("Capturing failed: {}", error);
+    }
 
-#[derive(Eq, PartialEq, Hash)]
-enum DataPointsKey {
-    SwitchLed = 20,
-    ColorMode = 21,
-    Color = 24,
+    if let Some(usage) = &mut usage {
+        if let Err(error) = usage.save() {
+            warn!("{}", error);
+        }
+    }
 }
 
-impl DataPointsKey {
-    fn get(&self) -> String {
-        match self {
-            DataPointsKey::SwitchLed => "20".to_string(),
-            DataPointsKey::ColorMode => "21".to_string(),
-            DataPointsKey::Color => "24".to_string(),
+/// Writes the color of a frame to stdout every `interval` until stdout is closed.
+fn emit_colors(format: EmitFormat, capture: CaptureOptions, interval: Duration) {
+    let mut grabber = Grabber::new(&capture).unwrap_or_else(|error| {
+        error!("{}", error);
+        std::process::exit(1);
+    });
+    let mut pacer = FramePacer::new(capture.refresh_rate);
+    let mut stdout = io::stdout();
+    let mut last_stats: Option<FrameStats> = None;
+
+    loop {
+        let Some((dominant_color, stats, _)) = generate_screenshot_and_get_dominant_color(
+            &mut grabber,
+            None,
+            None,
+            &capture,
+            last_stats.as_ref(),
+        ) else {
+            pacer.wait(interval);
+            continue;
+        };
+        let line = format_emitted_color(&dominant_color, &stats, format);
+        last_stats = Some(stats);
+
+        if writeln!(stdout, "{}", line)
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            info!("Stdout closed, stopping.");
+            break;
         }
+
+        pacer.wait(interval);
     }
 }
 
-#[derive(clap::ValueEnum, Debug, Clone, Default, Serialize)]
-#[serde(rename_all = "kebab-case")]
-enum Feature {
-    #[default]
-    SwitchLedOn,
-    SwitchLedOff,
-    ColorPicker,
-    WhiteMode,
-    ColorMode
+fn format_emitted_color(hsl: &Hsl, stats: &FrameStats, format: EmitFormat) -> String {
+    match format {
+        EmitFormat::Hex => hsl.to_rgb().to_css_hex_string(),
+        EmitFormat::Json => emitted_color(hsl, stats).to_string(),
+    }
 }
 
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// Name of the person to greet
-    #[arg(long)]
-    id: String,
+/// A color as `--emit-colors json` writes it.
+fn emitted_color(hsl: &Hsl, stats: &FrameStats) -> Value {
+    let rgb = hsl.to_rgb();
 
-    #[arg(long)]
-    key: String,
+    json!({
+        "hex": rgb.to_css_hex_string(),
+        "rgb": [
+            rgb.get_red().round() as u8,
+            rgb.get_green().round() as u8,
+            rgb.get_blue().round() as u8,
+        ],
+        "hsl": [hsl.get_hue(), hsl.get_saturation(), hsl.get_lightness()],
+        "stats": stats,
+        "t": unix_millis(),
+    })
+}
 
-    #[arg(long)]
-    ip: String,
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
 
-    #[arg(long, default_value_t = false)]
-    debug: bool,
+fn pipe(device: Result<Output, ErrorKind>, device_id: String, capabilities: &Capabilities) {
+    if let Ok(device) = device {
+        let started = Instant::now();
+        let mut last_sent: Option<Instant> = None;
 
-    #[arg(long)]
-    mode: Feature,
-}
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => {
+                    error!("Failed to read from stdin: {}", error);
+                    break;
+                }
+            };
 
-fn main() {
-    let args = Args::parse();
+            if line.trim().is_empty() {
+                continue;
+            }
 
-    if args.debug {
-        env::set_var("RUST_LOG", "none,tuya_bulb_screen_color=debug");
+            let Some((offset, color)) = parse_pipe_line(&line) else {
+                warn!("Ignoring malformed line: {}", line);
+                continue;
+            };
+
+            if let Some(offset) = offset {
+                let target = started + offset;
+                let now = Instant::now();
+                if target > now {
+                    thread::sleep(target - now);
+                }
+            }
+
+            if let Some(wait) = device.quiet_interval().and_then(|interval| {
+                last_sent.and_then(|sent| (sent + interval).checked_duration_since(Instant::now()))
+            }) {
+                debug!("Quiet hours, holding the color back for {:?}.", wait);
+                thread::sleep(wait);
+            }
+
+            debug!("Sending color from stdin: {:?}", color);
+
+            let payload = create_color_payload(device_id.clone(), capabilities, rgb2hsv(&color));
+            let _ = device.set(payload, 0);
+            last_sent = Some(Instant::now());
+        }
     } else {
-        env::set_var("RUST_LOG", "none,tuya_bulb_screen_color=info");
+        error!("Failed to connect to the device.");
     }
+}
 
-    pretty_env_logger::init();
+/// Sends the colors streamed by agents, skipping ahead to the latest one whenever they arrive
+/// faster than the bulb takes them.
+fn hub(
+    device: Result<Output, ErrorKind>,
+    device_id: String,
+    capabilities: &Capabilities,
+    colors: latest::Receiver<Rgb>,
+) {
+    let Ok(device) = device else {
+        error!("Failed to connect to the device.");
+        return;
+    };
 
-    let device = connect(args.key, args.ip);
+    while let Some(color) = colors.recv() {
+        debug!("Sending color from an agent: {:?}", color);
 
-    match args.mode {
-        Feature::SwitchLedOn => {
-            info!("Turning on the LED...");
-            switch_led(device, args.id.clone(), true);
-        }
-        Feature::SwitchLedOff => {
-            info!("Turning off the LED...");
-            switch_led(device, args.id.clone(), false);
-        }
-        Feature::ColorPicker => {
-            info!("Starting to see color on the screen...");
-            color_picker(device, args.id.clone());
-        }
-        Feature::ColorMode => {
-            info!("Changing mode to color");
-            color_mode(device, args.id.clone(), "colour".to_string());
-        },
-        Feature::WhiteMode => {
-            info!("Changing mode to white");
-            color_mode(device, args.id.clone(), "white".to_string());
+        let payload = create_color_payload(device_id.clone(), capabilities, rgb2hsv(&color));
+        send(&device, payload);
+        thread::sleep(
+            capabilities
+                .min_interval
+                .max(device.quiet_interval().unwrap_or_default()),
+        );
+    }
+}
+
+/// Parses a `[seconds] #rrggbb` line, where the optional timestamp is relative to the start of the stream.
+fn parse_pipe_line(line: &str) -> Option<(Option<Duration>, Rgb)> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+
+    match parts.as_slice() {
+        [hex] => Some((None, Rgb::from_hex_str(hex).ok()?)),
+        [timestamp, hex] => {
+            let seconds = timestamp.parse::<f64>().ok().filter(|s| *s >= 0.0)?;
+            Some((
+                Some(Duration::from_secs_f64(seconds)),
+                Rgb::from_hex_str(hex).ok()?,
+            ))
         }
+        _ => None,
     }
 }
 
-fn switch_led(device: Result<TuyaDevice, ErrorKind>, device_id: String, mode: bool) {
-    if let Ok(device) = device {
-        let payload = create_switch_led_payload(device_id.clone(), mode);
-        let _ = device.set(payload, 0);
-    } else {
-        error!("Failed to connect to the device.");
+fn send(device: &Output, payload: Payload) -> bool {
+    match device.set(payload, 0) {
+        Ok(()) => true,
+        Err(error) => {
+            error!("Failed to send payload: {}", error);
+            false
+        }
     }
 }
 
-fn color_mode(device: Result<TuyaDevice, ErrorKind>, device_id: String, mode: String) {
-    if let Ok(device) = device {
-        let payload = create_color_mode_payload(device_id.clone(), mode);
-        let _ = device.set(payload, 0);
-    } else {
+/// Reads whether the bulb is switched on, `None` when it can't be told.
+fn query_power(device: &Output, id: &str, capabilities: &Capabilities) -> Option<bool> {
+    query_dps(device, id)?
+        .get(&capabilities.switch_dp.to_string())?
+        .as_bool()
+}
+
+/// Reads the current data points of the device, `None` when they can't be read.
+fn query_dps(device: &Output, id: &str) -> Option<BTreeMap<String, Value>> {
+    let current_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32;
+
+    let payload = Payload::Struct(PayloadStruct {
+        dev_id: id.to_string(),
+        gw_id: Some(id.to_string()),
+        uid: Some(id.to_string()),
+        t: Some(current_time),
+        dp_id: None,
+        dps: Some(HashMap::new()),
+    });
+
+    let replies = match device.get(payload, 0) {
+        Ok(replies) => replies,
+        Err(error) => {
+            warn!("Failed to query the device state: {}", error);
+            return None;
+        }
+    };
+
+    replies.into_iter().find_map(|reply| match reply.payload {
+        Payload::Struct(payload) => payload.dps.map(|dps| dps.into_iter().collect()),
+        Payload::String(payload) => parse_dps(payload.as_bytes()).ok(),
+    })
+}
+
+/// Sends every test vector to the bulb, reads back what it reports and prints which colour
+/// mapping its firmware agrees with.
+fn verify_encoding(
+    device: Result<Output, ErrorKind>,
+    device_id: String,
+    capabilities: &Capabilities,
+) {
+    let Ok(device) = device else {
         error!("Failed to connect to the device.");
+        return;
+    };
+
+    let mode_key = capabilities.mode_dp.to_string();
+    let colour_key = capabilities.colour_dp.to_string();
+    let original = query_dps(&device, &device_id);
+    let mut agreeing = Mapping::ALL.to_vec();
+
+    println!("{:<15} {:<16} {:<16} matches", "hsv", "sent", "reported");
+
+    for vector in VECTORS {
+        let sent = capabilities.encode_colour(vector.hsv);
+        if !send(
+            &device,
+            create_color_payload(device_id.clone(), capabilities, vector.hsv),
+        ) {
+            return;
+        }
+        thread::sleep(VERIFY_SETTLE);
+
+        let reported = query_dps(&device, &device_id)
+            .and_then(|dps| dps.get(&colour_key)?.as_str().map(str::to_string))
+            .unwrap_or_default();
+        let matches = identify(vector.hsv, &reported);
+        agreeing.retain(|mapping| matches.contains(mapping));
+
+        let (h, s, v) = vector.hsv;
+        let matches: Vec<String> = matches.iter().map(Mapping::to_string).collect();
+        println!(
+            "{:<15} {:<16} {:<16} {}",
+            format!("{},{},{}", h, s, v),
+            sent,
+            reported,
+            if matches.is_empty() {
+                "none".to_string()
+            } else {
+                matches.join(", ")
+            }
+        );
+    }
+
+    match agreeing.as_slice() {
+        [] => println!(
+            "The bulb doesn't agree with any known mapping, please report the table above."
+        ),
+        mappings => {
+            let mappings: Vec<String> = mappings.iter().map(Mapping::to_string).collect();
+            println!("The bulb reports colours as {}.", mappings.join(" or "));
+        }
+    }
+
+    if let Some(original) = original {
+        let dps: HashMap<String, Value> = original
+            .into_iter()
+            .filter(|(key, _)| *key == mode_key || *key == colour_key)
+            .collect();
+        if !dps.is_empty() {
+            send(&device, create_dps_payload(device_id, dps));
+        }
     }
 }
 
-fn color_picker(device: Result<TuyaDevice, ErrorKind>, device_id: String) {
-    let mut last_color = Hsl::from(0.0, 0.0, 0.0);
+/// Switches the bulbs back off that `--wake-on-demand` powered on, once the color loop stopped.
+fn switch_back_off(
+    bulbs: Vec<(DeviceConfig, Capabilities, Arc<AtomicBool>)>,
+    connection: ConnectionConfig,
+) {
+    for (target, capabilities, woke) in &bulbs {
+        if !woke.load(Ordering::SeqCst) {
+            continue;
+        }
 
-    if let Ok(device) = device {
-        loop {
-            let dominant_color = generate_screenshot_and_get_dominant_color(false);
-            let payload = create_color_picker_payload(device_id.clone(), dominant_color);
-            let threshold = 10.0;
+        info!("Switching {} back off.", target.label());
+        let device = target.ip.clone().and_then(|ip| {
+            connect(target.key.clone(), ip, target.version.clone(), connection).ok()
+        });
+        if let Some(device) = device {
+            send(
+                &device,
+                create_switch_led_payload(target.id.clone(), capabilities, false),
+            );
+        }
+    }
+}
 
-            let diff = color_diff(&last_color, &dominant_color);
+/// Where updates go: a bulb, or the simulator window with `--simulate`.
+enum Output {
+    Device(TuyaDevice),
+    /// Protocol 3.4 and 3.5, see `session`.
+    Session(SessionDevice),
+    #[cfg(feature = "simulate")]
+    Simulator(simulator::Simulator),
+    /// Any of the above during `[quiet-hours]`.
+    Quiet(Box<Output>, quiet::QuietHours),
+}
 
-            if diff <= threshold {
-                info!("Color is the same, not sending payload.");
-            } else {
-                info!("Color is different, sending payload.");
-                let _ = device.set(payload, 0);
+impl Output {
+    fn set(&self, payload: Payload, seq_id: u32) -> Result<(), ErrorKind> {
+        match self {
+            Output::Device(device) => device.set(payload, seq_id),
+            Output::Session(device) => device.set(payload, seq_id),
+            #[cfg(feature = "simulate")]
+            Output::Simulator(simulator) => {
+                simulator.set(&payload);
+                Ok(())
             }
+            Output::Quiet(device, quiet) => device.set(quiet.limit(payload), seq_id),
+        }
+    }
 
-            last_color = dominant_color;
+    fn get(&self, payload: Payload, seq_id: u32) -> Result<Vec<Message>, ErrorKind> {
+        match self {
+            Output::Device(device) => device.get(payload, seq_id),
+            Output::Session(device) => device.get(payload, seq_id),
+            #[cfg(feature = "simulate")]
+            Output::Simulator(_) => Ok(Vec::new()),
+            Output::Quiet(device, _) => device.get(payload, seq_id),
+        }
+    }
 
-            thread::sleep(Duration::from_secs(1));
+    /// The shortest time between two colors during `[quiet-hours]`, `None` outside them.
+    fn quiet_interval(&self) -> Option<Duration> {
+        match self {
+            Output::Quiet(_, quiet) => quiet.min_interval(),
+            _ => None,
         }
-    } else {
-        println!("Failed to connect to the device.");
     }
+
+    /// Drops whatever is kept of the connection, so the next command starts afresh.
+    fn reset(&self) {
+        match self {
+            Output::Session(device) => device.disconnect(),
+            Output::Quiet(device, _) => device.reset(),
+            // Protocol 3.1/3.3 devices get a new connection for every command anyway.
+            _ => {}
+        }
+    }
+}
+
+#[cfg(feature = "simulate")]
+fn open_simulator(
+    capabilities: &Capabilities,
+    color_space: ColorSpace,
+) -> Result<Output, ErrorKind> {
+    info!("Sending updates to the simulator window.");
+    Ok(Output::Simulator(simulator::Simulator::open(
+        capabilities.clone(),
+        color_space,
+    )))
+}
+
+#[cfg(not(feature = "simulate"))]
+fn open_simulator(_: &Capabilities, _: ColorSpace) -> Result<Output, ErrorKind> {
+    error!("--simulate needs a build with the `simulate` feature.");
+    std::process::exit(1);
 }
 
-fn connect(key: String, ip: String) -> Result<TuyaDevice, ErrorKind> {
-    TuyaDevice::create("ver3.3", Some(&key), IpAddr::from_str(&ip).unwrap())
+fn connect(
+    key: String,
+    ip: String,
+    version: String,
+    connection: ConnectionConfig,
+) -> Result<Output, ErrorKind> {
+    let ip = IpAddr::from_str(&ip).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("\"{}\" is not an IP address", ip),
+        )
+    })?;
+
+    match Protocol::from_version(&version) {
+        Some(protocol) => {
+            SessionDevice::create(protocol, &key, ip, connection).map(Output::Session)
+        }
+        None => TuyaDevice::create(&format!("ver{}", version), Some(&key), ip).map(Output::Device),
+    }
 }
 
-fn hsv2tuya(hsv: (u32, u32, u32)) -> String {
-    let (h, s, v) = hsv;
-    let tuya_h = format!("{:04x}", h);
-    let tuya_s = format!("{:04x}", s * 10);
-    let tuya_v = format!("{:04x}", v * 10);
+fn hsv2rgb(hsv: (u32, u32, u32)) -> Rgb {
+    let [r, g, b] = color::hsv2rgb(hsv);
+    Rgb::from(r, g, b)
+}
 
-    format!("{}{}{}", tuya_h, tuya_s, tuya_v)
+/// A color for logs, its hex code and the name of the closest named color, e.g.
+/// `#1e90ff (dodger blue)`.
+fn describe_color(rgb: &Rgb) -> String {
+    format!("{} ({})", rgb.to_css_hex_string(), name(rgb_bytes(rgb)))
 }
 
-fn generate_screenshot_and_get_dominant_color(save_image: bool) -> Hsl {
-    let path = path::Path::new("./screenshots/");
-    let one_second = Duration::new(1, 0);
-    let one_frame = one_second / 60;
-    let display = Display::all().expect("Couldn't find any display.");
-    let second = display
-        .into_iter()
-        .next()
-        .expect("Couldn't find second display.");
+fn rgb2hsv(rgb: &Rgb) -> (u32, u32, u32) {
+    color::rgb2hsv([rgb.get_red(), rgb.get_green(), rgb.get_blue()])
+}
 
+fn generate_screenshot_and_get_dominant_color(
+    grabber: &mut Grabber,
+    screenshots: Option<&path::Path>,
+    shared_frame: Option<&mut SharedFrame>,
+    capture: &CaptureOptions,
+    previous: Option<&FrameStats>,
+) -> Option<(Hsl, FrameStats, Vec<Hsl>)> {
     let file_name = format!(
         "{}.jpeg",
         SystemTime::now()
@@ -183,154 +2410,245 @@ fn generate_screenshot_and_get_dominant_color(save_image: bool) -> Hsl {
             .as_secs()
     );
 
-    let mut capturer: Capturer = Capturer::new(second).expect("Failed to create capturer");
-    let (w, h) = (capturer.width(), capturer.height());
+    let (w, h) = grabber.size();
 
-    loop {
-        let buffer = match capturer.frame() {
-            Ok(buffer) => buffer,
-            Err(error) => {
-                if error.kind() == WouldBlock {
-                    thread::sleep(one_frame);
-                    continue;
-                } else {
-                    panic!("Error: {}", error);
-                }
-            }
-        };
+    if capture.sampling == Sampling::Grid {
+        let (columns, rows) = (GRID_COLUMNS.min(w), GRID_ROWS.min(h));
+        let samples = grabber
+            .grab_grid(columns, rows)
+            .map_err(|error| warn!("{}", error))
+            .ok()?;
+        let mut stats = FrameStats::compute(samples, columns, rows, previous);
+        let [r, g, b] = extraction(capture).sample_color(samples);
+        let dominant_color = Rgb::from(r, g, b);
+        stats.dominant_share = dominant_share(samples, rgb_bytes(&dominant_color));
+        let zones = capture.zones.map_or_else(Vec::new, |layout| {
+            zones::zone_colors(samples, columns, rows, layout, capture.color_space)
+        });
+        if let Some(shared_frame) = shared_frame {
+            let colors: Vec<Rgb> = iter::once(dominant_color)
+                .chain(zones.iter().copied())
+                .collect();
+            shared_frame.publish(samples, columns, rows, &colors);
+        }
+
+        debug!(
+            "Dominant color of {} samples: {:?}",
+            columns * rows,
+            dominant_color
+        );
 
-        let swapped_buffer = swap_color_channels(&buffer, w, h);
+        return Some((
+            dominant_color.to_hsl(),
+            stats,
+            zones.iter().map(Rgb::to_hsl).collect(),
+        ));
+    }
 
-        debug!("Swapped color channels.");
+    let img = grabber
+        .grab_image()
+        .map_err(|error| warn!("{}", error))
+        .ok()?;
 
-        if save_image {
-            save_screenshot(path, &file_name, &swapped_buffer, w, h);
+    debug!("Swapped color channels.");
 
-            debug!("Saved screenshot: {}", file_name);
-        } else {
-            debug!("Not saving screenshot.");
-        }
+    if let Some(path) = screenshots {
+        save_screenshot(path, &file_name, img.as_raw(), w, h);
+    } else {
+        debug!("Not saving screenshot.");
+    }
+
+    // Statistics and palette extraction each walk the whole frame, run them side by side.
+    let (mut stats, dominant_color) = rayon::join(
+        || FrameStats::compute(img.as_raw(), w, h, previous),
+        || extract_color(img, capture),
+    );
+    stats.dominant_share = dominant_share(img.as_raw(), rgb_bytes(&dominant_color));
+    let zones = capture.zones.map_or_else(Vec::new, |layout| {
+        zones::zone_colors(img.as_raw(), w, h, layout, capture.color_space)
+    });
+    if let Some(shared_frame) = shared_frame {
+        let colors: Vec<Rgb> = iter::once(dominant_color)
+            .chain(zones.iter().copied())
+            .collect();
+        shared_frame.publish(img.as_raw(), w, h, &colors);
+    }
 
-        let img = create_image_from_buffer(&swapped_buffer, w, h);
+    debug!("Frame stats: {:?}", stats);
+    debug!("Dominant color: {:?}", dominant_color);
+    if !zones.is_empty() {
+        debug!("Zone colors: {:?}", zones);
+    }
+
+    Some((
+        dominant_color.to_hsl(),
+        stats,
+        zones.iter().map(Rgb::to_hsl).collect(),
+    ))
+}
 
-        debug!("Created image from buffer.");
+/// How `--algorithm` and the flags that tune it pick the color of a frame.
+fn extraction(capture: &CaptureOptions) -> Extraction {
+    Extraction {
+        algorithm: capture.algorithm,
+        color_space: capture.color_space,
+        decimation: capture.decimation,
+        clusters: capture.clusters,
+    }
+}
 
-        let dominant_color = get_dominant_color(&img);
+/// The color of a whole image by the `--algorithm`.
+fn extract_color(img: &RgbaImage, capture: &CaptureOptions) -> Rgb {
+    let [r, g, b] = extraction(capture).color(img.as_raw());
+    Rgb::from(r, g, b)
+}
 
-        debug!("Dominant color: {:?}", dominant_color);
+fn rgb_bytes(rgb: &Rgb) -> [u8; 3] {
+    [rgb.get_red(), rgb.get_green(), rgb.get_blue()].map(|channel| channel.round() as u8)
+}
 
-        return dominant_color.to_hsl();
+/// Moves each of the `previous` colors towards its `next` one by its weight in `alphas`, one
+/// step of an exponential moving average. Colors without a previous one or a weight are taken
+/// as they are.
+fn smooth(
+    previous: &[Hsl],
+    mut next: Vec<Hsl>,
+    alphas: &[f32],
+    color_space: ColorSpace,
+) -> Vec<Hsl> {
+    let channels = |color: &Hsl| {
+        let rgb = color.to_rgb();
+        [rgb.get_red(), rgb.get_green(), rgb.get_blue()]
+    };
+    for ((next, previous), &alpha) in next.iter_mut().zip(previous).zip(alphas) {
+        let [r, g, b] = color_space.mix(channels(previous), channels(next), alpha);
+        *next = Rgb::from(r, g, b).to_hsl();
     }
+    next
 }
 
-fn swap_color_channels(buffer: &[u8], width: usize, height: usize) -> Vec<u8> {
-    let mut swapped_buffer = Vec::with_capacity(width * height * 4);
-    for i in (0..buffer.len()).step_by(4) {
-        swapped_buffer.extend_from_slice(&[buffer[i + 2], buffer[i + 1], buffer[i], buffer[i + 3]]);
+/// Combines colors channel by channel in RGB, so that a color only a minority of them had
+/// doesn't show at all.
+fn median_color(colors: &[Hsl]) -> Hsl {
+    if let [color] = colors {
+        return *color;
     }
-    swapped_buffer
+
+    let median = |channel: fn(&Rgb) -> f32| {
+        let mut values: Vec<f32> = colors
+            .iter()
+            .map(|color| channel(&color.to_rgb()))
+            .collect();
+        values.sort_by(f32::total_cmp);
+        values[values.len() / 2]
+    };
+
+    Rgb::from(
+        median(Rgb::get_red),
+        median(Rgb::get_green),
+        median(Rgb::get_blue),
+    )
+    .to_hsl()
 }
 
 fn save_screenshot(path: &path::Path, file_name: &str, buffer: &[u8], width: usize, height: usize) {
-    image::save_buffer(
+    let result = image::save_buffer(
         path.join(file_name),
         buffer,
         width as u32,
         height as u32,
         image::ColorType::Rgba8,
-    )
-    .unwrap();
-}
+    );
 
-fn create_image_from_buffer(buffer: &[u8], width: usize, height: usize) -> RgbaImage {
-    image::ImageBuffer::from_raw(width as u32, height as u32, buffer.to_vec())
-        .expect("Failed to create image")
+    match result {
+        Ok(()) => debug!("Saved screenshot: {}", file_name),
+        Err(error) => warn!("Failed to save screenshot {}: {}", file_name, error),
+    }
 }
 
-fn get_dominant_color(img: &RgbaImage) -> Rgb {
-    let palette = get_palette(
-        &img.clone().into_vec(),
-        color_thief::ColorFormat::Rgba,
-        10,
-        2,
-    )
-    .unwrap();
-    let dominant_color = palette.first().unwrap();
+/// Creates the screenshot directory, falling back to one in the temp dir when it can't be
+/// written to. Returns `None` when neither works.
+fn screenshot_dir(requested: &path::Path) -> Option<path::PathBuf> {
+    let writable = |dir: &path::Path| {
+        let probe = dir.join(".write-test");
+        fs::create_dir_all(dir)
+            .and_then(|()| fs::write(&probe, b""))
+            .and_then(|()| fs::remove_file(&probe))
+    };
 
-    debug!("get_dominant_color: {:?}", dominant_color);
+    let error = match writable(requested) {
+        Ok(()) => return Some(requested.to_path_buf()),
+        Err(error) => error,
+    };
 
-    Rgb::from(
-        dominant_color.r as f32,
-        dominant_color.g as f32,
-        dominant_color.b as f32,
-    )
+    let fallback = env::temp_dir().join("tuya-bulb-screen-color-screenshots");
+    match writable(&fallback) {
+        Ok(()) => {
+            warn!(
+                "Can't write screenshots to {} ({}), using {} instead.",
+                requested.display(),
+                error,
+                fallback.display()
+            );
+            Some(fallback)
+        }
+        Err(fallback_error) => {
+            error!(
+                "Can't write screenshots to {} ({}) or {} ({}), not saving any.",
+                requested.display(),
+                error,
+                fallback.display(),
+                fallback_error
+            );
+            None
+        }
+    }
 }
 
-fn create_switch_led_payload(id: String, mode: bool) -> Payload {
+fn create_switch_led_payload(id: String, capabilities: &Capabilities, mode: bool) -> Payload {
     let mut dps = HashMap::new();
-    dps.insert(DataPointsKey::SwitchLed.get(), json!(mode));
-
-    let current_time = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as u32;
+    dps.insert(capabilities.switch_dp.to_string(), json!(mode));
 
-    Payload::Struct(PayloadStruct {
-        dev_id: id.to_string(),
-        gw_id: Some(id.to_string()),
-        uid: None,
-        t: Some(current_time),
-        dp_id: None,
-        dps: Some(dps),
-    })
+    dps_payload(&id, dps)
 }
 
-fn create_color_picker_payload(id: String, hsl: Hsl) -> Payload {
-    let mut dps = HashMap::new();
-    dps.insert(DataPointsKey::ColorMode.get(), json!("colour"));
-
+/// The HSV sent to the bulb for a screen color in `color-picker` mode.
+fn color_picker_hsv(hsl: Hsl, gain: f32, max_brightness: Option<u32>) -> (u32, u32, u32) {
     let lightness = if hsl.get_lightness() > 50.0 { 50 } else { 100 };
+    let lightness = apply_gain(lightness, gain);
+    let lightness = max_brightness.map_or(lightness, |max| lightness.min(max));
+
+    (hsl.get_hue() as u32, hsl.get_saturation() as u32, lightness)
+}
+
+/// Scales a brightness (1–100) by the auto gain.
+fn apply_gain(value: u32, gain: f32) -> u32 {
+    ((value as f32 * gain).round() as u32).clamp(1, 100)
+}
 
+fn create_color_payload(id: String, capabilities: &Capabilities, hsv: (u32, u32, u32)) -> Payload {
+    let mut dps = HashMap::new();
+    if capabilities.has_quirk(Quirk::RequiresPowerBeforeColour) {
+        dps.insert(capabilities.switch_dp.to_string(), json!(true));
+    }
+    dps.insert(capabilities.mode_dp.to_string(), json!("colour"));
     dps.insert(
-        DataPointsKey::Color.get(),
-        json!(hsv2tuya((
-            hsl.get_hue() as u32,
-            hsl.get_saturation() as u32,
-            lightness as u32
-        ))),
+        capabilities.colour_dp.to_string(),
+        json!(capabilities.encode_colour(hsv)),
     );
-    let current_time = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as u32;
 
-    Payload::Struct(PayloadStruct {
-        dev_id: id.to_string(),
-        gw_id: Some(id.to_string()),
-        uid: None,
-        t: Some(current_time),
-        dp_id: None,
-        dps: Some(dps),
-    })
+    dps_payload(&id, dps)
 }
 
-fn create_color_mode_payload(id: String, mode: String) -> Payload {
-    let mut dps = HashMap::new();
-    dps.insert(DataPointsKey::ColorMode.get(), json!(mode));
+fn create_dps_payload(id: String, dps: HashMap<String, Value>) -> Payload {
+    dps_payload(&id, dps)
+}
 
-    let current_time = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as u32;
+fn create_color_mode_payload(id: String, capabilities: &Capabilities, mode: String) -> Payload {
+    let mut dps = HashMap::new();
+    dps.insert(capabilities.mode_dp.to_string(), json!(mode));
 
-    Payload::Struct(PayloadStruct {
-        dev_id: id.to_string(),
-        gw_id: Some(id.to_string()),
-        uid: None,
-        t: Some(current_time),
-        dp_id: None,
-        dps: Some(dps),
-    })
+    dps_payload(&id, dps)
 }
 
 fn color_diff(color1: &Hsl, color2: &Hsl) -> f32 {
@@ -0,0 +1,177 @@
+//! Names for colors, so logs and status output say "dodger blue" rather than only `#1e90ff`.
+
+use crate::colorspace::ColorSpace;
+
+/// The CSS named colors, spelled as words, without the duplicates (`cyan` is `aqua`,
+/// `magenta` is `fuchsia`, `grey` is `gray`).
+const NAMES: [(&str, [u8; 3]); 139] = [
+    ("alice blue", [0xf0, 0xf8, 0xff]),
+    ("antique white", [0xfa, 0xeb, 0xd7]),
+    ("aqua", [0x00, 0xff, 0xff]),
+    ("aquamarine", [0x7f, 0xff, 0xd4]),
+    ("azure", [0xf0, 0xff, 0xff]),
+    ("beige", [0xf5, 0xf5, 0xdc]),
+    ("bisque", [0xff, 0xe4, 0xc4]),
+    ("black", [0x00, 0x00, 0x00]),
+    ("blanched almond", [0xff, 0xeb, 0xcd]),
+    ("blue", [0x00, 0x00, 0xff]),
+    ("blue violet", [0x8a, 0x2b, 0xe2]),
+    ("brown", [0xa5, 0x2a, 0x2a]),
+    ("burlywood", [0xde, 0xb8, 0x87]),
+    ("cadet blue", [0x5f, 0x9e, 0xa0]),
+    ("chartreuse", [0x7f, 0xff, 0x00]),
+    ("chocolate", [0xd2, 0x69, 0x1e]),
+    ("coral", [0xff, 0x7f, 0x50]),
+    ("cornflower blue", [0x64, 0x95, 0xed]),
+    ("cornsilk", [0xff, 0xf8, 0xdc]),
+    ("crimson", [0xdc, 0x14, 0x3c]),
+    ("dark blue", [0x00, 0x00, 0x8b]),
+    ("dark cyan", [0x00, 0x8b, 0x8b]),
+    ("dark goldenrod", [0xb8, 0x86, 0x0b]),
+    ("dark gray", [0xa9, 0xa9, 0xa9]),
+    ("dark green", [0x00, 0x64, 0x00]),
+    ("dark khaki", [0xbd, 0xb7, 0x6b]),
+    ("dark magenta", [0x8b, 0x00, 0x8b]),
+    ("dark olive green", [0x55, 0x6b, 0x2f]),
+    ("dark orange", [0xff, 0x8c, 0x00]),
+    ("dark orchid", [0x99, 0x32, 0xcc]),
+    ("dark red", [0x8b, 0x00, 0x00]),
+    ("dark salmon", [0xe9, 0x96, 0x7a]),
+    ("dark sea green", [0x8f, 0xbc, 0x8f]),
+    ("dark slate blue", [0x48, 0x3d, 0x8b]),
+    ("dark slate gray", [0x2f, 0x4f, 0x4f]),
+    ("dark turquoise", [0x00, 0xce, 0xd1]),
+    ("dark violet", [0x94, 0x00, 0xd3]),
+    ("deep pink", [0xff, 0x14, 0x93]),
+    ("deep sky blue", [0x00, 0xbf, 0xff]),
+    ("dim gray", [0x69, 0x69, 0x69]),
+    ("dodger blue", [0x1e, 0x90, 0xff]),
+    ("firebrick", [0xb2, 0x22, 0x22]),
+    ("floral white", [0xff, 0xfa, 0xf0]),
+    ("forest green", [0x22, 0x8b, 0x22]),
+    ("fuchsia", [0xff, 0x00, 0xff]),
+    ("gainsboro", [0xdc, 0xdc, 0xdc]),
+    ("ghost white", [0xf8, 0xf8, 0xff]),
+    ("gold", [0xff, 0xd7, 0x00]),
+    ("goldenrod", [0xda, 0xa5, 0x20]),
+    ("gray", [0x80, 0x80, 0x80]),
+    ("green", [0x00, 0x80, 0x00]),
+    ("green yellow", [0xad, 0xff, 0x2f]),
+    ("honeydew", [0xf0, 0xff, 0xf0]),
+    ("hot pink", [0xff, 0x69, 0xb4]),
+    ("indian red", [0xcd, 0x5c, 0x5c]),
+    ("indigo", [0x4b, 0x00, 0x82]),
+    ("ivory", [0xff, 0xff, 0xf0]),
+    ("khaki", [0xf0, 0xe6, 0x8c]),
+    ("lavender", [0xe6, 0xe6, 0xfa]),
+    ("lavender blush", [0xff, 0xf0, 0xf5]),
+    ("lawn green", [0x7c, 0xfc, 0x00]),
+    ("lemon chiffon", [0xff, 0xfa, 0xcd]),
+    ("light blue", [0xad, 0xd8, 0xe6]),
+    ("light coral", [0xf0, 0x80, 0x80]),
+    ("light cyan", [0xe0, 0xff, 0xff]),
+    ("light goldenrod yellow", [0xfa, 0xfa, 0xd2]),
+    ("light gray", [0xd3, 0xd3, 0xd3]),
+    ("light green", [0x90, 0xee, 0x90]),
+    ("light pink", [0xff, 0xb6, 0xc1]),
+    ("light salmon", [0xff, 0xa0, 0x7a]),
+    ("light sea green", [0x20, 0xb2, 0xaa]),
+    ("light sky blue", [0x87, 0xce, 0xfa]),
+    ("light slate gray", [0x77, 0x88, 0x99]),
+    ("light steel blue", [0xb0, 0xc4, 0xde]),
+    ("light yellow", [0xff, 0xff, 0xe0]),
+    ("lime", [0x00, 0xff, 0x00]),
+    ("lime green", [0x32, 0xcd, 0x32]),
+    ("linen", [0xfa, 0xf0, 0xe6]),
+    ("maroon", [0x80, 0x00, 0x00]),
+    ("medium aquamarine", [0x66, 0xcd, 0xaa]),
+    ("medium blue", [0x00, 0x00, 0xcd]),
+    ("medium orchid", [0xba, 0x55, 0xd3]),
+    ("medium purple", [0x93, 0x70, 0xdb]),
+    ("medium sea green", [0x3c, 0xb3, 0x71]),
+    ("medium slate blue", [0x7b, 0x68, 0xee]),
+    ("medium spring green", [0x00, 0xfa, 0x9a]),
+    ("medium turquoise", [0x48, 0xd1, 0xcc]),
+    ("medium violet red", [0xc7, 0x15, 0x85]),
+    ("midnight blue", [0x19, 0x19, 0x70]),
+    ("mint cream", [0xf5, 0xff, 0xfa]),
+    ("misty rose", [0xff, 0xe4, 0xe1]),
+    ("moccasin", [0xff, 0xe4, 0xb5]),
+    ("navajo white", [0xff, 0xde, 0xad]),
+    ("navy", [0x00, 0x00, 0x80]),
+    ("old lace", [0xfd, 0xf5, 0xe6]),
+    ("olive", [0x80, 0x80, 0x00]),
+    ("olive drab", [0x6b, 0x8e, 0x23]),
+    ("orange", [0xff, 0xa5, 0x00]),
+    ("orange red", [0xff, 0x45, 0x00]),
+    ("orchid", [0xda, 0x70, 0xd6]),
+    ("pale goldenrod", [0xee, 0xe8, 0xaa]),
+    ("pale green", [0x98, 0xfb, 0x98]),
+    ("pale turquoise", [0xaf, 0xee, 0xee]),
+    ("pale violet red", [0xdb, 0x70, 0x93]),
+    ("papaya whip", [0xff, 0xef, 0xd5]),
+    ("peach puff", [0xff, 0xda, 0xb9]),
+    ("peru", [0xcd, 0x85, 0x3f]),
+    ("pink", [0xff, 0xc0, 0xcb]),
+    ("plum", [0xdd, 0xa0, 0xdd]),
+    ("powder blue", [0xb0, 0xe0, 0xe6]),
+    ("purple", [0x80, 0x00, 0x80]),
+    ("rebecca purple", [0x66, 0x33, 0x99]),
+    ("red", [0xff, 0x00, 0x00]),
+    ("rosy brown", [0xbc, 0x8f, 0x8f]),
+    ("royal blue", [0x41, 0x69, 0xe1]),
+    ("saddle brown", [0x8b, 0x45, 0x13]),
+    ("salmon", [0xfa, 0x80, 0x72]),
+    ("sandy brown", [0xf4, 0xa4, 0x60]),
+    ("sea green", [0x2e, 0x8b, 0x57]),
+    ("seashell", [0xff, 0xf5, 0xee]),
+    ("sienna", [0xa0, 0x52, 0x2d]),
+    ("silver", [0xc0, 0xc0, 0xc0]),
+    ("sky blue", [0x87, 0xce, 0xeb]),
+    ("slate blue", [0x6a, 0x5a, 0xcd]),
+    ("slate gray", [0x70, 0x80, 0x90]),
+    ("snow", [0xff, 0xfa, 0xfa]),
+    ("spring green", [0x00, 0xff, 0x7f]),
+    ("steel blue", [0x46, 0x82, 0xb4]),
+    ("tan", [0xd2, 0xb4, 0x8c]),
+    ("teal", [0x00, 0x80, 0x80]),
+    ("thistle", [0xd8, 0xbf, 0xd8]),
+    ("tomato", [0xff, 0x63, 0x47]),
+    ("turquoise", [0x40, 0xe0, 0xd0]),
+    ("violet", [0xee, 0x82, 0xee]),
+    ("wheat", [0xf5, 0xde, 0xb3]),
+    ("white", [0xff, 0xff, 0xff]),
+    ("white smoke", [0xf5, 0xf5, 0xf5]),
+    ("yellow", [0xff, 0xff, 0x00]),
+    ("yellow green", [0x9a, 0xcd, 0x32]),
+];
+
+/// Weights of the Oklab differences: hue and chroma count double, so that a dark gray is named
+/// after the nearest gray rather than a dark blue of about the same lightness.
+const WEIGHTS: [f32; 3] = [1.0, 4.0, 4.0];
+
+/// The name of the named color closest to `rgb`, judged in Oklab so that the distance matches
+/// how different the colors look.
+///
+/// ```
+/// use tuya_bulb_screen_color::names::name;
+///
+/// assert_eq!(name([0x1e, 0x90, 0xff]), "dodger blue");
+/// assert_eq!(name([250, 5, 5]), "red");
+/// assert_eq!(name([20, 20, 20]), "black");
+/// ```
+pub fn name(rgb: [u8; 3]) -> &'static str {
+    let oklab = |rgb: [u8; 3]| ColorSpace::Oklab.from_rgb(rgb.map(f32::from));
+    let target = oklab(rgb);
+    let distance = |color: [u8; 3]| {
+        let color = oklab(color);
+        (0..3)
+            .map(|i| (color[i] - target[i]).powi(2) * WEIGHTS[i])
+            .sum::<f32>()
+    };
+
+    NAMES
+        .iter()
+        .min_by(|a, b| distance(a.1).total_cmp(&distance(b.1)))
+        .map_or("black", |(name, _)| name)
+}
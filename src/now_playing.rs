@@ -0,0 +1,126 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use colors_transform::Rgb;
+use log::{debug, info, warn};
+
+use crate::{
+    capabilities::Capabilities, chromecast, config::DeviceConfig, create_color_payload,
+    describe_color, dlna, extract_color, grading::Grade, http, rgb2hsv, send, CaptureOptions,
+    Output,
+};
+
+/// How often casting sessions are looked for again, so devices turned on later are found.
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
+/// How often the devices found are asked what they are playing.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Artwork is scaled down to fit this size before its color is extracted.
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// Something on the LAN that media can be cast to.
+enum Player {
+    Cast(chromecast::Receiver),
+    Dlna(dlna::Renderer),
+}
+
+impl Player {
+    fn name(&self) -> &str {
+        match self {
+            Player::Cast(receiver) => &receiver.name,
+            Player::Dlna(renderer) => &renderer.name,
+        }
+    }
+
+    fn artwork(&self) -> Result<Option<String>, String> {
+        match self {
+            Player::Cast(receiver) => receiver.artwork(),
+            Player::Dlna(renderer) => renderer.artwork(),
+        }
+    }
+}
+
+fn discover() -> Vec<Player> {
+    let players: Vec<Player> = chromecast::discover()
+        .into_iter()
+        .map(Player::Cast)
+        .chain(dlna::discover().into_iter().map(Player::Dlna))
+        .collect();
+    for player in &players {
+        debug!("Found {}.", player.name());
+    }
+    players
+}
+
+/// Colors every device from the poster or cover of whatever is being cast on the LAN, to a
+/// Chromecast or a DLNA renderer, for when the content isn't on the local screen at all. The
+/// first player found playing wins.
+pub fn run(
+    devices: Vec<(DeviceConfig, Capabilities, Output)>,
+    capture: CaptureOptions,
+    grade: Grade,
+) {
+    info!("Looking for media being cast...");
+    let mut players = Vec::new();
+    let mut discovered: Option<Instant> = None;
+    let mut showing: Option<String> = None;
+    let mut idle = false;
+
+    loop {
+        if discovered.is_none_or(|discovered| discovered.elapsed() >= DISCOVERY_INTERVAL) {
+            players = discover();
+            discovered = Some(Instant::now());
+        }
+
+        let playing = players.iter().find_map(|player| match player.artwork() {
+            Ok(artwork) => artwork.map(|artwork| (player.name(), artwork)),
+            Err(error) => {
+                debug!("{}", error);
+                None
+            }
+        });
+
+        match playing {
+            Some((player, artwork)) if showing.as_ref() != Some(&artwork) => {
+                idle = false;
+                match artwork_color(&artwork, &capture) {
+                    Ok(color) => {
+                        let color = grade.apply(&color);
+                        info!(
+                            "{} is playing, showing its artwork as {}.",
+                            player,
+                            describe_color(&color)
+                        );
+                        for (target, capabilities, device) in &devices {
+                            let payload = create_color_payload(
+                                target.id.clone(),
+                                capabilities,
+                                rgb2hsv(&color),
+                            );
+                            send(device, payload);
+                        }
+                    }
+                    Err(error) => warn!("Skipping the artwork of {}: {}", player, error),
+                }
+                showing = Some(artwork);
+            }
+            Some(_) => {}
+            None if !idle => {
+                info!("Nothing is being cast, waiting...");
+                idle = true;
+                showing = None;
+            }
+            None => {}
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn artwork_color(url: &str, capture: &CaptureOptions) -> Result<Rgb, String> {
+    let bytes = http::request("GET", url, &[], &[])?;
+    let image = image::load_from_memory(&bytes).map_err(|error| error.to_string())?;
+    let image = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgba8();
+    Ok(extract_color(&image, capture))
+}
@@ -0,0 +1,226 @@
+use std::sync::{Arc, Mutex};
+
+/// What the on-screen display shows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub mode: &'static str,
+    pub profile: String,
+    pub color: [u8; 3],
+}
+
+/// Handle to the on-screen display, updated by the color loop.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "osd"), allow(dead_code))]
+pub struct Osd(Arc<Mutex<Option<Status>>>);
+
+impl Osd {
+    pub fn show(&self, status: Status) {
+        *self.0.lock().unwrap() = Some(status);
+    }
+}
+
+#[cfg(feature = "osd")]
+pub use self::window::open;
+
+/// Without the `osd` feature there is no window to show the state in.
+#[cfg(not(feature = "osd"))]
+pub fn open(_hotkey: &str) -> Result<Osd, String> {
+    Err("The on-screen display needs the `osd` feature.".to_string())
+}
+
+#[cfg(feature = "osd")]
+mod window {
+    use std::{
+        str::FromStr,
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use global_hotkey::{hotkey::HotKey, GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState};
+    use log::{error, info};
+    use minifb::{Window, WindowOptions};
+
+    use super::{Osd, Status};
+
+    const WIDTH: usize = 180;
+    const HEIGHT: usize = 46;
+    const MARGIN: isize = 16;
+    const SWATCH: usize = 34;
+    /// Glyphs are drawn at this many pixels per font pixel.
+    const SCALE: usize = 2;
+    const BACKGROUND: u32 = 0x202020;
+    const TEXT: u32 = 0xe0e0e0;
+    const HIDDEN_POLL: Duration = Duration::from_millis(50);
+
+    /// Opens the display in the top left corner; `hotkey` (e.g. `ctrl+alt+o`) hides and shows it.
+    pub fn open(hotkey: &str) -> Result<Osd, String> {
+        let hotkey = HotKey::from_str(hotkey)
+            .map_err(|error| format!("Invalid hotkey \"{}\": {}", hotkey, error))?;
+        let osd = Osd(Arc::new(Mutex::new(None)));
+
+        let shown = osd.clone();
+        thread::spawn(move || run(shown, hotkey));
+
+        Ok(osd)
+    }
+
+    fn run(osd: Osd, hotkey: HotKey) {
+        // The manager has to live on the thread that handles the window.
+        let manager = match GlobalHotKeyManager::new() {
+            Ok(manager) => manager,
+            Err(error) => {
+                error!("Failed to set up the OSD hotkey: {}", error);
+                return;
+            }
+        };
+        if let Err(error) = manager.register(hotkey) {
+            error!("Failed to register the OSD hotkey: {}", error);
+        }
+
+        let mut window = open_window();
+        let mut buffer = vec![BACKGROUND; WIDTH * HEIGHT];
+
+        loop {
+            for event in GlobalHotKeyEvent::receiver().try_iter() {
+                if event.id == hotkey.id() && event.state == HotKeyState::Pressed {
+                    window = match window {
+                        Some(_) => None,
+                        None => open_window(),
+                    };
+                    info!("OSD {}.", if window.is_some() { "shown" } else { "hidden" });
+                }
+            }
+
+            let Some(shown) = window.as_mut().filter(|window| window.is_open()) else {
+                window = None;
+                thread::sleep(HIDDEN_POLL);
+                continue;
+            };
+
+            let status = osd.0.lock().unwrap().clone();
+            draw(&mut buffer, status.as_ref());
+
+            if let Err(error) = shown.update_with_buffer(&buffer, WIDTH, HEIGHT) {
+                error!("Failed to draw the OSD: {}", error);
+                window = None;
+            }
+        }
+    }
+
+    fn open_window() -> Option<Window> {
+        let options = WindowOptions {
+            borderless: true,
+            title: false,
+            topmost: true,
+            ..WindowOptions::default()
+        };
+
+        match Window::new("tuya-bulb-screen-color", WIDTH, HEIGHT, options) {
+            Ok(mut window) => {
+                window.set_position(MARGIN, MARGIN);
+                window.set_target_fps(10);
+                Some(window)
+            }
+            Err(error) => {
+                error!("Failed to open the OSD: {}", error);
+                None
+            }
+        }
+    }
+
+    fn draw(buffer: &mut [u32], status: Option<&Status>) {
+        buffer.fill(BACKGROUND);
+
+        let Some(status) = status else {
+            draw_text(buffer, "waiting", 6, 4);
+            return;
+        };
+
+        let [r, g, b] = status.color.map(u32::from);
+        let swatch = (r << 16) | (g << 8) | b;
+        for y in 6..6 + SWATCH {
+            buffer[y * WIDTH + 6..y * WIDTH + 6 + SWATCH].fill(swatch);
+        }
+
+        let x = SWATCH + 14;
+        draw_text(buffer, status.mode, x, 4);
+        draw_text(buffer, &status.profile, x, 18);
+        draw_text(
+            buffer,
+            &format!(
+                "#{:02x}{:02x}{:02x}",
+                status.color[0], status.color[1], status.color[2]
+            ),
+            x,
+            32,
+        );
+    }
+
+    fn draw_text(buffer: &mut [u32], text: &str, left: usize, top: usize) {
+        for (i, c) in text.chars().enumerate() {
+            let x0 = left + i * 4 * SCALE;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) == 0 {
+                        continue;
+                    }
+                    for dy in 0..SCALE {
+                        for dx in 0..SCALE {
+                            let (x, y) = (x0 + column * SCALE + dx, top + row * SCALE + dy);
+                            if x < WIDTH && y < HEIGHT {
+                                buffer[y * WIDTH + x] = TEXT;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// A 3x5 pixel font, one row of three bits per entry. Unknown characters are blank.
+    fn glyph(c: char) -> [u8; 5] {
+        match c.to_ascii_lowercase() {
+            'a' => [0b010, 0b101, 0b111, 0b101, 0b101],
+            'b' => [0b110, 0b101, 0b110, 0b101, 0b110],
+            'c' => [0b011, 0b100, 0b100, 0b100, 0b011],
+            'd' => [0b110, 0b101, 0b101, 0b101, 0b110],
+            'e' => [0b111, 0b100, 0b110, 0b100, 0b111],
+            'f' => [0b111, 0b100, 0b110, 0b100, 0b100],
+            'g' => [0b011, 0b100, 0b101, 0b101, 0b011],
+            'h' => [0b101, 0b101, 0b111, 0b101, 0b101],
+            'i' => [0b111, 0b010, 0b010, 0b010, 0b111],
+            'j' => [0b001, 0b001, 0b001, 0b101, 0b010],
+            'k' => [0b101, 0b101, 0b110, 0b101, 0b101],
+            'l' => [0b100, 0b100, 0b100, 0b100, 0b111],
+            'm' => [0b101, 0b111, 0b111, 0b101, 0b101],
+            'n' => [0b110, 0b101, 0b101, 0b101, 0b101],
+            'o' => [0b010, 0b101, 0b101, 0b101, 0b010],
+            'p' => [0b110, 0b101, 0b110, 0b100, 0b100],
+            'q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+            'r' => [0b110, 0b101, 0b110, 0b101, 0b101],
+            's' => [0b011, 0b100, 0b010, 0b001, 0b110],
+            't' => [0b111, 0b010, 0b010, 0b010, 0b010],
+            'u' => [0b101, 0b101, 0b101, 0b101, 0b111],
+            'v' => [0b101, 0b101, 0b101, 0b101, 0b010],
+            'w' => [0b101, 0b101, 0b111, 0b111, 0b101],
+            'x' => [0b101, 0b101, 0b010, 0b101, 0b101],
+            'y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+            'z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+            '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+            '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+            '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+            '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+            '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+            '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+            '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+            '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+            '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+            '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+            '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+            '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+            ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+            _ => [0; 5],
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+/// Schedules loop iterations on fixed deadlines that are whole multiples of the display's
+/// frame time, instead of sleeping a fixed amount after each iteration. Capture and send time
+/// no longer add to the interval, and updates don't drift against the refresh rate, which
+/// avoids slow beat patterns between the capture interval and what is shown on screen.
+pub struct FramePacer {
+    frame: Duration,
+    next: Instant,
+}
+
+impl FramePacer {
+    pub fn new(refresh_rate: f64) -> FramePacer {
+        FramePacer {
+            frame: Duration::from_secs_f64(1.0 / refresh_rate.max(1.0)),
+            next: Instant::now(),
+        }
+    }
+
+    /// Rounds `interval` to the nearest whole number of display frames, at least one.
+    pub fn snap(&self, interval: Duration) -> Duration {
+        let frames = (interval.as_secs_f64() / self.frame.as_secs_f64())
+            .round()
+            .max(1.0);
+        self.frame.mul_f64(frames)
+    }
+
+    /// Sleeps until the next deadline `interval` after the previous one. When an iteration took
+    /// longer than that, the missed deadlines are skipped instead of rushing to catch up.
+    pub fn wait(&mut self, interval: Duration) {
+        let period = self.snap(interval);
+        self.next += period;
+
+        let now = Instant::now();
+        if now > self.next {
+            let behind = now - self.next;
+            let missed = (behind.as_nanos() / period.as_nanos()) as u32 + 1;
+            debug!(
+                "Iteration overran by {:?}, skipping {} slot(s).",
+                behind, missed
+            );
+            self.next += period * missed;
+        }
+
+        thread::sleep(self.next - now);
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    net::SocketAddr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::{debug, info};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use crate::coordination::host_name;
+
+/// mDNS service type hubs advertise themselves under.
+const SERVICE_TYPE: &str = "_tuya-screen._tcp.local.";
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A random six digit code agents have to present to the hub.
+pub fn new_code() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(now.as_nanos());
+    }
+    format!("{:06}", hasher.finish() % 1_000_000)
+}
+
+/// Advertises the hub accepting agents on `port` over mDNS. The returned daemon keeps answering
+/// queries on its own thread for as long as it is kept around.
+pub fn advertise(port: u16) -> Result<ServiceDaemon, String> {
+    let daemon =
+        ServiceDaemon::new().map_err(|error| format!("Failed to start mDNS: {}", error))?;
+    let name = host_name();
+    let service = ServiceInfo::new(
+        SERVICE_TYPE,
+        &name,
+        &format!("{}.local.", name),
+        (),
+        port,
+        None,
+    )
+    .map_err(|error| format!("Failed to describe the hub for mDNS: {}", error))?
+    .enable_addr_auto();
+
+    daemon
+        .register(service)
+        .map_err(|error| format!("Failed to advertise the hub: {}", error))?;
+    info!("Advertising the hub as {} over mDNS.", name);
+
+    Ok(daemon)
+}
+
+/// Looks for a hub on the local network and returns the address of the first one found.
+pub fn discover() -> Result<SocketAddr, String> {
+    let daemon =
+        ServiceDaemon::new().map_err(|error| format!("Failed to start mDNS: {}", error))?;
+    let events = daemon
+        .browse(SERVICE_TYPE)
+        .map_err(|error| format!("Failed to look for hubs: {}", error))?;
+    let deadline = Instant::now() + DISCOVERY_TIMEOUT;
+
+    let found = loop {
+        let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+            break None;
+        };
+        match events.recv_timeout(left) {
+            Ok(ServiceEvent::ServiceResolved(service)) => {
+                let address = service.get_addresses_v4().into_iter().next();
+                if let Some(address) = address {
+                    info!("Found hub {} at {}.", service.fullname, address);
+                    break Some(SocketAddr::from((address, service.get_port())));
+                }
+            }
+            Ok(event) => debug!("mDNS: {:?}", event),
+            Err(_) => break None,
+        }
+    };
+
+    let _ = daemon.shutdown();
+    found.ok_or_else(|| "Couldn't find a hub on the local network.".to_string())
+}
@@ -0,0 +1,286 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use colors_transform::{Hsl, Rgb};
+use log::{error, info, warn};
+use rust_tuyapi::Payload;
+use serde_json::json;
+use tokio::{
+    sync::{mpsc, watch},
+    task::{self, JoinHandle},
+    time,
+};
+use tuya_bulb_screen_color::stats::FrameStats;
+
+use crate::{
+    bulb::{Bulb, Frame},
+    control::Control,
+    coordination::Coordinator,
+    daemon::Shutdown,
+    generate_screenshot_and_get_dominant_color,
+    grabber::Grabber,
+    health::Health,
+    hsv2rgb,
+    pacing::FramePacer,
+    shm::SharedFrame,
+    unix_millis,
+    websocket::Feed,
+    CaptureOptions,
+};
+
+/// An update of one bulb taking longer than this, on top of its transition, is logged. It only
+/// holds back that bulb, which skips to the newest frame once it is done.
+const SLOW_UPDATE: Duration = Duration::from_secs(3);
+
+/// One frame as the capture task took it.
+pub struct Captured {
+    pub color: Hsl,
+    pub stats: FrameStats,
+    /// Colors of the `--zones`, empty without them.
+    pub zones: Vec<Hsl>,
+    /// The RGBA pixels with their width and height, kept for `--debug-dump-on-change`.
+    pub pixels: Option<(Vec<u8>, usize, usize)>,
+}
+
+/// Everything the capture task owns.
+pub struct Capture {
+    pub options: CaptureOptions,
+    pub screenshots: Option<PathBuf>,
+    pub shared_frame: Option<SharedFrame>,
+    pub keep_pixels: bool,
+}
+
+/// Captures frames on a thread of their own, at the interval the color loop asks for, until
+/// shutdown is requested or the color loop stops taking them. Frames that can't be captured
+/// are skipped. The capturers can't move between threads, they are opened on that thread and
+/// exit the process when that fails.
+pub fn capture(
+    capture: Capture,
+    interval: watch::Receiver<Duration>,
+    health: Health,
+    shutdown: Shutdown,
+) -> (JoinHandle<()>, mpsc::Receiver<Captured>) {
+    let (frames, receiver) = mpsc::channel(1);
+    let Capture {
+        options,
+        screenshots,
+        mut shared_frame,
+        keep_pixels,
+    } = capture;
+
+    let task = task::spawn_blocking(move || {
+        let mut grabber = Grabber::new(&options).unwrap_or_else(|error| {
+            error!("{}", error);
+            std::process::exit(1);
+        });
+        let mut pacer = FramePacer::new(options.refresh_rate);
+        let mut last_stats: Option<FrameStats> = None;
+
+        while !shutdown.requested() && !frames.is_closed() {
+            let frame_interval = *interval.borrow();
+            let Some((color, stats, zones)) = generate_screenshot_and_get_dominant_color(
+                &mut grabber,
+                screenshots.as_deref(),
+                shared_frame.as_mut(),
+                &options,
+                last_stats.as_ref(),
+            ) else {
+                pacer.wait(frame_interval);
+                continue;
+            };
+            health.capture_succeeded(grabber.source());
+            last_stats = Some(stats.clone());
+
+            let pixels = keep_pixels.then(|| {
+                let (rgba, width, height) = grabber.last_frame();
+                (rgba.to_vec(), width, height)
+            });
+            let captured = Captured {
+                color,
+                stats,
+                zones,
+                pixels,
+            };
+            if frames.blocking_send(captured).is_err() {
+                break;
+            }
+            pacer.wait(frame_interval);
+        }
+    });
+    (task, receiver)
+}
+
+/// What the color loop hands every sender along with the frames.
+#[derive(Clone)]
+pub struct Shared {
+    pub health: Health,
+    pub control: Control,
+    pub coordinator: Option<Coordinator>,
+    /// Told whether each update was sent, see `websocket::Feed`.
+    pub feed: Feed,
+}
+
+/// What the color loop knows of a bulb, as of the last update its sender finished.
+#[derive(Clone, Default)]
+pub struct Status {
+    pub online: bool,
+    /// See `Bulb::brightness`.
+    pub brightness: Option<u32>,
+    /// See `Bulb::last_payload`.
+    pub last_payload: Option<Payload>,
+}
+
+enum Job {
+    Update(Arc<Frame>),
+    Flash(Rgb),
+}
+
+/// A bulb updated by a task of its own, so that a slow or unreachable bulb holds up neither
+/// the capture nor the other bulbs. The task takes the newest frame whenever it is done with
+/// the previous one; frames that came in meanwhile are skipped.
+pub struct Sender {
+    label: String,
+    zone: Option<usize>,
+    status: watch::Receiver<Status>,
+    flashes: mpsc::UnboundedSender<Rgb>,
+    task: JoinHandle<()>,
+}
+
+impl Sender {
+    pub fn spawn(
+        bulb: Bulb,
+        frames: watch::Receiver<Option<Arc<Frame>>>,
+        shared: Shared,
+    ) -> Sender {
+        let label = bulb.label().to_string();
+        let zone = bulb.zone;
+        let (status, status_receiver) = watch::channel(Status {
+            online: bulb.is_online(),
+            ..Status::default()
+        });
+        let (flashes, flash_receiver) = mpsc::unbounded_channel();
+        let task = tokio::spawn(send(bulb, frames, flash_receiver, shared, status));
+
+        Sender {
+            label,
+            zone,
+            status: status_receiver,
+            flashes,
+            task,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// See `Bulb::zone`.
+    pub fn zone(&self) -> Option<usize> {
+        self.zone
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.borrow().clone()
+    }
+
+    /// Shows `color` on the bulb ahead of any frame, see `Bulb::flash`.
+    pub fn flash(&self, color: Rgb) {
+        // The task only stops once the frames do.
+        let _ = self.flashes.send(color);
+    }
+
+    /// Waits for the update in progress once the frames stopped coming.
+    pub async fn stopped(self) {
+        drop(self.flashes);
+        if let Err(error) = self.task.await {
+            error!("Updating {} failed: {}", self.label, error);
+        }
+    }
+}
+
+async fn send(
+    mut bulb: Bulb,
+    mut frames: watch::Receiver<Option<Arc<Frame>>>,
+    mut flashes: mpsc::UnboundedReceiver<Rgb>,
+    shared: Shared,
+    status: watch::Sender<Status>,
+) {
+    let label = bulb.label().to_string();
+
+    loop {
+        let job = tokio::select! {
+            biased;
+            Some(color) = flashes.recv() => Job::Flash(color),
+            changed = frames.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                match frames.borrow_and_update().clone() {
+                    Some(frame) => Job::Update(frame),
+                    None => continue,
+                }
+            }
+        };
+
+        let timeout = match &job {
+            Job::Update(frame) => {
+                SLOW_UPDATE + frame.transition.map_or(Duration::ZERO, |t| t.duration)
+            }
+            Job::Flash(_) => SLOW_UPDATE,
+        };
+        // The device libraries block, the update runs where that doesn't stall other tasks.
+        let shared = shared.clone();
+        let mut update = task::spawn_blocking(move || {
+            match job {
+                Job::Update(frame) => {
+                    bulb.update(
+                        &frame,
+                        &shared.health,
+                        &shared.control,
+                        shared.coordinator.as_ref(),
+                    );
+                    let sent = bulb.last_payload().is_some();
+                    shared.feed.publish(|| {
+                        json!({
+                            "type": "update",
+                            "device": bulb.label(),
+                            "sent": sent,
+                            "hex": bulb
+                                .last_sent()
+                                .filter(|_| sent)
+                                .map(|hsv| hsv2rgb(hsv).to_css_hex_string()),
+                            "t": unix_millis(),
+                        })
+                    });
+                }
+                Job::Flash(color) => bulb.flash(&color, &shared.health),
+            }
+            bulb
+        });
+        let updated = match time::timeout(timeout, &mut update).await {
+            Ok(updated) => updated,
+            Err(_) => {
+                warn!(
+                    "{} didn't take the update within {:.1} s, skipping the frames it misses.",
+                    label,
+                    timeout.as_secs_f32()
+                );
+                let updated = update.await;
+                info!("{} caught up.", label);
+                updated
+            }
+        };
+        bulb = match updated {
+            Ok(bulb) => bulb,
+            Err(error) => {
+                error!("Updating {} failed: {}", label, error);
+                return;
+            }
+        };
+
+        status.send_replace(Status {
+            online: bulb.is_online(),
+            brightness: bulb.brightness(),
+            last_payload: bulb.last_payload().cloned(),
+        });
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use log::{debug, info, warn};
+use rumqttc::{Event, Packet, QoS};
+use serde::Deserialize;
+
+use crate::mqtt::{self, MqttConfig};
+
+/// `[presence]` in the config: MQTT topics that report whether someone is home, e.g. the state
+/// of Home Assistant's device trackers.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct PresenceConfig {
+    pub topics: Vec<String>,
+    /// Payload that means home, anything else means away.
+    #[serde(default = "default_home")]
+    pub home: String,
+}
+
+fn default_home() -> String {
+    "home".to_string()
+}
+
+/// Whether anyone is home, as last reported over MQTT. Topics that haven't reported yet count
+/// as home, so nothing is switched off before the first report.
+#[derive(Clone)]
+pub struct Presence(Arc<AtomicBool>);
+
+impl Presence {
+    pub fn anyone_home(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+pub fn watch(mqtt: &MqttConfig, config: PresenceConfig) -> Presence {
+    let presence = Presence(Arc::new(AtomicBool::new(true)));
+    let (client, mut connection) = mqtt::connect(mqtt, "presence");
+    let broker = mqtt.address();
+
+    let home = presence.0.clone();
+    thread::spawn(move || {
+        let mut states: HashMap<String, bool> = HashMap::new();
+
+        for event in connection.iter() {
+            match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Watching presence on {}.", broker);
+                    for topic in &config.topics {
+                        if let Err(error) = client.subscribe(topic, QoS::AtLeastOnce) {
+                            warn!("Failed to subscribe to {}: {}", topic, error);
+                        }
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    let payload = String::from_utf8_lossy(&publish.payload);
+                    debug!("Presence {} is \"{}\".", publish.topic, payload);
+                    states.insert(publish.topic, payload.trim() == config.home);
+
+                    let anyone = config.topics.iter().any(|filter| {
+                        let mut reported = states
+                            .iter()
+                            .filter(|(topic, _)| matches(filter, topic))
+                            .map(|(_, &home)| home)
+                            .peekable();
+                        reported.peek().is_none() || reported.any(|home| home)
+                    });
+                    if home.swap(anyone, Ordering::SeqCst) != anyone {
+                        info!(
+                            "{}",
+                            if anyone {
+                                "Someone came home."
+                            } else {
+                                "Nobody is home."
+                            }
+                        );
+                    }
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    warn!("Lost the MQTT broker {}: {}", broker, error);
+                    thread::sleep(mqtt::RECONNECT_INTERVAL);
+                }
+            }
+        }
+    });
+
+    presence
+}
+
+/// Whether `topic` falls under the subscription `filter`, with `+` standing for one level and
+/// a trailing `#` for any number of them, none included. Topics starting with `$` are only
+/// matched by filters that spell out their first level.
+fn matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+
+    let mut levels = topic.split('/');
+    for part in filter.split('/') {
+        match (part, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (part, Some(level)) if part == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topics_match_themselves() {
+        assert!(matches("home/alice/state", "home/alice/state"));
+        assert!(!matches("home/alice/state", "home/bob/state"));
+        assert!(!matches("home/alice", "home/alice/state"));
+        assert!(!matches("home/alice/state", "home/alice"));
+    }
+
+    #[test]
+    fn single_level_wildcards() {
+        assert!(matches("home/+/state", "home/alice/state"));
+        assert!(matches("home/+/state", "home//state"));
+        assert!(!matches("home/+/state", "home/alice/phone/state"));
+        assert!(!matches("home/+", "home/alice/state"));
+        assert!(matches("+/+", "home/alice"));
+    }
+
+    #[test]
+    fn multi_level_wildcards() {
+        assert!(matches("home/#", "home/alice/state"));
+        assert!(matches("home/#", "home"));
+        assert!(matches("#", "home/alice/state"));
+        assert!(!matches("home/#", "work/alice"));
+        assert!(matches("home/+/#", "home/alice/phone/state"));
+    }
+
+    #[test]
+    fn system_topics_need_their_first_level() {
+        assert!(!matches("#", "$SYS/broker/uptime"));
+        assert!(!matches("+/broker/uptime", "$SYS/broker/uptime"));
+        assert!(matches("$SYS/#", "$SYS/broker/uptime"));
+    }
+}
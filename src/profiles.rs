@@ -0,0 +1,149 @@
+use std::{collections::VecDeque, fmt, time::Duration};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use tuya_bulb_screen_color::stats::FrameStats;
+
+/// Number of recent frames the content classification looks at.
+const WINDOW: usize = 10;
+/// How many consecutive classifications must agree before the profile is switched.
+const STABLE_FOR: usize = 5;
+/// Frames whose change magnitude exceeds this count as "moving".
+const MOVING_CHANGE: f32 = 0.02;
+/// Below this share of moving frames the screen is considered a static desktop.
+const MOVING_SHARE: f32 = 0.3;
+/// Average change magnitude above which moving content is considered a game.
+const GAME_CHANGE: f32 = 0.08;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContentType {
+    Desktop,
+    Video,
+    Game,
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContentType::Desktop => write!(f, "desktop"),
+            ContentType::Video => write!(f, "video"),
+            ContentType::Game => write!(f, "game"),
+        }
+    }
+}
+
+/// Overrides for a built-in profile in the `[profiles.<content type>]` config section, or for the
+/// default update rate and threshold in `[update]`.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ProfileConfig {
+    pub interval_ms: Option<u64>,
+    pub threshold: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Profile {
+    pub interval: Duration,
+    pub threshold: f32,
+}
+
+impl ContentType {
+    pub fn profile(&self, overrides: Option<&ProfileConfig>) -> Profile {
+        let default = match self {
+            ContentType::Desktop => Profile {
+                interval: Duration::from_secs(2),
+                threshold: 15.0,
+            },
+            ContentType::Video => Profile {
+                interval: Duration::from_millis(500),
+                threshold: 10.0,
+            },
+            ContentType::Game => Profile {
+                interval: Duration::from_millis(200),
+                threshold: 5.0,
+            },
+        };
+
+        default.with(overrides)
+    }
+}
+
+impl Profile {
+    /// Replaces the values `overrides` sets.
+    pub fn with(self, overrides: Option<&ProfileConfig>) -> Profile {
+        let Some(overrides) = overrides else {
+            return self;
+        };
+
+        Profile {
+            interval: overrides
+                .interval_ms
+                .map_or(self.interval, Duration::from_millis),
+            threshold: overrides.threshold.unwrap_or(self.threshold),
+        }
+    }
+}
+
+/// Guesses what is on screen from the recent frame statistics.
+pub struct ContentClassifier {
+    changes: VecDeque<f32>,
+    current: Option<ContentType>,
+    candidate: Option<(ContentType, usize)>,
+}
+
+impl ContentClassifier {
+    pub fn new() -> ContentClassifier {
+        ContentClassifier {
+            changes: VecDeque::with_capacity(WINDOW),
+            current: None,
+            candidate: None,
+        }
+    }
+
+    /// Feeds the statistics of a new frame, returning the content type once the
+    /// classification has settled.
+    pub fn update(&mut self, stats: &FrameStats) -> Option<ContentType> {
+        if self.changes.len() == WINDOW {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(stats.change_magnitude);
+
+        if self.changes.len() < WINDOW {
+            return self.current;
+        }
+
+        let classified = self.classify();
+
+        let streak = match self.candidate {
+            Some((candidate, streak)) if candidate == classified => streak + 1,
+            _ => 1,
+        };
+        self.candidate = Some((classified, streak));
+
+        if streak >= STABLE_FOR && self.current != Some(classified) {
+            info!("Content looks like {} now.", classified);
+            self.current = Some(classified);
+        }
+
+        self.current
+    }
+
+    fn classify(&self) -> ContentType {
+        let moving = self
+            .changes
+            .iter()
+            .filter(|change| **change > MOVING_CHANGE)
+            .count();
+        let moving_share = moving as f32 / self.changes.len() as f32;
+        let mean_change = self.changes.iter().sum::<f32>() / self.changes.len() as f32;
+
+        if moving_share < MOVING_SHARE {
+            ContentType::Desktop
+        } else if mean_change > GAME_CHANGE {
+            ContentType::Game
+        } else {
+            ContentType::Video
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{collections::HashMap, time::Duration};
+
+use rust_tuyapi::Payload;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tuya_bulb_screen_color::tuya::tuya2hsv;
+
+use crate::{capabilities::Capabilities, rules::TimeOfDay};
+
+/// `[quiet-hours]` in the config: a time of day during which the bulbs stay dim and change
+/// slowly, whatever the mode asks for.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct QuietHoursConfig {
+    pub from: TimeOfDay,
+    pub to: TimeOfDay,
+    /// Brightness ceiling in percent.
+    #[serde(default = "default_max_brightness")]
+    pub max_brightness: u32,
+    /// Shortest time between two updates of the screen color.
+    #[serde(default = "default_min_interval_ms")]
+    pub min_interval_ms: u64,
+}
+
+fn default_max_brightness() -> u32 {
+    30
+}
+
+fn default_min_interval_ms() -> u64 {
+    2000
+}
+
+/// Applies the quiet hours to everything sent to one device.
+pub struct QuietHours {
+    config: QuietHoursConfig,
+    capabilities: Capabilities,
+}
+
+impl QuietHours {
+    pub fn new(config: QuietHoursConfig, capabilities: Capabilities) -> QuietHours {
+        QuietHours {
+            config,
+            capabilities,
+        }
+    }
+
+    fn active(&self) -> bool {
+        TimeOfDay::now().between(self.config.from, self.config.to)
+    }
+
+    /// The shortest time between two updates of the screen color, `None` outside the quiet
+    /// hours. Whoever drives the bulb spaces its updates, not single payloads: the steps of a
+    /// fade still go out as they come.
+    pub fn min_interval(&self) -> Option<Duration> {
+        self.active()
+            .then(|| Duration::from_millis(self.config.min_interval_ms))
+    }
+
+    /// Caps the brightness and colour DPs of `payload` to the ceiling. Outside the quiet hours
+    /// the payload is left alone.
+    pub fn limit(&self, mut payload: Payload) -> Payload {
+        if !self.active() {
+            return payload;
+        }
+
+        if let Payload::Struct(payload) = &mut payload {
+            if let Some(dps) = &mut payload.dps {
+                self.cap(dps);
+            }
+        }
+        payload
+    }
+
+    fn cap(&self, dps: &mut HashMap<String, Value>) {
+        let max = self.config.max_brightness.min(100);
+        let capabilities = &self.capabilities;
+
+        if let Some(value) = dps.get_mut(&capabilities.brightness_dp.to_string()) {
+            let ceiling = capabilities.encode_brightness(max);
+            if value
+                .as_u64()
+                .is_some_and(|brightness| brightness > ceiling as u64)
+            {
+                *value = json!(ceiling);
+            }
+        }
+
+        if let Some(value) = dps.get_mut(&capabilities.colour_dp.to_string()) {
+            let capped = value
+                .as_str()
+                .and_then(|colour| tuya2hsv(colour).ok())
+                .filter(|&(_, _, brightness)| brightness > max)
+                .map(|(hue, saturation, _)| capabilities.encode_colour((hue, saturation, max)));
+            if let Some(colour) = capped {
+                *value = json!(colour);
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    net::{IpAddr, SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use log::{info, warn};
+use serde::Deserialize;
+
+/// Port Tuya devices accept LAN connections on.
+const TUYA_PORT: u16 = 6668;
+
+/// `[connection]` in the config: how patiently devices are talked to.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConnectionConfig {
+    /// For probes and protocol 3.4/3.5 connections.
+    pub connect_timeout_ms: u64,
+    /// For protocol 3.4/3.5 replies and writes, rust-tuyapi fixes them at 2 s for 3.1/3.3.
+    pub read_timeout_ms: u64,
+    pub write_timeout_ms: u64,
+    /// How often a failed update is sent again before the bulb counts as lost.
+    pub retries: u32,
+    /// Wait before the second probe of a lost bulb, doubled after every failed one up to
+    /// `max-probe-interval-ms`.
+    pub min_probe_interval_ms: u64,
+    pub max_probe_interval_ms: u64,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> ConnectionConfig {
+        ConnectionConfig {
+            connect_timeout_ms: 2000,
+            read_timeout_ms: 2000,
+            write_timeout_ms: 2000,
+            retries: 0,
+            min_probe_interval_ms: 1000,
+            max_probe_interval_ms: 60_000,
+        }
+    }
+}
+
+/// Zero would mean no timeout for reads and writes, and is an error for connects.
+impl ConnectionConfig {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.connect_timeout_ms.max(1))
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_millis(self.read_timeout_ms.max(1))
+    }
+
+    pub fn write_timeout(&self) -> Duration {
+        Duration::from_millis(self.write_timeout_ms.max(1))
+    }
+}
+
+/// Tracks whether a device accepts connections. While it doesn't, it is probed on a background
+/// thread with exponential backoff, so the color loop can keep capturing instead of waiting on
+/// timeouts.
+pub struct Reachability {
+    label: String,
+    address: SocketAddr,
+    connection: ConnectionConfig,
+    online: Arc<AtomicBool>,
+    probing: Arc<AtomicBool>,
+}
+
+impl Reachability {
+    /// Probes the device once and starts probing in the background when it isn't reachable.
+    pub fn new(label: &str, ip: IpAddr, connection: ConnectionConfig) -> Reachability {
+        let reachability = Reachability {
+            label: label.to_string(),
+            address: SocketAddr::new(ip, TUYA_PORT),
+            connection,
+            online: Arc::new(AtomicBool::new(true)),
+            probing: Arc::new(AtomicBool::new(false)),
+        };
+
+        if !probe(reachability.address, &connection) {
+            warn!(
+                "{} is not reachable, capturing anyway until it shows up.",
+                label
+            );
+            reachability.lost();
+        }
+
+        reachability
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::SeqCst)
+    }
+
+    /// Marks the device as unreachable and probes it in the background until it answers.
+    pub fn lost(&self) {
+        self.online.store(false, Ordering::SeqCst);
+
+        if self.probing.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let label = self.label.clone();
+        let address = self.address;
+        let connection = self.connection;
+        let online = self.online.clone();
+        let probing = self.probing.clone();
+
+        thread::spawn(move || {
+            let mut attempts = 1;
+            let mut interval = Duration::from_millis(connection.min_probe_interval_ms.max(1));
+            let max_interval =
+                Duration::from_millis(connection.max_probe_interval_ms).max(interval);
+            while !probe(address, &connection) {
+                info!(
+                    "Reconnecting to {} failed (attempt {}), retrying in {:.1} s.",
+                    label,
+                    attempts,
+                    interval.as_secs_f32()
+                );
+                thread::sleep(interval);
+                attempts += 1;
+                interval = (interval * 2).min(max_interval);
+            }
+            info!(
+                "{} is reachable again after {} attempt(s).",
+                label, attempts
+            );
+            online.store(true, Ordering::SeqCst);
+            probing.store(false, Ordering::SeqCst);
+        });
+    }
+}
+
+fn probe(address: SocketAddr, connection: &ConnectionConfig) -> bool {
+    TcpStream::connect_timeout(&address, connection.connect_timeout()).is_ok()
+}
@@ -0,0 +1,363 @@
+use std::time::Duration;
+
+use chrono::{Datelike, Local, NaiveDate, Timelike};
+use log::info;
+use serde::Deserialize;
+use tuya_bulb_screen_color::stats::FrameStats;
+
+use crate::profiles::ContentType;
+
+/// A single `[[rules]]` entry: when every condition in `when` holds, `then` is applied.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    #[serde(default)]
+    pub when: Conditions,
+    pub then: Action,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Conditions {
+    pub screen_black_for_secs: Option<u64>,
+    pub black_ratio_above: Option<f32>,
+    pub mean_luminance_above: Option<f32>,
+    pub mean_luminance_below: Option<f32>,
+    pub change_above: Option<f32>,
+    pub profile: Option<ContentType>,
+    pub after: Option<TimeOfDay>,
+    pub before: Option<TimeOfDay>,
+    pub weekdays: Option<Vec<Weekday>>,
+    /// Only on these dates, e.g. holidays that get their own rules.
+    pub dates: Option<Vec<Date>>,
+    /// Never on these dates, e.g. holidays on a workday rule.
+    pub except_dates: Vec<Date>,
+    pub device_offline: Option<bool>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    PowerOff,
+    MaxBrightness(u32),
+}
+
+/// Minutes since midnight, written as `"HH:MM"` in the config.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay(u32);
+
+impl TimeOfDay {
+    pub fn now() -> TimeOfDay {
+        let now = Local::now();
+        TimeOfDay(now.hour() * 60 + now.minute())
+    }
+
+    /// Whether this is at or after `from` and before `to`, across midnight when `to` comes
+    /// first.
+    pub fn between(self, from: TimeOfDay, to: TimeOfDay) -> bool {
+        if from > to {
+            self >= from || self < to
+        } else {
+            self >= from && self < to
+        }
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid time of day \"{}\", expected HH:MM", value);
+        let (hours, minutes) = value.split_once(':').ok_or_else(invalid)?;
+        let hours: u32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+
+        if hours > 23 || minutes > 59 {
+            return Err(invalid());
+        }
+
+        Ok(TimeOfDay(hours * 60 + minutes))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Weekday {
+    fn to_chrono(self) -> chrono::Weekday {
+        match self {
+            Weekday::Mon => chrono::Weekday::Mon,
+            Weekday::Tue => chrono::Weekday::Tue,
+            Weekday::Wed => chrono::Weekday::Wed,
+            Weekday::Thu => chrono::Weekday::Thu,
+            Weekday::Fri => chrono::Weekday::Fri,
+            Weekday::Sat => chrono::Weekday::Sat,
+            Weekday::Sun => chrono::Weekday::Sun,
+        }
+    }
+}
+
+/// A calendar day, written as `"YYYY-MM-DD"` in the config.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Date(NaiveDate);
+
+impl Date {
+    pub fn today() -> NaiveDate {
+        Local::now().date_naive()
+    }
+}
+
+impl TryFrom<String> for Date {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        NaiveDate::parse_from_str(&value, "%Y-%m-%d")
+            .map(Date)
+            .map_err(|_| format!("invalid date \"{}\", expected YYYY-MM-DD", value))
+    }
+}
+
+/// What the color loop knows about the world when the rules are evaluated.
+pub struct Context<'a> {
+    pub time: TimeOfDay,
+    pub date: NaiveDate,
+    pub screen_black_for: Duration,
+    pub device_offline: bool,
+    pub stats: &'a FrameStats,
+    pub profile: Option<ContentType>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Outcome {
+    pub power_off: bool,
+    pub max_brightness: Option<u32>,
+}
+
+pub struct Rules {
+    rules: Vec<Rule>,
+    matched: Vec<bool>,
+}
+
+impl Rules {
+    pub fn new(rules: Vec<Rule>) -> Rules {
+        let matched = vec![false; rules.len()];
+        Rules { rules, matched }
+    }
+
+    pub fn evaluate(&mut self, context: &Context) -> Outcome {
+        let mut outcome = Outcome::default();
+
+        for (index, rule) in self.rules.iter().enumerate() {
+            let matches = rule.when.matches(context);
+
+            if matches != self.matched[index] {
+                self.matched[index] = matches;
+                if matches {
+                    info!("Rule #{} matched, applying {:?}.", index + 1, rule.then);
+                } else {
+                    info!("Rule #{} no longer matches.", index + 1);
+                }
+            }
+
+            if !matches {
+                continue;
+            }
+
+            match rule.then {
+                Action::PowerOff => outcome.power_off = true,
+                Action::MaxBrightness(max) => {
+                    outcome.max_brightness = Some(
+                        outcome
+                            .max_brightness
+                            .map_or(max, |current| current.min(max)),
+                    );
+                }
+            }
+        }
+
+        outcome
+    }
+}
+
+impl Conditions {
+    fn matches(&self, context: &Context) -> bool {
+        if let Some(seconds) = self.screen_black_for_secs {
+            if context.screen_black_for < Duration::from_secs(seconds) {
+                return false;
+            }
+        }
+
+        let stats = context.stats;
+        let thresholds = [
+            (self.black_ratio_above, stats.black_ratio, true),
+            (self.mean_luminance_above, stats.mean_luminance, true),
+            (self.mean_luminance_below, stats.mean_luminance, false),
+            (self.change_above, stats.change_magnitude, true),
+        ];
+
+        for (threshold, value, above) in thresholds {
+            let Some(threshold) = threshold else {
+                continue;
+            };
+            if (above && value <= threshold) || (!above && value >= threshold) {
+                return false;
+            }
+        }
+
+        if self.profile.is_some() && self.profile != context.profile {
+            return false;
+        }
+
+        if let Some(offline) = self.device_offline {
+            if context.device_offline != offline {
+                return false;
+            }
+        }
+
+        // A window across midnight belongs to the day it starts on: `fri` from 22:00 to 02:00
+        // still holds at 01:00 on Saturday.
+        let date = match (self.after, self.before) {
+            (Some(after), Some(before)) if after > before && context.time < before => {
+                context.date.pred_opt().unwrap_or(context.date)
+            }
+            _ => context.date,
+        };
+
+        if let Some(weekdays) = &self.weekdays {
+            if !weekdays.iter().any(|day| day.to_chrono() == date.weekday()) {
+                return false;
+            }
+        }
+
+        if let Some(dates) = &self.dates {
+            if !dates.contains(&Date(date)) {
+                return false;
+            }
+        }
+
+        if self.except_dates.contains(&Date(date)) {
+            return false;
+        }
+
+        match (self.after, self.before) {
+            (Some(after), Some(before)) => context.time.between(after, before),
+            (after, before) => {
+                after.is_none_or(|after| context.time >= after)
+                    && before.is_none_or(|before| context.time < before)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conditions(toml: &str) -> Conditions {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn time(value: &str) -> TimeOfDay {
+        TimeOfDay::try_from(value.to_string()).unwrap()
+    }
+
+    /// Whether `when` holds at `time` on `date`, with a grey frame and the bulb online unless
+    /// `offline`.
+    fn holds(when: &str, date: &str, at: &str, offline: bool) -> bool {
+        let stats = FrameStats::compute(&[128, 128, 128, 255], 1, 1, None);
+        let context = Context {
+            time: time(at),
+            date: NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap(),
+            screen_black_for: Duration::ZERO,
+            device_offline: offline,
+            stats: &stats,
+            profile: None,
+        };
+        conditions(when).matches(&context)
+    }
+
+    // 2026-10-16 is a Friday.
+    const FRIDAY: &str = "2026-10-16";
+    const SATURDAY: &str = "2026-10-17";
+
+    #[test]
+    fn windows_cross_midnight() {
+        let night = r#"after = "23:00"
+before = "07:00""#;
+        assert!(holds(night, FRIDAY, "23:00", false));
+        assert!(holds(night, FRIDAY, "02:30", false));
+        assert!(!holds(night, FRIDAY, "07:00", false));
+        assert!(!holds(night, FRIDAY, "22:59", false));
+
+        let day = r#"after = "09:00"
+before = "17:00""#;
+        assert!(holds(day, FRIDAY, "09:00", false));
+        assert!(!holds(day, FRIDAY, "17:00", false));
+        assert!(!holds(day, FRIDAY, "02:30", false));
+    }
+
+    #[test]
+    fn open_windows() {
+        assert!(holds(r#"after = "20:00""#, FRIDAY, "23:59", false));
+        assert!(!holds(r#"after = "20:00""#, FRIDAY, "00:30", false));
+        assert!(holds(r#"before = "06:00""#, FRIDAY, "00:30", false));
+        assert!(!holds(r#"before = "06:00""#, FRIDAY, "06:00", false));
+    }
+
+    #[test]
+    fn weekdays() {
+        let weekdays = r#"weekdays = ["mon", "tue", "wed", "thu", "fri"]"#;
+        assert!(holds(weekdays, FRIDAY, "12:00", false));
+        assert!(!holds(weekdays, SATURDAY, "12:00", false));
+    }
+
+    #[test]
+    fn windows_across_midnight_keep_the_weekday_they_start_on() {
+        let friday_night = r#"weekdays = ["fri"]
+after = "22:00"
+before = "02:00""#;
+        assert!(holds(friday_night, FRIDAY, "22:30", false));
+        assert!(holds(friday_night, SATURDAY, "01:00", false));
+        assert!(!holds(friday_night, SATURDAY, "22:30", false));
+        assert!(!holds(friday_night, FRIDAY, "01:00", false));
+        assert!(!holds(friday_night, SATURDAY, "02:00", false));
+    }
+
+    #[test]
+    fn dates_and_exceptions() {
+        let new_year = r#"dates = ["2027-01-01"]"#;
+        assert!(holds(new_year, "2027-01-01", "12:00", false));
+        assert!(!holds(new_year, "2027-01-02", "12:00", false));
+
+        let workdays = r#"weekdays = ["fri"]
+except-dates = ["2026-12-25"]"#;
+        assert!(holds(workdays, "2026-12-18", "12:00", false));
+        assert!(!holds(workdays, "2026-12-25", "12:00", false));
+
+        // The night of New Year's Eve goes on into the next year.
+        let new_years_eve = r#"dates = ["2026-12-31"]
+after = "20:00"
+before = "04:00""#;
+        assert!(holds(new_years_eve, "2027-01-01", "01:00", false));
+        assert!(!holds(new_years_eve, "2027-01-01", "20:00", false));
+    }
+
+    #[test]
+    fn device_offline() {
+        assert!(holds("device-offline = true", FRIDAY, "12:00", true));
+        assert!(!holds("device-offline = true", FRIDAY, "12:00", false));
+        assert!(holds("device-offline = false", FRIDAY, "12:00", false));
+        assert!(holds("", FRIDAY, "12:00", true));
+    }
+}
@@ -0,0 +1,433 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::Duration,
+};
+
+use colors_transform::Rgb;
+use log::{debug, info, warn};
+
+use crate::control::Control;
+use crate::health::Health;
+use crate::tray::TrayAction;
+use crate::websocket::{self, Feed};
+
+/// How long a client gets to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest request line or header, and most headers read.
+const MAX_LINE: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+/// Largest request body read and thrown away; none of the routes take one.
+const MAX_BODY: u64 = 64 * 1024;
+
+/// Starts the HTTP server on its own thread. Pausing is done right away, switching the mode and
+/// forcing a color come out of the returned receiver for the color loop; `brightness_sync` is
+/// the hue and saturation brightness-sync is switched to. WebSockets on `/colors` get the
+/// `feed`.
+pub fn spawn(
+    address: &str,
+    health: Health,
+    control: Control,
+    brightness_sync: (u32, u32),
+    feed: Feed,
+) -> Result<Receiver<TrayAction>, String> {
+    let listener = TcpListener::bind(address)
+        .map_err(|error| format!("Failed to listen on {}: {}", address, error))?;
+    let (actions, receiver) = mpsc::channel();
+    let api = Api {
+        health,
+        control,
+        actions,
+        brightness_sync,
+        feed,
+    };
+
+    info!("Listening on http://{}", address);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    warn!("Failed to accept a connection: {}", error);
+                    continue;
+                }
+            };
+            let api = api.clone();
+            thread::spawn(move || {
+                if let Err(error) = api.serve(stream) {
+                    debug!("Dropped a connection: {}", error);
+                }
+            });
+        }
+    });
+
+    Ok(receiver)
+}
+
+/// What the routes act on.
+#[derive(Clone)]
+struct Api {
+    health: Health,
+    control: Control,
+    actions: Sender<TrayAction>,
+    brightness_sync: (u32, u32),
+    feed: Feed,
+}
+
+/// The parts of a request the routes look at.
+#[derive(Debug)]
+struct Request {
+    method: String,
+    target: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Reads the request line and headers, and throws away the body.
+fn read_request(reader: &mut impl BufRead) -> io::Result<Request> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed request");
+    let mut read_line = |line: &mut String| {
+        line.clear();
+        reader.by_ref().take(MAX_LINE).read_line(line)?;
+        if !line.ends_with('\n') {
+            return Err(invalid());
+        }
+        Ok(line.trim_end().to_string())
+    };
+
+    let mut line = String::new();
+    let request_line = read_line(&mut line)?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(_version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        let header = read_line(&mut line)?;
+        if header.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(invalid());
+        }
+        let (field, value) = header.split_once(':').ok_or_else(invalid)?;
+        headers.push((field.trim().to_string(), value.trim().to_string()));
+    }
+
+    let request = Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        headers,
+    };
+    let length = request
+        .header("Content-Length")
+        .map_or(Ok(0), str::parse::<u64>)
+        .map_err(|_| invalid())?;
+    io::copy(&mut reader.take(length.min(MAX_BODY)), &mut io::sink())?;
+    Ok(request)
+}
+
+/// Writes a JSON response and leaves the connection to be closed.
+fn respond(writer: &mut impl Write, status: u16, body: &str) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    )?;
+    writer.flush()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+impl Api {
+    /// Answers the one request of a connection, or hands it to the feed when it asks for a
+    /// WebSocket on `/colors`.
+    fn serve(&self, stream: TcpStream) -> io::Result<()> {
+        let peer = stream
+            .peer_addr()
+            .map_or("A client".to_string(), |peer| peer.to_string());
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+
+        let request = match read_request(&mut reader) {
+            Ok(request) => request,
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+                return respond(&mut writer, 400, "{\"error\":\"malformed request\"}");
+            }
+            Err(error) => return Err(error),
+        };
+        debug!("{} {}", request.method, request.target);
+
+        if (request.method.as_str(), path(&request.target)) == ("GET", "/colors") {
+            let Some(accept) = request
+                .header("Sec-WebSocket-Key")
+                .map(websocket::accept_key)
+            else {
+                return respond(&mut writer, 400, "{\"error\":\"expected a WebSocket\"}");
+            };
+            write!(
+                writer,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept
+            )?;
+            writer.flush()?;
+            // Watchers stay connected for as long as they like.
+            reader.get_ref().set_read_timeout(None)?;
+            writer.set_write_timeout(None)?;
+            self.feed.watch(reader, writer, peer);
+            return Ok(());
+        }
+
+        let (status, body) = self.route(&request.method, path(&request.target));
+        respond(&mut writer, status, &body)
+    }
+
+    fn route(&self, method: &str, path: &str) -> (u16, String) {
+        let control = &self.control;
+        match (method, path) {
+            ("GET", "/healthz") => {
+                let (healthy, report) = self.health.report();
+                (if healthy { 200 } else { 503 }, report.to_string())
+            }
+            ("GET", "/devices") => (200, control.report().to_string()),
+            ("GET", "/status") => (200, control.status().to_string()),
+            ("POST", "/pause" | "/resume") => {
+                let enabled = path == "/resume";
+                control.set_all_enabled(enabled);
+                info!(
+                    "{} syncing through the control API.",
+                    if enabled { "Resumed" } else { "Paused" }
+                );
+                (200, control.status().to_string())
+            }
+            ("DELETE", "/color") => {
+                // Nothing is listening once the color loop stopped.
+                let _ = self.actions.send(TrayAction::Color(None));
+                (202, control.status().to_string())
+            }
+            ("POST", path) if path.starts_with("/mode/") => {
+                let mode = match &path["/mode/".len()..] {
+                    "color-picker" => Some(None),
+                    "brightness-sync" => Some(Some(self.brightness_sync)),
+                    _ => None,
+                };
+                match mode {
+                    Some(mode) => {
+                        let _ = self.actions.send(TrayAction::Mode(mode));
+                        (202, control.status().to_string())
+                    }
+                    None => not_found(),
+                }
+            }
+            ("POST", path) if path.starts_with("/color/") => {
+                match Rgb::from_hex_str(&path["/color/".len()..]) {
+                    Ok(color) => {
+                        let _ = self.actions.send(TrayAction::Color(Some(color)));
+                        (202, control.status().to_string())
+                    }
+                    Err(_) => (400, "{\"error\":\"expected /color/rrggbb\"}".to_string()),
+                }
+            }
+            ("POST", path) => match device_action(path) {
+                Some((label, enabled)) if control.set_enabled(&label, enabled) => {
+                    info!(
+                        "{} {} through the control API.",
+                        if enabled { "Enabled" } else { "Disabled" },
+                        label
+                    );
+                    (200, control.report().to_string())
+                }
+                _ => not_found(),
+            },
+            _ => not_found(),
+        }
+    }
+}
+
+fn not_found() -> (u16, String) {
+    (404, "{\"error\":\"not found\"}".to_string())
+}
+
+/// The path of a request target, without the query string.
+fn path(url: &str) -> &str {
+    url.split_once('?').map_or(url, |(path, _)| path)
+}
+
+/// Parses `/devices/<name>/enable` and `/devices/<name>/disable`, with the name percent-encoded.
+fn device_action(url: &str) -> Option<(String, bool)> {
+    let rest = url.strip_prefix("/devices/")?;
+    let (name, action) = rest.rsplit_once('/')?;
+    let enabled = match action {
+        "enable" => true,
+        "disable" => false,
+        _ => return None,
+    };
+
+    Some((percent_decode(name)?, enabled))
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.bytes();
+
+    while let Some(byte) = rest.next() {
+        if byte == b'%' {
+            let hex = [rest.next()?, rest.next()?];
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+/// Queries `/healthz` on a running instance, returning whether it reported healthy and the
+/// response body.
+pub fn probe(address: &str) -> Result<(bool, String), String> {
+    let socket: SocketAddr = address
+        .parse()
+        .map_err(|error| format!("Invalid address {}: {}", address, error))?;
+    let timeout = Duration::from_secs(2);
+
+    let mut stream = TcpStream::connect_timeout(&socket, timeout)
+        .map_err(|error| format!("Failed to connect to {}: {}", address, error))?;
+    stream.set_read_timeout(Some(timeout)).ok();
+    stream.set_write_timeout(Some(timeout)).ok();
+
+    write!(
+        stream,
+        "GET /healthz HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        address
+    )
+    .map_err(|error| format!("Failed to send request: {}", error))?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|error| format!("Failed to read response: {}", error))?;
+
+    let status = response.lines().next().unwrap_or_default();
+    let body = response
+        .split_once("\r\n\r\n")
+        .map_or("", |(_, body)| body)
+        .to_string();
+
+    Ok((status.split_whitespace().nth(1) == Some("200"), body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_requests_and_skips_their_body() {
+        let mut reader = io::Cursor::new(
+            b"POST /pause?now HTTP/1.1\r\nHost: lights\r\ncontent-length: 3\r\n\r\n{}\nGET"
+                .to_vec(),
+        );
+        let request = read_request(&mut reader).unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(path(&request.target), "/pause");
+        assert_eq!(request.header("Host"), Some("lights"));
+        assert_eq!(request.header("Content-Length"), Some("3"));
+        assert_eq!(reader.position(), reader.get_ref().len() as u64 - 3);
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        for request in [
+            &b"GET /status\r\n\r\n"[..],
+            b"GET /status HTTP/1.1\r\nHost\r\n\r\n",
+            b"GET /status HTTP/1.1\r\nContent-Length: lots\r\n\r\n",
+            b"GET /status HTTP/1.1\r\nHost: lights",
+        ] {
+            let error = read_request(&mut io::Cursor::new(request)).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE as usize));
+        assert!(read_request(&mut io::Cursor::new(long)).is_err());
+    }
+
+    #[test]
+    fn query_strings_are_not_part_of_the_path() {
+        assert_eq!(path("/status?x=1"), "/status");
+        assert_eq!(path("/status?"), "/status");
+        assert_eq!(path("/status"), "/status");
+        assert_eq!(path("/devices/a%3Fb/enable?now"), "/devices/a%3Fb/enable");
+    }
+
+    #[test]
+    fn device_actions() {
+        assert_eq!(
+            device_action("/devices/desk/enable"),
+            Some(("desk".to_string(), true))
+        );
+        assert_eq!(
+            device_action("/devices/desk/disable"),
+            Some(("desk".to_string(), false))
+        );
+        assert_eq!(
+            device_action("/devices/living%20room%2Fleft/disable"),
+            Some(("living room/left".to_string(), false))
+        );
+        assert_eq!(
+            device_action("/devices/caf%C3%A9/enable"),
+            Some(("café".to_string(), true))
+        );
+    }
+
+    #[test]
+    fn unknown_device_actions() {
+        assert_eq!(device_action("/devices/desk/toggle"), None);
+        assert_eq!(device_action("/devices/desk"), None);
+        assert_eq!(device_action("/devices/desk/"), None);
+        assert_eq!(device_action("/desk/enable"), None);
+    }
+
+    #[test]
+    fn bad_escapes() {
+        assert_eq!(percent_decode("a%2"), None);
+        assert_eq!(percent_decode("a%"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%+1"), None);
+        // Not UTF-8.
+        assert_eq!(percent_decode("%ff"), None);
+        assert_eq!(device_action("/devices/a%2/enable"), None);
+        assert_eq!(percent_decode("100%25"), Some("100%".to_string()));
+    }
+}
@@ -0,0 +1,600 @@
+use std::{
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use log::debug;
+use openssl::{
+    hash::MessageDigest,
+    pkey::PKey,
+    rand::rand_bytes,
+    sign::Signer,
+    symm::{decrypt, decrypt_aead, encrypt, encrypt_aead, Cipher, Crypter, Mode},
+};
+use rust_tuyapi::{
+    error::ErrorKind,
+    mesparse::{CommandType, Message},
+    Payload,
+};
+use serde_json::json;
+
+use crate::reachability::ConnectionConfig;
+
+const PORT: u16 = 6668;
+
+const PREFIX_55AA: [u8; 4] = [0x00, 0x00, 0x55, 0xaa];
+const SUFFIX_55AA: [u8; 4] = [0x00, 0x00, 0xaa, 0x55];
+const PREFIX_6699: [u8; 4] = [0x00, 0x00, 0x66, 0x99];
+const SUFFIX_6699: [u8; 4] = [0x00, 0x00, 0x99, 0x66];
+
+const SESSION_KEY_START: u32 = 3;
+const SESSION_KEY_RESPONSE: u32 = 4;
+const SESSION_KEY_FINISH: u32 = 5;
+/// Pushed by the device whenever a DP changes, unasked.
+const STATUS: u32 = 8;
+const CONTROL: u32 = 13;
+const QUERY: u32 = 16;
+
+/// Longest frame accepted from a device. Bulbs reply with a few hundred bytes at most, the
+/// length is checked before anything is allocated for it.
+const MAX_FRAME: usize = 64 * 1024;
+
+/// Most frames read past while waiting for the reply to a command, so a device pushing status
+/// nonstop can't keep a request waiting forever.
+const MAX_SKIPPED: usize = 16;
+
+/// The protocol versions that negotiate a session key before accepting commands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    /// Frames with the `0x55aa` prefix, AES-ECB payloads and an HMAC-SHA256 instead of a CRC.
+    V34,
+    /// Frames with the `0x6699` prefix and AES-GCM payloads.
+    V35,
+}
+
+impl Protocol {
+    pub fn from_version(version: &str) -> Option<Protocol> {
+        match version {
+            "3.4" => Some(Protocol::V34),
+            "3.5" => Some(Protocol::V35),
+            _ => None,
+        }
+    }
+
+    /// Prepended to control payloads: the version followed by twelve zero bytes.
+    fn header(&self) -> [u8; 15] {
+        let mut header = [0; 15];
+        header[..3].copy_from_slice(match self {
+            Protocol::V34 => b"3.4",
+            Protocol::V35 => b"3.5",
+        });
+        header
+    }
+}
+
+/// A device on protocol 3.4 or 3.5, which rust-tuyapi doesn't speak. The connection and its
+/// session key are kept between commands and negotiated again after any failure.
+pub struct SessionDevice {
+    address: SocketAddr,
+    local_key: [u8; 16],
+    protocol: Protocol,
+    timeouts: ConnectionConfig,
+    connection: Mutex<Option<Connection>>,
+}
+
+struct Connection {
+    stream: TcpStream,
+    key: [u8; 16],
+    sequence: u32,
+}
+
+impl SessionDevice {
+    pub fn create(
+        protocol: Protocol,
+        key: &str,
+        ip: IpAddr,
+        timeouts: ConnectionConfig,
+    ) -> Result<SessionDevice, ErrorKind> {
+        let local_key = key
+            .as_bytes()
+            .try_into()
+            .map_err(|_| ErrorKind::KeyLength(key.len()))?;
+
+        Ok(SessionDevice {
+            address: SocketAddr::new(ip, PORT),
+            local_key,
+            protocol,
+            timeouts,
+            connection: Mutex::new(None),
+        })
+    }
+
+    pub fn set(&self, payload: Payload, _seq_id: u32) -> Result<(), ErrorKind> {
+        let dps = match payload {
+            Payload::Struct(payload) => json!(payload.dps),
+            Payload::String(payload) => serde_json::from_str(&payload)?,
+        };
+        let body = json!({
+            "protocol": 5,
+            "t": now()?,
+            "data": { "dps": dps },
+        });
+
+        let mut plain = self.protocol.header().to_vec();
+        plain.extend(serde_json::to_vec(&body)?);
+        self.request(CONTROL, &plain).map(|_| ())
+    }
+
+    pub fn get(&self, payload: Payload, seq_id: u32) -> Result<Vec<Message>, ErrorKind> {
+        let plain = match payload {
+            Payload::Struct(payload) => serde_json::to_vec(&payload)?,
+            Payload::String(payload) => payload.into_bytes(),
+        };
+        let reply = self.request(QUERY, &plain)?;
+
+        Ok(vec![Message {
+            payload: Payload::String(String::from_utf8_lossy(&reply).into_owned()),
+            command: Some(CommandType::DpQuery),
+            seq_nr: Some(seq_id),
+            ret_code: None,
+        }])
+    }
+
+    /// Drops the connection, the next command connects and negotiates a new session.
+    pub fn disconnect(&self) {
+        self.connection.lock().unwrap().take();
+    }
+
+    /// Sends a command over the session, connecting first when there is none, and returns the
+    /// payload of the reply.
+    fn request(&self, command: u32, plain: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+        let mut kept = self.connection.lock().unwrap();
+        let mut connection = match kept.take() {
+            Some(connection) => connection,
+            None => self.connect()?,
+        };
+
+        let result = connection
+            .send(self.protocol, command, plain)
+            .and_then(|()| connection.receive_reply(self.protocol, command));
+        if result.is_ok() {
+            *kept = Some(connection);
+        }
+
+        result
+    }
+
+    /// Opens a connection and negotiates its session key: both sides send a random nonce and
+    /// prove they know the local key with an HMAC of the other side's nonce.
+    fn connect(&self) -> Result<Connection, ErrorKind> {
+        let stream = TcpStream::connect_timeout(&self.address, self.timeouts.connect_timeout())?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(self.timeouts.read_timeout()))?;
+        stream.set_write_timeout(Some(self.timeouts.write_timeout()))?;
+
+        let mut connection = Connection {
+            stream,
+            key: self.local_key,
+            sequence: 0,
+        };
+
+        let mut local_nonce = [0; 16];
+        rand_bytes(&mut local_nonce)?;
+        connection.send(self.protocol, SESSION_KEY_START, &local_nonce)?;
+
+        let (command, reply) = connection.receive(self.protocol)?;
+        if command != SESSION_KEY_RESPONSE || reply.len() < 48 {
+            return Err(ErrorKind::ParsingIncomplete);
+        }
+        let remote_nonce = &reply[..16];
+        if reply[16..48] != hmac(&self.local_key, &local_nonce)? {
+            return Err(ErrorKind::CRCError);
+        }
+
+        connection.send(
+            self.protocol,
+            SESSION_KEY_FINISH,
+            &hmac(&self.local_key, remote_nonce)?,
+        )?;
+
+        connection.key = session_key(self.protocol, &self.local_key, &local_nonce, remote_nonce)?;
+
+        debug!("Negotiated a session with {}.", self.address);
+        Ok(connection)
+    }
+}
+
+impl Connection {
+    fn send(&mut self, protocol: Protocol, command: u32, plain: &[u8]) -> Result<(), ErrorKind> {
+        self.sequence += 1;
+        let mut iv = [0; 12];
+        rand_bytes(&mut iv)?;
+
+        let frame = encode(protocol, &self.key, self.sequence, command, plain, iv)?;
+        self.stream.write_all(&frame)?;
+        Ok(())
+    }
+
+    /// Reads the next frame and returns its command and decrypted payload, without the return
+    /// code and version header.
+    fn receive(&mut self, protocol: Protocol) -> Result<(u32, Vec<u8>), ErrorKind> {
+        read_frame(&mut self.stream, protocol, &self.key)
+    }
+
+    /// Reads frames until the reply to `command` and returns its payload, see `read_reply`.
+    fn receive_reply(&mut self, protocol: Protocol, command: u32) -> Result<Vec<u8>, ErrorKind> {
+        read_reply(&mut self.stream, protocol, &self.key, command)
+    }
+}
+
+/// Derives the key of a session from the nonces both sides sent.
+fn session_key(
+    protocol: Protocol,
+    local_key: &[u8; 16],
+    local_nonce: &[u8],
+    remote_nonce: &[u8],
+) -> Result<[u8; 16], ErrorKind> {
+    let mixed: Vec<u8> = local_nonce
+        .iter()
+        .zip(remote_nonce)
+        .map(|(a, b)| a ^ b)
+        .collect();
+    let key = match protocol {
+        Protocol::V34 => encrypt_unpadded(local_key, &mixed)?,
+        Protocol::V35 => {
+            let mut tag = [0; 16];
+            encrypt_aead(
+                Cipher::aes_128_gcm(),
+                local_key,
+                Some(&local_nonce[..12]),
+                &[],
+                &mixed,
+                &mut tag,
+            )?
+        }
+    };
+    key.get(..16)
+        .and_then(|key| key.try_into().ok())
+        .ok_or(ErrorKind::ParsingIncomplete)
+}
+
+/// Frames a command, `iv` is only used by 3.5.
+fn encode(
+    protocol: Protocol,
+    key: &[u8; 16],
+    sequence: u32,
+    command: u32,
+    plain: &[u8],
+    iv: [u8; 12],
+) -> Result<Vec<u8>, ErrorKind> {
+    Ok(match protocol {
+        Protocol::V34 => {
+            let encrypted = encrypt(Cipher::aes_128_ecb(), key, None, plain)?;
+            let mut frame = PREFIX_55AA.to_vec();
+            frame.extend(sequence.to_be_bytes());
+            frame.extend(command.to_be_bytes());
+            frame.extend((encrypted.len() as u32 + 36).to_be_bytes());
+            frame.extend(encrypted);
+            frame.extend(hmac(key, &frame)?);
+            frame.extend(SUFFIX_55AA);
+            frame
+        }
+        Protocol::V35 => {
+            let mut frame = PREFIX_6699.to_vec();
+            frame.extend([0, 0]);
+            frame.extend(sequence.to_be_bytes());
+            frame.extend(command.to_be_bytes());
+            frame.extend((plain.len() as u32 + 28).to_be_bytes());
+
+            let mut tag = [0; 16];
+            let encrypted = encrypt_aead(
+                Cipher::aes_128_gcm(),
+                key,
+                Some(&iv),
+                &frame[4..],
+                plain,
+                &mut tag,
+            )?;
+            frame.extend(iv);
+            frame.extend(encrypted);
+            frame.extend(tag);
+            frame.extend(SUFFIX_6699);
+            frame
+        }
+    })
+}
+
+/// Reads the next frame and returns its command and decrypted payload, without the return code
+/// and version header.
+fn read_frame(
+    stream: &mut impl Read,
+    protocol: Protocol,
+    key: &[u8; 16],
+) -> Result<(u32, Vec<u8>), ErrorKind> {
+    let (command, payload) = match protocol {
+        Protocol::V34 => {
+            let mut header = [0; 16];
+            stream.read_exact(&mut header)?;
+            if header[..4] != PREFIX_55AA {
+                return Err(ErrorKind::ParsingIncomplete);
+            }
+            let command = be_u32(&header[8..12]);
+            let length = be_u32(&header[12..16]) as usize;
+            if length < 36 {
+                return Err(ErrorKind::ParsingIncomplete);
+            }
+            check_length(length)?;
+
+            let mut rest = vec![0; length];
+            stream.read_exact(&mut rest)?;
+            let (body, trailer) = rest.split_at(length - 36);
+            let signed = [&header[..], body].concat();
+            if trailer[..32] != hmac(key, &signed)? {
+                return Err(ErrorKind::CRCError);
+            }
+
+            let body = strip_return_code(body);
+            let payload = if body.is_empty() {
+                Vec::new()
+            } else {
+                decrypt(Cipher::aes_128_ecb(), key, None, body)?
+            };
+            (command, payload)
+        }
+        Protocol::V35 => {
+            let mut header = [0; 18];
+            stream.read_exact(&mut header)?;
+            if header[..4] != PREFIX_6699 {
+                return Err(ErrorKind::ParsingIncomplete);
+            }
+            let command = be_u32(&header[10..14]);
+            let length = be_u32(&header[14..18]) as usize;
+            if length < 28 {
+                return Err(ErrorKind::ParsingIncomplete);
+            }
+            check_length(length)?;
+
+            let mut rest = vec![0; length + SUFFIX_6699.len()];
+            stream.read_exact(&mut rest)?;
+            let (iv, rest) = rest.split_at(12);
+            let (encrypted, tag) = rest[..length - 12].split_at(length - 28);
+            let payload = decrypt_aead(
+                Cipher::aes_128_gcm(),
+                key,
+                Some(iv),
+                &header[4..],
+                encrypted,
+                tag,
+            )?;
+            (command, strip_return_code(&payload).to_vec())
+        }
+    };
+
+    let payload = match payload.get(..2) {
+        Some(b"3.") if payload.len() >= 15 => payload[15..].to_vec(),
+        _ => payload,
+    };
+    Ok((command, payload))
+}
+
+/// Reads frames until one answers `command` and returns its payload. Status pushes the device
+/// sent on its own since the last request are still waiting in a kept connection; they are
+/// skipped, as is anything else that isn't the reply.
+fn read_reply(
+    stream: &mut impl Read,
+    protocol: Protocol,
+    key: &[u8; 16],
+    command: u32,
+) -> Result<Vec<u8>, ErrorKind> {
+    for _ in 0..=MAX_SKIPPED {
+        match read_frame(stream, protocol, key)? {
+            (received, payload) if received == command => return Ok(payload),
+            (STATUS, payload) => debug!(
+                "Skipped a status push while waiting for a reply: {}",
+                String::from_utf8_lossy(&payload)
+            ),
+            (received, _) => debug!(
+                "Skipped a frame with command {} while waiting for {}.",
+                received, command
+            ),
+        }
+    }
+    Err(ErrorKind::ParsingIncomplete)
+}
+
+fn check_length(length: usize) -> Result<(), ErrorKind> {
+    if length > MAX_FRAME {
+        return Err(ErrorKind::TcpError(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the device announced a frame of {} bytes, more than {}",
+                length, MAX_FRAME
+            ),
+        )));
+    }
+    Ok(())
+}
+
+/// Replies from the device may start with a four byte return code, which is always small.
+fn strip_return_code(payload: &[u8]) -> &[u8] {
+    match payload.get(..4) {
+        Some(code) if be_u32(code) & 0xffff_ff00 == 0 => &payload[4..],
+        _ => payload,
+    }
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+    let key = PKey::hmac(key)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data)?;
+    Ok(signer.sign_to_vec()?)
+}
+
+fn encrypt_unpadded(key: &[u8], data: &[u8]) -> Result<Vec<u8>, ErrorKind> {
+    let mut crypter = Crypter::new(Cipher::aes_128_ecb(), Mode::Encrypt, key, None)?;
+    crypter.pad(false);
+    let mut encrypted = vec![0; data.len() + 16];
+    let mut length = crypter.update(data, &mut encrypted)?;
+    length += crypter.finalize(&mut encrypted[length..])?;
+    encrypted.truncate(length);
+    Ok(encrypted)
+}
+
+fn now() -> Result<u64, ErrorKind> {
+    Ok(SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    // Built the way tinytuya packs messages, with the local key below and fixed nonces and IVs.
+    const KEY: &[u8; 16] = b"0123456789abcdef";
+    const V34_CONTROL: &str = "000055aa000000010000000d000000544490b05d74be9368c24a038cbaeded8e7dafb7b4370dab264878a7dbed69289164215f365b0dad6b2ac8fab15b1d40bd31d31e644440c3cb2aa9794463ff27135c052e15699e0424d21c8884e94dd6ef0000aa55";
+    const V35_CONTROL: &str = "000066990000000000010000000d0000003e303132333435363738396162516c38fb2e602d89030377e484daa388b4ec061b48813e2156af534a03e0aeeb8684911217f5e03c26f9e7d9f82409a8602000009966";
+    const V34_REPLY: &str = "000055aa000000070000001000000058000000006c43740537c46421392c83bfa222a5eec4d334f77e153f0e350f65f3a1aea88403000aeb4ff51d29188f82e4a162a1f6faec73a9165b4facd880a45b33996ac130206eb5d95c76fd7bb6c06d3a64b97a0000aa55";
+    const V35_REPLY: &str = "000066990000000000070000001000000050626139383736353433323130df793e8874d3d1e3afb6e28c9fee066571211152d1249a0c4931a105ea6090ad66492a3106301348a6f731a4ff401a29f741ce21a59093811cbc470f5869cb0e9d6c3dfe00009966";
+    const REPLY: &[u8] = br#"{"dps":{"20":true,"21":"colour"}}"#;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn control(protocol: Protocol) -> Vec<u8> {
+        [&protocol.header()[..], br#"{"dps":{"20":true}}"#].concat()
+    }
+
+    #[test]
+    fn encodes_34_frames() {
+        let frame = encode(
+            Protocol::V34,
+            KEY,
+            1,
+            CONTROL,
+            &control(Protocol::V34),
+            [0; 12],
+        );
+        assert_eq!(frame.unwrap(), hex(V34_CONTROL));
+    }
+
+    #[test]
+    fn encodes_35_frames() {
+        let iv = *b"0123456789ab";
+        let frame = encode(Protocol::V35, KEY, 1, CONTROL, &control(Protocol::V35), iv);
+        assert_eq!(frame.unwrap(), hex(V35_CONTROL));
+    }
+
+    #[test]
+    fn decodes_34_replies() {
+        let (command, payload) =
+            read_frame(&mut Cursor::new(hex(V34_REPLY)), Protocol::V34, KEY).unwrap();
+        assert_eq!(command, QUERY);
+        assert_eq!(payload, REPLY);
+    }
+
+    #[test]
+    fn decodes_35_replies() {
+        let (command, payload) =
+            read_frame(&mut Cursor::new(hex(V35_REPLY)), Protocol::V35, KEY).unwrap();
+        assert_eq!(command, QUERY);
+        assert_eq!(payload, REPLY);
+    }
+
+    #[test]
+    fn round_trips_both_versions() {
+        for protocol in [Protocol::V34, Protocol::V35] {
+            let frame = encode(protocol, KEY, 9, CONTROL, &control(protocol), [7; 12]).unwrap();
+            let (command, payload) = read_frame(&mut Cursor::new(frame), protocol, KEY).unwrap();
+            assert_eq!(command, CONTROL);
+            assert_eq!(payload, br#"{"dps":{"20":true}}"#);
+        }
+    }
+
+    #[test]
+    fn rejects_a_wrong_hmac() {
+        let mut frame = hex(V34_REPLY);
+        let signature = frame.len() - 10;
+        frame[signature] ^= 1;
+        let result = read_frame(&mut Cursor::new(frame), Protocol::V34, KEY);
+        assert!(matches!(result, Err(ErrorKind::CRCError)));
+    }
+
+    #[test]
+    fn rejects_a_wrong_gcm_tag_or_header() {
+        for byte in [9, hex(V35_REPLY).len() - 6] {
+            let mut frame = hex(V35_REPLY);
+            frame[byte] ^= 1;
+            let result = read_frame(&mut Cursor::new(frame), Protocol::V35, KEY);
+            assert!(result.is_err(), "byte {} was not authenticated", byte);
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_frames_before_reading_them() {
+        let mut header = hex(V34_REPLY)[..16].to_vec();
+        header[12..16].copy_from_slice(&u32::MAX.to_be_bytes());
+        let result = read_frame(&mut Cursor::new(header), Protocol::V34, KEY);
+        assert!(
+            matches!(result, Err(ErrorKind::TcpError(error)) if error.kind() == io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn derives_session_keys() {
+        let (local, remote) = (b"0123456789abcdef", b"fedcba9876543210");
+        assert_eq!(
+            session_key(Protocol::V34, KEY, local, remote)
+                .unwrap()
+                .to_vec(),
+            hex("6575cf6b37479d9215337ff9767fe786")
+        );
+        assert_eq!(
+            session_key(Protocol::V35, KEY, local, remote)
+                .unwrap()
+                .to_vec(),
+            hex("34165bab783422860c0c23b2d48cf7a5")
+        );
+    }
+
+    #[test]
+    fn skips_status_pushes_ahead_of_the_reply() {
+        let push = [
+            &Protocol::V34.header()[..],
+            br#"{"dps":{"20":false},"t":1}"#,
+        ]
+        .concat();
+        let mut frames = encode(Protocol::V34, KEY, 2, STATUS, &push, [0; 12]).unwrap();
+        frames.extend(hex(V34_REPLY));
+
+        let reply = read_reply(&mut Cursor::new(frames), Protocol::V34, KEY, QUERY).unwrap();
+        assert_eq!(reply, REPLY);
+    }
+
+    #[test]
+    fn gives_up_on_endless_pushes() {
+        let push = encode(Protocol::V35, KEY, 2, STATUS, b"{}", [0; 12]).unwrap();
+        let frames = push.repeat(MAX_SKIPPED + 2);
+        let result = read_reply(&mut Cursor::new(frames), Protocol::V35, KEY, CONTROL);
+        assert!(matches!(result, Err(ErrorKind::ParsingIncomplete)));
+    }
+
+    #[test]
+    fn strips_only_small_return_codes() {
+        assert_eq!(strip_return_code(&[0, 0, 0, 1, b'{']), b"{");
+        assert_eq!(strip_return_code(b"{\"dps\""), b"{\"dps\"");
+        assert_eq!(strip_return_code(&[0, 0]), &[0, 0]);
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::HashMap,
+    io::{self, IsTerminal, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use log::{info, warn};
+use serde_json::json;
+use toml_edit::{value, DocumentMut, Item};
+use tuya_bulb_screen_color::capture::display_sizes;
+
+use crate::{
+    capabilities,
+    config::{self, Config, DeviceConfig},
+    connect, create_dps_payload, devices, discovery, query_dps, send,
+    zones::EDGES,
+};
+
+/// How long to listen for devices announcing themselves.
+const DISCOVERY: Duration = Duration::from_secs(10);
+/// How long the bulb stays on and off in each of its test blinks.
+const BLINK: Duration = Duration::from_millis(600);
+const BLINKS: usize = 3;
+
+/// Whether to set up a config before doing anything: there is none at `path` (or the default
+/// location) yet and someone at the terminal to answer the questions.
+pub fn first_run(path: Option<&Path>) -> bool {
+    let path = path.map(Path::to_path_buf).or_else(config::default_path);
+    path.is_some_and(|path| !path.exists()) && io::stdin().is_terminal()
+}
+
+fn ask(question: &str) -> Result<String, String> {
+    print!("{} ", question);
+    io::stdout().flush().map_err(|error| error.to_string())?;
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .map_err(|error| format!("Failed to read the answer: {}", error))?;
+    Ok(answer.trim().to_string())
+}
+
+fn confirm(question: &str, default: bool) -> Result<bool, String> {
+    let answer = ask(&format!(
+        "{} [{}]",
+        question,
+        if default { "Y/n" } else { "y/N" }
+    ))?;
+    Ok(match answer.as_str() {
+        "" => default,
+        answer => matches!(answer, "y" | "Y" | "yes"),
+    })
+}
+
+/// Walks through setting up the config: finds the devices on the LAN, takes their local keys
+/// from a tinytuya/tuya-cli dump or asks for them, blinks each one to check it answers, asks
+/// which display to capture and which edge of the screen each bulb follows, and writes it all
+/// to the config at `path`.
+pub fn run(path: Option<PathBuf>) -> Result<(), String> {
+    let existing = path.clone().or_else(config::default_path);
+    if let Some(existing) = existing.filter(|existing| existing.exists()) {
+        let question = format!(
+            "Replace {}? It is kept in the backups next to it.",
+            existing.display()
+        );
+        if !confirm(&question, false)? {
+            return Ok(());
+        }
+    }
+
+    println!("Let's set up the config, press Ctrl+C to stop at any point.");
+    println!();
+
+    let mut document = DocumentMut::new();
+    add_devices(&mut document)?;
+    test_devices(&mut document)?;
+    pick_display(&mut document)?;
+    pick_zones(&mut document)?;
+    document["mode"] = value("color-picker");
+
+    let path = config::edit(path, |config| {
+        *config = document;
+        Ok(())
+    })?;
+    info!("Wrote the config to {}.", path.display());
+    Ok(())
+}
+
+fn add_devices(document: &mut DocumentMut) -> Result<(), String> {
+    let seen = discovery::run(DISCOVERY).unwrap_or_else(|error| {
+        warn!("{}", error);
+        Vec::new()
+    });
+    println!();
+
+    // The local keys are in the Tuya cloud, which the tinytuya and tuya-cli wizards pull them from.
+    let dump = ask(
+        "Path to a tinytuya or tuya-cli device dump with the local keys, or empty to enter them:",
+    )?;
+    if !dump.is_empty() {
+        let imported = devices::import(Path::new(&dump), document)?;
+        devices::track(&seen, document)?;
+        info!("Imported {} device(s).", imported);
+    }
+
+    for announcement in &seen {
+        let known = document["devices"]
+            .as_array_of_tables()
+            .is_some_and(|tables| {
+                tables
+                    .iter()
+                    .any(|table| table.get("id").and_then(Item::as_str) == Some(&announcement.id))
+            });
+        if known {
+            continue;
+        }
+
+        let key = ask(&format!(
+            "Local key of {} at {}, or empty to leave it out:",
+            announcement.id, announcement.ip
+        ))?;
+        if key.is_empty() {
+            continue;
+        }
+        let name = ask("Name for it, e.g. Desk lamp (optional):")?;
+        let fields = devices::DeviceFields {
+            name: Some(name).filter(|name| !name.is_empty()),
+            key: Some(key),
+            ip: Some(announcement.ip.clone()),
+            version: Some(announcement.version.clone()),
+            product_key: announcement.product_key.clone(),
+            model: None,
+        };
+        devices::add(&announcement.id, &fields, document)?;
+    }
+
+    // Devices on protocol 3.5 don't announce themselves.
+    loop {
+        let id = ask("Id of another device to add, or empty when done:")?;
+        if id.is_empty() {
+            return Ok(());
+        }
+        let key = ask("Its local key:")?;
+        let ip = ask("Its IP address:")?;
+        if ip.parse::<IpAddr>().is_err() {
+            warn!("{} isn't an IP address, leaving the device out.", ip);
+            continue;
+        }
+        let version = ask("Its protocol version, 3.1, 3.3, 3.4 or 3.5 [3.3]:")?;
+        let name = ask("Name for it (optional):")?;
+        let fields = devices::DeviceFields {
+            name: Some(name).filter(|name| !name.is_empty()),
+            key: Some(key),
+            ip: Some(ip),
+            version: Some(version).filter(|version| !version.is_empty()),
+            ..Default::default()
+        };
+        if let Err(error) = devices::add(&id, &fields, document) {
+            warn!("{}", error);
+        }
+    }
+}
+
+/// Blinks every device and drops those that don't answer or weren't seen blinking, unless told
+/// to keep them.
+fn test_devices(document: &mut DocumentMut) -> Result<(), String> {
+    let config: Config =
+        toml::from_str(&document.to_string()).map_err(|error| error.to_string())?;
+    if config.devices.is_empty() {
+        return Err("No devices to set up.".to_string());
+    }
+
+    for target in &config.devices {
+        println!("Blinking {}...", target.label());
+        let keep = match blink(target, &config) {
+            Ok(()) => confirm(&format!("Did {} blink?", target.label()), true)?,
+            Err(error) => {
+                warn!("{}", error);
+                false
+            }
+        } || confirm(
+            &format!(
+                "Keep {} anyway? Check its key and IP otherwise.",
+                target.label()
+            ),
+            false,
+        )?;
+        if !keep {
+            devices::remove(&target.id, document)?;
+        }
+    }
+    Ok(())
+}
+
+/// Flashes the device red a few times, then puts back what it showed before.
+fn blink(target: &DeviceConfig, config: &Config) -> Result<(), String> {
+    let capabilities = capabilities::resolve(target, &HashMap::new())?;
+    let ip = target
+        .ip
+        .clone()
+        .ok_or_else(|| format!("{} has no IP address.", target.label()))?;
+    let device = connect(
+        target.key.clone(),
+        ip,
+        target.version.clone(),
+        config.connection,
+    )
+    .map_err(|error| format!("Failed to connect to {}: {}", target.label(), error))?;
+    let before = query_dps(&device, &target.id)
+        .ok_or_else(|| format!("{} didn't answer, is the key right?", target.label()))?;
+
+    let switch = capabilities.switch_dp.to_string();
+    for _ in 0..BLINKS {
+        let red = [
+            (switch.clone(), json!(true)),
+            (capabilities.mode_dp.to_string(), json!("colour")),
+            (
+                capabilities.colour_dp.to_string(),
+                json!(capabilities.encode_colour((0, 100, 100))),
+            ),
+        ];
+        send(&device, create_dps_payload(target.id.clone(), red.into()));
+        thread::sleep(BLINK);
+        let off = [(switch.clone(), json!(false))];
+        send(&device, create_dps_payload(target.id.clone(), off.into()));
+        thread::sleep(BLINK);
+    }
+
+    let before = before.into_iter().collect();
+    if !send(&device, create_dps_payload(target.id.clone(), before)) {
+        return Err(format!("{} didn't take its state back.", target.label()));
+    }
+    Ok(())
+}
+
+fn pick_display(document: &mut DocumentMut) -> Result<(), String> {
+    let sizes = match display_sizes() {
+        Ok(sizes) => sizes,
+        Err(error) => {
+            warn!("{}, leaving the display to pick later.", error);
+            return Ok(());
+        }
+    };
+    if sizes.len() < 2 {
+        return Ok(());
+    }
+
+    for (index, (width, height)) in sizes.iter().enumerate() {
+        println!("{}: {}x{}", index, width, height);
+    }
+    loop {
+        let answer = ask("Display to capture, its number or all [0]:")?;
+        let display = match answer.as_str() {
+            "" => return Ok(()),
+            "all" => value("all"),
+            index => match index.parse::<usize>() {
+                Ok(index) if index < sizes.len() => value(index as i64),
+                _ => continue,
+            },
+        };
+        document["capture"]["display"] = display;
+        return Ok(());
+    }
+}
+
+/// Lets every bulb follow an edge of the screen, when there are several.
+fn pick_zones(document: &mut DocumentMut) -> Result<(), String> {
+    let Some(tables) = document["devices"].as_array_of_tables_mut() else {
+        return Ok(());
+    };
+    if tables.len() < 2 || !confirm("Give each bulb an edge of the screen to follow?", false)? {
+        return Ok(());
+    }
+
+    for table in tables.iter_mut() {
+        let label = table
+            .get("name")
+            .or_else(|| table.get("id"))
+            .and_then(Item::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let edge = loop {
+            let edge = ask(&format!(
+                "Edge {} follows, {} or empty for the whole screen:",
+                label,
+                EDGES.join(", ")
+            ))?;
+            if edge.is_empty() || EDGES.contains(&edge.as_str()) {
+                break edge;
+            }
+        };
+        if !edge.is_empty() {
+            table["zone"] = value(edge);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,130 @@
+use std::{
+    env,
+    fs::{File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use colors_transform::Rgb;
+use log::{info, warn};
+
+use crate::rgb_bytes;
+
+/// Longest side of the published frame in pixels.
+const MAX_SIDE: usize = 64;
+/// Most colors published, the frame's color first.
+const MAX_COLORS: usize = 32;
+
+const MAGIC: &[u8; 8] = b"TBSCFRM1";
+const HEADER: usize = 32;
+const PIXELS: usize = HEADER + MAX_COLORS * 4;
+/// The segment never changes size, so readers can map it once.
+const SIZE: usize = PIXELS + MAX_SIDE * MAX_SIDE * 4;
+
+/// Where the segment goes without a path: `/dev/shm` where there is one, the temp dir
+/// elsewhere.
+pub fn default_path() -> PathBuf {
+    let shm = Path::new("/dev/shm");
+    if shm.is_dir() {
+        shm.join("tuya-bulb-screen-color")
+    } else {
+        env::temp_dir().join("tuya-bulb-screen-color.shm")
+    }
+}
+
+/// A file in shared memory that every captured frame is published to, downscaled, with the
+/// colors extracted from it, for other programs to read without capturing the screen again.
+///
+/// Layout, little endian:
+///
+/// | offset | size        | content                                                     |
+/// |--------|-------------|-------------------------------------------------------------|
+/// | 0      | 8           | `TBSCFRM1`                                                  |
+/// | 8      | 4           | sequence, odd while a frame is being written                |
+/// | 12     | 4           | frame width                                                 |
+/// | 16     | 4           | frame height                                                |
+/// | 20     | 4           | number of colors                                            |
+/// | 24     | 8           | capture time, milliseconds since the Unix epoch             |
+/// | 32     | 32 × 4      | colors as RGBA, the frame's color first, then its zones'    |
+/// | 160    | 64 × 64 × 4 | the frame as RGBA, `width` × `height` of it, row after row |
+///
+/// Readers copy what they need and read the sequence again; when it changed or is odd, a frame
+/// was written meanwhile and they try again.
+pub struct SharedFrame {
+    file: File,
+    sequence: u32,
+    buffer: Vec<u8>,
+}
+
+impl SharedFrame {
+    pub fn create(path: &Path) -> Result<SharedFrame, String> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .and_then(|file| file.set_len(SIZE as u64).map(|()| file))
+            .map_err(|error| format!("Failed to create {}: {}", path.display(), error))?;
+
+        info!("Publishing frames to {}.", path.display());
+        Ok(SharedFrame {
+            file,
+            sequence: 0,
+            buffer: vec![0; SIZE],
+        })
+    }
+
+    /// Publishes a `width` × `height` RGBA frame, scaled down to fit `MAX_SIDE`, and `colors`.
+    pub fn publish(&mut self, rgba: &[u8], width: usize, height: usize, colors: &[Rgb]) {
+        let scale = (width.max(height) as f32 / MAX_SIDE as f32).max(1.0);
+        let scaled_width = ((width as f32 / scale) as usize).clamp(1, MAX_SIDE);
+        let scaled_height = ((height as f32 / scale) as usize).clamp(1, MAX_SIDE);
+        let colors = &colors[..colors.len().min(MAX_COLORS)];
+        let millis = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+
+        let buffer = &mut self.buffer;
+        buffer[12..16].copy_from_slice(&(scaled_width as u32).to_le_bytes());
+        buffer[16..20].copy_from_slice(&(scaled_height as u32).to_le_bytes());
+        buffer[20..24].copy_from_slice(&(colors.len() as u32).to_le_bytes());
+        buffer[24..32].copy_from_slice(&millis.to_le_bytes());
+
+        for (slot, color) in buffer[HEADER..PIXELS].chunks_exact_mut(4).zip(colors) {
+            let [r, g, b] = rgb_bytes(color);
+            slot.copy_from_slice(&[r, g, b, 255]);
+        }
+
+        for row in 0..scaled_height {
+            let y = row * height / scaled_height;
+            for column in 0..scaled_width {
+                let x = column * width / scaled_width;
+                let source = (y * width + x) * 4;
+                let target = PIXELS + (row * scaled_width + column) * 4;
+                buffer[target..target + 4].copy_from_slice(&rgba[source..source + 4]);
+            }
+        }
+
+        if let Err(error) = self.write() {
+            warn!("Failed to publish the frame: {}", error);
+        }
+    }
+
+    fn write(&mut self) -> io::Result<()> {
+        self.sequence = self.sequence.wrapping_add(1);
+        self.write_at(8, &self.sequence.to_le_bytes())?;
+        self.write_at(12, &self.buffer[12..])?;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.write_at(8, &self.sequence.to_le_bytes())?;
+        // The magic goes last, so it only shows once a whole frame is there.
+        self.write_at(0, MAGIC)
+    }
+
+    fn write_at(&self, offset: u64, bytes: &[u8]) -> io::Result<()> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(bytes)
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, warn};
+use minifb::{Window, WindowOptions};
+use rust_tuyapi::Payload;
+use tuya_bulb_screen_color::{colorspace::ColorSpace, tuya::tuya2hsv};
+
+use crate::capabilities::Capabilities;
+
+const WIDTH: usize = 240;
+const HEIGHT: usize = 160;
+/// How long the simulated bulb takes to fade to a new color.
+const FADE: Duration = Duration::from_millis(400);
+/// What white mode looks like in the window.
+const WHITE: [f32; 3] = [255.0, 236.0, 210.0];
+
+enum Message {
+    Power(bool),
+    Color([f32; 3]),
+}
+
+/// Stands in for a bulb: payloads are decoded like a bulb would and the result is shown in a
+/// small always-on-top window.
+pub struct Simulator {
+    capabilities: Capabilities,
+    messages: Sender<Message>,
+}
+
+impl Simulator {
+    pub fn open(capabilities: Capabilities, color_space: ColorSpace) -> Simulator {
+        let (messages, receiver) = mpsc::channel();
+        thread::spawn(move || run(receiver, color_space));
+
+        Simulator {
+            capabilities,
+            messages,
+        }
+    }
+
+    pub fn set(&self, payload: &Payload) {
+        let Payload::Struct(payload) = payload else {
+            return;
+        };
+        let Some(dps) = &payload.dps else {
+            return;
+        };
+
+        if let Some(power) = dps
+            .get(&self.capabilities.switch_dp.to_string())
+            .and_then(|value| value.as_bool())
+        {
+            let _ = self.messages.send(Message::Power(power));
+        }
+
+        let mode = dps
+            .get(&self.capabilities.mode_dp.to_string())
+            .and_then(|value| value.as_str());
+        let colour = dps
+            .get(&self.capabilities.colour_dp.to_string())
+            .and_then(|value| value.as_str());
+
+        let color = match (mode, colour) {
+            (Some("white"), _) => Some(WHITE),
+            (_, Some(colour)) => match tuya2hsv(colour) {
+                Ok((h, s, v)) => Some(ColorSpace::Hsv.to_rgb([h as f32, s as f32, v as f32])),
+                Err(error) => {
+                    warn!("Simulated bulb can't decode {}: {}", colour, error);
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        if let Some(color) = color {
+            debug!("Simulated bulb fades to {:?}.", color);
+            let _ = self.messages.send(Message::Color(color));
+        }
+    }
+}
+
+fn run(messages: Receiver<Message>, color_space: ColorSpace) {
+    let options = WindowOptions {
+        topmost: true,
+        ..WindowOptions::default()
+    };
+    let mut window = match Window::new("Simulated bulb", WIDTH, HEIGHT, options) {
+        Ok(window) => window,
+        Err(error) => {
+            error!("Failed to open the simulator window: {}", error);
+            return;
+        }
+    };
+    window.set_target_fps(60);
+
+    let mut buffer = vec![0u32; WIDTH * HEIGHT];
+    let mut powered = true;
+    let mut color = [0.0; 3];
+    let mut from = color;
+    let mut shown = color;
+    let mut fade_started = Instant::now();
+
+    while window.is_open() {
+        for message in messages.try_iter() {
+            match message {
+                Message::Power(on) => powered = on,
+                Message::Color(new) => color = new,
+            }
+            from = shown;
+            fade_started = Instant::now();
+        }
+
+        let target = if powered { color } else { [0.0; 3] };
+        let t = fade_started.elapsed().as_secs_f32() / FADE.as_secs_f32();
+        shown = color_space.mix(from, target, t);
+
+        let [r, g, b] = shown.map(|channel| channel.round() as u32);
+        buffer.fill((r << 16) | (g << 8) | b);
+
+        if let Err(error) = window.update_with_buffer(&buffer, WIDTH, HEIGHT) {
+            error!("Failed to draw the simulator window: {}", error);
+            return;
+        }
+    }
+
+    info!("Simulator window closed.");
+    std::process::exit(0);
+}
@@ -0,0 +1,85 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+
+use crate::{
+    capabilities::Capabilities, config::DeviceConfig, create_color_payload, describe_color,
+    extract_color, grading::Grade, rgb2hsv, send, CaptureOptions, Output,
+};
+
+/// Images are scaled down to fit this size before their color is extracted, photos are far
+/// larger than needed.
+const THUMBNAIL_SIZE: u32 = 256;
+
+const EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+/// The images in `dir`, sorted by name.
+fn images(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(dir)
+        .map_err(|error| format!("Failed to read {}: {}", dir.display(), error))?;
+
+    let mut images: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        })
+        .collect();
+    images.sort();
+    Ok(images)
+}
+
+/// Shows the color of one image of `dir` after another, `interval` apart, on every device. The
+/// directory is read again before each image, so images added or removed meanwhile are picked up
+/// in name order.
+pub fn run(
+    dir: &Path,
+    interval: Duration,
+    devices: Vec<(DeviceConfig, Capabilities, Output)>,
+    capture: CaptureOptions,
+    grade: Grade,
+) {
+    info!("Showing the images in {}...", dir.display());
+    let mut last: Option<PathBuf> = None;
+
+    loop {
+        let images = images(dir).unwrap_or_else(|error| {
+            warn!("{}", error);
+            Vec::new()
+        });
+        let next = images
+            .iter()
+            .find(|image| last.as_ref().is_none_or(|last| *image > last))
+            .or(images.first());
+
+        let Some(next) = next.cloned() else {
+            debug!("No images in {}.", dir.display());
+            thread::sleep(interval);
+            continue;
+        };
+
+        match image::open(&next) {
+            Ok(image) => {
+                let image = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgba8();
+                let color = grade.apply(&extract_color(&image, &capture));
+                info!("Showing {} as {}.", next.display(), describe_color(&color));
+
+                for (target, capabilities, device) in &devices {
+                    let payload =
+                        create_color_payload(target.id.clone(), capabilities, rgb2hsv(&color));
+                    send(device, payload);
+                }
+            }
+            Err(error) => warn!("Skipping {}: {}", next.display(), error),
+        }
+
+        last = Some(next);
+        thread::sleep(interval);
+    }
+}
@@ -0,0 +1,133 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use log::{info, warn};
+use serde_json::Value;
+
+use crate::{
+    config::{Config, DeviceConfig},
+    connect, create_dps_payload, query_dps, send, state, Output,
+};
+
+/// The data points of each device by id, as they reported them.
+type Snapshot = BTreeMap<String, BTreeMap<String, Value>>;
+
+fn path() -> Option<PathBuf> {
+    state::dir().map(|dir| dir.join("snapshots.json"))
+}
+
+fn load() -> BTreeMap<String, Snapshot> {
+    path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn store(snapshots: &BTreeMap<String, Snapshot>) -> Result<PathBuf, String> {
+    let path = path().ok_or_else(|| "Could not determine the state directory.".to_string())?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|error| format!("Failed to create {}: {}", parent.display(), error))?;
+    }
+
+    let contents = serde_json::to_string_pretty(snapshots).map_err(|error| error.to_string())?;
+    fs::write(&path, contents)
+        .map_err(|error| format!("Failed to write {}: {}", path.display(), error))?;
+    Ok(path)
+}
+
+fn open(target: &DeviceConfig, config: &Config) -> Result<Output, String> {
+    let ip = target
+        .ip
+        .clone()
+        .ok_or_else(|| format!("Device {} has no IP address configured.", target.label()))?;
+    connect(
+        target.key.clone(),
+        ip,
+        target.version.clone(),
+        config.connection,
+    )
+    .map_err(|error| format!("Failed to connect to {}: {}", target.label(), error))
+}
+
+/// Queries every target for all its data points and keeps them under `name`, replacing a
+/// snapshot of that name. Fails when a device didn't answer, after saving the others.
+pub fn save(name: &str, targets: Vec<DeviceConfig>, config: &Config) -> Result<(), String> {
+    let mut snapshot = Snapshot::new();
+    let mut failed = Vec::new();
+
+    for target in targets {
+        let dps = open(&target, config).and_then(|device| {
+            query_dps(&device, &target.id)
+                .ok_or_else(|| format!("{} did not report its state.", target.label()))
+        });
+        match dps {
+            Ok(dps) => {
+                snapshot.insert(target.id, dps);
+            }
+            Err(error) => failed.push(error),
+        }
+    }
+
+    if snapshot.is_empty() {
+        return Err(failed.join("\n"));
+    }
+
+    let mut snapshots = load();
+    let devices = snapshot.len();
+    snapshots.insert(name.to_string(), snapshot);
+    let path = store(&snapshots)?;
+    info!(
+        "Saved {} device(s) as \"{}\" in {}.",
+        devices,
+        name,
+        path.display()
+    );
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(failed.join("\n"))
+    }
+}
+
+/// Sends every target the data points saved for it under `name`, all in one payload so that the
+/// bulb switches straight to the saved state. Targets that aren't in the snapshot are left alone.
+pub fn recall(name: &str, targets: Vec<DeviceConfig>, config: &Config) -> Result<(), String> {
+    let snapshots = load();
+    let Some(snapshot) = snapshots.get(name) else {
+        let known: Vec<&str> = snapshots.keys().map(String::as_str).collect();
+        return Err(if known.is_empty() {
+            "No snapshots saved yet.".to_string()
+        } else {
+            format!("No snapshot \"{}\", saved are {}.", name, known.join(", "))
+        });
+    };
+
+    let mut failed = Vec::new();
+    for target in targets {
+        let Some(dps) = snapshot.get(&target.id) else {
+            warn!(
+                "{} isn't in \"{}\", leaving it as it is.",
+                target.label(),
+                name
+            );
+            continue;
+        };
+
+        let dps = dps.iter().map(|(dp, value)| (dp.clone(), value.clone()));
+        let payload = create_dps_payload(target.id.clone(), dps.collect());
+        match open(&target, config) {
+            Ok(device) if send(&device, payload) => {
+                info!("Restored {} from \"{}\".", target.label(), name)
+            }
+            Ok(_) => failed.push(format!("{} didn't take the snapshot.", target.label())),
+            Err(error) => failed.push(error),
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(failed.join("\n"))
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::HexColor, profiles::ContentType, Feature};
+
+/// How often a changed color alone is written, other changes are written right away.
+const COLOR_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// `startup` in the config: what to do when no `--mode` is given.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Startup {
+    /// Run the `mode` from the config.
+    #[default]
+    Mode,
+    /// Run the mode of the previous run, or the config's `mode` when there is none.
+    Resume,
+    /// Switch the bulbs off.
+    Off,
+    /// Show a fixed color.
+    Scene(HexColor),
+}
+
+/// What is kept between runs. Everything but the mode is only restored when the same mode runs
+/// again, so a crash or reboot picks up where the previous run left off.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct State {
+    pub mode: Option<Feature>,
+    /// Profile picked on the gamepad, `None` while it is detected.
+    pub profile: Option<ContentType>,
+    /// Devices whose syncing is paused, by label.
+    pub paused: Vec<String>,
+    /// The last screen color as hue, saturation and lightness.
+    pub color: Option<[f32; 3]>,
+}
+
+/// Where the state and the other files kept between runs go.
+pub fn dir() -> Option<PathBuf> {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join("tuya-bulb-screen-color"))
+}
+
+fn path() -> Option<PathBuf> {
+    dir().map(|dir| dir.join("state.json"))
+}
+
+/// Reads the state of the previous run; a missing or unreadable state is an empty one.
+pub fn load() -> State {
+    path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+pub fn save(state: &State) -> Result<(), String> {
+    let path = path().ok_or_else(|| "Could not determine the state directory.".to_string())?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|error| format!("Failed to create {}: {}", parent.display(), error))?;
+    }
+
+    let contents = serde_json::to_string_pretty(state).map_err(|error| error.to_string())?;
+    fs::write(&path, contents)
+        .map_err(|error| format!("Failed to write {}: {}", path.display(), error))?;
+
+    debug!("Saved the state to {}.", path.display());
+    Ok(())
+}
+
+/// Writes the state as it changes while a mode runs.
+pub struct Recorder {
+    saved: State,
+    saved_at: Instant,
+}
+
+impl Recorder {
+    pub fn new(state: State) -> Recorder {
+        Recorder {
+            saved: state,
+            saved_at: Instant::now(),
+        }
+    }
+
+    pub fn record(&mut self, state: State) {
+        if state == self.saved {
+            return;
+        }
+
+        let only_color = State {
+            color: self.saved.color,
+            ..state.clone()
+        } == self.saved;
+        if only_color && self.saved_at.elapsed() < COLOR_SAVE_INTERVAL {
+            return;
+        }
+
+        if let Err(error) = save(&state) {
+            warn!("{}", error);
+        }
+        self.saved = state;
+        self.saved_at = Instant::now();
+    }
+}
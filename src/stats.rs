@@ -0,0 +1,175 @@
+//! Cheap per-frame statistics that describe a captured frame beyond its dominant color.
+
+use serde::Serialize;
+
+/// Number of buckets in [`FrameStats::saturation_histogram`], each covering 10% of saturation.
+pub const SATURATION_BUCKETS: usize = 10;
+
+/// A pixel counts as black when none of its channels exceeds this value.
+const BLACK_CHANNEL_MAX: u8 = 16;
+
+/// Size of the luminance grid used to measure how much the picture changed between frames.
+const GRID_WIDTH: usize = 16;
+const GRID_HEIGHT: usize = 9;
+
+/// Pixels within this RGB distance of the dominant color count towards its share.
+const DOMINANT_RADIUS: u32 = 48;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameStats {
+    /// Average luminance of all pixels, 0.0–1.0.
+    pub mean_luminance: f32,
+    /// Share of pixels per saturation bucket, summing up to 1.0.
+    pub saturation_histogram: [f32; SATURATION_BUCKETS],
+    /// Share of pixels that are (nearly) black, 0.0–1.0.
+    pub black_ratio: f32,
+    /// Mean absolute luminance difference to the previous frame on a coarse grid, 0.0–1.0.
+    /// Zero for the first frame.
+    pub change_magnitude: f32,
+    /// Share of pixels close to the dominant color, see [`dominant_share`]. Left at 1.0 by
+    /// [`FrameStats::compute`], as the dominant color is picked separately.
+    pub dominant_share: f32,
+    #[serde(skip)]
+    grid: Vec<f32>,
+}
+
+impl FrameStats {
+    /// Computes the statistics of a tightly packed RGBA buffer, comparing against the
+    /// statistics of the previous frame when available. Fully transparent pixels are left out.
+    pub fn compute(
+        rgba: &[u8],
+        width: usize,
+        height: usize,
+        previous: Option<&FrameStats>,
+    ) -> FrameStats {
+        let mut luminance_sum = 0.0f64;
+        let mut black = 0usize;
+        let mut histogram = [0usize; SATURATION_BUCKETS];
+        let mut grid_sums = vec![0.0f64; GRID_WIDTH * GRID_HEIGHT];
+        let mut grid_counts = vec![0usize; GRID_WIDTH * GRID_HEIGHT];
+        let mut count = 0usize;
+
+        for (index, pixel) in rgba.chunks_exact(4).take(width * height).enumerate() {
+            if pixel[3] == 0 {
+                continue;
+            }
+
+            let (r, g, b) = (pixel[0], pixel[1], pixel[2]);
+            let max = r.max(g).max(b);
+            let min = r.min(g).min(b);
+
+            let luminance = (0.2126 * r as f64 + 0.7152 * g as f64 + 0.0722 * b as f64) / 255.0;
+            luminance_sum += luminance;
+
+            if max <= BLACK_CHANNEL_MAX {
+                black += 1;
+            }
+
+            let saturation = if max == 0 {
+                0.0
+            } else {
+                (max - min) as f32 / max as f32
+            };
+            let bucket =
+                ((saturation * SATURATION_BUCKETS as f32) as usize).min(SATURATION_BUCKETS - 1);
+            histogram[bucket] += 1;
+
+            let (x, y) = (index % width, index / width);
+            let cell = (y * GRID_HEIGHT / height) * GRID_WIDTH + x * GRID_WIDTH / width;
+            grid_sums[cell] += luminance;
+            grid_counts[cell] += 1;
+
+            count += 1;
+        }
+
+        if count == 0 {
+            return FrameStats {
+                mean_luminance: 0.0,
+                saturation_histogram: [0.0; SATURATION_BUCKETS],
+                black_ratio: 0.0,
+                change_magnitude: 0.0,
+                dominant_share: 1.0,
+                grid: Vec::new(),
+            };
+        }
+
+        let grid: Vec<f32> = grid_sums
+            .iter()
+            .zip(&grid_counts)
+            .map(|(sum, count)| {
+                if *count == 0 {
+                    0.0
+                } else {
+                    (sum / *count as f64) as f32
+                }
+            })
+            .collect();
+
+        let change_magnitude = match previous {
+            Some(previous) if previous.grid.len() == grid.len() => {
+                grid.iter()
+                    .zip(&previous.grid)
+                    .map(|(current, previous)| (current - previous).abs())
+                    .sum::<f32>()
+                    / grid.len() as f32
+            }
+            _ => 0.0,
+        };
+
+        FrameStats {
+            mean_luminance: (luminance_sum / count as f64) as f32,
+            saturation_histogram: histogram.map(|bucket| bucket as f32 / count as f32),
+            black_ratio: black as f32 / count as f32,
+            change_magnitude,
+            dominant_share: 1.0,
+            grid,
+        }
+    }
+
+    /// Average saturation estimated from the histogram, 0.0–1.0.
+    pub fn mean_saturation(&self) -> f32 {
+        self.saturation_histogram
+            .iter()
+            .enumerate()
+            .map(|(bucket, share)| (bucket as f32 + 0.5) / SATURATION_BUCKETS as f32 * share)
+            .sum()
+    }
+}
+
+/// How much of a tightly packed RGBA buffer the dominant color stands for: the share of its
+/// visible pixels close to `dominant`, 0.0–1.0. Low values mean a fragmented frame whose
+/// dominant color is little more than a guess.
+///
+/// ```
+/// use tuya_bulb_screen_color::stats::dominant_share;
+///
+/// // Three reds, a blue and a transparent pixel.
+/// let rgba = [
+///     255, 0, 0, 255, 250, 10, 0, 255, 240, 0, 20, 255, 0, 0, 255, 255, 255, 0, 0, 0,
+/// ];
+/// assert_eq!(dominant_share(&rgba, [255, 0, 0]), 0.75);
+/// assert_eq!(dominant_share(&rgba, [0, 255, 0]), 0.0);
+/// assert_eq!(dominant_share(&[], [0, 0, 0]), 0.0);
+/// ```
+pub fn dominant_share(rgba: &[u8], dominant: [u8; 3]) -> f32 {
+    let mut close = 0usize;
+    let mut count = 0usize;
+
+    for pixel in rgba.chunks_exact(4).filter(|pixel| pixel[3] != 0) {
+        let distance: u32 = pixel[..3]
+            .iter()
+            .zip(dominant)
+            .map(|(&channel, dominant)| (channel as i32 - dominant as i32).pow(2) as u32)
+            .sum();
+        if distance <= DOMINANT_RADIUS * DOMINANT_RADIUS {
+            close += 1;
+        }
+        count += 1;
+    }
+
+    if count == 0 {
+        0.0
+    } else {
+        close as f32 / count as f32
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use serde_json::Value;
+use tuya_bulb_screen_color::{names::name, tuya::tuya2hsv};
+
+use crate::{
+    capabilities::{self, Capabilities},
+    config::{Config, DeviceConfig},
+    connect, hsv2rgb, query_dps, rgb_bytes,
+};
+
+/// What a bulb reported, read through the data points of its model.
+#[derive(Serialize, Debug)]
+struct Status {
+    device: String,
+    id: String,
+    power: Option<bool>,
+    mode: Option<String>,
+    /// In percent.
+    brightness: Option<u32>,
+    /// In Kelvin.
+    temperature: Option<u32>,
+    colour: Option<Colour>,
+    /// Everything the bulb reported, as it reported it.
+    dps: BTreeMap<String, Value>,
+}
+
+#[derive(Serialize, Debug)]
+struct Colour {
+    hue: u32,
+    saturation: u32,
+    value: u32,
+    /// Closest named color, e.g. `dodger blue`.
+    name: &'static str,
+}
+
+impl Status {
+    fn read(
+        target: &DeviceConfig,
+        capabilities: &Capabilities,
+        dps: BTreeMap<String, Value>,
+    ) -> Status {
+        let dp = |dp: u32| dps.get(&dp.to_string());
+        let number = |value: &Value| value.as_u64().and_then(|value| u32::try_from(value).ok());
+
+        Status {
+            device: target.label().to_string(),
+            id: target.id.clone(),
+            power: dp(capabilities.switch_dp).and_then(Value::as_bool),
+            mode: dp(capabilities.mode_dp)
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            brightness: dp(capabilities.brightness_dp)
+                .and_then(number)
+                .map(|value| capabilities.decode_brightness(value)),
+            temperature: dp(capabilities.temperature_dp)
+                .and_then(number)
+                .map(|value| capabilities.decode_temperature(value)),
+            colour: dp(capabilities.colour_dp)
+                .and_then(Value::as_str)
+                .and_then(|colour| tuya2hsv(colour).ok())
+                .map(|(hue, saturation, value)| Colour {
+                    hue,
+                    saturation,
+                    value,
+                    name: name(rgb_bytes(&hsv2rgb((hue, saturation, value)))),
+                }),
+            dps,
+        }
+    }
+
+    fn print(&self) {
+        println!("{} ({})", self.device, self.id);
+
+        let power = self.power.map(|on| if on { "on" } else { "off" });
+        println!("  {:<12} {}", "power", power.unwrap_or("-"));
+        println!("  {:<12} {}", "mode", self.mode.as_deref().unwrap_or("-"));
+        println!(
+            "  {:<12} {}",
+            "brightness",
+            self.brightness
+                .map_or("-".to_string(), |percent| format!("{}%", percent))
+        );
+        println!(
+            "  {:<12} {}",
+            "temperature",
+            self.temperature
+                .map_or("-".to_string(), |kelvin| format!("{} K", kelvin))
+        );
+        println!(
+            "  {:<12} {}",
+            "colour",
+            self.colour
+                .as_ref()
+                .map_or("-".to_string(), |colour| format!(
+                    "hue {}, saturation {}%, value {}% ({})",
+                    colour.hue, colour.saturation, colour.value, colour.name
+                ))
+        );
+
+        let dps: Vec<String> = self
+            .dps
+            .iter()
+            .map(|(dp, value)| format!("{}={}", dp, value))
+            .collect();
+        println!("  {:<12} {}", "dps", dps.join(" "));
+    }
+}
+
+/// Queries every target for its data points and prints them, as text or as a JSON array. Fails
+/// when a device didn't answer, after printing the others.
+pub fn run(targets: Vec<DeviceConfig>, config: &Config, json: bool) -> Result<(), String> {
+    let mut statuses = Vec::new();
+    let mut failed = Vec::new();
+
+    for target in targets {
+        let status = capabilities::resolve(&target, &config.products).and_then(|capabilities| {
+            let ip = target.ip.clone().ok_or_else(|| {
+                format!("Device {} has no IP address configured.", target.label())
+            })?;
+            let device = connect(
+                target.key.clone(),
+                ip,
+                target.version.clone(),
+                config.connection,
+            )
+            .map_err(|error| format!("Failed to connect to {}: {}", target.label(), error))?;
+            let dps = query_dps(&device, &target.id)
+                .ok_or_else(|| format!("{} did not report its state.", target.label()))?;
+
+            Ok(Status::read(&target, &capabilities, dps))
+        });
+
+        match status {
+            Ok(status) => statuses.push(status),
+            Err(error) => failed.push(error),
+        }
+    }
+
+    if json {
+        let json = serde_json::to_string_pretty(&statuses).map_err(|error| error.to_string())?;
+        println!("{}", json);
+    } else {
+        for (index, status) in statuses.iter().enumerate() {
+            if index > 0 {
+                println!();
+            }
+            status.print();
+        }
+    }
+
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(failed.join("\n"))
+    }
+}
@@ -0,0 +1,137 @@
+use colors_transform::Rgb;
+
+/// What was picked in the tray menu, or asked for in Home Assistant with `--mqtt-discovery` or
+/// through the control API.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(not(all(feature = "tray", target_os = "linux")), allow(dead_code))]
+pub enum TrayAction {
+    /// Pause syncing, or resume it with `false`.
+    Pause(bool),
+    /// Follow the colors of the screen, or its brightness with the hue and saturation given.
+    Mode(Option<(u32, u32)>),
+    /// Show this color instead of the screen's, or the screen's again with `None`.
+    Color(Option<Rgb>),
+    /// Stop syncing and exit.
+    Quit,
+}
+
+#[cfg(all(feature = "tray", target_os = "linux"))]
+pub use self::icon::open;
+
+/// Without the `tray` feature, or off Linux, there is no tray to put the icon in.
+#[cfg(not(all(feature = "tray", target_os = "linux")))]
+pub fn open(
+    _brightness_sync: (u32, u32),
+    _started_in: Option<(u32, u32)>,
+) -> Result<std::sync::mpsc::Receiver<TrayAction>, String> {
+    Err("The tray icon needs the `tray` feature, on Linux.".to_string())
+}
+
+#[cfg(all(feature = "tray", target_os = "linux"))]
+mod icon {
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    use ksni::{
+        blocking::TrayMethods,
+        menu::{CheckmarkItem, RadioGroup, RadioItem, StandardItem},
+        MenuItem,
+    };
+
+    use super::TrayAction;
+
+    struct Menu {
+        paused: bool,
+        /// Hue and saturation of brightness-sync.
+        brightness_sync: (u32, u32),
+        /// What the color loop is in, see `TrayAction::Mode`.
+        mode: Option<(u32, u32)>,
+        actions: Sender<TrayAction>,
+    }
+
+    impl Menu {
+        fn send(&self, action: TrayAction) {
+            // Nothing is listening once the color loop stopped.
+            let _ = self.actions.send(action);
+        }
+    }
+
+    impl ksni::Tray for Menu {
+        fn id(&self) -> String {
+            env!("CARGO_PKG_NAME").into()
+        }
+
+        fn title(&self) -> String {
+            if self.paused {
+                "Screen color (paused)".into()
+            } else {
+                "Screen color".into()
+            }
+        }
+
+        fn icon_name(&self) -> String {
+            "preferences-desktop-color".into()
+        }
+
+        fn menu(&self) -> Vec<MenuItem<Self>> {
+            vec![
+                CheckmarkItem {
+                    label: "Paused".into(),
+                    checked: self.paused,
+                    activate: Box::new(|menu: &mut Self| {
+                        menu.paused = !menu.paused;
+                        menu.send(TrayAction::Pause(menu.paused));
+                    }),
+                    ..Default::default()
+                }
+                .into(),
+                MenuItem::Separator,
+                RadioGroup {
+                    selected: self.mode.is_some() as usize,
+                    select: Box::new(|menu: &mut Self, index| {
+                        menu.mode = (index == 1).then_some(menu.brightness_sync);
+                        menu.send(TrayAction::Mode(menu.mode));
+                    }),
+                    options: vec![
+                        RadioItem {
+                            label: "Color picker".into(),
+                            ..Default::default()
+                        },
+                        RadioItem {
+                            label: "Brightness sync".into(),
+                            ..Default::default()
+                        },
+                    ],
+                }
+                .into(),
+                MenuItem::Separator,
+                StandardItem {
+                    label: "Quit".into(),
+                    icon_name: "application-exit".into(),
+                    activate: Box::new(|menu: &mut Self| menu.send(TrayAction::Quit)),
+                    ..Default::default()
+                }
+                .into(),
+            ]
+        }
+    }
+
+    /// Puts the icon in the tray through the StatusNotifierItem D-Bus interface, which KDE and
+    /// most other desktops (GNOME with the AppIndicator extension) show. `started_in` is the
+    /// mode the color loop starts in, `brightness_sync` the hue and saturation it switches to.
+    pub fn open(
+        brightness_sync: (u32, u32),
+        started_in: Option<(u32, u32)>,
+    ) -> Result<Receiver<TrayAction>, String> {
+        let (actions, receiver) = mpsc::channel();
+        let menu = Menu {
+            paused: false,
+            brightness_sync,
+            mode: started_in,
+            actions,
+        };
+        // The service keeps running on its own thread when the handle is dropped.
+        menu.spawn()
+            .map_err(|error| format!("Failed to show the tray icon: {}", error))?;
+        Ok(receiver)
+    }
+}
@@ -0,0 +1,394 @@
+//! Encoding and decoding of the values Tuya bulbs exchange over the LAN protocol.
+//!
+//! Everything in here treats device input as untrusted: decoders return a [`DecodeError`]
+//! for malformed data and never panic.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    time::SystemTime,
+};
+
+use rust_tuyapi::{Payload, PayloadStruct};
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The colour string has neither the 12 character (v2) nor the 14 character (v1) length.
+    Length(usize),
+    /// The input contains something that isn't a hexadecimal digit.
+    InvalidHex,
+    /// A component is outside the range the protocol allows.
+    OutOfRange { component: &'static str, value: u32 },
+    /// The payload isn't valid JSON or doesn't have the expected shape.
+    Json(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Length(length) => write!(
+                f,
+                "colour string has {} characters, expected 12 or 14",
+                length
+            ),
+            DecodeError::InvalidHex => write!(f, "colour string is not hexadecimal"),
+            DecodeError::OutOfRange { component, value } => {
+                write!(f, "{} {} is out of range", component, value)
+            }
+            DecodeError::Json(error) => write!(f, "invalid payload: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Encodes hue (0–360), saturation (0–100) and value (0–100) as the 12 character `colour_data_v2`
+/// string used by DP 24. Out of range components are clamped.
+///
+/// ```
+/// use tuya_bulb_screen_color::tuya::hsv2tuya;
+///
+/// assert_eq!(hsv2tuya((0, 100, 100)), "000003e803e8");
+/// assert_eq!(hsv2tuya((240, 50, 10)), "00f001f40064");
+/// assert_eq!(hsv2tuya((400, 150, 100)), "016803e803e8");
+/// ```
+pub fn hsv2tuya(hsv: (u32, u32, u32)) -> String {
+    let (h, s, v) = hsv;
+    let tuya_h = format!("{:04x}", h.min(360));
+    let tuya_s = format!("{:04x}", s.min(100) * 10);
+    let tuya_v = format!("{:04x}", v.min(100) * 10);
+
+    format!("{}{}{}", tuya_h, tuya_s, tuya_v)
+}
+
+/// Encodes hue (0–360), saturation (0–100) and value (0–100) as the 14 character legacy
+/// `colour_data` string (`RRGGBBHHHHSSVV`) used by older bulbs on DP 5. Out of range components
+/// are clamped.
+///
+/// ```
+/// use tuya_bulb_screen_color::tuya::{hsv2tuya_v1, tuya2hsv};
+///
+/// assert_eq!(hsv2tuya_v1((0, 100, 100)), "ff00000000ffff");
+/// assert_eq!(hsv2tuya_v1((120, 50, 50)), "40804000788080");
+/// assert_eq!(tuya2hsv(&hsv2tuya_v1((240, 50, 10))), Ok((240, 50, 10)));
+/// ```
+pub fn hsv2tuya_v1(hsv: (u32, u32, u32)) -> String {
+    let (h, s, v) = (hsv.0.min(360), hsv.1.min(100), hsv.2.min(100));
+
+    let chroma = v as f32 / 100.0 * s as f32 / 100.0;
+    let sector = (h % 360) as f32 / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = v as f32 / 100.0 - chroma;
+    let channel = |c: f32| ((c + m) * 255.0).round() as u32;
+
+    format!(
+        "{:02x}{:02x}{:02x}{:04x}{:02x}{:02x}",
+        channel(r),
+        channel(g),
+        channel(b),
+        h,
+        (s * 255 + 50) / 100,
+        (v * 255 + 50) / 100
+    )
+}
+
+/// Decodes a colour string reported by a bulb into hue (0–360), saturation (0–100) and
+/// value (0–100).
+///
+/// Both the 12 character `colour_data_v2` format (`HHHHSSSSVVVV`, saturation and value in
+/// 0–1000) and the legacy 14 character `colour_data` format (`RRGGBBHHHHSSVV`, saturation and
+/// value in 0–255) are accepted.
+///
+/// ```
+/// use tuya_bulb_screen_color::tuya::{tuya2hsv, DecodeError};
+///
+/// assert_eq!(tuya2hsv("000003e803e8"), Ok((0, 100, 100)));
+/// assert_eq!(tuya2hsv("ff00000000ffff"), Ok((0, 100, 100)));
+/// assert_eq!(tuya2hsv("00f0"), Err(DecodeError::Length(4)));
+/// assert_eq!(tuya2hsv("zzzzzzzzzzzz"), Err(DecodeError::InvalidHex));
+/// assert!(tuya2hsv("0fff03e803e8").is_err());
+/// ```
+pub fn tuya2hsv(colour: &str) -> Result<(u32, u32, u32), DecodeError> {
+    if !colour.is_ascii() {
+        return Err(DecodeError::InvalidHex);
+    }
+
+    match colour.len() {
+        12 => {
+            let h = hex_component(colour, 0..4)?;
+            let s = hex_component(colour, 4..8)?;
+            let v = hex_component(colour, 8..12)?;
+
+            check_range("hue", h, 360)?;
+            check_range("saturation", s, 1000)?;
+            check_range("value", v, 1000)?;
+
+            Ok((h, (s + 5) / 10, (v + 5) / 10))
+        }
+        14 => {
+            // The RGB part is redundant, but still has to be valid hex.
+            hex_component(colour, 0..6)?;
+            let h = hex_component(colour, 6..10)?;
+            let s = hex_component(colour, 10..12)?;
+            let v = hex_component(colour, 12..14)?;
+
+            check_range("hue", h, 360)?;
+
+            Ok((h, (s * 100 + 127) / 255, (v * 100 + 127) / 255))
+        }
+        length => Err(DecodeError::Length(length)),
+    }
+}
+
+fn hex_component(colour: &str, range: std::ops::Range<usize>) -> Result<u32, DecodeError> {
+    let digits = colour.get(range).ok_or(DecodeError::InvalidHex)?;
+    if !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(DecodeError::InvalidHex);
+    }
+    u32::from_str_radix(digits, 16).map_err(|_| DecodeError::InvalidHex)
+}
+
+fn check_range(component: &'static str, value: u32, max: u32) -> Result<(), DecodeError> {
+    if value > max {
+        return Err(DecodeError::OutOfRange { component, value });
+    }
+    Ok(())
+}
+
+/// Extracts the `dps` map from a decrypted device response. Protocol 3.1/3.3 devices put it at
+/// the top level, 3.4+ devices nest it under `data`.
+///
+/// ```
+/// use tuya_bulb_screen_color::tuya::parse_dps;
+///
+/// let dps = parse_dps(br#"{"devId":"abc","dps":{"20":true,"21":"colour"}}"#).unwrap();
+/// assert_eq!(dps["20"], true);
+///
+/// let dps = parse_dps(br#"{"protocol":4,"data":{"dps":{"22":500}}}"#).unwrap();
+/// assert_eq!(dps["22"], 500);
+///
+/// assert!(parse_dps(b"\xff\xfe").is_err());
+/// assert!(parse_dps(br#"{"dps":[1,2]}"#).is_err());
+/// ```
+pub fn parse_dps(payload: &[u8]) -> Result<BTreeMap<String, Value>, DecodeError> {
+    let value: Value =
+        serde_json::from_slice(payload).map_err(|error| DecodeError::Json(error.to_string()))?;
+
+    let dps = value
+        .get("dps")
+        .or_else(|| value.get("data").and_then(|data| data.get("dps")))
+        .ok_or_else(|| DecodeError::Json("no dps in payload".to_string()))?;
+
+    match dps {
+        Value::Object(map) => Ok(map
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()),
+        _ => Err(DecodeError::Json("dps is not an object".to_string())),
+    }
+}
+
+/// The state of a colour bulb as far as it can be read from its data points.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BulbState {
+    /// DP 20, `switch_led`.
+    pub power: Option<bool>,
+    /// DP 21, `work_mode` (`white`, `colour`, `scene` or `music`).
+    pub mode: Option<String>,
+    /// DP 22, `bright_value_v2` (10–1000).
+    pub brightness: Option<u32>,
+    /// DP 23, `temp_value_v2` (0–1000).
+    pub temperature: Option<u32>,
+    /// DP 24, `colour_data_v2`, decoded to hue, saturation and value.
+    pub colour: Option<(u32, u32, u32)>,
+}
+
+impl BulbState {
+    /// Interprets a dps map. Data points with an unexpected type or an undecodable colour are
+    /// left as `None` instead of failing the whole state.
+    ///
+    /// ```
+    /// use tuya_bulb_screen_color::tuya::{parse_dps, BulbState};
+    ///
+    /// let dps = parse_dps(br#"{"dps":{"20":true,"21":"colour","22":"oops","24":"00f001f40064"}}"#).unwrap();
+    /// let state = BulbState::from_dps(&dps);
+    ///
+    /// assert_eq!(state.power, Some(true));
+    /// assert_eq!(state.mode.as_deref(), Some("colour"));
+    /// assert_eq!(state.brightness, None);
+    /// assert_eq!(state.colour, Some((240, 50, 10)));
+    /// ```
+    pub fn from_dps(dps: &BTreeMap<String, Value>) -> BulbState {
+        let number = |key: &str| {
+            dps.get(key)
+                .and_then(Value::as_u64)
+                .and_then(|value| u32::try_from(value).ok())
+        };
+
+        BulbState {
+            power: dps.get("20").and_then(Value::as_bool),
+            mode: dps.get("21").and_then(Value::as_str).map(str::to_string),
+            brightness: number("22"),
+            temperature: number("23"),
+            colour: dps
+                .get("24")
+                .and_then(Value::as_str)
+                .and_then(|colour| tuya2hsv(colour).ok()),
+        }
+    }
+}
+
+/// A payload setting the data points in `dps` of the device `id`.
+///
+/// ```
+/// use rust_tuyapi::Payload;
+/// use serde_json::json;
+/// use tuya_bulb_screen_color::tuya::dps_payload;
+///
+/// let Payload::Struct(payload) = dps_payload("bf01", [("20".to_string(), json!(false))].into())
+/// else {
+///     unreachable!()
+/// };
+///
+/// assert_eq!(payload.dev_id, "bf01");
+/// assert_eq!(payload.dps.unwrap()["20"], json!(false));
+/// ```
+pub fn dps_payload(id: &str, dps: HashMap<String, Value>) -> Payload {
+    let current_time = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32;
+
+    Payload::Struct(PayloadStruct {
+        dev_id: id.to_string(),
+        gw_id: Some(id.to_string()),
+        uid: None,
+        t: Some(current_time),
+        dp_id: None,
+        dps: Some(dps),
+    })
+}
+
+/// A payload switching the device `id` to colour mode with hue (0–360), saturation (0–100) and
+/// value (0–100), for bulbs with the usual data points: 21 for the mode and 24 for the
+/// `colour_data_v2`.
+///
+/// ```
+/// use rust_tuyapi::Payload;
+/// use serde_json::json;
+/// use tuya_bulb_screen_color::tuya::colour_payload;
+///
+/// let Payload::Struct(payload) = colour_payload("bf01", (240, 50, 10)) else {
+///     unreachable!()
+/// };
+/// let dps = payload.dps.unwrap();
+///
+/// assert_eq!(dps["21"], json!("colour"));
+/// assert_eq!(dps["24"], json!("00f001f40064"));
+/// ```
+pub fn colour_payload(id: &str, hsv: (u32, u32, u32)) -> Payload {
+    let dps = [
+        ("21".to_string(), json!("colour")),
+        ("24".to_string(), json!(hsv2tuya(hsv))),
+    ];
+    dps_payload(id, dps.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// JSON values of every shape, nested a few levels deep.
+    fn json_value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            Just(Value::Null),
+            any::<bool>().prop_map(Value::from),
+            any::<i64>().prop_map(Value::from),
+            any::<u64>().prop_map(Value::from),
+            // Floats that survive printing and parsing unchanged.
+            (-4000..4000).prop_map(|quarters| Value::from(quarters as f64 / 4.0)),
+            "[0-9a-fA-Fz]{0,16}".prop_map(Value::from),
+            any::<String>().prop_map(Value::from),
+        ];
+        leaf.prop_recursive(3, 16, 4, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..4).prop_map(Value::from),
+                prop::collection::btree_map("[0-9a-z]{1,3}", inner, 0..4)
+                    .prop_map(|map| Value::Object(map.into_iter().collect())),
+            ]
+        })
+    }
+
+    /// Maps that mostly use the data points `from_dps` looks at.
+    fn dps_map() -> impl Strategy<Value = BTreeMap<String, Value>> {
+        prop::collection::btree_map(prop_oneof!["2[0-4]", "[0-9]{1,3}"], json_value(), 0..8)
+    }
+
+    proptest! {
+        #[test]
+        fn parse_dps_never_panics(payload in prop::collection::vec(any::<u8>(), 0..256)) {
+            if let Ok(dps) = parse_dps(&payload) {
+                BulbState::from_dps(&dps);
+            }
+        }
+
+        #[test]
+        fn parse_dps_reads_both_layouts(dps in dps_map(), nested in any::<bool>()) {
+            let object = Value::Object(dps.clone().into_iter().collect());
+            let payload = if nested {
+                json!({ "protocol": 4, "data": { "dps": object } })
+            } else {
+                json!({ "devId": "bf01", "dps": object })
+            };
+
+            prop_assert_eq!(parse_dps(payload.to_string().as_bytes()), Ok(dps));
+        }
+
+        #[test]
+        fn from_dps_never_panics(dps in dps_map()) {
+            let state = BulbState::from_dps(&dps);
+            if let Some((h, s, v)) = state.colour {
+                prop_assert!(h <= 360 && s <= 100 && v <= 100);
+            }
+        }
+
+        #[test]
+        fn tuya2hsv_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..32)) {
+            if let Ok((h, s, v)) = tuya2hsv(&String::from_utf8_lossy(&bytes)) {
+                prop_assert!(h <= 360 && s <= 100 && v <= 100);
+            }
+        }
+
+        #[test]
+        fn tuya2hsv_never_panics_on_hex(colour in "[0-9a-fA-F]{12}|[0-9a-fA-F]{14}") {
+            if let Ok((h, s, v)) = tuya2hsv(&colour) {
+                prop_assert!(h <= 360 && s <= 100 && v <= 100);
+            }
+        }
+
+        #[test]
+        fn colours_round_trip(h in 0..=360u32, s in 0..=100u32, v in 0..=100u32) {
+            prop_assert_eq!(tuya2hsv(&hsv2tuya((h, s, v))), Ok((h, s, v)));
+            prop_assert_eq!(tuya2hsv(&hsv2tuya_v1((h, s, v))), Ok((h, s, v)));
+        }
+
+        #[test]
+        fn out_of_range_colours_are_clamped(h in any::<u32>(), s in any::<u32>(), v in any::<u32>()) {
+            prop_assert_eq!(
+                tuya2hsv(&hsv2tuya((h, s, v))),
+                Ok((h.min(360), s.min(100), v.min(100)))
+            );
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use chrono::Local;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::state;
+
+/// How often the counters are written while running.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// Days older than this are dropped.
+const KEEP_DAYS: i64 = 90;
+
+/// How long a bulb was on during a day and how bright, weighted by time.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+struct Day {
+    on_secs: f64,
+    /// Brightness in percent times seconds, divided by `on_secs` for the average.
+    brightness_secs: f64,
+}
+
+/// Estimated on-time and brightness of each bulb per day, from what was sent to it.
+pub struct Usage {
+    /// Keyed by `YYYY-MM-DD`, which sorts by date.
+    days: BTreeMap<String, BTreeMap<String, Day>>,
+    saved_at: Instant,
+}
+
+fn path() -> Option<PathBuf> {
+    state::dir().map(|dir| dir.join("usage.json"))
+}
+
+impl Usage {
+    pub fn load() -> Usage {
+        let days = path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        Usage {
+            days,
+            saved_at: Instant::now(),
+        }
+    }
+
+    /// Adds `elapsed` to the day's counters of `device`, which was at `brightness` (0-100) or
+    /// off when it is `None`.
+    pub fn record(&mut self, device: &str, brightness: Option<u32>, elapsed: Duration) {
+        let Some(brightness) = brightness else {
+            return;
+        };
+
+        let today = Local::now().date_naive().to_string();
+        let day = self
+            .days
+            .entry(today)
+            .or_default()
+            .entry(device.to_string())
+            .or_default();
+        day.on_secs += elapsed.as_secs_f64();
+        day.brightness_secs += brightness as f64 * elapsed.as_secs_f64();
+
+        if self.saved_at.elapsed() >= SAVE_INTERVAL {
+            self.saved_at = Instant::now();
+            if let Err(error) = self.save() {
+                warn!("{}", error);
+            }
+        }
+    }
+
+    pub fn save(&mut self) -> Result<(), String> {
+        let oldest = (Local::now().date_naive() - chrono::Duration::days(KEEP_DAYS)).to_string();
+        self.days.retain(|date, _| *date >= oldest);
+
+        let path = path().ok_or_else(|| "Could not determine the state directory.".to_string())?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|error| format!("Failed to create {}: {}", parent.display(), error))?;
+        }
+
+        let contents = serde_json::to_string(&self.days).map_err(|error| error.to_string())?;
+        fs::write(&path, contents)
+            .map_err(|error| format!("Failed to write {}: {}", path.display(), error))
+    }
+}
+
+/// Prints the on-time and average brightness of every bulb over the last `days` days.
+pub fn report(days: i64) -> Result<(), String> {
+    let usage = Usage::load();
+    let oldest = (Local::now().date_naive() - chrono::Duration::days(days - 1)).to_string();
+
+    println!(
+        "{:<12} {:<24} {:>9} {:>15}",
+        "date", "device", "on-time", "avg brightness"
+    );
+
+    let mut total = Day::default();
+    for (date, devices) in usage.days.range(oldest..) {
+        for (device, day) in devices {
+            println!(
+                "{:<12} {:<24} {:>9} {:>14.0}%",
+                date,
+                device,
+                format_duration(day.on_secs),
+                day.brightness_secs / day.on_secs.max(1.0)
+            );
+            total.on_secs += day.on_secs;
+            total.brightness_secs += day.brightness_secs;
+        }
+    }
+
+    if total.on_secs == 0.0 {
+        println!("No usage recorded in the last {} day(s).", days);
+        return Ok(());
+    }
+
+    println!(
+        "{:<37} {:>9} {:>14.0}%",
+        "total",
+        format_duration(total.on_secs),
+        total.brightness_secs / total.on_secs
+    );
+
+    Ok(())
+}
+
+fn format_duration(secs: f64) -> String {
+    let minutes = (secs / 60.0).round() as u64;
+    format!("{}h {:02}m", minutes / 60, minutes % 60)
+}
@@ -0,0 +1,185 @@
+//! Known conversions between HSV and the colour strings Tuya bulbs use.
+//!
+//! Firmwares disagree on how colours are encoded. The vectors pin down every encoding this crate
+//! knows, so other implementations can be checked against them and a bulb's reported colour can
+//! be traced back to the mapping its firmware expects.
+
+use std::fmt;
+
+use crate::tuya::{hsv2tuya, hsv2tuya_v1};
+
+/// A way of encoding hue, saturation and value as a colour string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mapping {
+    /// `colour_data_v2`: `HHHHSSSSVVVV`, saturation and value in 0–1000.
+    V2,
+    /// 12 character `colour_data` of some early DP 24 firmwares: `HHHHSSSSVVVV`, saturation and
+    /// value in 0–255.
+    V2Byte,
+    /// Legacy `colour_data`: `RRGGBBHHHHSSVV`, saturation and value in 0–255.
+    V1,
+}
+
+impl Mapping {
+    pub const ALL: [Mapping; 3] = [Mapping::V2, Mapping::V2Byte, Mapping::V1];
+
+    /// Encodes hue (0–360), saturation (0–100) and value (0–100) with this mapping.
+    ///
+    /// ```
+    /// use tuya_bulb_screen_color::vectors::Mapping;
+    ///
+    /// assert_eq!(Mapping::V2Byte.encode((240, 100, 100)), "00f000ff00ff");
+    /// ```
+    pub fn encode(&self, hsv: (u32, u32, u32)) -> String {
+        match self {
+            Mapping::V2 => hsv2tuya(hsv),
+            Mapping::V2Byte => {
+                let (h, s, v) = hsv;
+                format!(
+                    "{:04x}{:04x}{:04x}",
+                    h.min(360),
+                    to_byte(s.min(100)),
+                    to_byte(v.min(100))
+                )
+            }
+            Mapping::V1 => hsv2tuya_v1(hsv),
+        }
+    }
+
+    /// The string a vector is expected to encode to with this mapping.
+    pub fn expected(&self, vector: &Vector) -> &'static str {
+        match self {
+            Mapping::V2 => vector.v2,
+            Mapping::V2Byte => vector.v2_byte,
+            Mapping::V1 => vector.v1,
+        }
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mapping::V2 => write!(f, "v2 (HHHHSSSSVVVV, 0-1000)"),
+            Mapping::V2Byte => write!(f, "v2-byte (HHHHSSSSVVVV, 0-255)"),
+            Mapping::V1 => write!(f, "v1 (RRGGBBHHHHSSVV, 0-255)"),
+        }
+    }
+}
+
+fn to_byte(percent: u32) -> u32 {
+    (percent * 255 + 50) / 100
+}
+
+/// One HSV colour with its encoding under every [`Mapping`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vector {
+    pub hsv: (u32, u32, u32),
+    pub v2: &'static str,
+    pub v2_byte: &'static str,
+    pub v1: &'static str,
+}
+
+/// Primaries, white, and a few dim and desaturated colours where rounding differs between
+/// mappings.
+pub const VECTORS: &[Vector] = &[
+    Vector {
+        hsv: (0, 100, 100),
+        v2: "000003e803e8",
+        v2_byte: "000000ff00ff",
+        v1: "ff00000000ffff",
+    },
+    Vector {
+        hsv: (120, 100, 100),
+        v2: "007803e803e8",
+        v2_byte: "007800ff00ff",
+        v1: "00ff000078ffff",
+    },
+    Vector {
+        hsv: (240, 100, 100),
+        v2: "00f003e803e8",
+        v2_byte: "00f000ff00ff",
+        v1: "0000ff00f0ffff",
+    },
+    Vector {
+        hsv: (0, 0, 100),
+        v2: "0000000003e8",
+        v2_byte: "0000000000ff",
+        v1: "ffffff000000ff",
+    },
+    Vector {
+        hsv: (30, 100, 25),
+        v2: "001e03e800fa",
+        v2_byte: "001e00ff0040",
+        v1: "402000001eff40",
+    },
+    Vector {
+        hsv: (200, 40, 80),
+        v2: "00c801900320",
+        v2_byte: "00c8006600cc",
+        v1: "7ab1cc00c866cc",
+    },
+    Vector {
+        hsv: (300, 75, 60),
+        v2: "012c02ee0258",
+        v2_byte: "012c00bf0099",
+        v1: "992699012cbf99",
+    },
+    Vector {
+        hsv: (45, 10, 5),
+        v2: "002d00640032",
+        v2_byte: "002d001a000d",
+        v1: "0d0c0b002d1a0d",
+    },
+];
+
+/// A vector an encoder got wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub hsv: (u32, u32, u32),
+    pub expected: &'static str,
+    pub actual: String,
+}
+
+/// Runs `encode` over all vectors and returns the ones it doesn't encode like `mapping`.
+///
+/// ```
+/// use tuya_bulb_screen_color::tuya::{hsv2tuya, hsv2tuya_v1};
+/// use tuya_bulb_screen_color::vectors::{verify, Mapping};
+///
+/// assert!(verify(Mapping::V2, hsv2tuya).is_empty());
+/// assert!(verify(Mapping::V1, hsv2tuya_v1).is_empty());
+/// assert!(verify(Mapping::V2Byte, |hsv| Mapping::V2Byte.encode(hsv)).is_empty());
+/// assert_eq!(verify(Mapping::V1, hsv2tuya).len(), 8);
+/// ```
+pub fn verify<F>(mapping: Mapping, encode: F) -> Vec<Mismatch>
+where
+    F: Fn((u32, u32, u32)) -> String,
+{
+    VECTORS
+        .iter()
+        .filter_map(|vector| {
+            let expected = mapping.expected(vector);
+            let actual = encode(vector.hsv);
+            (!actual.eq_ignore_ascii_case(expected)).then_some(Mismatch {
+                hsv: vector.hsv,
+                expected,
+                actual,
+            })
+        })
+        .collect()
+}
+
+/// Returns the mappings under which `reported` is the encoding of `hsv`.
+///
+/// ```
+/// use tuya_bulb_screen_color::vectors::{identify, Mapping};
+///
+/// assert_eq!(identify((240, 100, 100), "00F000FF00FF"), vec![Mapping::V2Byte]);
+/// assert!(identify((240, 100, 100), "000003e803e8").is_empty());
+/// ```
+pub fn identify(hsv: (u32, u32, u32), reported: &str) -> Vec<Mapping> {
+    Mapping::ALL
+        .into_iter()
+        .filter(|mapping| mapping.encode(hsv).eq_ignore_ascii_case(reported))
+        .collect()
+}
@@ -0,0 +1,335 @@
+use std::{
+    io::{self, Read, Write},
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use log::{debug, info};
+use serde_json::Value;
+
+/// Appended to the key of the handshake before hashing it, see RFC 6455.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Messages a watcher can fall behind by before it misses some.
+const BACKLOG: usize = 64;
+
+/// Longest frame read from a watcher, who has no reason to send more than control frames.
+const MAX_PAYLOAD: u64 = 64 * 1024;
+
+const TEXT: u8 = 0x1;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// Close status for a watcher that broke the protocol.
+const PROTOCOL_ERROR: u16 = 1002;
+
+/// The `Sec-WebSocket-Accept` answering the client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let digest = openssl::sha::sha1(format!("{}{}", key.trim(), GUID).as_bytes());
+    openssl::base64::encode_block(&digest)
+}
+
+/// An unmasked frame, the only kind a server sends.
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        length @ 0..=125 => frame.push(length as u8),
+        length @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Reads a frame sent by a client, returning its opcode and unmasked payload. Clients have to
+/// mask their frames; unmasked and oversized ones are rejected.
+fn read_frame(reader: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let mut head = [0; 2];
+    reader.read_exact(&mut head)?;
+    let opcode = head[0] & 0x0f;
+    if head[1] & 0x80 == 0 {
+        return Err(invalid("unmasked frame from a client"));
+    }
+    let length = match head[1] & 0x7f {
+        126 => {
+            let mut length = [0; 2];
+            reader.read_exact(&mut length)?;
+            u16::from_be_bytes(length) as u64
+        }
+        127 => {
+            let mut length = [0; 8];
+            reader.read_exact(&mut length)?;
+            u64::from_be_bytes(length)
+        }
+        length => length as u64,
+    };
+    if length > MAX_PAYLOAD {
+        return Err(invalid("frame too large"));
+    }
+
+    let mut mask = [0; 4];
+    reader.read_exact(&mut mask)?;
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((opcode, payload))
+}
+
+/// The colors of the color loop and what the bulbs did with them, as JSON text messages to
+/// every WebSocket connected to `/colors`. Each watcher is written to by a thread of its own,
+/// so a slow one misses messages rather than holding up the others.
+#[derive(Clone, Default)]
+pub struct Feed(Arc<Mutex<Vec<SyncSender<Frame>>>>);
+
+/// A message framed once for every watcher.
+type Frame = Arc<Vec<u8>>;
+
+impl Feed {
+    /// Streams the messages to an upgraded connection until it goes away. `reader` and `writer`
+    /// are its two halves: a thread of its own reads what the watcher sends, answering pings
+    /// and closing the connection when asked to.
+    pub fn watch(
+        &self,
+        mut reader: impl Read + Send + 'static,
+        mut writer: impl Write + Send + 'static,
+        peer: String,
+    ) {
+        let (messages, receiver) = mpsc::sync_channel::<Frame>(BACKLOG);
+        self.0.lock().unwrap().push(messages.clone());
+        info!("{} is watching the colors.", peer);
+
+        thread::spawn(move || {
+            for message in receiver {
+                let closing = message.first() == Some(&(0x80 | CLOSE));
+                if writer
+                    .write_all(&message)
+                    .and_then(|_| writer.flush())
+                    .is_err()
+                    || closing
+                {
+                    break;
+                }
+            }
+            info!("{} stopped watching the colors.", peer);
+        });
+
+        thread::spawn(move || {
+            let close = loop {
+                match read_frame(&mut reader) {
+                    Ok((PING, payload)) => {
+                        if messages.send(Arc::new(frame(PONG, &payload))).is_err() {
+                            return;
+                        }
+                    }
+                    // Answered with the status it came with, see RFC 6455 section 5.5.1.
+                    Ok((CLOSE, payload)) => break frame(CLOSE, payload.get(..2).unwrap_or(&[])),
+                    Ok(_) => {}
+                    Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+                        debug!("Closing a color watcher: {}", error);
+                        break frame(CLOSE, &PROTOCOL_ERROR.to_be_bytes());
+                    }
+                    // Gone without a close, the writer stops at its next message.
+                    Err(_) => return,
+                }
+            };
+            let _ = messages.send(Arc::new(close));
+        });
+    }
+
+    /// Sends a message to every watcher, `message` is only built when there are any.
+    pub fn publish(&self, message: impl FnOnce() -> Value) {
+        let mut watchers = self.0.lock().unwrap();
+        if watchers.is_empty() {
+            return;
+        }
+
+        let frame = Arc::new(frame(TEXT, message().to_string().as_bytes()));
+        watchers.retain(|watcher| match watcher.try_send(frame.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                debug!("A color watcher is behind, it misses a message.");
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Cursor,
+        time::{Duration, Instant},
+    };
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// A frame the way a client sends it, masked.
+    fn masked(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = frame(opcode, payload);
+        let start = frame.len() - payload.len();
+        frame[1] |= 0x80;
+        for (i, byte) in frame[start..].iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        frame.splice(start..start, mask);
+        frame
+    }
+
+    /// What the server wrote, shared with the test.
+    #[derive(Clone, Default)]
+    struct Written(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Written {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Written {
+        /// The frames written once the last of them closed the connection.
+        fn frames_until_closed(&self) -> Vec<(u8, Vec<u8>)> {
+            let started = Instant::now();
+            loop {
+                let written = self.0.lock().unwrap().clone();
+                let mut frames = Vec::new();
+                let mut rest = &written[..];
+                while rest.len() >= 2 {
+                    let length = rest[1] as usize;
+                    frames.push((rest[0] & 0x0f, rest[2..2 + length].to_vec()));
+                    rest = &rest[2 + length..];
+                }
+                if frames.last().is_some_and(|&(opcode, _)| opcode == CLOSE) {
+                    return frames;
+                }
+                assert!(started.elapsed() < Duration::from_secs(5), "never closed");
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+
+    #[test]
+    fn accepts_the_key_of_the_rfc() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn frames_every_length() {
+        assert_eq!(frame(TEXT, b"hi"), [0x81, 2, b'h', b'i']);
+        assert_eq!(frame(TEXT, &[0; 126])[..4], [0x81, 126, 0, 126]);
+        assert_eq!(
+            frame(TEXT, &[0; 0x10000])[..10],
+            [0x81, 127, 0, 0, 0, 0, 0, 1, 0, 0]
+        );
+    }
+
+    #[test]
+    fn reads_masked_frames() {
+        let mut frames = Cursor::new([masked(PING, b"hello"), masked(TEXT, &[7; 300])].concat());
+        assert_eq!(read_frame(&mut frames).unwrap(), (PING, b"hello".to_vec()));
+        assert_eq!(read_frame(&mut frames).unwrap(), (TEXT, vec![7; 300]));
+        assert!(read_frame(&mut frames).is_err());
+    }
+
+    #[test]
+    fn rejects_unmasked_and_oversized_frames() {
+        let error = read_frame(&mut Cursor::new(frame(PING, b"hi"))).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut oversized = vec![0x82, 0x80 | 127];
+        oversized.extend_from_slice(&(MAX_PAYLOAD + 1).to_be_bytes());
+        let error = read_frame(&mut Cursor::new(oversized)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn answers_pings_and_closes() {
+        let sent = [
+            masked(PING, b"are you there"),
+            masked(TEXT, b"ignored"),
+            masked(CLOSE, &1000u16.to_be_bytes()),
+        ]
+        .concat();
+        let written = Written::default();
+        Feed::default().watch(Cursor::new(sent), written.clone(), "test".to_string());
+
+        assert_eq!(
+            written.frames_until_closed(),
+            [
+                (PONG, b"are you there".to_vec()),
+                (CLOSE, 1000u16.to_be_bytes().to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn closes_on_protocol_errors() {
+        let written = Written::default();
+        Feed::default().watch(
+            Cursor::new(frame(TEXT, b"unmasked")),
+            written.clone(),
+            "test".to_string(),
+        );
+
+        assert_eq!(
+            written.frames_until_closed(),
+            [(CLOSE, PROTOCOL_ERROR.to_be_bytes().to_vec())]
+        );
+    }
+
+    #[test]
+    fn publishes_to_watchers() {
+        let feed = Feed::default();
+        // Nobody is watching yet, the message isn't even built.
+        feed.publish(|| unreachable!());
+
+        let (_wake, quiet) = mpsc::channel();
+        let written = Written::default();
+        feed.watch(Quiet(quiet), written.clone(), "test".to_string());
+        feed.publish(|| json!({ "type": "color" }));
+
+        let started = Instant::now();
+        while written.0.lock().unwrap()[..] != frame(TEXT, br#"{"type":"color"}"#)[..] {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "never published"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// A watcher that keeps quiet until its sender is dropped.
+    struct Quiet(mpsc::Receiver<()>);
+
+    impl Read for Quiet {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            let _ = self.0.recv();
+            Ok(0)
+        }
+    }
+}
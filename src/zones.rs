@@ -0,0 +1,660 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Write},
+    iter,
+    path::PathBuf,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use colors_transform::{Color, Hsl, Rgb};
+use log::info;
+use rayon::prelude::*;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use toml_edit::value;
+use tuya_bulb_screen_color::{cluster::k_means, color::clustered_color, colorspace::ColorSpace};
+
+use crate::{config, grabber::Grabber, CaptureOptions, GRID_COLUMNS, GRID_ROWS};
+
+/// Depth of each edge zone, as a share of the frame's height (top, bottom) or width (left,
+/// right).
+const EDGE_DEPTH: f32 = 0.2;
+/// Most points sampled across and down a zone, so a zone of a full frame costs about as much as
+/// grid sampling.
+const ZONE_COLUMNS: usize = 32;
+const ZONE_ROWS: usize = 18;
+
+/// Most zones a grid can have, far more than there are bulbs around a screen.
+const MAX_ZONES: usize = 64;
+
+/// Time between two frames sampled while proposing a layout.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The zones of `ZoneLayout::Edges`, clockwise from the top.
+pub const EDGES: [&str; 4] = ["top", "right", "bottom", "left"];
+
+/// How the frame is split so that each bulb follows its own part of the screen, written as
+/// `edges` or `COLUMNSxROWS`, e.g. `3x2`.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub enum ZoneLayout {
+    /// A band along each edge, clockwise from the top like an ambilight frame.
+    Edges,
+    /// Cells of a grid, row by row from the top left.
+    Grid { columns: usize, rows: usize },
+}
+
+/// `[zones.<name>]` in the config, e.g. `[zones.bottom]` or `[zones."1,2"]`: the smoothing and
+/// change threshold of one zone, over `--smoothing` and those of the active profile.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ZoneConfig {
+    pub smoothing: Option<f32>,
+    pub threshold: Option<f32>,
+}
+
+impl FromStr for ZoneLayout {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == "edges" {
+            return Ok(ZoneLayout::Edges);
+        }
+
+        let (columns, rows) = value
+            .split_once('x')
+            .and_then(|(columns, rows)| Some((columns.parse().ok()?, rows.parse().ok()?)))
+            .filter(|&(columns, rows): &(usize, usize)| columns > 0 && rows > 0)
+            .ok_or_else(|| {
+                format!(
+                    "invalid zones \"{}\", expected \"edges\" or COLUMNSxROWS, e.g. 3x2",
+                    value
+                )
+            })?;
+        if columns.saturating_mul(rows) > MAX_ZONES {
+            return Err(format!(
+                "zones \"{}\" has more than {} zones",
+                value, MAX_ZONES
+            ));
+        }
+        Ok(ZoneLayout::Grid { columns, rows })
+    }
+}
+
+impl TryFrom<String> for ZoneLayout {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for ZoneLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZoneLayout::Edges => write!(f, "edges"),
+            ZoneLayout::Grid { columns, rows } => write!(f, "{}x{}", columns, rows),
+        }
+    }
+}
+
+impl ZoneLayout {
+    pub fn len(self) -> usize {
+        match self {
+            ZoneLayout::Edges => EDGES.len(),
+            ZoneLayout::Grid { columns, rows } => columns * rows,
+        }
+    }
+
+    /// What the zone at `index` is called in logs and the config: its edge, or `column,row` counted from 1.
+    pub fn name(self, index: usize) -> String {
+        match self {
+            ZoneLayout::Edges => EDGES[index].to_string(),
+            ZoneLayout::Grid { columns, .. } => {
+                format!("{},{}", index % columns + 1, index / columns + 1)
+            }
+        }
+    }
+
+    /// The index of the zone called `name`.
+    pub fn index(self, name: &str) -> Option<usize> {
+        (0..self.len()).find(|&index| self.name(index) == name)
+    }
+
+    /// The `[zones.<name>]` of every zone in order, and the names in `config` that aren't zones
+    /// of this layout.
+    pub fn overrides(self, config: &HashMap<String, ZoneConfig>) -> (Vec<ZoneConfig>, Vec<&str>) {
+        let mut unknown: Vec<&str> = config
+            .keys()
+            .filter(|name| self.index(name).is_none())
+            .map(String::as_str)
+            .collect();
+        unknown.sort_unstable();
+        let overrides = (0..self.len())
+            .map(|index| config.get(&self.name(index)).copied().unwrap_or_default())
+            .collect();
+        (overrides, unknown)
+    }
+
+    /// The `(left, top, width, height)` of every zone of a `width` × `height` frame, none of
+    /// them empty.
+    fn areas(self, width: usize, height: usize) -> Vec<(usize, usize, usize, usize)> {
+        match self {
+            ZoneLayout::Edges => {
+                let depth_x = ((width as f32 * EDGE_DEPTH).round() as usize).clamp(1, width);
+                let depth_y = ((height as f32 * EDGE_DEPTH).round() as usize).clamp(1, height);
+                vec![
+                    (0, 0, width, depth_y),
+                    (width - depth_x, 0, depth_x, height),
+                    (0, height - depth_y, width, depth_y),
+                    (0, 0, depth_x, height),
+                ]
+            }
+            ZoneLayout::Grid { columns, rows } => (0..columns * rows)
+                .map(|index| {
+                    let (column, row) = (index % columns, index / columns);
+                    let left = column * width / columns;
+                    let top = row * height / rows;
+                    // Cells of a grid finer than the frame get a pixel each.
+                    let right = ((column + 1) * width / columns).max(left + 1);
+                    let bottom = ((row + 1) * height / rows).max(top + 1);
+                    (left, top, right - left, bottom - top)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Side of the frame the zones are laid out on for `hyperion_leds`, split evenly by most grids.
+const HYPERION_FRAME: usize = 3600;
+
+/// The zones of `layout` as the `leds` of a Hyperion config, so the same geometry carries over
+/// to LED strips: one LED per zone in the order the bulbs follow them, with `hmin`, `hmax`,
+/// `vmin` and `vmax` the edges of the zone as fractions of the frame.
+pub fn hyperion_leds(layout: ZoneLayout) -> Value {
+    let fraction =
+        |pixels: usize| (pixels as f64 / HYPERION_FRAME as f64 * 10000.0).round() / 10000.0;
+    let leds: Vec<Value> = layout
+        .areas(HYPERION_FRAME, HYPERION_FRAME)
+        .into_iter()
+        .map(|(left, top, width, height)| {
+            json!({
+                "hmin": fraction(left),
+                "hmax": fraction(left + width),
+                "vmin": fraction(top),
+                "vmax": fraction(top + height),
+            })
+        })
+        .collect();
+    json!({ "leds": leds })
+}
+
+/// Hands the zone of every offline bulb to the bulb online whose zone is nearest, which then
+/// shows the blend of both zones' colors. `owners` has the zone of each bulb driving one and
+/// whether it is online. Returns the colors to show per zone and the zones taken over, as pairs
+/// of the orphaned zone and the zone of the bulb that took it.
+pub fn fall_back(
+    layout: ZoneLayout,
+    colors: &[Hsl],
+    owners: &[(usize, bool)],
+    color_space: ColorSpace,
+) -> (Vec<Hsl>, Vec<(usize, usize)>) {
+    let centers: Vec<(f32, f32)> = layout
+        .areas(1000, 1000)
+        .into_iter()
+        .map(|(left, top, width, height)| {
+            (
+                left as f32 + width as f32 / 2.0,
+                top as f32 + height as f32 / 2.0,
+            )
+        })
+        .collect();
+    let distance = |a: usize, b: usize| {
+        let ((ax, ay), (bx, by)) = (centers[a], centers[b]);
+        (ax - bx).powi(2) + (ay - by).powi(2)
+    };
+
+    let taken_over: Vec<(usize, usize)> = owners
+        .iter()
+        .filter(|&&(zone, online)| !online && zone < colors.len())
+        .filter_map(|&(orphan, _)| {
+            owners
+                .iter()
+                .filter(|&&(zone, online)| online && zone < colors.len())
+                .map(|&(zone, _)| zone)
+                .min_by(|&a, &b| distance(orphan, a).total_cmp(&distance(orphan, b)))
+                .map(|heir| (orphan, heir))
+        })
+        .collect();
+
+    let rgb = |color: &Hsl| {
+        let rgb = color.to_rgb();
+        [rgb.get_red(), rgb.get_green(), rgb.get_blue()]
+    };
+    let mut shown = colors.to_vec();
+    for &(_, heir) in &taken_over {
+        let blended = iter::once(heir)
+            .chain(
+                taken_over
+                    .iter()
+                    .filter(|&&(_, to)| to == heir)
+                    .map(|&(orphan, _)| orphan),
+            )
+            .map(|zone| rgb(&colors[zone]));
+        if let Some([r, g, b]) = color_space.average(blended) {
+            shown[heir] = Rgb::from(r, g, b).to_hsl();
+        }
+    }
+
+    (shown, taken_over)
+}
+
+/// The color of each zone of a `width` × `height` RGBA frame, in the order of the layout. Each
+/// zone is sampled on a sparse grid and clustered like `--sampling grid`, the zones in parallel.
+pub fn zone_colors(
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    layout: ZoneLayout,
+    color_space: ColorSpace,
+) -> Vec<Rgb> {
+    layout
+        .areas(width, height)
+        .into_par_iter()
+        .map(|(left, top, zone_width, zone_height)| {
+            let (columns, rows) = (ZONE_COLUMNS.min(zone_width), ZONE_ROWS.min(zone_height));
+            let mut samples = Vec::with_capacity(columns * rows * 4);
+            for row in 0..rows {
+                let y = top + (row * 2 + 1) * zone_height / (rows * 2);
+                for column in 0..columns {
+                    let x = left + (column * 2 + 1) * zone_width / (columns * 2);
+                    let offset = (y * width + x) * 4;
+                    samples.extend_from_slice(&rgba[offset..offset + 4]);
+                }
+            }
+            let [r, g, b] = clustered_color(&samples, color_space);
+            Rgb::from(r, g, b)
+        })
+        .collect()
+}
+
+/// The layouts with exactly `bulbs` zones.
+fn candidates(bulbs: usize) -> Vec<ZoneLayout> {
+    let mut layouts: Vec<ZoneLayout> = (1..=bulbs)
+        .filter(|&columns| bulbs.is_multiple_of(columns))
+        .map(|columns| ZoneLayout::Grid {
+            columns,
+            rows: bulbs / columns,
+        })
+        .collect();
+    if bulbs == EDGES.len() {
+        layouts.insert(0, ZoneLayout::Edges);
+    }
+    layouts
+}
+
+/// How well `layout` follows the color regions of a `columns` × `rows` map of clusters: the
+/// share of each zone taken by its most common region, averaged over the zones, times the share
+/// of the `k` regions that are the most common one of some zone.
+fn score(layout: ZoneLayout, clusters: &[usize], columns: usize, rows: usize, k: usize) -> f32 {
+    let mut purity = 0.0;
+    let mut covered = vec![false; k];
+
+    let areas = layout.areas(columns, rows);
+    for &(left, top, width, height) in &areas {
+        let mut counts = vec![0; k];
+        for row in top..top + height {
+            for column in left..left + width {
+                counts[clusters[row * columns + column]] += 1;
+            }
+        }
+        let (region, count) = counts
+            .iter()
+            .enumerate()
+            .max_by_key(|&(_, &count)| count)
+            .unwrap_or((0, &0));
+        purity += *count as f32 / (width * height) as f32;
+        covered[region] = true;
+    }
+
+    let covered = covered.iter().filter(|&&covered| covered).count();
+    purity / areas.len() as f32 * covered as f32 / k as f32
+}
+
+/// The layouts with `bulbs` zones and their scores against a `columns` × `rows` map of
+/// clusters, best first.
+fn rank(bulbs: usize, clusters: &[usize], columns: usize, rows: usize) -> Vec<(ZoneLayout, f32)> {
+    let mut scored: Vec<(ZoneLayout, f32)> = candidates(bulbs)
+        .into_iter()
+        .map(|layout| (layout, score(layout, clusters, columns, rows, bulbs)))
+        .collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored
+}
+
+/// Watches the screen for `duration`, clusters the average colors of a grid over it into one
+/// region per bulb and proposes the zone layout that follows the regions best. The proposal is
+/// written to `[capture]` in the config once confirmed, right away with `yes`.
+pub fn propose(
+    capture: CaptureOptions,
+    bulbs: usize,
+    duration: Duration,
+    config: Option<PathBuf>,
+    yes: bool,
+) -> Result<(), String> {
+    if bulbs < 2 {
+        return Err(
+            "Zones need at least two bulbs, configure more devices or pass --bulbs.".to_string(),
+        );
+    }
+
+    let mut grabber = Grabber::new(&capture)?;
+    let (width, height) = grabber.size();
+    let (columns, rows) = (GRID_COLUMNS.min(width), GRID_ROWS.min(height));
+    let mut sums = vec![([0.0f32; 3], 0usize); columns * rows];
+    let mut frames = 0;
+
+    info!("Watching the screen for {} s...", duration.as_secs());
+    let started = Instant::now();
+    while started.elapsed() < duration {
+        let samples = grabber.grab_grid(columns, rows)?;
+        for ((sum, count), pixel) in sums.iter_mut().zip(samples.chunks_exact(4)) {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let oklab = ColorSpace::Oklab.from_rgb([0, 1, 2].map(|i| pixel[i] as f32));
+            for i in 0..3 {
+                sum[i] += oklab[i];
+            }
+            *count += 1;
+        }
+        frames += 1;
+        thread::sleep(SAMPLE_INTERVAL);
+    }
+
+    let points: Vec<[f32; 3]> = sums
+        .iter()
+        .map(|(sum, count)| sum.map(|channel| channel / (*count).max(1) as f32))
+        .collect();
+    let (_, clusters) = k_means(&points, bulbs);
+
+    println!(
+        "{} frames clustered into {} regions of a {}x{} grid:",
+        frames, bulbs, columns, rows
+    );
+    for row in clusters.chunks(columns) {
+        let line: String = row
+            .iter()
+            .map(|&cluster| char::from(b'A' + (cluster % 26) as u8))
+            .collect();
+        println!("  {}", line);
+    }
+
+    let scored = rank(bulbs, &clusters, columns, rows);
+
+    println!("{:<8} {:>6}", "zones", "score");
+    for (layout, score) in &scored {
+        println!("{:<8} {:>6.2}", layout.to_string(), score);
+    }
+
+    let &(proposal, _) = scored
+        .first()
+        .ok_or_else(|| format!("There is no layout with {} zones.", bulbs))?;
+    println!("Proposed zones: {}", proposal);
+
+    if !yes {
+        print!("Write zones = \"{}\" to the config? [y/N] ", proposal);
+        io::stdout().flush().map_err(|error| error.to_string())?;
+        let mut answer = String::new();
+        io::stdin()
+            .read_line(&mut answer)
+            .map_err(|error| format!("Failed to read the answer: {}", error))?;
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            info!("Left the config alone.");
+            return Ok(());
+        }
+    }
+
+    let path = config::edit(config, |document| {
+        document["capture"]["zones"] = value(proposal.to_string());
+        Ok(())
+    })?;
+    info!("Wrote zones = \"{}\" to {}.", proposal, path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: (f32, f32, f32) = (0.0, 100.0, 50.0);
+    const BLUE: (f32, f32, f32) = (240.0, 100.0, 50.0);
+
+    fn hsl((h, s, l): (f32, f32, f32)) -> Hsl {
+        Hsl::from(h, s, l)
+    }
+
+    fn rgb(color: &Hsl) -> (u8, u8, u8) {
+        let rgb = color.to_rgb();
+        (
+            rgb.get_red().round() as u8,
+            rgb.get_green().round() as u8,
+            rgb.get_blue().round() as u8,
+        )
+    }
+
+    #[test]
+    fn parses_layouts() {
+        assert_eq!("edges".parse(), Ok(ZoneLayout::Edges));
+        assert_eq!(
+            "3x2".parse(),
+            Ok(ZoneLayout::Grid {
+                columns: 3,
+                rows: 2
+            })
+        );
+        assert_eq!(
+            "8x8".parse(),
+            Ok(ZoneLayout::Grid {
+                columns: 8,
+                rows: 8
+            })
+        );
+        for invalid in ["", "3", "0x2", "3x", "x2", "-1x2"] {
+            assert!(invalid.parse::<ZoneLayout>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn rejects_huge_grids() {
+        for huge in ["9x8", "100000x100000", "18446744073709551615x2"] {
+            let error = huge.parse::<ZoneLayout>().unwrap_err();
+            assert!(error.contains("more than 64 zones"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn offline_bulbs_hand_their_zone_to_the_nearest_online_one() {
+        let layout = ZoneLayout::Grid {
+            columns: 3,
+            rows: 1,
+        };
+        let colors = [hsl(RED), hsl(BLUE), hsl(BLUE)];
+        let owners = [(0, true), (1, false), (2, true)];
+
+        let (shown, taken_over) = fall_back(layout, &colors, &owners, ColorSpace::Rgb);
+
+        // Zones 0 and 2 are equally near, the first one wins.
+        assert_eq!(taken_over, vec![(1, 0)]);
+        assert_eq!(rgb(&shown[0]), (128, 0, 128));
+        assert_eq!(rgb(&shown[1]), (0, 0, 255));
+        assert_eq!(rgb(&shown[2]), (0, 0, 255));
+    }
+
+    #[test]
+    fn zones_without_a_bulb_are_left_alone() {
+        let layout = ZoneLayout::Grid {
+            columns: 3,
+            rows: 1,
+        };
+        let colors = [hsl(RED), hsl(BLUE), hsl(BLUE)];
+        // Nobody drives zone 1, so nobody takes it over.
+        let owners = [(0, true), (2, true)];
+
+        let (shown, taken_over) = fall_back(layout, &colors, &owners, ColorSpace::Rgb);
+
+        assert!(taken_over.is_empty());
+        assert_eq!(
+            shown.iter().map(rgb).collect::<Vec<_>>(),
+            colors.iter().map(rgb).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn bulbs_without_a_zone_are_ignored() {
+        let layout = ZoneLayout::Grid {
+            columns: 2,
+            rows: 1,
+        };
+        let colors = [hsl(RED), hsl(BLUE)];
+        // Zone 5 doesn't exist, offline or online it neither orphans nor inherits anything.
+        let owners = [(0, true), (1, true), (5, false), (5, true)];
+
+        let (shown, taken_over) = fall_back(layout, &colors, &owners, ColorSpace::Rgb);
+        assert!(taken_over.is_empty());
+        assert_eq!(rgb(&shown[1]), (0, 0, 255));
+
+        let owners = [(0, false), (5, true)];
+        let (_, taken_over) = fall_back(layout, &colors, &owners, ColorSpace::Rgb);
+        assert!(taken_over.is_empty());
+    }
+
+    #[test]
+    fn exports_zones_as_hyperion_leds() {
+        assert_eq!(
+            hyperion_leds(ZoneLayout::Edges),
+            json!({ "leds": [
+                { "hmin": 0.0, "hmax": 1.0, "vmin": 0.0, "vmax": 0.2 },
+                { "hmin": 0.8, "hmax": 1.0, "vmin": 0.0, "vmax": 1.0 },
+                { "hmin": 0.0, "hmax": 1.0, "vmin": 0.8, "vmax": 1.0 },
+                { "hmin": 0.0, "hmax": 0.2, "vmin": 0.0, "vmax": 1.0 },
+            ]})
+        );
+        assert_eq!(
+            hyperion_leds(ZoneLayout::Grid {
+                columns: 3,
+                rows: 2
+            })["leds"][4],
+            json!({ "hmin": 0.3333, "hmax": 0.6667, "vmin": 0.5, "vmax": 1.0 })
+        );
+    }
+
+    #[test]
+    fn every_bulb_count_has_a_candidate() {
+        for bulbs in 1..=64 {
+            let layouts = candidates(bulbs);
+            assert!(!layouts.is_empty(), "no layout for {} bulbs", bulbs);
+            assert!(layouts.iter().all(|layout| layout.len() == bulbs));
+        }
+        assert_eq!(candidates(4)[0], ZoneLayout::Edges);
+    }
+
+    #[test]
+    fn split_frames_rank_the_matching_layout_first() {
+        // A B / A B: left and right halves.
+        let clusters = [0, 0, 1, 1, 0, 0, 1, 1];
+        let scored = rank(2, &clusters, 4, 2);
+        assert_eq!(
+            scored[0],
+            (
+                ZoneLayout::Grid {
+                    columns: 2,
+                    rows: 1
+                },
+                1.0
+            )
+        );
+        assert!(scored[1].1 < 1.0);
+
+        // A A A A / B B B B: top and bottom halves.
+        let clusters = [0, 0, 0, 0, 1, 1, 1, 1];
+        let scored = rank(2, &clusters, 4, 2);
+        assert_eq!(
+            scored[0],
+            (
+                ZoneLayout::Grid {
+                    columns: 1,
+                    rows: 2
+                },
+                1.0
+            )
+        );
+
+        // Three columns of a 6x2 map.
+        let clusters = [0, 0, 1, 1, 2, 2, 0, 0, 1, 1, 2, 2];
+        let scored = rank(3, &clusters, 6, 2);
+        assert_eq!(
+            scored[0].0,
+            ZoneLayout::Grid {
+                columns: 3,
+                rows: 1
+            }
+        );
+    }
+
+    #[test]
+    fn uniform_frames_score_by_coverage() {
+        // One region everywhere covers one of the two clusters asked for.
+        let clusters = [0; 8];
+        assert_eq!(
+            score(
+                ZoneLayout::Grid {
+                    columns: 2,
+                    rows: 1
+                },
+                &clusters,
+                4,
+                2,
+                2
+            ),
+            0.5
+        );
+    }
+
+    #[test]
+    fn overrides_follow_the_zones() {
+        let bottom = ZoneConfig {
+            smoothing: Some(0.3),
+            threshold: None,
+        };
+        let corner = ZoneConfig {
+            smoothing: None,
+            threshold: Some(20.0),
+        };
+        let config = HashMap::from([("bottom".to_string(), bottom), ("1,2".to_string(), corner)]);
+
+        let (overrides, unknown) = ZoneLayout::Edges.overrides(&config);
+        assert_eq!(
+            overrides,
+            [
+                ZoneConfig::default(),
+                ZoneConfig::default(),
+                bottom,
+                ZoneConfig::default()
+            ]
+        );
+        assert_eq!(unknown, ["1,2"]);
+
+        let (overrides, unknown) = ZoneLayout::Grid {
+            columns: 2,
+            rows: 2,
+        }
+        .overrides(&config);
+        assert_eq!(overrides[2], corner);
+        assert_eq!(overrides.iter().filter(|zone| **zone == corner).count(), 1);
+        assert_eq!(unknown, ["bottom"]);
+    }
+}